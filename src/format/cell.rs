@@ -1,5 +1,5 @@
-use crate::format::{varint, Record};
-use anyhow::Result;
+use crate::format::{varint, Enc, Error, Record};
+use anyhow::{bail, Result};
 use std::convert::TryInto;
 
 #[derive(Debug)]
@@ -26,56 +26,74 @@ pub struct IntrIdxCell<'a> {
 }
 
 impl<'a> LeafTblCell<'a> {
-    pub fn parse(stream: &'a [u8]) -> Result<Self> {
+    pub fn parse(stream: &'a [u8], enc: Enc) -> Result<Self> {
         let mut offset = 0;
 
-        let (payload_size, bytes_read) = varint::parse(stream);
-        let payload_size: usize = payload_size.try_into()?;
+        let (payload_size, bytes_read) = varint::try_parse(stream)?;
         offset += bytes_read;
 
-        let (row_id, bytes_read) = varint::parse(&stream[offset..]);
+        let (row_id, bytes_read) = varint::try_parse(&stream[offset..])?;
         offset += bytes_read;
 
         Ok(Self {
             row_id,
-            payload: Record::parse(&stream[offset..offset + payload_size])?,
+            payload: Record::parse(payload(stream, offset, payload_size)?, enc)?,
         })
     }
 }
 
 impl<'a> IntrTblCell {
     pub fn parse(stream: &'a [u8]) -> Result<Self> {
-        let child_page = i32::from_be_bytes(stream[..4].try_into()?);
-        let (row_id, _) = varint::parse(&stream[4..]);
+        let child_page = child_page(stream)?;
+        let (row_id, _) = varint::try_parse(&stream[4..])?;
         Ok(Self { child_page, row_id })
     }
 }
 
 impl<'a> LeafIdxCell<'a> {
-    pub fn parse(stream: &'a [u8]) -> Result<Self> {
-        let (payload_size, offset) = varint::parse(stream);
-        let payload_size: usize = payload_size.try_into()?;
+    pub fn parse(stream: &'a [u8], enc: Enc) -> Result<Self> {
+        let (payload_size, offset) = varint::try_parse(stream)?;
 
         Ok(Self {
-            payload: Record::parse(&stream[offset..offset + payload_size])?,
+            payload: Record::parse(payload(stream, offset, payload_size)?, enc)?,
         })
     }
 }
 
 impl<'a> IntrIdxCell<'a> {
-    pub fn parse(stream: &'a [u8]) -> Result<Self> {
+    pub fn parse(stream: &'a [u8], enc: Enc) -> Result<Self> {
         let mut offset = 0;
 
-        let child_page = i32::from_be_bytes(stream[..4].try_into()?);
+        let child_page = child_page(stream)?;
         offset += 4;
 
-        let (payload_size, bytes_read) = varint::parse(&stream[offset..]);
-        let payload_size: usize = payload_size.try_into()?;
+        let (payload_size, bytes_read) = varint::try_parse(&stream[offset..])?;
         offset += bytes_read;
 
         Ok(Self {
             child_page,
-            payload: Record::parse(&stream[offset..offset + payload_size])?,
+            payload: Record::parse(payload(stream, offset, payload_size)?, enc)?,
         })
     }
 }
+
+fn child_page(stream: &[u8]) -> Result<i32> {
+    match stream.get(..4) {
+        Some(bytes) => Ok(i32::from_be_bytes(bytes.try_into()?)),
+        None => bail!("Child page pointer extends past end of page"),
+    }
+}
+
+/// The `size` bytes of payload at `offset`. Payloads that don't fit would continue on overflow
+/// pages.
+fn payload(stream: &[u8], offset: usize, size: i64) -> Result<&[u8]> {
+    let size: usize = size.try_into()?;
+    match stream.get(offset..).filter(|rest| rest.len() >= size) {
+        Some(rest) => Ok(&rest[..size]),
+        None => Err(Error::Unsupported(format!(
+            "payload of {} bytes spills onto overflow pages",
+            size
+        ))
+        .into()),
+    }
+}
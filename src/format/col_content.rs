@@ -1,7 +1,8 @@
+use crate::format::Enc;
 use anyhow::{anyhow, bail, Result};
 use std::{
+    borrow::Cow,
     convert::{TryFrom, TryInto},
-    str,
 };
 
 #[derive(Debug)]
@@ -17,35 +18,43 @@ pub enum ColContent<'a> {
     Zero,
     One,
     Blob(&'a [u8]),
-    Text(&'a [u8]),
+    Text(&'a [u8], Enc),
 }
 
 impl<'a> ColContent<'a> {
-    pub fn parse(serial_type: i64, stream: &[u8]) -> Result<(ColContent, usize)> {
+    pub fn parse(serial_type: i64, stream: &[u8], enc: Enc) -> Result<(ColContent<'_>, usize)> {
         Ok(match serial_type {
             0 => (ColContent::Null, 0),
-            1 => (ColContent::Int8(stream[..1].try_into()?), 1),
-            2 => (ColContent::Int16(stream[..2].try_into()?), 2),
-            3 => (ColContent::Int24(stream[..3].try_into()?), 3),
-            4 => (ColContent::Int32(stream[..4].try_into()?), 4),
-            5 => (ColContent::Int48(stream[..6].try_into()?), 6),
-            6 => (ColContent::Int64(stream[..8].try_into()?), 8),
-            7 => (ColContent::Float64(stream[..8].try_into()?), 8),
+            1 => (ColContent::Int8(take(stream, 1)?.try_into()?), 1),
+            2 => (ColContent::Int16(take(stream, 2)?.try_into()?), 2),
+            3 => (ColContent::Int24(take(stream, 3)?.try_into()?), 3),
+            4 => (ColContent::Int32(take(stream, 4)?.try_into()?), 4),
+            5 => (ColContent::Int48(take(stream, 6)?.try_into()?), 6),
+            6 => (ColContent::Int64(take(stream, 8)?.try_into()?), 8),
+            7 => (ColContent::Float64(take(stream, 8)?.try_into()?), 8),
             8 => (ColContent::Zero, 0),
             9 => (ColContent::One, 0),
             n if n >= 12 && n % 2 == 0 => {
                 let len = ((n - 12) / 2).try_into()?;
-                (ColContent::Blob(&stream[..len]), len)
+                (ColContent::Blob(take(stream, len)?), len)
             }
             n if n >= 13 && n % 2 == 1 => {
                 let len = ((n - 13) / 2).try_into()?;
-                (ColContent::Text(&stream[..len]), len)
+                (ColContent::Text(take(stream, len)?, enc), len)
             }
             n => bail!("Invalid serial type: {}", n),
         })
     }
 }
 
+/// The first `len` bytes of `stream`, which are missing if a corrupt record claims more content
+/// than its payload holds.
+fn take(stream: &[u8], len: usize) -> Result<&[u8]> {
+    stream
+        .get(..len)
+        .ok_or_else(|| anyhow!("Column of {} bytes extends past end of record", len))
+}
+
 impl<'a> TryFrom<&ColContent<'a>> for i8 {
     type Error = anyhow::Error;
 
@@ -111,24 +120,32 @@ impl<'a> TryFrom<&ColContent<'a>> for f64 {
     }
 }
 
-impl<'a> TryFrom<&ColContent<'a>> for &'a str {
+impl<'a> TryFrom<&ColContent<'a>> for Cow<'a, str> {
     type Error = anyhow::Error;
 
     fn try_from(value: &ColContent<'a>) -> Result<Self, Self::Error> {
         Ok(match value {
-            ColContent::Text(bytes) => str::from_utf8(bytes)?,
+            ColContent::Text(bytes, enc) => enc.decode(bytes)?,
             _ => bail!("ColContent cannot be converted to str: {:?}", value),
         })
     }
 }
 
-impl<'a> TryFrom<&ColContent<'a>> for Option<&'a str> {
+impl<'a> TryFrom<&ColContent<'a>> for String {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &ColContent<'a>) -> Result<Self, Self::Error> {
+        Ok(<Cow<str>>::try_from(value)?.into_owned())
+    }
+}
+
+impl<'a> TryFrom<&ColContent<'a>> for Option<String> {
     type Error = anyhow::Error;
 
     fn try_from(value: &ColContent<'a>) -> Result<Self, Self::Error> {
         Ok(match value {
             ColContent::Null => None,
-            _ => Some(<&str>::try_from(value)?),
+            _ => Some(String::try_from(value)?),
         })
     }
 }
@@ -0,0 +1,25 @@
+use crate::format::{DbHeader, Enc, Page};
+use anyhow::Result;
+
+/// A database file loaded into memory together with the header fields needed to decode its
+/// pages.
+#[derive(Debug, Clone, Copy)]
+pub struct DbFile<'a> {
+    pub bytes: &'a [u8],
+    pub page_size: usize,
+    pub enc: Enc,
+}
+
+impl<'a> DbFile<'a> {
+    pub fn new(bytes: &'a [u8], header: &DbHeader) -> Self {
+        Self {
+            bytes,
+            page_size: header.page_size as usize,
+            enc: header.text_encoding,
+        }
+    }
+
+    pub fn page(&self, page_num: i32) -> Result<Page<'a>> {
+        Page::parse(page_num, self.page_size, self.bytes)
+    }
+}
@@ -1,7 +1,8 @@
+use crate::format::Error;
 use anyhow::{bail, Result};
-use std::{convert::TryInto, fmt};
+use std::{borrow::Cow, convert::TryInto, fmt, str};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Enc {
     Utf8 = 1,
     Utf16Le = 2,
@@ -11,7 +12,7 @@ pub enum Enc {
 #[derive(Debug)]
 pub struct DbHeader {
     pub header_string: String,
-    pub page_size: u16,
+    pub page_size: u32,
     pub write_format: u8,
     pub read_format: u8,
     pub reserved_bytes: u8,
@@ -37,10 +38,22 @@ pub struct DbHeader {
 impl DbHeader {
     pub const SIZE: usize = 100;
 
-    pub fn parse(stream: &[u8]) -> Result<Self> {
-        Ok(Self {
+    const HEADER_STRING: &'static [u8] = b"SQLite format 3\0";
+
+    /// Parses the header at the start of the database file `db` and checks it the way SQLite does
+    /// when opening a file.
+    pub fn parse(db: &[u8]) -> Result<Self> {
+        let stream = match db.get(..Self::SIZE) {
+            Some(stream) if stream.starts_with(Self::HEADER_STRING) => stream,
+            _ => return Err(Error::NotADatabase.into()),
+        };
+        let header = Self {
             header_string: String::from_utf8_lossy(&stream[..16]).to_string(),
-            page_size: u16::from_be_bytes(stream[16..18].try_into()?),
+            // 65536 doesn't fit into the 2 bytes, so it's stored as 1.
+            page_size: match u16::from_be_bytes(stream[16..18].try_into()?) {
+                1 => 65536,
+                x => x.into(),
+            },
             write_format: stream[18],
             read_format: stream[19],
             reserved_bytes: stream[20],
@@ -59,14 +72,98 @@ impl DbHeader {
                 1 => Enc::Utf8,
                 2 => Enc::Utf16Le,
                 3 => Enc::Utf16Be,
-                x => bail!("Invalid text encoding value encountered: {}", x),
+                x => return Err(invalid("text encoding", x)),
             },
             user_version: u32::from_be_bytes(stream[60..64].try_into()?),
             incremental_vacuum: u32::from_be_bytes(stream[64..68].try_into()?),
             application_id: u32::from_be_bytes(stream[68..72].try_into()?),
             version_valid_for: u32::from_be_bytes(stream[92..96].try_into()?),
             software_version: u32::from_be_bytes(stream[96..100].try_into()?),
-        })
+        };
+
+        let h = &header;
+        if !h.page_size.is_power_of_two() || !(512..=65536).contains(&h.page_size) {
+            return Err(invalid("page size", h.page_size));
+        }
+        // Version 2 is for WAL mode, later versions may change the format in any way.
+        if h.read_format > 2 {
+            return Err(Error::Unsupported(format!("read version {}", h.read_format)).into());
+        }
+        if !(1..=4).contains(&h.schema_format) {
+            return Err(invalid("schema format", h.schema_format));
+        }
+        if h.usable_size() < 480 {
+            return Err(invalid("reserved bytes", h.reserved_bytes));
+        }
+        for (field, value, expected) in [
+            ("max embedded payload fraction", h.max_emb_payload_frac, 64),
+            ("min embedded payload fraction", h.min_emb_payload_frac, 32),
+            ("leaf payload fraction", h.leaf_payload_frac, 32),
+        ] {
+            if value != expected {
+                return Err(invalid(field, value));
+            }
+        }
+        if h.page_count(db.len()) as usize > db.len() / h.page_size as usize {
+            return Err(invalid("database size", h.db_page_count));
+        }
+        Ok(header)
+    }
+
+    /// The number of bytes on each page that B-trees can use. Extensions may reserve space at the
+    /// end of every page.
+    pub fn usable_size(&self) -> usize {
+        self.page_size as usize - usize::from(self.reserved_bytes)
+    }
+
+    /// The number of pages in a file of `file_size` bytes. The count in the header is only up to
+    /// date if the version it's valid for is the current one, as older versions of SQLite didn't
+    /// maintain it.
+    pub fn page_count(&self, file_size: usize) -> u32 {
+        if self.db_page_count != 0 && self.version_valid_for == self.file_change_counter {
+            self.db_page_count
+        } else {
+            (file_size / self.page_size as usize) as u32
+        }
+    }
+}
+
+fn invalid(field: &'static str, value: impl Into<u32>) -> anyhow::Error {
+    Error::InvalidHeader {
+        field,
+        value: value.into(),
+    }
+    .into()
+}
+
+impl Enc {
+    /// Decodes text stored in the database with this encoding. Only UTF-8 text can be borrowed.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, str>> {
+        let from_utf16 = |to_u16: fn([u8; 2]) -> u16| -> Result<Cow<'a, str>> {
+            if bytes.len() % 2 != 0 {
+                bail!("Invalid UTF-16 text of odd length {}", bytes.len());
+            }
+            let units = bytes
+                .chunks_exact(2)
+                .map(|b| to_u16([b[0], b[1]]))
+                .collect::<Vec<_>>();
+            Ok(Cow::Owned(String::from_utf16(&units)?))
+        };
+
+        match self {
+            Enc::Utf8 => Ok(Cow::Borrowed(str::from_utf8(bytes)?)),
+            Enc::Utf16Le => from_utf16(u16::from_le_bytes),
+            Enc::Utf16Be => from_utf16(u16::from_be_bytes),
+        }
+    }
+
+    /// Encodes text the way it would be stored in the database with this encoding.
+    pub fn encode<'a>(&self, s: &'a str) -> Cow<'a, [u8]> {
+        match self {
+            Enc::Utf8 => Cow::Borrowed(s.as_bytes()),
+            Enc::Utf16Le => Cow::Owned(s.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Enc::Utf16Be => Cow::Owned(s.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+        }
     }
 }
 
@@ -80,3 +177,99 @@ impl fmt::Display for Enc {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{DbHeader, Enc};
+    use crate::{format::Error, interpreter::database::Database};
+
+    fn parse_err(db: &[u8]) -> Error {
+        let err = DbHeader::parse(db).unwrap_err();
+        err.downcast_ref::<Error>().unwrap().clone()
+    }
+
+    #[test]
+    fn decodes_page_size_of_65536() {
+        let mut db = Database::empty();
+        db.resize(65536, 0);
+        db[16..18].copy_from_slice(&[0, 1]);
+        assert_eq!(DbHeader::parse(&db).unwrap().page_size, 65536);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let invalid = |field, value| Error::InvalidHeader { field, value };
+
+        let mut db = Database::empty();
+        db[15] = b'!';
+        assert_eq!(parse_err(&db), Error::NotADatabase);
+        assert_eq!(parse_err(&db[..50]), Error::NotADatabase);
+
+        let mut db = Database::empty();
+        db[16..18].copy_from_slice(&768u16.to_be_bytes());
+        assert_eq!(parse_err(&db), invalid("page size", 768));
+        db[16..18].copy_from_slice(&256u16.to_be_bytes());
+        assert_eq!(parse_err(&db), invalid("page size", 256));
+
+        let mut db = Database::empty();
+        db[19] = 3;
+        assert_eq!(
+            parse_err(&db),
+            Error::Unsupported("read version 3".to_owned())
+        );
+
+        let mut db = Database::empty();
+        db[44..48].copy_from_slice(&5u32.to_be_bytes());
+        assert_eq!(parse_err(&db), invalid("schema format", 5));
+        db[44..48].copy_from_slice(&0u32.to_be_bytes());
+        assert_eq!(parse_err(&db), invalid("schema format", 0));
+
+        let mut db = Database::empty();
+        db[16..18].copy_from_slice(&512u16.to_be_bytes());
+        db[20] = 40;
+        assert_eq!(parse_err(&db), invalid("reserved bytes", 40));
+
+        let mut db = Database::empty();
+        db[22] = 64;
+        assert_eq!(parse_err(&db), invalid("min embedded payload fraction", 64));
+    }
+
+    #[test]
+    fn ignores_stale_page_count() {
+        let mut db = Database::empty();
+        db[28..32].copy_from_slice(&2u32.to_be_bytes());
+        assert_eq!(
+            parse_err(&db),
+            Error::InvalidHeader {
+                field: "database size",
+                value: 2
+            }
+        );
+
+        // The count was written by a version of SQLite that didn't maintain it.
+        db[92..96].copy_from_slice(&0u32.to_be_bytes());
+        let header = DbHeader::parse(&db).unwrap();
+        assert_eq!(header.page_count(db.len()), 1);
+    }
+
+    #[test]
+    fn round_trips_text() {
+        for enc in [Enc::Utf8, Enc::Utf16Le, Enc::Utf16Be] {
+            for s in ["", "foo", "Grüße", "日本語", "🦀"] {
+                assert_eq!(enc.decode(&enc.encode(s)).unwrap(), s);
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_utf16_with_byte_order() {
+        assert_eq!(&*Enc::Utf16Le.encode("aĀ"), &[0x61, 0x00, 0x00, 0x01]);
+        assert_eq!(&*Enc::Utf16Be.encode("aĀ"), &[0x00, 0x61, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn rejects_invalid_utf16() {
+        assert!(Enc::Utf16Le.decode(&[0x61]).is_err());
+        assert!(Enc::Utf16Le.decode(&[0x00, 0xD8]).is_err());
+    }
+}
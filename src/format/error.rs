@@ -0,0 +1,68 @@
+use std::{error, fmt};
+
+/// Why a file can't be read as a database. Decoders return these inside `anyhow::Error`, so
+/// callers that care about the kind of problem can get it back with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The structure of page `page` is broken at `offset` bytes into it.
+    Corrupt {
+        page: u32,
+        offset: usize,
+        reason: String,
+    },
+    /// The schema record of object `name` can't be used to read it.
+    CorruptSchema { name: String, reason: String },
+    /// A pointer refers to a page that isn't in the file.
+    InvalidPage(i64),
+    /// The file doesn't start with a database header.
+    NotADatabase,
+    /// A field of the database header has a value SQLite never writes.
+    InvalidHeader { field: &'static str, value: u32 },
+    /// The file uses a feature that can't be read yet.
+    Unsupported(String),
+}
+
+impl Error {
+    /// Attributes a problem found while decoding part of a page to that page. Errors that already
+    /// say where they happened are kept as they are.
+    pub fn corrupt(page: u32, offset: usize, err: anyhow::Error) -> anyhow::Error {
+        if err.is::<Error>() {
+            return err;
+        }
+        Error::Corrupt {
+            page,
+            offset,
+            reason: err.to_string(),
+        }
+        .into()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Corrupt {
+                page,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "database disk image is malformed: page {} offset {}: {}",
+                page, offset, reason
+            ),
+            Error::CorruptSchema { name, reason } => {
+                write!(f, "malformed database schema ({}) - {}", name, reason)
+            }
+            Error::InvalidPage(page) => {
+                write!(f, "database disk image is malformed: no page {}", page)
+            }
+            Error::NotADatabase => write!(f, "file is not a database"),
+            Error::InvalidHeader { field, value } => {
+                write!(f, "invalid {} in database header: {}", field, value)
+            }
+            Error::Unsupported(what) => write!(f, "unsupported file format: {}", what),
+        }
+    }
+}
+
+impl error::Error for Error {}
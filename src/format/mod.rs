@@ -1,14 +1,18 @@
 mod cell;
 mod col_content;
+mod db_file;
 mod db_header;
+mod error;
 mod page;
 mod page_header;
 mod record;
-mod varint;
+pub mod varint;
 
 pub use cell::*;
 pub use col_content::*;
+pub use db_file::*;
 pub use db_header::*;
+pub use error::*;
 pub use page::*;
 pub use page_header::*;
 pub use record::*;
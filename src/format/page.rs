@@ -1,9 +1,10 @@
-use crate::format::{DbHeader, PageHeader};
-use anyhow::Result;
-use std::convert::{TryFrom, TryInto};
+use crate::format::{DbHeader, Error, PageHeader};
+use anyhow::{anyhow, Result};
+use std::{convert::TryFrom, fmt::Display};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Page<'a> {
+    pub num: u32,
     pub header: PageHeader,
     pub data: &'a [u8],
     pub is_db_schema: bool,
@@ -11,30 +12,129 @@ pub struct Page<'a> {
 
 impl<'a> Page<'a> {
     pub fn parse_schema(page_size: usize, db: &'a [u8]) -> Result<Self> {
-        Ok(Page {
-            header: PageHeader::parse(&db[DbHeader::SIZE..])?,
-            data: &db[..page_size],
-            is_db_schema: true,
-        })
+        Self::parse(1, page_size, db)
     }
 
     pub fn parse(page_num: i32, page_size: usize, db: &'a [u8]) -> Result<Self> {
-        let page_offset = usize::try_from(page_num - 1).unwrap() * page_size;
+        let data = usize::try_from(i64::from(page_num) - 1)
+            .ok()
+            .and_then(|i| i.checked_mul(page_size))
+            .and_then(|offset| db.get(offset..offset.checked_add(page_size)?))
+            .filter(|data| !data.is_empty())
+            .ok_or(Error::InvalidPage(page_num.into()))?;
+        let num = page_num as u32;
 
-        Ok(Page {
-            header: PageHeader::parse(&db[page_offset..])?,
-            data: &db[page_offset..page_offset + page_size],
-            is_db_schema: false,
-        })
+        let is_db_schema = page_num == 1;
+        let header_offset = if is_db_schema { DbHeader::SIZE } else { 0 };
+        let header = data
+            .get(header_offset..)
+            .ok_or_else(|| anyhow!("Page of {} bytes has no room for a header", page_size))
+            .and_then(PageHeader::parse)
+            .map_err(|e| Error::corrupt(num, header_offset, e))?;
+
+        let page = Page {
+            num,
+            header,
+            data,
+            is_db_schema,
+        };
+        let cell_ptrs_end = page.cell_ptrs_offset() + 2 * usize::from(page.header.number_of_cells);
+        if cell_ptrs_end > data.len() {
+            return Err(page.corrupt(
+                page.header_offset(),
+                format!(
+                    "{} cells don't fit on the page",
+                    page.header.number_of_cells
+                ),
+            ));
+        }
+        Ok(page)
     }
 
     pub fn cell_ptrs(&self) -> impl Iterator<Item = usize> + 'a {
-        let cell_ptrs_offset =
-            self.header.size() + if self.is_db_schema { DbHeader::SIZE } else { 0 };
-
-        self.data[cell_ptrs_offset..]
+        self.data[self.cell_ptrs_offset()..]
             .chunks_exact(2)
             .take(self.header.number_of_cells.into())
-            .map(|bytes| usize::from(u16::from_be_bytes(bytes.try_into().unwrap())))
+            .map(|bytes| usize::from(u16::from_be_bytes([bytes[0], bytes[1]])))
+    }
+
+    /// Decodes the cell at `ptr` with `parse`. Problems are reported as corruption of this page.
+    pub fn parse_cell<T>(
+        &self,
+        ptr: usize,
+        parse: impl FnOnce(&'a [u8]) -> Result<T>,
+    ) -> Result<T> {
+        let cell = match self.data.get(ptr..) {
+            Some(cell) if ptr >= self.cell_ptrs_offset() && !cell.is_empty() => cell,
+            _ => return Err(self.corrupt(ptr, "Cell pointer out of range")),
+        };
+        parse(cell).map_err(|e| Error::corrupt(self.num, ptr, e))
+    }
+
+    /// An error about the contents of this page at `offset`.
+    pub fn corrupt(&self, offset: usize, reason: impl Display) -> anyhow::Error {
+        Error::Corrupt {
+            page: self.num,
+            offset,
+            reason: reason.to_string(),
+        }
+        .into()
+    }
+
+    /// Where the page header starts, which is after the database header on page 1.
+    pub fn header_offset(&self) -> usize {
+        if self.is_db_schema {
+            DbHeader::SIZE
+        } else {
+            0
+        }
+    }
+
+    fn cell_ptrs_offset(&self) -> usize {
+        self.header_offset() + self.header.size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Page;
+    use crate::{
+        format::{Enc, Error, LeafTblCell},
+        interpreter::database::Database,
+    };
+
+    #[test]
+    fn rejects_pages_outside_file() {
+        let db = Database::empty();
+        for page_num in [-1, 0, 2] {
+            let err = Page::parse(page_num, 4096, &db).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<Error>(),
+                Some(&Error::InvalidPage(page_num.into()))
+            );
+        }
+    }
+
+    #[test]
+    fn reports_corrupt_cells() {
+        let mut db = Database::empty();
+        db[103..105].copy_from_slice(&1u16.to_be_bytes());
+        db[108..110].copy_from_slice(&4094u16.to_be_bytes());
+        db[4094..].copy_from_slice(&[0x05, 0x80]);
+        let page = Page::parse_schema(4096, &db).unwrap();
+        let err = page
+            .parse_cell(4094, |c| LeafTblCell::parse(c, Enc::Utf8))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Error>(),
+            Some(&Error::Corrupt {
+                page: 1,
+                offset: 4094,
+                reason: "Varint extends past end of page".to_owned(),
+            })
+        );
+
+        db[103..105].copy_from_slice(&3000u16.to_be_bytes());
+        assert!(Page::parse_schema(4096, &db).is_err());
     }
 }
@@ -1,7 +1,7 @@
 use anyhow::{bail, Result};
 use std::convert::TryInto;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PageType {
     IntrIdx = 2,
     IntrTbl = 5,
@@ -9,7 +9,7 @@ pub enum PageType {
     LeafTbl = 13,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PageHeader {
     pub page_type: PageType,
     pub first_free_block_start: u16,
@@ -21,6 +21,9 @@ pub struct PageHeader {
 
 impl PageHeader {
     pub fn parse(stream: &[u8]) -> Result<Self> {
+        if stream.len() < 8 {
+            bail!("Page header extends past end of page");
+        }
         let page_type = match stream[0] {
             2 => PageType::IntrIdx,
             5 => PageType::IntrTbl,
@@ -33,9 +36,10 @@ impl PageHeader {
         let start_of_content_area = u16::from_be_bytes(stream[5..7].try_into()?);
         let fragmented_free_bytes = stream[7];
         let right_most_ptr = match page_type {
-            PageType::IntrTbl | PageType::IntrIdx => {
-                Some(i32::from_be_bytes(stream[8..12].try_into()?))
-            }
+            PageType::IntrTbl | PageType::IntrIdx => match stream.get(8..12) {
+                Some(ptr) => Some(i32::from_be_bytes(ptr.try_into()?)),
+                None => bail!("Page header extends past end of page"),
+            },
             _ => None,
         };
 
@@ -1,22 +1,31 @@
-use crate::format::{varint, ColContent};
-use anyhow::Result;
+use crate::format::{varint, ColContent, Enc};
+use anyhow::{bail, Result};
 use std::{convert::TryInto, ops::Index};
 
 #[derive(Debug)]
 pub struct Record<'a>(pub Vec<ColContent<'a>>);
 
 impl<'a> Record<'a> {
-    pub fn parse(stream: &'a [u8]) -> Result<Self> {
+    pub fn parse(stream: &'a [u8], enc: Enc) -> Result<Self> {
         let (header_size, mut header_offset) = varint::parse(stream);
         let header_size = header_size.try_into()?;
+        if header_size > stream.len() {
+            bail!(
+                "Record header of {} bytes extends past end of record",
+                header_size
+            );
+        }
         let mut content_offset = header_size;
 
         let mut record = vec![];
 
         while header_offset < header_size {
             let (serial_type, read_bytes) = varint::parse(&stream[header_offset..]);
-            let (col_content, content_size) =
-                ColContent::parse(serial_type, &stream[content_offset..])?;
+            let (col_content, content_size) = ColContent::parse(
+                serial_type,
+                stream.get(content_offset..).unwrap_or_default(),
+                enc,
+            )?;
             record.push(col_content);
             header_offset += read_bytes;
             content_offset += content_size;
@@ -26,10 +35,12 @@ impl<'a> Record<'a> {
     }
 }
 
+/// Columns beyond the end of a record are NULL. SQLite writes such records for tables that got
+/// more columns after the rows were inserted.
 impl<'a> Index<usize> for Record<'a> {
     type Output = ColContent<'a>;
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self.0[index]
+        self.0.get(index).unwrap_or(&ColContent::Null)
     }
 }
@@ -1,4 +1,5 @@
 use crate::util::TakeWhileInclExt;
+use anyhow::{bail, Result};
 
 /// Parses SQLite's "varint" (short for variable-length integer) as mentioned here:
 /// [varint](https://www.sqlite.org/fileformat2.html#varint)
@@ -17,9 +18,47 @@ pub fn parse(stream: &[u8]) -> (i64, usize) {
         })
 }
 
+/// Like [parse], but fails if `stream` ends before the varint does instead of returning what was
+/// read so far.
+pub fn try_parse(stream: &[u8]) -> Result<(i64, usize)> {
+    let (varint, bytes_read) = parse(stream);
+    let terminated = bytes_read
+        .checked_sub(1)
+        .map_or(false, |i| stream[i] & 0x80 == 0);
+    if bytes_read < 9 && !terminated {
+        bail!("Varint extends past end of page");
+    }
+    Ok((varint, bytes_read))
+}
+
+/// Serializes `varint` into SQLite's variable-length format, which is the inverse of [parse].
+pub fn serialize(varint: i64) -> Vec<u8> {
+    let mut v = varint as u64;
+    // The 9th byte holds 8 bits, so it's only needed if the top 8 bits are in use.
+    if v >> 56 != 0 {
+        let mut bytes = vec![0; 9];
+        bytes[8] = v as u8;
+        v >>= 8;
+        for b in bytes[..8].iter_mut().rev() {
+            *b = (v & 0x7f) as u8 | 0x80;
+            v >>= 7;
+        }
+        return bytes;
+    }
+
+    let mut bytes = vec![(v & 0x7f) as u8];
+    v >>= 7;
+    while v != 0 {
+        bytes.push((v & 0x7f) as u8 | 0x80);
+        v >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
 #[cfg(test)]
 mod test {
-    use super::parse;
+    use super::{parse, serialize, try_parse};
 
     #[test]
     fn conversion() {
@@ -59,4 +98,33 @@ mod test {
             (i64::MAX, 9)
         );
     }
+
+    #[test]
+    fn rejects_truncated_varints() {
+        assert!(try_parse(&[]).is_err());
+        assert!(try_parse(&[0b1000_0001]).is_err());
+        assert_eq!(try_parse(&[0b1000_0001, 0b0000_0000]).unwrap(), (128, 2));
+        assert_eq!(try_parse(&[0xff; 9]).unwrap(), (-1, 9));
+    }
+
+    #[test]
+    fn round_trips() {
+        for v in [
+            0,
+            1,
+            127,
+            128,
+            300,
+            1 << 56,
+            (1 << 56) - 1,
+            i64::MAX,
+            -1,
+            i64::MIN,
+        ] {
+            let bytes = serialize(v);
+            assert_eq!(parse(&bytes), (v, bytes.len()));
+        }
+        assert_eq!(serialize(300), [0b1000_0010, 0b0010_1100]);
+        assert_eq!(serialize(-1).len(), 9);
+    }
 }
@@ -0,0 +1,213 @@
+//! ANALYZE, which counts the rows of tables and how many of them share each key of their
+//! indexes. The query planner weighs the ways of finding rows with these statistics.
+
+use crate::{
+    format::{DbFile, Enc, Error},
+    interpreter::{
+        btree,
+        database::Database,
+        eval::Value,
+        write::{self, Pager},
+    },
+    schema::{DbSchema, KeyCol, ObjSchema, Stat},
+    util::MapOkAndThenExt,
+};
+use anyhow::{bail, Result};
+use std::{borrow::Cow, convert::TryFrom};
+
+const STAT_TBL: &str = "sqlite_stat1";
+
+/// Analyzes the table or index called `name`, or all tables if there's none, and replaces their
+/// rows in `sqlite_stat1`. The table is created the first time.
+pub fn run(name: Option<&str>, db: &mut Database) -> Result<()> {
+    let stats = {
+        let db_schema = &db.schema;
+        let file = DbFile::new(&db.bytes, &db_schema.db_header);
+        let analyzed = |s: &Stat| match name {
+            None | Some("main") => true,
+            Some(name) => s.tbl == name || s.idx.as_deref() == Some(name),
+        };
+        let mut stats = db_schema
+            .stats
+            .iter()
+            .filter(|s| !analyzed(s))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        match name.and_then(|n| db_schema.objs.iter().find(|o| o.name == n)) {
+            Some(idx) if idx.type_ == "index" => {
+                let tbl = db_schema
+                    .table(&idx.tbl_name)
+                    .ok_or_else(|| Error::CorruptSchema {
+                        name: idx.name.clone(),
+                        reason: "orphan index".to_string(),
+                    })?;
+                stats.extend(idx_stat(tbl, idx, file)?);
+            }
+            Some(tbl) if tbl.type_ == "table" => stats.extend(tbl_stats(tbl, db_schema, file)?),
+            None if matches!(name, None | Some("main")) => {
+                for tbl in db_schema.tables() {
+                    stats.extend(tbl_stats(tbl, db_schema, file)?);
+                }
+            }
+            _ => bail!("no such table: {}", name.unwrap_or_default()),
+        }
+        stats
+    };
+
+    if db.schema.table(STAT_TBL).is_none() {
+        let sql = format!("CREATE TABLE {}(tbl,idx,stat)", STAT_TBL);
+        write::create_tbl(db, STAT_TBL, sql)?;
+    }
+    let bytes = {
+        let mut pager = Pager::new(db)?;
+        let rows = (1..)
+            .zip(&stats)
+            .map(|(rowid, s)| {
+                let record = [
+                    Value::String(Cow::Borrowed(&s.tbl)),
+                    s.idx
+                        .as_deref()
+                        .map_or(Value::Null, |idx| Value::String(Cow::Borrowed(idx))),
+                    Value::String(Cow::Owned(s.stat())),
+                ];
+                (rowid, pager.encode_record(&record))
+            })
+            .collect::<Vec<_>>();
+        let stat_tbl = db.schema.table(STAT_TBL).unwrap();
+        pager.build_tbl(stat_tbl.rootpage as u32, rows)?;
+        pager.finish(false)
+    };
+    db.commit(bytes)
+}
+
+/// Collects the statistics of a table's indexes, or only its number of rows if it has no index
+/// that covers all of them. SQLite's internal tables and empty tables are left out.
+fn tbl_stats(tbl: &ObjSchema, db_schema: &DbSchema, db: DbFile) -> Result<Vec<Stat>> {
+    if tbl.name.starts_with("sqlite_") || tbl.cols.is_none() {
+        return Ok(vec![]);
+    }
+
+    // The primary key of a WITHOUT ROWID table is the table itself.
+    let cols = tbl.cols()?;
+    let pk = Some(tbl).filter(|_| cols.is_without_rowid());
+    let idxs = db_schema
+        .indexes()
+        .filter(|i| i.tbl_name == tbl.name && i.rootpage != tbl.rootpage);
+    let mut stats = vec![];
+    for idx in pk.into_iter().chain(idxs) {
+        stats.extend(idx_stat(tbl, idx, db)?);
+    }
+
+    let has_all_rows = |s: &Stat| {
+        let idx = s.idx.as_deref();
+        db_schema
+            .indexes()
+            .chain(pk)
+            .any(|i| Some(i.name.as_str()) == idx && i.cols().map_or(false, |c| !c.is_partial()))
+    };
+    if !stats.iter().any(has_all_rows) {
+        let n = btree::full_tbl_scan(db.page(tbl.rootpage)?, db).try_fold(0, |n, row| {
+            row?;
+            Ok::<_, anyhow::Error>(n + 1)
+        })?;
+        if n > 0 {
+            stats.push(Stat {
+                tbl: tbl.name.clone(),
+                idx: None,
+                counts: vec![n],
+            });
+        }
+    }
+    Ok(stats)
+}
+
+/// Counts the entries of an index and how many distinct values the first 1, 2, … of its key
+/// columns take. Like in SQLite, NULLs count as equal to each other and values are compared by
+/// the collations the index sorts its columns by.
+fn idx_stat(tbl: &ObjSchema, idx: &ObjSchema, db: DbFile) -> Result<Option<Stat>> {
+    let tbl_cols = tbl.cols()?;
+    let key = idx
+        .cols()?
+        .key()
+        .iter()
+        .map(|c| tbl_cols.collated(c))
+        .collect::<Vec<_>>();
+    let mut n: u64 = 0;
+    let mut distinct = vec![0; key.len()];
+    let mut prev: Option<Vec<Value>> = None;
+    let entries = btree::idx_seek(vec![], db.page(idx.rootpage)?, db).map_ok_and_then(|entry| {
+        (0..key.len())
+            .map(|i| Value::try_from(&entry[i]))
+            .collect::<Result<Vec<_>>>()
+    });
+    for entry in entries {
+        let entry = entry?;
+        let same = prev
+            .as_ref()
+            .map_or(0, |prev| same_prefix_len(prev, &entry, &key, db.enc));
+        distinct[same..].iter_mut().for_each(|d| *d += 1);
+        n += 1;
+        prev = Some(entry);
+    }
+    if n == 0 {
+        return Ok(None);
+    }
+
+    let avg_eqs = distinct.iter().map(|&d| avg_eq(n, d));
+    Ok(Some(Stat {
+        tbl: tbl.name.clone(),
+        idx: Some(idx.name.clone()),
+        counts: Some(n).into_iter().chain(avg_eqs).collect(),
+    }))
+}
+
+/// The average number of the `n` entries that share one of `distinct` values, rounded up so
+/// that it's never 0. Like in SQLite, values that are nearly unique count as unique, so that
+/// rounding up doesn't make them look twice as common as they are.
+fn avg_eq(n: u64, distinct: u64) -> u64 {
+    match (n + distinct - 1) / distinct {
+        2 if n * 10 <= distinct * 11 => 1,
+        avg_eq => avg_eq,
+    }
+}
+
+/// The number of leading key columns two index entries have the same values in.
+fn same_prefix_len(a: &[Value], b: &[Value], key: &[KeyCol], enc: Enc) -> usize {
+    (0..key.len())
+        .take_while(|&i| btree::cmp_keys(&a[i..=i], &b[i..=i], &key[i..=i], enc).is_eq())
+        .count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{avg_eq, tbl_stats};
+    use crate::{format::DbFile, interpreter::test_db::db, schema::Stat};
+    use anyhow::Result;
+
+    #[test]
+    fn counts_rows_of_tbls_without_indexes() -> Result<()> {
+        let db = db()?;
+        let file = DbFile::new(&db.bytes, &db.schema.db_header);
+        let stats = tbl_stats(db.schema.table("t").unwrap(), &db.schema, file)?;
+        assert_eq!(
+            stats,
+            [Stat {
+                tbl: "t".to_owned(),
+                idx: None,
+                counts: vec![2],
+            }]
+        );
+        assert_eq!(stats[0].stat(), "2");
+        Ok(())
+    }
+
+    #[test]
+    fn counts_nearly_unique_values_as_unique() {
+        assert_eq!(avg_eq(5000, 5000), 1);
+        assert_eq!(avg_eq(5000, 4995), 1);
+        assert_eq!(avg_eq(5000, 4500), 2);
+        assert_eq!(avg_eq(5000, 2500), 2);
+        assert_eq!(avg_eq(5000, 1667), 3);
+    }
+}
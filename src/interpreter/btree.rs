@@ -1,67 +1,70 @@
 use crate::{
-    format::{IntrIdxCell, IntrTblCell, LeafIdxCell, LeafTblCell, Page, PageType},
+    format::{
+        ColContent, DbFile, Enc, IntrIdxCell, IntrTblCell, LeafIdxCell, LeafTblCell, Page,
+        PageType, Record,
+    },
     interpreter::eval::Value,
+    schema::KeyCol,
+    syntax::SortOrder,
     util::{FlatMapOkAndThenExt, IterEither, MapOkAndThenExt},
 };
-use anyhow::Result;
+use anyhow::{bail, Result};
 use itertools::Itertools;
-use std::{convert::TryFrom, iter::once};
+use std::{borrow::Cow, cmp::Ordering, convert::TryFrom, iter::once};
+
+/// The maximum depth of a B-tree, which SQLite's cursors can't go beyond.
+const MAX_DEPTH: usize = 20;
 
 pub fn full_tbl_scan<'a>(
     page: Page<'a>,
-    page_size: usize,
-    db: &'a [u8],
+    db: DbFile<'a>,
 ) -> impl Iterator<Item = Result<LeafTblCell<'a>>> {
     fn leaf_pages<'a>(
         page: Page<'a>,
-        page_size: usize,
-        db: &'a [u8],
+        db: DbFile<'a>,
+        depth: usize,
     ) -> impl Iterator<Item = Result<Page<'a>>> {
         if page.header.page_type == PageType::LeafTbl {
             return IterEither::left(once(Ok(page)));
         }
 
-        assert!(
-            page.header.page_type == PageType::IntrTbl,
-            "Cannot get leaf pages of {:?}",
-            page.header.page_type
-        );
-
-        let right_most_child_page = page.header.right_most_ptr.unwrap_or_else(|| {
-            panic!(
-                "Expected {:?} to have right most child page pointer",
-                page.header.page_type
-            )
-        });
+        let right_most_child_page = match right_most_child(&page, PageType::IntrTbl) {
+            Ok(ptr) => ptr,
+            Err(e) => return IterEither::left(once(Err(e))),
+        };
 
         let leaves = page
             .cell_ptrs()
-            .map(move |ptr| IntrTblCell::parse(&page.data[ptr..]))
-            .map_ok_and_then(move |cell| Page::parse(cell.child_page, page_size, db))
-            .chain(once(Page::parse(right_most_child_page, page_size, db)))
+            .map(move |ptr| page.parse_cell(ptr, IntrTblCell::parse))
+            .map_ok_and_then(move |cell| child(db, cell.child_page, depth + 1))
+            .chain(once(child(db, right_most_child_page, depth + 1)))
             .flat_map_ok_and_then(move |p| {
-                Box::new(leaf_pages(p, page_size, db)) as Box<dyn Iterator<Item = Result<Page<'a>>>>
+                Box::new(leaf_pages(p, db, depth + 1)) as Box<dyn Iterator<Item = Result<Page<'a>>>>
             });
 
         IterEither::right(leaves)
     }
 
-    leaf_pages(page, page_size, db).flat_map_ok_and_then(|p| {
+    leaf_pages(page, db, 0).flat_map_ok_and_then(move |p| {
         p.cell_ptrs()
-            .map(move |ptr| LeafTblCell::parse(&p.data[ptr..]))
+            .map(move |ptr| p.parse_cell(ptr, |c| LeafTblCell::parse(c, db.enc)))
     })
 }
 
-pub fn pk_scan<'a>(
+pub fn pk_scan<'a>(pk: i64, page: &Page<'a>, db: DbFile<'a>) -> Result<Option<LeafTblCell<'a>>> {
+    pk_scan_at(pk, page, db, 0)
+}
+
+fn pk_scan_at<'a>(
     pk: i64,
     page: &Page<'a>,
-    page_size: usize,
-    db: &'a [u8],
+    db: DbFile<'a>,
+    depth: usize,
 ) -> Result<Option<LeafTblCell<'a>>> {
     if page.header.page_type == PageType::LeafTbl {
         for cell in page
             .cell_ptrs()
-            .map(move |ptr| LeafTblCell::parse(&page.data[ptr..]))
+            .map(move |ptr| page.parse_cell(ptr, |c| LeafTblCell::parse(c, db.enc)))
         {
             let cell = cell?;
             if cell.row_id == pk {
@@ -72,95 +75,222 @@ pub fn pk_scan<'a>(
         return Ok(None);
     }
 
-    assert!(
-        page.header.page_type == PageType::IntrTbl,
-        "Cannot search cells by integer primary key in {:?}",
-        page.header.page_type
-    );
+    let right_most_child_page = right_most_child(page, PageType::IntrTbl)?;
 
     let intr_cells = page
         .cell_ptrs()
-        .map(|ptr| IntrTblCell::parse(&page.data[ptr..]));
+        .map(|ptr| page.parse_cell(ptr, IntrTblCell::parse));
 
     for cell in intr_cells {
         let cell = cell?;
         if pk <= cell.row_id {
-            return pk_scan(
-                pk,
-                &Page::parse(cell.child_page, page_size, db)?,
-                page_size,
-                db,
-            );
+            return pk_scan_at(pk, &child(db, cell.child_page, depth + 1)?, db, depth + 1);
         }
     }
 
-    let right_most_child_page = page
-        .header
-        .right_most_ptr
-        .map(|ptr| Page::parse(ptr, page_size, db))
-        .transpose()?
-        .unwrap_or_else(|| {
-            panic!(
-                "Expected {:?} to have right most child page pointer",
-                page.header.page_type
-            )
-        });
-
-    pk_scan(pk, &right_most_child_page, page_size, db)
+    let right_most_child_page = child(db, right_most_child_page, depth + 1)?;
+    pk_scan_at(pk, &right_most_child_page, db, depth + 1)
 }
 
 pub fn idx_scan<'a>(
-    key: Value<'a>,
+    key: Vec<(Value<'a>, SortOrder)>,
     idx_page: Page<'a>,
-    tbl_page: &'a Page,
-    page_size: usize,
-    db: &'a [u8],
+    tbl_page: Page<'a>,
+    db: DbFile<'a>,
 ) -> impl Iterator<Item = Result<LeafTblCell<'a>>> {
-    fn find_idx_cells<'a>(
-        key: Value<'a>,
-        idx_page: Page<'a>,
-        page_size: usize,
-        db: &'a [u8],
-    ) -> impl Iterator<Item = Result<LeafIdxCell<'a>>> {
-        if idx_page.header.page_type == PageType::LeafIdx {
-            let cells = idx_page
-                .cell_ptrs()
-                .map(move |ptr| LeafIdxCell::parse(&idx_page.data[ptr..]).unwrap())
-                .skip_while(move |cell| Value::try_from(&cell.payload[0]).unwrap() < key)
-                .take_while(move |cell| Value::try_from(&cell.payload[0]).unwrap() == key)
-                .map(Ok);
-
-            return IterEither::left(cells);
+    idx_seek(key, idx_page, db)
+        .map_ok_and_then(|entry| match entry.0.last() {
+            Some(row_id) => i64::try_from(row_id),
+            None => bail!("Index entry has no rowid"),
+        })
+        .map_ok_and_then(move |pk| pk_scan(pk, &tbl_page, db))
+        .flatten_ok()
+}
+
+/// Finds all index entries whose left-most columns equal the values of `key` in the order given
+/// by the entries. An empty key thus yields all entries of the index. Interior pages of an index
+/// hold entries themselves, so those have to be yielded in between the entries of their
+/// children.
+pub fn idx_seek<'a>(
+    key: Vec<(Value<'a>, SortOrder)>,
+    idx_page: Page<'a>,
+    db: DbFile<'a>,
+) -> impl Iterator<Item = Result<Record<'a>>> {
+    idx_seek_at(key, idx_page, db, 0)
+}
+
+fn idx_seek_at<'a>(
+    key: Vec<(Value<'a>, SortOrder)>,
+    idx_page: Page<'a>,
+    db: DbFile<'a>,
+    depth: usize,
+) -> impl Iterator<Item = Result<Record<'a>>> {
+    enum Step<'a> {
+        Child(i32),
+        Entry(Record<'a>),
+    }
+
+    let cmp_key = {
+        let key = key.clone();
+        move |entry: &Record<'a>| -> Result<Ordering> {
+            for (i, (val, order)) in key.iter().enumerate() {
+                let ord = match cmp_col(&entry[i], val)? {
+                    Ordering::Equal => continue,
+                    ord if *order == SortOrder::Desc => ord.reverse(),
+                    ord => ord,
+                };
+                return Ok(ord);
+            }
+            Ok(Ordering::Equal)
         }
-        assert!(
-            idx_page.header.page_type == PageType::IntrIdx,
-            "Cannot search cells by index in {:?}",
-            idx_page.header.page_type
-        );
-
-        let right_most_child_page = idx_page.header.right_most_ptr.unwrap_or_else(|| {
-            panic!(
-                "Expected {:?} to have right most child page pointer",
-                idx_page.header.page_type
-            )
-        });
+    };
 
-        let cells = idx_page
+    if idx_page.header.page_type == PageType::LeafIdx {
+        let entries = idx_page
             .cell_ptrs()
-            .map(move |ptr| IntrIdxCell::parse(&idx_page.data[ptr..]).unwrap())
-            .filter(move |cell| key <= Value::try_from(&cell.payload[0]).unwrap())
-            .map(move |cell| Page::parse(cell.child_page, page_size, db))
-            .chain(once(Page::parse(right_most_child_page, page_size, db)))
-            .flat_map_ok_and_then(move |page| {
-                Box::new(find_idx_cells(key, page, page_size, db))
-                    as Box<dyn Iterator<Item = Result<LeafIdxCell<'a>>>>
-            });
+            .map(move |ptr| {
+                idx_page
+                    .parse_cell(ptr, |c| LeafIdxCell::parse(c, db.enc))
+                    .map(|c| c.payload)
+            })
+            .map_ok_and_then(move |entry| Ok((cmp_key(&entry)?, entry)))
+            .skip_while(|e| matches!(e, Ok((Ordering::Less, _))))
+            .take_while(|e| !matches!(e, Ok((ord, _)) if *ord != Ordering::Equal))
+            .map_ok(|(_, entry)| entry);
 
-        IterEither::right(cells)
+        return IterEither::left(entries);
     }
 
-    find_idx_cells(key, idx_page, page_size, db)
-        .map_ok_and_then(|cell| i64::try_from(&cell.payload[1]))
-        .map_ok_and_then(move |pk| pk_scan(pk, tbl_page, page_size, db))
-        .flatten_ok()
+    let right_most_child_page = right_most_child(&idx_page, PageType::IntrIdx);
+    let mut steps = vec![];
+    let mut visit_right_most = right_most_child_page.is_ok();
+
+    for cell in idx_page
+        .cell_ptrs()
+        .take_while(|_| right_most_child_page.is_ok())
+        .map(|ptr| idx_page.parse_cell(ptr, |c| IntrIdxCell::parse(c, db.enc)))
+    {
+        let cell = match cell.and_then(|c| Ok((cmp_key(&c.payload)?, c))) {
+            Ok(cell) => cell,
+            Err(e) => {
+                steps.push(Err(e));
+                break;
+            }
+        };
+
+        match cell {
+            (Ordering::Less, _) => {}
+            (Ordering::Equal, cell) => {
+                steps.push(Ok(Step::Child(cell.child_page)));
+                steps.push(Ok(Step::Entry(cell.payload)));
+            }
+            (Ordering::Greater, cell) => {
+                steps.push(Ok(Step::Child(cell.child_page)));
+                visit_right_most = false;
+                break;
+            }
+        }
+    }
+
+    match right_most_child_page {
+        Ok(ptr) if visit_right_most => steps.push(Ok(Step::Child(ptr))),
+        Ok(_) => {}
+        Err(e) => steps.push(Err(e)),
+    }
+
+    let entries = steps
+        .into_iter()
+        .flat_map_ok_and_then(move |step| match step {
+            Step::Child(page) => match child(db, page, depth + 1) {
+                Ok(page) => Box::new(idx_seek_at(key.clone(), page, db, depth + 1))
+                    as Box<dyn Iterator<Item = Result<Record<'a>>>>,
+                Err(e) => Box::new(once(Err(e))),
+            },
+            Step::Entry(entry) => Box::new(once(Ok(entry))),
+        });
+
+    IterEither::right(entries)
+}
+
+/// Reads the page `num` that is `depth` levels below the root of a B-tree. SQLite's B-trees are
+/// never deeper than [MAX_DEPTH], so pointers that lead further down are part of a cycle.
+fn child(db: DbFile, num: i32, depth: usize) -> Result<Page> {
+    let page = db.page(num)?;
+    if depth > MAX_DEPTH {
+        return Err(page.corrupt(
+            page.header_offset(),
+            format!("B-tree is more than {} levels deep", MAX_DEPTH),
+        ));
+    }
+    Ok(page)
+}
+
+/// The right-most child of an interior page of type `page_type`. Pointers in corrupt files can
+/// lead to pages of other types, even into other B-trees.
+fn right_most_child(page: &Page, page_type: PageType) -> Result<i32> {
+    match page.header.right_most_ptr {
+        Some(ptr) if page.header.page_type == page_type => Ok(ptr),
+        _ => Err(page.corrupt(
+            page.header_offset(),
+            format!(
+                "Expected {:?} page but found {:?}",
+                page_type, page.header.page_type
+            ),
+        )),
+    }
+}
+
+/// Compares a column of an index entry with a key value. Text is compared by its encoded bytes,
+/// because that is how SQLite's default BINARY collation orders it in databases of any encoding.
+fn cmp_col(col: &ColContent, key: &Value) -> Result<Ordering> {
+    Ok(match (col, key) {
+        (ColContent::Text(bytes, enc), Value::String(s)) => (*bytes).cmp(&*enc.encode(s)),
+        _ => Value::try_from(col)?.cmp(key),
+    })
+}
+
+/// Compares keys in the order of their columns and by the collation of each column. Values
+/// beyond `key_cols`, like the rowid at the end of index entries, are sorted in ascending order.
+pub fn cmp_keys(a: &[Value], b: &[Value], key_cols: &[KeyCol], enc: Enc) -> Ordering {
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .map(|(i, (a, b))| {
+            let col = key_cols.get(i);
+            let ord = match (a, b) {
+                (Value::String(a), Value::String(b)) => {
+                    cmp_text(a, b, col.and_then(|c| c.collation.as_deref()), enc)
+                }
+                _ => a.cmp(b),
+            };
+            match col {
+                Some(KeyCol {
+                    order: SortOrder::Desc,
+                    ..
+                }) => ord.reverse(),
+                _ => ord,
+            }
+        })
+        .find(|ord| *ord != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Folds text the way a collation ignores differences in it, which is the case of ASCII letters
+/// for NOCASE and trailing spaces for RTRIM. Texts a collation considers equal fold the same.
+pub fn fold_text<'s>(s: &'s str, collation: Option<&str>) -> Cow<'s, str> {
+    match collation {
+        Some(c) if c.eq_ignore_ascii_case("NOCASE") => Cow::Owned(s.to_ascii_lowercase()),
+        Some(c) if c.eq_ignore_ascii_case("RTRIM") => Cow::Borrowed(s.trim_end_matches(' ')),
+        _ => Cow::Borrowed(s),
+    }
+}
+
+/// Compares text by one of SQLite's built-in collations, which fold it before it's compared by
+/// its encoded bytes like with BINARY.
+fn cmp_text(a: &str, b: &str, collation: Option<&str>, enc: Enc) -> Ordering {
+    let (a, b) = (fold_text(a, collation), fold_text(b, collation));
+    match enc {
+        Enc::Utf8 => a.cmp(&b),
+        _ => enc.encode(&a).cmp(&enc.encode(&b)),
+    }
 }
@@ -0,0 +1,416 @@
+//! Using the database from other programs. Instead of being printed like in the shell, the
+//! results of statements are returned row by row, and problems are returned as errors.
+
+use crate::{
+    interpreter::{
+        convert::{FromSql, OwnedValue, ToSql},
+        database::Database,
+        explain, pragma,
+        select_stmt::{self, ResultRows},
+    },
+    syntax::{parse, Literal, SqlStmt},
+};
+use anyhow::{anyhow, bail, Result};
+use std::{path::Path, rc::Rc};
+
+/// The largest parameter number SQLite accepts by default.
+const MAX_PARAM_NUM: usize = 32766;
+
+/// An open database file.
+pub struct Connection {
+    db: Database,
+}
+
+/// A parsed statement, which can be run any number of times.
+pub struct Statement<'a> {
+    conn: &'a Connection,
+    stmt: SqlStmt<'a>,
+    /// The number of each parameter in the order they appear in the statement.
+    param_nums: Vec<usize>,
+    /// The name of each parameter number, if it's used with a name.
+    param_names: Vec<Option<&'a str>>,
+}
+
+/// The result of a statement. Rows are read from the database as they're iterated over.
+pub struct Rows<'a> {
+    cols: Rc<[String]>,
+    rows: ResultRows<'a>,
+}
+
+/// A row of the result of a statement. It owns its values, so it can be kept after the query.
+#[derive(Debug, Clone)]
+pub struct Row {
+    cols: Rc<[String]>,
+    values: Vec<OwnedValue>,
+}
+
+/// Refers to a column of a result by its position or by its name, ignoring case like SQLite.
+pub trait ColIndex {
+    fn index(&self, cols: &[String]) -> Result<usize>;
+}
+
+/// Refers to a parameter of a statement by its number, starting at 1, or by its name including
+/// the prefix, like `:name`.
+pub trait ParamIndex {
+    fn num(&self, names: &[Option<&str>]) -> Result<usize>;
+}
+
+impl Connection {
+    /// Opens the database file at `path`. A file that doesn't exist is an empty database.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            db: Database::open(path)?,
+        })
+    }
+
+    /// A connection to a database built in memory, which tests use.
+    #[cfg(test)]
+    pub(crate) fn with_db(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Parses a single statement, which may end with a semicolon. Only queries are supported.
+    pub fn prepare<'a>(&'a self, sql: &'a str) -> Result<Statement<'a>> {
+        let sql = sql.trim();
+        let mut stmt = parse::sql_stmt(sql.strip_suffix(';').unwrap_or(sql).trim_end())
+            .map_err(|e| anyhow!("Invalid SQL: {}", e))?;
+        if !matches!(
+            stmt,
+            SqlStmt::Select(_) | SqlStmt::ExplainQueryPlan(_) | SqlStmt::Pragma { .. }
+        ) {
+            bail!("Only SELECT, EXPLAIN QUERY PLAN and PRAGMA statements can be prepared");
+        }
+
+        let (param_nums, param_names) =
+            number_params(params_mut(&mut stmt).into_iter().map(|(name, _)| name))?;
+        Ok(Statement {
+            conn: self,
+            stmt,
+            param_nums,
+            param_names,
+        })
+    }
+}
+
+/// Numbers parameters like SQLite: `?` gets the number after the largest one so far, `?NNN` gets
+/// NNN, and a name gets the same number everywhere it's used.
+fn number_params<'a>(
+    names: impl Iterator<Item = &'a str>,
+) -> Result<(Vec<usize>, Vec<Option<&'a str>>)> {
+    let mut nums = vec![];
+    let mut names_by_num: Vec<Option<&str>> = vec![];
+    for name in names {
+        let num = match name.strip_prefix('?') {
+            Some("") => names_by_num.len() + 1,
+            Some(digits) => match digits.parse() {
+                Ok(num) if (1..=MAX_PARAM_NUM).contains(&num) => num,
+                _ => bail!(
+                    "Parameter {} must be between ?1 and ?{}",
+                    name,
+                    MAX_PARAM_NUM
+                ),
+            },
+            None => match names_by_num.iter().position(|&n| n == Some(name)) {
+                Some(i) => i + 1,
+                None => names_by_num.len() + 1,
+            },
+        };
+        if names_by_num.len() < num {
+            names_by_num.resize(num, None);
+        }
+        if name != "?" {
+            names_by_num[num - 1].get_or_insert(name);
+        }
+        nums.push(num);
+    }
+    Ok((nums, names_by_num))
+}
+
+/// The names of the parameters of `stmt` and their values in the order they appear.
+fn params_mut<'s, 'a>(stmt: &'s mut SqlStmt<'a>) -> Vec<(&'a str, &'s mut Literal<'a>)> {
+    match stmt {
+        SqlStmt::Select(select) | SqlStmt::ExplainQueryPlan(select) => select.params_mut(),
+        _ => vec![],
+    }
+}
+
+impl<'a> Statement<'a> {
+    /// The largest parameter number, which is the number of parameters unless some are skipped.
+    pub fn parameter_count(&self) -> usize {
+        self.param_names.len()
+    }
+
+    /// The name of parameter `num`, or `None` if it's only used as `?`.
+    pub fn parameter_name(&self, num: usize) -> Option<&'a str> {
+        self.param_names.get(num.checked_sub(1)?).copied().flatten()
+    }
+
+    /// The number of the parameter with `name`, including its prefix.
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        name.num(&self.param_names).ok()
+    }
+
+    /// Sets the value of a parameter for the following queries. Unbound parameters are NULL.
+    pub fn bind(&mut self, param: impl ParamIndex, value: impl ToSql) -> Result<()> {
+        let num = param.num(&self.param_names)?;
+        let value = Literal::from(value.to_sql());
+
+        let params = params_mut(&mut self.stmt).into_iter().zip(&self.param_nums);
+        for ((_, slot), _) in params.filter(|(_, &n)| n == num) {
+            *slot = value.clone();
+        }
+        Ok(())
+    }
+
+    /// Sets all parameters back to NULL.
+    pub fn clear_bindings(&mut self) {
+        params_mut(&mut self.stmt)
+            .into_iter()
+            .for_each(|(_, slot)| *slot = Literal::Null);
+    }
+
+    pub fn query(&self) -> Result<Rows<'_>> {
+        let db = &self.conn.db;
+        let (cols, rows): (_, ResultRows) = match &self.stmt {
+            SqlStmt::Select(select) => select_stmt::query(select, &db.schema, &db.bytes)?,
+            SqlStmt::ExplainQueryPlan(select) => {
+                let (cols, rows) = explain::query(select, &db.schema)?;
+                (cols, Box::new(rows.into_iter().map(Ok)))
+            }
+            SqlStmt::Pragma { schema, name, arg } => {
+                let (cols, rows) = pragma::query(*schema, name, *arg, db)?;
+                (cols, Box::new(rows.into_iter().map(Ok)))
+            }
+            _ => unreachable!("Other statements can't be prepared"),
+        };
+
+        Ok(Rows {
+            cols: cols.into(),
+            rows,
+        })
+    }
+}
+
+impl<'a> Rows<'a> {
+    pub fn column_names(&self) -> &[String] {
+        &self.cols
+    }
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.rows.next()?;
+        Some(row.map(|values| Row {
+            cols: Rc::clone(&self.cols),
+            values: values.into_iter().map(OwnedValue::from).collect(),
+        }))
+    }
+}
+
+impl Row {
+    pub fn column_names(&self) -> &[String] {
+        &self.cols
+    }
+
+    pub fn values(&self) -> &[OwnedValue] {
+        &self.values
+    }
+
+    pub fn get_value(&self, col: impl ColIndex) -> Result<&OwnedValue> {
+        Ok(&self.values[col.index(&self.cols)?])
+    }
+
+    pub fn is_null(&self, col: impl ColIndex) -> Result<bool> {
+        Ok(matches!(self.get_value(col)?, OwnedValue::Null))
+    }
+
+    /// Reads a column as `T`, like `row.get::<String, _>("name")`.
+    pub fn get<T: FromSql, C: ColIndex>(&self, col: C) -> Result<T> {
+        let val = self.get_value(&col)?;
+        T::from_sql(val).map_err(|expected| self.mismatch(&col, val, expected))
+    }
+
+    /// Reads text without copying it.
+    pub fn get_str(&self, col: impl ColIndex) -> Result<&str> {
+        match self.get_value(&col)? {
+            OwnedValue::String(s) => Ok(s),
+            val => Err(self.mismatch(&col, val, "text")),
+        }
+    }
+
+    /// Reads a blob without copying it.
+    pub fn get_bytes(&self, col: impl ColIndex) -> Result<&[u8]> {
+        match self.get_value(&col)? {
+            OwnedValue::Bytes(bytes) => Ok(bytes),
+            val => Err(self.mismatch(&col, val, "a blob")),
+        }
+    }
+
+    fn mismatch(&self, col: &impl ColIndex, val: &OwnedValue, expected: &str) -> anyhow::Error {
+        let name = col.index(&self.cols).map_or("?", |i| &self.cols[i]);
+        anyhow!(
+            "Column {} holds {} instead of {}",
+            name,
+            val.kind(),
+            expected
+        )
+    }
+}
+
+impl ColIndex for usize {
+    fn index(&self, cols: &[String]) -> Result<usize> {
+        match *self < cols.len() {
+            true => Ok(*self),
+            false => bail!("Column index {} out of range 0..{}", self, cols.len()),
+        }
+    }
+}
+
+impl ColIndex for &str {
+    fn index(&self, cols: &[String]) -> Result<usize> {
+        cols.iter()
+            .position(|c| c.eq_ignore_ascii_case(self))
+            .ok_or_else(|| anyhow!("No column named {}", self))
+    }
+}
+
+impl<T: ColIndex> ColIndex for &T {
+    fn index(&self, cols: &[String]) -> Result<usize> {
+        (*self).index(cols)
+    }
+}
+
+impl ParamIndex for usize {
+    fn num(&self, names: &[Option<&str>]) -> Result<usize> {
+        match (1..=names.len()).contains(self) {
+            true => Ok(*self),
+            false => bail!("Parameter number {} out of range 1..={}", self, names.len()),
+        }
+    }
+}
+
+impl ParamIndex for &str {
+    fn num(&self, names: &[Option<&str>]) -> Result<usize> {
+        names
+            .iter()
+            .position(|n| n == &Some(*self))
+            .map(|i| i + 1)
+            .ok_or_else(|| anyhow!("No parameter named {}", self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::{
+        convert::{Date, OwnedValue},
+        test_db::conn,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn reads_typed_columns() -> Result<()> {
+        let conn = conn()?;
+        let stmt = conn.prepare("SELECT id, name, score FROM t;")?;
+        let rows = stmt.query()?;
+        assert_eq!(rows.column_names(), ["id", "name", "score"]);
+
+        let rows = rows.collect::<Result<Vec<_>>>()?;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].get::<i64, _>("id")?, 2);
+        assert_eq!(rows[1].get::<String, _>(1)?, "bob");
+        assert_eq!(rows[1].get::<f64, _>("SCORE")?, 2.0);
+        assert_eq!(rows[1].get::<Option<i64>, _>("id")?, Some(2));
+
+        // Statements can be run again.
+        assert_eq!(stmt.query()?.count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn returns_errors() -> Result<()> {
+        let conn = conn()?;
+        assert!(conn.prepare("SELECT FROM").is_err());
+        assert!(conn.prepare("CREATE TABLE u(a)").is_err());
+        assert!(conn.prepare("SELECT nope FROM t")?.query().is_err());
+
+        let stmt = conn.prepare("SELECT name FROM t")?;
+        let row = stmt.query()?.next().unwrap()?;
+        assert_eq!(
+            row.get::<i64, _>("name").unwrap_err().to_string(),
+            "Column name holds text instead of an integer"
+        );
+        assert_eq!(
+            row.get::<Date, _>("name").unwrap_err().to_string(),
+            "Column name holds text instead of a date"
+        );
+        assert!(row.get_value(1).is_err());
+        assert!(row.get_value("id").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn queries_pragmas() -> Result<()> {
+        let conn = conn()?;
+        let stmt = conn.prepare("PRAGMA table_info(t)")?;
+        let rows = stmt.query()?;
+        assert_eq!(rows.column_names()[1], "name");
+        assert_eq!(rows.count(), 3);
+
+        let stmt = conn.prepare("PRAGMA unknown")?;
+        let rows = stmt.query()?;
+        assert!(rows.column_names().is_empty());
+        assert_eq!(rows.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn binds_parameters() -> Result<()> {
+        let conn = conn()?;
+        let mut stmt = conn.prepare("SELECT name FROM t WHERE id = ?")?;
+        assert_eq!(stmt.parameter_count(), 1);
+        assert_eq!(stmt.query()?.count(), 0);
+
+        for (id, name) in [(1, "ann"), (2, "bob")] {
+            stmt.bind(1, id)?;
+            let rows = stmt.query()?.collect::<Result<Vec<_>>>()?;
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0].get_str(0)?, name);
+        }
+        stmt.clear_bindings();
+        assert_eq!(stmt.query()?.count(), 0);
+        assert!(stmt.bind(2, None::<i64>).is_err());
+
+        let mut stmt = conn.prepare("SELECT :x, ?, @y, ?5, :x, ?1 FROM t WHERE name = :x")?;
+        assert_eq!(stmt.parameter_count(), 5);
+        assert_eq!(stmt.parameter_index(":x"), Some(1));
+        assert_eq!(stmt.parameter_index("@y"), Some(3));
+        assert_eq!(stmt.parameter_index(":y"), None);
+        assert_eq!(stmt.parameter_name(2), None);
+        assert_eq!(stmt.parameter_name(5), Some("?5"));
+        stmt.bind(":x", "bob")?;
+        stmt.bind(2, 0.5)?;
+        stmt.bind("@y", vec![1u8, 2])?;
+        let rows = stmt.query()?;
+        assert_eq!(rows.column_names(), [":x", "?", "@y", "?5", ":x", "?1"]);
+        let rows = rows.collect::<Result<Vec<_>>>()?;
+        let bob = OwnedValue::String("bob".to_owned());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].values(),
+            [
+                bob.clone(),
+                OwnedValue::Float(0.5),
+                OwnedValue::Bytes(vec![1, 2]),
+                OwnedValue::Null,
+                bob.clone(),
+                bob
+            ]
+        );
+
+        assert!(conn.prepare("SELECT ?0 FROM t").is_err());
+        assert!(conn.prepare("SELECT ?32767 FROM t").is_err());
+        Ok(())
+    }
+}
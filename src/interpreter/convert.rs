@@ -0,0 +1,521 @@
+//! Converting between Rust types and the values of columns and parameters.
+
+use crate::{interpreter::eval::Value, syntax::Literal};
+use anyhow::{anyhow, Result};
+use nom::{
+    bytes::complete::take_while_m_n,
+    character::complete::{char, one_of},
+    combinator::{all_consuming, map_opt, map_res, opt},
+    sequence::{preceded, tuple},
+    IResult,
+};
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A value that doesn't borrow from the database, so it can be kept after the query is done.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedValue {
+    Null,
+    Int(i64),
+    Float(f64),
+    Bytes(Vec<u8>),
+    String(String),
+}
+
+/// Types that can be bound to parameters.
+pub trait ToSql {
+    fn to_sql(&self) -> OwnedValue;
+}
+
+/// Types that columns can be read as. If `value` can't be converted, `from_sql` returns a
+/// description of what it expected instead, like "an integer".
+pub trait FromSql: Sized {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str>;
+}
+
+/// A calendar date, stored as text like `2024-02-29` the way SQLite's date functions write it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+}
+
+/// A time of day, stored as text like `13:45:00` or `13:45:00.250`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+/// A date and time without a time zone, stored as text like `2024-02-29 13:45:00`. It can also
+/// be read from an integer number of seconds since 1970, which is UTC in SQLite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub date: Date,
+    pub time: Time,
+}
+
+impl OwnedValue {
+    /// What kind of value this is, for error messages.
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            OwnedValue::Null => "NULL",
+            OwnedValue::Int(_) => "an integer",
+            OwnedValue::Float(_) => "a real",
+            OwnedValue::Bytes(_) => "a blob",
+            OwnedValue::String(_) => "text",
+        }
+    }
+}
+
+impl<'a> From<Value<'a>> for OwnedValue {
+    fn from(value: Value<'a>) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Int(x) => Self::Int(x),
+            Value::Float(x) => Self::Float(x),
+            Value::Bytes(bytes) => Self::Bytes(bytes.into_owned()),
+            Value::String(s) => Self::String(s.into_owned()),
+        }
+    }
+}
+
+impl<'a> From<OwnedValue> for Literal<'a> {
+    fn from(value: OwnedValue) -> Self {
+        match value {
+            OwnedValue::Null => Self::Null,
+            OwnedValue::Int(x) => Self::Int(x),
+            OwnedValue::Float(x) => Self::Float(x),
+            OwnedValue::Bytes(bytes) => Self::Blob(Cow::Owned(bytes)),
+            OwnedValue::String(s) => Self::String(Cow::Owned(s)),
+        }
+    }
+}
+
+impl<T: ToSql + ?Sized> ToSql for &T {
+    fn to_sql(&self) -> OwnedValue {
+        (*self).to_sql()
+    }
+}
+
+impl ToSql for OwnedValue {
+    fn to_sql(&self) -> OwnedValue {
+        self.clone()
+    }
+}
+
+impl ToSql for i64 {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::Int(*self)
+    }
+}
+
+impl ToSql for i32 {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::Int((*self).into())
+    }
+}
+
+impl ToSql for f64 {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::Float(*self)
+    }
+}
+
+/// Booleans are stored as 0 and 1 like in SQLite.
+impl ToSql for bool {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::Int((*self).into())
+    }
+}
+
+impl ToSql for str {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::String(self.to_owned())
+    }
+}
+
+impl ToSql for String {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::String(self.clone())
+    }
+}
+
+impl ToSql for [u8] {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::Bytes(self.to_vec())
+    }
+}
+
+impl ToSql for Vec<u8> {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::Bytes(self.clone())
+    }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn to_sql(&self) -> OwnedValue {
+        self.as_ref().map_or(OwnedValue::Null, T::to_sql)
+    }
+}
+
+impl ToSql for Date {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::String(self.to_string())
+    }
+}
+
+impl ToSql for Time {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::String(self.to_string())
+    }
+}
+
+impl ToSql for DateTime {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::String(self.to_string())
+    }
+}
+
+/// System times are stored as whole seconds since 1970.
+impl ToSql for SystemTime {
+    fn to_sql(&self) -> OwnedValue {
+        OwnedValue::Int(match self.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs_f64().ceil() as i64),
+        })
+    }
+}
+
+impl FromSql for OwnedValue {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        Ok(value.clone())
+    }
+}
+
+impl FromSql for i64 {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        match value {
+            OwnedValue::Int(x) => Ok(*x),
+            _ => Err("an integer"),
+        }
+    }
+}
+
+impl FromSql for i32 {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        match value {
+            OwnedValue::Int(x) => i32::try_from(*x).map_err(|_| "a 32-bit integer"),
+            _ => Err("an integer"),
+        }
+    }
+}
+
+/// Integers are converted like SQLite does when asked for a REAL.
+impl FromSql for f64 {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        match value {
+            OwnedValue::Float(x) => Ok(*x),
+            OwnedValue::Int(x) => Ok(*x as f64),
+            _ => Err("a real"),
+        }
+    }
+}
+
+impl FromSql for bool {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        match value {
+            OwnedValue::Int(x) => Ok(*x != 0),
+            _ => Err("an integer"),
+        }
+    }
+}
+
+impl FromSql for String {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        match value {
+            OwnedValue::String(s) => Ok(s.clone()),
+            _ => Err("text"),
+        }
+    }
+}
+
+impl FromSql for Vec<u8> {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        match value {
+            OwnedValue::Bytes(bytes) => Ok(bytes.clone()),
+            _ => Err("a blob"),
+        }
+    }
+}
+
+impl<T: FromSql> FromSql for Option<T> {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        match value {
+            OwnedValue::Null => Ok(None),
+            _ => T::from_sql(value).map(Some),
+        }
+    }
+}
+
+impl FromSql for Date {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        from_text(value, "a date")
+    }
+}
+
+impl FromSql for Time {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        from_text(value, "a time")
+    }
+}
+
+impl FromSql for DateTime {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        match value {
+            OwnedValue::Int(secs) => DateTime::from_unix(*secs).ok_or("a date and time"),
+            _ => from_text(value, "a date and time"),
+        }
+    }
+}
+
+impl FromSql for SystemTime {
+    fn from_sql(value: &OwnedValue) -> Result<Self, &'static str> {
+        match value {
+            OwnedValue::Int(secs) if *secs >= 0 => {
+                Ok(UNIX_EPOCH + Duration::from_secs(*secs as u64))
+            }
+            OwnedValue::Int(secs) => Ok(UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())),
+            _ => Err("an integer"),
+        }
+    }
+}
+
+/// Parses text with `T`'s `FromStr` implementation.
+fn from_text<T: FromStr>(value: &OwnedValue, expected: &'static str) -> Result<T, &'static str> {
+    match value {
+        OwnedValue::String(s) => s.parse().map_err(|_| expected),
+        _ => Err(expected),
+    }
+}
+
+impl Date {
+    pub fn new(year: i32, month: u8, day: u8) -> Option<Self> {
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days_in_month = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return None,
+        };
+        (1..=days_in_month)
+            .contains(&day)
+            .then(|| Self { year, month, day })
+    }
+
+    /// The date `days` days after 1970-01-01.
+    fn from_days(days: i64) -> Option<Self> {
+        // Howard Hinnant's algorithm: http://howardhinnant.github.io/date_algorithms.html
+        let z = days.checked_add(719_468)?;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        Self::new(i32::try_from(year).ok()?, month as u8, day as u8)
+    }
+}
+
+impl Time {
+    pub fn new(hour: u8, minute: u8, second: u8, nanosecond: u32) -> Option<Self> {
+        (hour < 24 && minute < 60 && second < 60 && nanosecond < 1_000_000_000).then(|| Self {
+            hour,
+            minute,
+            second,
+            nanosecond,
+        })
+    }
+}
+
+impl DateTime {
+    /// The UTC time `secs` seconds after 1970-01-01 00:00:00.
+    pub fn from_unix(secs: i64) -> Option<Self> {
+        let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+        Some(Self {
+            date: Date::from_days(days)?,
+            time: Time::new(
+                (secs / 3600) as u8,
+                (secs / 60 % 60) as u8,
+                (secs % 60) as u8,
+                0,
+            )?,
+        })
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}", self.hour, self.minute, self.second)?;
+        match self.nanosecond {
+            0 => Ok(()),
+            ns if ns % 1_000_000 == 0 => write!(f, ".{:03}", ns / 1_000_000),
+            ns => write!(f, ".{:09}", ns),
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.date, self.time)
+    }
+}
+
+impl FromStr for Date {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        all_consuming(date)(s)
+            .map(|(_, date)| date)
+            .map_err(|_| anyhow!("Invalid date: {}", s))
+    }
+}
+
+impl FromStr for Time {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        all_consuming(time)(s)
+            .map(|(_, time)| time)
+            .map_err(|_| anyhow!("Invalid time: {}", s))
+    }
+}
+
+/// Accepts the formats of SQLite's date functions. A date alone is at midnight.
+impl FromStr for DateTime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let midnight = Time::new(0, 0, 0, 0).unwrap();
+        all_consuming(tuple((
+            date,
+            opt(preceded(one_of(" T"), time)),
+            opt(char('Z')),
+        )))(s)
+        .map(|(_, (date, time, _))| DateTime {
+            date,
+            time: time.unwrap_or(midnight),
+        })
+        .map_err(|_| anyhow!("Invalid date and time: {}", s))
+    }
+}
+
+fn digits<'a>(n: usize) -> impl FnMut(&'a str) -> IResult<&'a str, u32> {
+    map_res(
+        take_while_m_n(n, n, |c: char| c.is_ascii_digit()),
+        str::parse,
+    )
+}
+
+/// `YYYY-MM-DD`
+fn date(i: &str) -> IResult<&str, Date> {
+    map_opt(
+        tuple((digits(4), char('-'), digits(2), char('-'), digits(2))),
+        |(year, _, month, _, day)| Date::new(year as i32, month as u8, day as u8),
+    )(i)
+}
+
+/// `HH:MM`, `HH:MM:SS` or `HH:MM:SS.SSS` with up to 9 digits of fractional seconds.
+fn time(i: &str) -> IResult<&str, Time> {
+    let fraction = take_while_m_n(1, 9, |c: char| c.is_ascii_digit());
+    map_opt(
+        tuple((
+            digits(2),
+            preceded(char(':'), digits(2)),
+            opt(preceded(char(':'), digits(2))),
+            opt(preceded(char('.'), fraction)),
+        )),
+        |(hour, minute, second, fraction)| {
+            let nanos = fraction.map_or(0, |f: &str| {
+                f.parse::<u32>().unwrap() * 10u32.pow(9 - f.len() as u32)
+            });
+            Time::new(hour as u8, minute as u8, second.unwrap_or(0) as u8, nanos)
+        },
+    )(i)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn converts_options_and_bools() {
+        assert_eq!(Some(3).to_sql(), OwnedValue::Int(3));
+        assert_eq!(None::<i64>.to_sql(), OwnedValue::Null);
+        assert_eq!(true.to_sql(), OwnedValue::Int(1));
+        assert_eq!(Option::<String>::from_sql(&OwnedValue::Null), Ok(None));
+        assert_eq!(Option::<f64>::from_sql(&OwnedValue::Int(2)), Ok(Some(2.0)));
+        assert_eq!(bool::from_sql(&OwnedValue::Int(0)), Ok(false));
+        assert_eq!(String::from_sql(&OwnedValue::Int(0)), Err("text"));
+        assert_eq!(
+            i32::from_sql(&OwnedValue::Int(1 << 40)),
+            Err("a 32-bit integer")
+        );
+    }
+
+    #[test]
+    fn parses_and_formats_dates() {
+        let dt = "2024-02-29T13:45:07.25".parse::<DateTime>().unwrap();
+        assert_eq!(dt.date, Date::new(2024, 2, 29).unwrap());
+        assert_eq!(dt.time, Time::new(13, 45, 7, 250_000_000).unwrap());
+        assert_eq!(dt.to_string(), "2024-02-29 13:45:07.250");
+        assert_eq!(
+            DateTime::from_sql(&OwnedValue::String("2023-01-05".into()))
+                .unwrap()
+                .to_string(),
+            "2023-01-05 00:00:00"
+        );
+        assert_eq!("12:30".parse::<Time>().unwrap().to_string(), "12:30:00");
+
+        for bad in ["2023-02-29", "2024-13-01", "24-01-01", "2024-01-01 25:00"] {
+            assert!(bad.parse::<DateTime>().is_err(), "{}", bad);
+        }
+        assert_eq!(
+            Date::from_sql(&OwnedValue::String("soon".into())),
+            Err("a date")
+        );
+    }
+
+    #[test]
+    fn converts_unix_times() {
+        let dt = |secs| {
+            DateTime::from_sql(&OwnedValue::Int(secs))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(dt(0), "1970-01-01 00:00:00");
+        assert_eq!(dt(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(dt(-1), "1969-12-31 23:59:59");
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(time.to_sql(), OwnedValue::Int(1_700_000_000));
+        assert_eq!(SystemTime::from_sql(&time.to_sql()), Ok(time));
+    }
+}
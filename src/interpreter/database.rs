@@ -0,0 +1,128 @@
+use crate::{
+    format::{DbHeader, PageType},
+    schema::DbSchema,
+};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// A database file loaded into memory together with its parsed schema. Changes are made to a
+/// copy of the file's bytes, which replaces the file once they are complete.
+#[derive(Debug)]
+pub struct Database {
+    path: PathBuf,
+    pub bytes: Vec<u8>,
+    pub schema: DbSchema,
+}
+
+impl Database {
+    const PAGE_SIZE: usize = 4096;
+
+    /// Opens the database at `path`. Like in sqlite3, a missing or empty file is an empty
+    /// database, which is only written once something is stored in it.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let bytes = match fs::read(&path) {
+            Ok(bytes) if !bytes.is_empty() => bytes,
+            Ok(_) => Self::empty(),
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::empty(),
+            Err(e) => return Err(anyhow!("Cannot open {}: {}", path.display(), e)),
+        };
+        let schema = DbSchema::parse(&bytes)?;
+        Ok(Self {
+            path,
+            bytes,
+            schema,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the database file with `bytes` and reloads the schema from them. The bytes are
+    /// written to a temporary file next to the database first, which is then renamed over it, so
+    /// that a failed write leaves the database as it was.
+    pub fn commit(&mut self, bytes: Vec<u8>) -> Result<()> {
+        let schema = DbSchema::parse(&bytes)?;
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_owned();
+        tmp_name.push("-tmp");
+        let tmp = self.path.with_file_name(tmp_name);
+        let write = || -> io::Result<()> {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+            fs::rename(&tmp, &self.path)
+        };
+        if let Err(e) = write() {
+            let _ = fs::remove_file(&tmp);
+            bail!("Cannot write {}: {}", self.path.display(), e);
+        }
+        self.bytes = bytes;
+        self.schema = schema;
+        Ok(())
+    }
+
+    /// The image of a database without any tables, which consists of page 1 holding the header
+    /// and the empty schema table.
+    pub(crate) fn empty() -> Vec<u8> {
+        let mut bytes = vec![0; Self::PAGE_SIZE];
+        let mut put_u32 = |offset: usize, v: u32| {
+            bytes[offset..offset + 4].copy_from_slice(&v.to_be_bytes());
+        };
+        put_u32(24, 1); // file change counter
+        put_u32(28, 1); // database page count
+        put_u32(44, 4); // schema format
+        put_u32(56, 1); // text encoding UTF-8
+        put_u32(92, 1); // version valid for
+
+        bytes[..16].copy_from_slice(b"SQLite format 3\0");
+        bytes[16..18].copy_from_slice(&(Self::PAGE_SIZE as u16).to_be_bytes());
+        bytes[18] = 1; // write format
+        bytes[19] = 1; // read format
+        bytes[21] = 64; // max embedded payload fraction
+        bytes[22] = 32; // min embedded payload fraction
+        bytes[23] = 32; // leaf payload fraction
+
+        let page_header = &mut bytes[DbHeader::SIZE..];
+        page_header[0] = PageType::LeafTbl as u8;
+        page_header[5..7].copy_from_slice(&(Self::PAGE_SIZE as u16).to_be_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Database;
+    use crate::schema::DbSchema;
+    use anyhow::Result;
+    use std::{env, fs, process};
+
+    #[test]
+    fn empty_db_has_no_objs() {
+        let schema = DbSchema::parse(&Database::empty()).unwrap();
+        assert!(schema.objs.is_empty());
+        assert_eq!(schema.db_header.page_size, 4096);
+        assert_eq!(schema.db_header.db_page_count, 1);
+    }
+
+    #[test]
+    fn commit_replaces_file_without_leaving_temp_file() -> Result<()> {
+        let path = env::temp_dir().join(format!("commit-{}.db", process::id()));
+        fs::write(&path, b"old")?;
+        let mut db = Database::open("/nonexistent/db")?;
+        db.path = path.clone();
+
+        db.commit(Database::empty())?;
+        let written = fs::read(&path)?;
+        let tmp_left = path.with_file_name(format!("commit-{}.db-tmp", process::id()));
+        let tmp_left = tmp_left.exists();
+        fs::remove_file(&path)?;
+        assert_eq!(written, Database::empty());
+        assert!(!tmp_left);
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+//! Deserializing rows into structs, tuples or maps with serde. Struct fields are matched to
+//! columns by name, and tuples take the columns in order.
+
+use crate::interpreter::{
+    connection::{Row, Statement},
+    convert::OwnedValue,
+};
+use serde::de::{
+    self, value::StrDeserializer, DeserializeOwned, DeserializeSeed, Deserializer, Expected,
+    IntoDeserializer, MapAccess, SeqAccess, Unexpected, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use std::{error, fmt};
+
+impl<'a> Statement<'a> {
+    /// Runs the query and deserializes each row into a `T`, like `query_as::<Person>()`.
+    pub fn query_as<T: DeserializeOwned>(
+        &self,
+    ) -> anyhow::Result<impl Iterator<Item = anyhow::Result<T>> + '_> {
+        Ok(self.query()?.map(|row| row?.deserialize()))
+    }
+}
+
+impl Row {
+    pub fn deserialize<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        Ok(T::deserialize(RowDeserializer(self))?)
+    }
+}
+
+/// Why a row can't be deserialized. Problems with a value say which column it's in.
+#[derive(Debug)]
+struct Error {
+    col: Option<String>,
+    kind: ErrorKind,
+}
+
+#[derive(Debug)]
+enum ErrorKind {
+    Mismatch { actual: String, expected: String },
+    Custom(String),
+}
+
+struct RowDeserializer<'r>(&'r Row);
+
+/// Visits the columns of a row as a map from their names to their values.
+struct Cols<'r> {
+    row: &'r Row,
+    i: usize,
+}
+
+struct ValueDeserializer<'v>(&'v OwnedValue);
+
+impl<'de, 'r> Deserializer<'de> for RowDeserializer<'r> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(Cols { row: self.0, i: 0 })
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Cols { row: self.0, i: 0 })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'r> Cols<'r> {
+    /// Deserializes the value of the next column, noting the column in errors.
+    fn next_value<'de, T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, Error> {
+        let i = self.i;
+        self.i += 1;
+        seed.deserialize(ValueDeserializer(&self.row.values()[i]))
+            .map_err(|e| Error {
+                col: Some(self.row.column_names()[i].clone()),
+                ..e
+            })
+    }
+}
+
+impl<'de, 'r> MapAccess<'de> for Cols<'r> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.row.column_names().get(self.i) {
+            Some(name) => {
+                let name: StrDeserializer<Error> = name.as_str().into_deserializer();
+                seed.deserialize(name).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        self.next_value(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.row.values().len() - self.i)
+    }
+}
+
+impl<'de, 'r> SeqAccess<'de> for Cols<'r> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.i < self.row.values().len() {
+            true => self.next_value(seed).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.row.values().len() - self.i)
+    }
+}
+
+impl<'de, 'v> Deserializer<'de> for ValueDeserializer<'v> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            OwnedValue::Null => visitor.visit_unit(),
+            OwnedValue::Int(x) => visitor.visit_i64(*x),
+            OwnedValue::Float(x) => visitor.visit_f64(*x),
+            OwnedValue::Bytes(bytes) => visitor.visit_bytes(bytes),
+            OwnedValue::String(s) => visitor.visit_str(s),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            OwnedValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    /// Booleans are stored as integers in SQLite.
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            OwnedValue::Int(x) => visitor.visit_bool(*x != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    /// Enums with unit variants are stored as the names of the variants.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            OwnedValue::String(s) => {
+                let variant: StrDeserializer<Error> = s.as_str().into_deserializer();
+                visitor.visit_enum(variant)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error {
+            col: None,
+            kind: ErrorKind::Custom(msg.to_string()),
+        }
+    }
+
+    fn invalid_type(unexp: Unexpected, exp: &dyn Expected) -> Self {
+        let actual = match unexp {
+            Unexpected::Unit => "NULL".to_owned(),
+            Unexpected::Signed(_) => "an integer".to_owned(),
+            Unexpected::Float(_) => "a real".to_owned(),
+            Unexpected::Str(_) => "text".to_owned(),
+            Unexpected::Bytes(_) => "a blob".to_owned(),
+            _ => unexp.to_string(),
+        };
+        Error {
+            col: None,
+            kind: ErrorKind::Mismatch {
+                actual,
+                expected: exp.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.col, &self.kind) {
+            (Some(col), ErrorKind::Mismatch { actual, expected }) => {
+                write!(f, "Column {} holds {} instead of {}", col, actual, expected)
+            }
+            (None, ErrorKind::Mismatch { actual, expected }) => {
+                write!(f, "Row holds {} instead of {}", actual, expected)
+            }
+            (Some(col), ErrorKind::Custom(msg)) => write!(f, "Column {}: {}", col, msg),
+            (None, ErrorKind::Custom(msg)) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::test_db::conn;
+    use anyhow::Result;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Person {
+        name: String,
+        score: Option<f64>,
+        id: i64,
+    }
+
+    #[test]
+    fn maps_columns_to_fields_by_name() -> Result<()> {
+        let conn = conn()?;
+        let stmt = conn.prepare("SELECT id, name, score FROM t")?;
+        let people = stmt.query_as::<Person>()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            people[1],
+            Person {
+                name: "bob".to_owned(),
+                score: Some(2.0),
+                id: 2
+            }
+        );
+
+        let rows = stmt
+            .query_as::<(i64, String)>()?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(rows[0], (1, "ann".to_owned()));
+
+        let stmt = conn.prepare("SELECT name, ? FROM t")?;
+        let maps = stmt
+            .query_as::<HashMap<String, Option<String>>>()?
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(maps[0]["name"].as_deref(), Some("ann"));
+        assert_eq!(maps[0]["?"], None);
+        Ok(())
+    }
+
+    #[test]
+    fn names_columns_in_errors() -> Result<()> {
+        #[derive(Debug, Deserialize)]
+        struct Wrong {
+            #[allow(dead_code)]
+            name: i64,
+        }
+
+        let conn = conn()?;
+        let stmt = conn.prepare("SELECT name FROM t")?;
+        let err = stmt.query_as::<Wrong>()?.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Column name holds text instead of i64");
+
+        let stmt = conn.prepare("SELECT ? FROM t")?;
+        let err = stmt.query_as::<(String,)>()?.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Column ? holds NULL instead of a string");
+
+        let stmt = conn.prepare("SELECT id FROM t")?;
+        let err = stmt.query_as::<Person>()?.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "missing field `name`");
+        Ok(())
+    }
+}
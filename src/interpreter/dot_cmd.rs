@@ -1,11 +1,53 @@
-use crate::{schema::DbSchema, syntax::DotCmd};
+use crate::{
+    format::DbFile,
+    interpreter::{
+        btree,
+        database::Database,
+        eval::{Eval, Value},
+        import,
+        output::{quote_ident, sql_literal, OutputConfig},
+        row::Row,
+    },
+    schema::{DbSchema, ObjSchema},
+    syntax::{DotCmd, Expr},
+    util::{like, IterEither, MapOkAndThenExt},
+};
 use anyhow::Result;
+use itertools::Itertools;
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    convert::TryFrom,
+    io::{self, Write},
+};
 
-pub fn run(cmd: &DotCmd, db_schema: &DbSchema) -> Result<()> {
+pub fn run(cmd: &DotCmd, database: &mut Database, config: &mut OutputConfig) -> Result<()> {
+    let (db_schema, db) = (&database.schema, database.bytes.as_slice());
     match cmd {
         DotCmd::DbInfo => dbinfo(db_schema),
-        DotCmd::Tables => tables(db_schema),
-        DotCmd::Schema => schema(db_schema),
+        DotCmd::Tables(pattern) => tables(db_schema, pattern.as_deref()),
+        DotCmd::Indexes(pattern) => indexes(db_schema, pattern.as_deref()),
+        DotCmd::Schema { pattern, indent } => schema(db_schema, pattern.as_deref(), *indent),
+        DotCmd::FullSchema { indent } => full_schema(db_schema, db, *indent)?,
+        DotCmd::Dump(patterns) => dump(db_schema, db, patterns)?,
+        DotCmd::Import {
+            file,
+            tbl,
+            csv,
+            skip,
+        } => import::run(database, file, tbl, *csv, *skip, config)?,
+        // Only the interactive shell has a session to end.
+        DotCmd::Quit => {}
+        DotCmd::Mode { mode, tbl } => config.set_mode(*mode, tbl.clone()),
+        DotCmd::Headers(on) => config.headers = Some(*on),
+        DotCmd::NullValue(null_value) => config.null_value = null_value.clone(),
+        DotCmd::ScanStats(on) => config.scan_stats = *on,
+        DotCmd::Separator { col, row } => {
+            config.col_sep = col.clone();
+            if let Some(row) = row {
+                config.row_sep = row.clone();
+            }
+        }
     };
     Ok(())
 }
@@ -37,19 +79,318 @@ fn dbinfo(db_schema: &DbSchema) {
     println!("schema size:         {}", s.size);
 }
 
-fn tables(db_schema: &DbSchema) {
+fn matches(pattern: Option<&str>, name: &str) -> bool {
+    pattern.map_or(true, |p| like(p, name))
+}
+
+fn tables(db_schema: &DbSchema, pattern: Option<&str>) {
     db_schema
         .tables()
-        .filter(|t| !t.is_sequence_tbl())
+        .filter(|t| !t.is_sequence_tbl() && matches(pattern, &t.name))
         .for_each(|t| print!("{} ", t.name));
+    println!();
+}
+
+fn indexes(db_schema: &DbSchema, tbl_pattern: Option<&str>) {
+    db_schema
+        .indexes()
+        .filter(|i| matches(tbl_pattern, &i.tbl_name))
+        .for_each(|i| print!("{} ", i.name));
+    println!();
+}
+
+fn schema(db_schema: &DbSchema, tbl_pattern: Option<&str>, indent: bool) {
+    db_schema
+        .objs
+        .iter()
+        .filter(|o| matches(tbl_pattern, &o.tbl_name))
+        .for_each(|o| print_create_stmt(o, indent));
+}
+
+/// Prints the schema without SQLite's internal objects, followed by the contents of the tables
+/// ANALYZE stores its statistics in. Loading the output into an empty database gives the query
+/// planner the same information as in the original one.
+fn full_schema(db_schema: &DbSchema, db: &[u8], indent: bool) -> Result<()> {
+    db_schema
+        .objs
+        .iter()
+        .filter(|o| !o.name.starts_with("sqlite_"))
+        .for_each(|o| print_create_stmt(o, indent));
+
+    let stat_tbls = ["sqlite_stat1", "sqlite_stat4"]
+        .iter()
+        .filter_map(|name| db_schema.table(name))
+        .collect::<Vec<_>>();
+    if stat_tbls.is_empty() {
+        println!("/* No STAT tables available */");
+        return Ok(());
+    }
+
+    let db = DbFile::new(db, &db_schema.db_header);
+    println!("ANALYZE sqlite_schema;");
+    for tbl in stat_tbls {
+        btree::full_tbl_scan(db.page(tbl.rootpage)?, db)
+            .map_ok_and_then(|cell| {
+                let vals = cell
+                    .payload
+                    .0
+                    .iter()
+                    .map(|col| Ok(sql_literal(&Value::try_from(col)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                println!(
+                    "INSERT INTO {} VALUES({});",
+                    quote_ident(&tbl.name),
+                    vals.join(",")
+                );
+                Ok(())
+            })
+            .collect::<Result<()>>()?;
+    }
+    println!("ANALYZE sqlite_schema;");
+    Ok(())
+}
+
+/// Prints an SQL script that recreates the matching tables with their rows in a transaction,
+/// followed by the matching views, triggers and indexes. The rows of SQLite's internal tables
+/// are restored as well, while their CREATE statements are left to SQLite.
+fn dump(db_schema: &DbSchema, db: &[u8], patterns: &[String]) -> Result<()> {
+    let is_match =
+        |o: &&ObjSchema| patterns.is_empty() || patterns.iter().any(|p| like(p, &o.name));
+    let db = DbFile::new(db, &db_schema.db_header);
+
+    println!("PRAGMA foreign_keys=OFF;");
+    println!("BEGIN TRANSACTION;");
+
+    // Writing to sqlite_sequence requires a writable schema, so it's restored last.
+    let mut writable_schema = false;
+    let tbls = db_schema
+        .tables()
+        .filter(|t| t.sql.is_some())
+        .filter(is_match)
+        .sorted_by_key(|t| t.is_sequence_tbl());
+    for tbl in tbls {
+        if tbl.is_sequence_tbl() {
+            if !writable_schema {
+                println!("PRAGMA writable_schema=ON;");
+                writable_schema = true;
+            }
+            println!("CREATE TABLE IF NOT EXISTS sqlite_sequence(name,seq);");
+            println!("DELETE FROM sqlite_sequence;");
+        } else if like("sqlite\\_stat_", &tbl.name) {
+            println!("ANALYZE sqlite_schema;");
+        } else if tbl.name.starts_with("sqlite_") {
+            continue;
+        } else {
+            print_create_stmt(tbl, false);
+        }
+        dump_rows(tbl, db)?;
+    }
+
+    db_schema
+        .objs
+        .iter()
+        .filter(|o| o.is_view() || o.is_trigger() || o.is_index())
+        .filter(|o| o.sql.is_some())
+        .filter(is_match)
+        .sorted_by_key(|o| Reverse(&o.type_))
+        .for_each(|o| print_create_stmt(o, false));
+
+    if writable_schema {
+        println!("PRAGMA writable_schema=OFF;");
+    }
+    println!("COMMIT;");
+    if writable_schema {
+        eprintln!("/* WARNING: Script requires that SQLITE_DBCONFIG_DEFENSIVE be disabled */");
+    }
+    Ok(())
+}
+
+/// Prints an INSERT statement for every row of the table. Like in sqlite3, generated columns are
+/// left out, as they can't be inserted into.
+fn dump_rows(tbl: &ObjSchema, db: DbFile) -> Result<()> {
+    let cols = tbl.cols()?;
+    let rootpage = db.page(tbl.rootpage)?;
+    let rows = if cols.is_without_rowid() {
+        IterEither::left(btree::idx_seek(vec![], rootpage, db).map_ok(Row::from))
+    } else {
+        IterEither::right(btree::full_tbl_scan(rootpage, db).map_ok(Row::from))
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    rows.map_ok_and_then(|row| {
+        let vals = cols
+            .names()
+            .filter(|col| cols.generated(col).is_none())
+            .map(|col| Ok(sql_literal(&Expr::ColName(col).eval(&row, tbl)?)))
+            .collect::<Result<Vec<_>>>()?;
+        writeln!(
+            out,
+            "INSERT INTO {} VALUES({});",
+            quote_ident(&tbl.name),
+            vals.join(",")
+        )?;
+        Ok(())
+    })
+    .collect()
 }
 
-fn schema(db_schema: &DbSchema) {
-    db_schema.objs.iter().for_each(|schema| match schema.sql {
-        Some(sql) => println!("{};", sql),
+fn print_create_stmt(schema: &ObjSchema, indent: bool) {
+    match &schema.sql {
+        Some(sql) if indent => println!("{};", if_not_exists(&indent_sql(sql))),
+        Some(sql) => println!("{};", if_not_exists(sql)),
         None => println!(
             "-- The {} '{}' has no CREATE statement",
             schema.type_, schema.name
         ),
-    });
+    }
+}
+
+/// Like sqlite3, adds IF NOT EXISTS to the CREATE TABLE statements of tables with quoted
+/// names, since those may be SQLite's own tables that already exist.
+fn if_not_exists(sql: &str) -> Cow<'_, str> {
+    let quoted_name = sql
+        .strip_prefix("CREATE TABLE ")
+        .filter(|rest| rest.starts_with(['"', '\''].as_ref()));
+    match quoted_name {
+        Some(rest) => Cow::Owned(format!("CREATE TABLE IF NOT EXISTS {}", rest)),
+        None => Cow::Borrowed(sql),
+    }
+}
+
+/// Pretty-prints a CREATE statement the way sqlite3's `.schema --indent` does: Runs of
+/// whitespace are collapsed, and if the statement is still long, every column definition of a
+/// table goes on a line of its own, as does every AND term of a partial index's WHERE clause.
+/// Views and triggers are left alone.
+fn indent_sql(sql: &str) -> String {
+    let starts_with_ci = |s: &[u8], prefix: &str| {
+        s.len() >= prefix.len() && s[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+    };
+    let is_space = |c: &u8| c.is_ascii_whitespace() || *c == b'\x0b';
+
+    let sql = sql.as_bytes();
+    if starts_with_ci(sql, "CREATE VIEW") || starts_with_ci(sql, "CREATE TRIG") {
+        return String::from_utf8_lossy(sql).into_owned();
+    }
+    let is_index =
+        starts_with_ci(sql, "CREATE INDEX") || starts_with_ci(sql, "CREATE UNIQUE INDEX");
+
+    let mut z = Vec::with_capacity(sql.len());
+    for &c in sql.iter().skip_while(|c| is_space(c)) {
+        if is_space(&c) {
+            if let Some(last) = z.last_mut().filter(|l| **l == b'\r') {
+                *last = b'\n';
+            }
+            if z.last().map_or(false, |l| is_space(l) || *l == b'(') {
+                continue;
+            }
+        } else if (c == b'(' || c == b')') && z.last().map_or(false, is_space) {
+            z.pop();
+        }
+        z.push(c);
+    }
+    while z.last().map_or(false, is_space) {
+        z.pop();
+    }
+    if z.len() < 79 {
+        return String::from_utf8_lossy(&z).into_owned();
+    }
+
+    // A keyword that isn't just the start of a longer identifier.
+    let is_word = |s: &[u8], word: &str| {
+        starts_with_ci(s, word)
+            && !s
+                .get(word.len())
+                .map_or(false, |c| c.is_ascii_alphanumeric() || *c == b'_')
+    };
+    let ws_to_eol = |s: &[u8]| s.iter().take_while(|c| **c != b'\n').all(is_space);
+
+    let mut out = Vec::with_capacity(z.len() + 32);
+    let mut line = vec![];
+    let (mut quote_end, mut n_paren, mut n_lines, mut is_where) = (None, 0, 0, false);
+    let mut i = 0;
+    while i < z.len() {
+        let c = z[i];
+        if Some(c) == quote_end {
+            quote_end = None;
+        } else if matches!(c, b'"' | b'\'' | b'`') {
+            quote_end = Some(c);
+        } else if c == b'[' {
+            quote_end = Some(b']');
+        } else if c == b'-' && z.get(i + 1) == Some(&b'-') {
+            quote_end = Some(b'\n');
+        } else if c == b'(' {
+            n_paren += 1;
+        } else if c == b')' {
+            n_paren -= 1;
+            if n_lines > 0 && n_paren == 0 && !line.is_empty() && !is_where {
+                out.append(&mut line);
+                out.push(b'\n');
+            }
+        } else if is_index && n_paren == 0 && is_word(&z[i..], "WHERE") {
+            is_where = true;
+        } else if is_where && n_paren == 0 && is_word(&z[i..], "AND") {
+            out.append(&mut line);
+            out.extend_from_slice(b"\n    ");
+        }
+
+        line.push(c);
+        let ends_line = c == b'(' || c == b'\n' || (c == b',' && !ws_to_eol(&z[i + 1..]));
+        if n_paren == 1 && quote_end.is_none() && ends_line && !is_where {
+            if c == b'\n' {
+                line.pop();
+            }
+            out.append(&mut line);
+            out.extend_from_slice(b"\n  ");
+            n_lines += 1;
+            while z.get(i + 1).map_or(false, is_space) {
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+    out.append(&mut line);
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{if_not_exists, indent_sql};
+
+    #[test]
+    fn adds_if_not_exists_to_tbls_with_quoted_names() {
+        assert_eq!(
+            if_not_exists("CREATE TABLE \"my tbl\"(a)"),
+            "CREATE TABLE IF NOT EXISTS \"my tbl\"(a)"
+        );
+        assert_eq!(if_not_exists("CREATE TABLE t(a)"), "CREATE TABLE t(a)");
+    }
+
+    #[test]
+    fn indents_long_create_tbl_stmts() {
+        assert_eq!(
+            indent_sql("CREATE TABLE x  ( a int, b text check(b in ('x','y')),  c   default ( 1 ) , primary key(a,b))"),
+            "CREATE TABLE x(\n  a int,\n  b text check(b in('x','y')),\n  c default(1) ,\n  primary key(a,b)\n)"
+        );
+    }
+
+    #[test]
+    fn only_collapses_whitespace_of_short_stmts() {
+        assert_eq!(
+            indent_sql("CREATE TABLE t (id integer primary key,\n    name text)"),
+            "CREATE TABLE t(id integer primary key,\nname text)"
+        );
+        assert_eq!(
+            indent_sql("CREATE VIEW v  as select a from x"),
+            "CREATE VIEW v  as select a from x"
+        );
+    }
+
+    #[test]
+    fn puts_and_terms_of_partial_indexes_on_lines_of_their_own() {
+        assert_eq!(
+            indent_sql("CREATE INDEX some_long_index_name ON some_table(a, b) WHERE a > 1 AND b < 2 AND c IS NULL"),
+            "CREATE INDEX some_long_index_name ON some_table(\n  a,\n  b\n) WHERE a > 1 \n    AND b < 2 \n    AND c IS NULL"
+        );
+    }
 }
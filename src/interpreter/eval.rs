@@ -1,47 +1,254 @@
 use crate::{
-    format::{ColContent, LeafTblCell},
-    schema::ObjSchema,
-    syntax::{BoolExpr, Expr, Literal},
+    format::ColContent,
+    interpreter::row::Row,
+    schema::{Generated, ObjSchema},
+    syntax::{parse, Affinity, ArithOp, Expr, Literal},
+    util::float_fmt,
+};
+use anyhow::{bail, Result};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt,
+    hash::{Hash, Hasher},
 };
-use anyhow::Result;
-use std::{convert::TryFrom, fmt};
 
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone)]
 pub enum Value<'a> {
     Null,
     Int(i64),
     Float(f64),
-    Bytes(&'a [u8]),
-    String(&'a str),
-    CountPlaceholder,
+    Bytes(Cow<'a, [u8]>),
+    String(Cow<'a, str>),
 }
 
 pub trait Eval<'a> {
-    fn eval(&self, c: &LeafTblCell<'a>, s: &ObjSchema<'a>) -> Result<Value<'a>>;
+    fn eval(&self, row: &Row<'a>, s: &ObjSchema) -> Result<Value<'a>>;
 }
 
 impl<'a> Eval<'a> for Expr<'a> {
-    fn eval(&self, cell: &LeafTblCell<'a>, schema: &ObjSchema<'a>) -> Result<Value<'a>> {
+    fn eval(&self, row: &Row<'a>, schema: &ObjSchema) -> Result<Value<'a>> {
         Ok(match self {
-            Expr::Literal(l) => l.into(),
+            Expr::Literal(l) | Expr::Param { value: l, .. } => l.into(),
             Expr::ColName(col) => {
-                if schema.cols().is_int_pk(col) {
-                    Value::Int(cell.row_id)
-                } else {
-                    Value::try_from(&cell.payload[schema.cols().record_pos(col)])?
+                let cols = schema.cols()?;
+                match row.row_id {
+                    Some(row_id) if cols.is_int_pk(col) => Value::Int(row_id),
+                    _ => match cols.generated(col) {
+                        Some(Generated::Virtual(text)) => virtual_col(col, text, row, schema)?,
+                        _ => match Value::try_from(&row.record[cols.record_pos(col)?])? {
+                            // SQLite stores integral REAL values as integers to save space.
+                            Value::Int(x) if cols.affinity(col) == Affinity::Real => {
+                                Value::Float(x as f64)
+                            }
+                            val => val,
+                        },
+                    },
                 }
             }
-            Expr::Count => Value::CountPlaceholder,
+            Expr::QualifiedColName { tbl, col } if tbl.eq_ignore_ascii_case(&schema.name) => {
+                Expr::ColName(col).eval(row, schema)?
+            }
+            Expr::QualifiedColName { .. } => bail!("Unknown column '{}'", self),
+            Expr::Count | Expr::CountDistinct(_) => {
+                bail!("{} can only be used as a result column", self)
+            }
+            Expr::Subquery { .. } => bail!("Subqueries can only be used in queries"),
+            Expr::Arith { op, l, r, .. } => {
+                arith(*op, &l.eval(row, schema)?, &r.eval(row, schema)?)
+            }
         })
     }
 }
 
-impl<'a> Eval<'a> for BoolExpr<'a> {
-    fn eval(&self, c: &LeafTblCell<'a>, s: &ObjSchema<'a>) -> Result<Value<'a>> {
-        Ok(match self {
-            BoolExpr::Equals { l, r } => Value::Int((l.eval(c, s)? == r.eval(c, s)?) as i64),
-            BoolExpr::NotEquals { l, r } => Value::Int((l.eval(c, s)? != r.eval(c, s)?) as i64),
-        })
+/// Computes a VIRTUAL column from the text of its expression, which is only possible for the
+/// expressions that can be parsed.
+fn virtual_col<'a>(col: &str, text: &str, row: &Row<'a>, schema: &ObjSchema) -> Result<Value<'a>> {
+    match parse::expr(text) {
+        Ok(expr) => Ok(expr.eval(row, schema)?.into_owned()),
+        Err(_) => bail!(
+            "Unsupported expression of generated column {}.{}: {}",
+            schema.name,
+            col,
+            text
+        ),
+    }
+}
+
+impl<'a> Value<'a> {
+    /// The value with copies of any borrowed text or bytes.
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Value::Null => Value::Null,
+            Value::Int(x) => Value::Int(x),
+            Value::Float(x) => Value::Float(x),
+            Value::Bytes(b) => Value::Bytes(Cow::Owned(b.into_owned())),
+            Value::String(s) => Value::String(Cow::Owned(s.into_owned())),
+        }
+    }
+
+    /// Rank of the value's storage class in SQLite's sort order as described here:
+    /// [Sort Order](https://www.sqlite.org/datatype3.html#sort_order)
+    const fn class_rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Int(_) | Value::Float(_) => 1,
+            Value::String(_) => 2,
+            Value::Bytes(_) => 3,
+        }
+    }
+}
+
+impl<'a> Ord for Value<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Int(a), Value::Float(b)) => cmp_int_float(*a, *b),
+            (Value::Float(a), Value::Int(b)) => cmp_int_float(*b, *a).reverse(),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+            _ => self.class_rank().cmp(&other.class_rank()),
+        }
+    }
+}
+
+/// Compares an integer with a float exactly, like SQLite does, rather than rounding the integer
+/// to the nearest float, which is only exact up to 2^53.
+fn cmp_int_float(i: i64, r: f64) -> Ordering {
+    // 2^63, the first float past the largest integer.
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if r.is_nan() || r < -LIMIT {
+        Ordering::Greater
+    } else if r >= LIMIT {
+        Ordering::Less
+    } else {
+        // The whole parts are compared first, as integers, and then the fraction.
+        i.cmp(&(r as i64))
+            .then_with(|| (i as f64).partial_cmp(&r).unwrap_or(Ordering::Equal))
+    }
+}
+
+/// Computes an arithmetic operation like SQLite does. Integers stay integers unless the result
+/// doesn't fit, and the result is NULL if an operand is or if dividing by zero. `%` takes the
+/// remainder of the whole parts of floats.
+pub fn arith(op: ArithOp, l: &Value, r: &Value) -> Value<'static> {
+    let (l, r) = match (numeric(l), numeric(r)) {
+        (Some(l), Some(r)) => (l, r),
+        _ => return Value::Null,
+    };
+    if let (Value::Int(a), Value::Int(b)) = (&l, &r) {
+        let (a, b) = (*a, *b);
+        let int = match op {
+            ArithOp::Add => a.checked_add(b),
+            ArithOp::Sub => a.checked_sub(b),
+            ArithOp::Mul => a.checked_mul(b),
+            ArithOp::Div | ArithOp::Rem if b == 0 => return Value::Null,
+            ArithOp::Div => a.checked_div(b),
+            // Only i64::MIN % -1 overflows, whose remainder is 0 all the same.
+            ArithOp::Rem => Some(a.checked_rem(b).unwrap_or(0)),
+        };
+        if let Some(n) = int {
+            return Value::Int(n);
+        }
+    }
+    let float = |x: Value| match x {
+        Value::Int(n) => n as f64,
+        Value::Float(x) => x,
+        _ => unreachable!(),
+    };
+    let (a, b) = (float(l), float(r));
+    let x = match op {
+        ArithOp::Add => a + b,
+        ArithOp::Sub => a - b,
+        ArithOp::Mul => a * b,
+        ArithOp::Div if b == 0.0 => return Value::Null,
+        ArithOp::Div => a / b,
+        ArithOp::Rem => match (a as i64, b as i64) {
+            (_, 0) => return Value::Null,
+            (a, b) => a.checked_rem(b).unwrap_or(0) as f64,
+        },
+    };
+    match x.is_nan() {
+        true => Value::Null,
+        false => Value::Float(x),
+    }
+}
+
+/// The number a value counts as in arithmetic, which for text and bytes is the one they start
+/// with, or 0. NULL has none.
+fn numeric(value: &Value) -> Option<Value<'static>> {
+    let text = match value {
+        Value::Null => return None,
+        Value::Int(n) => return Some(Value::Int(*n)),
+        Value::Float(x) => return Some(Value::Float(*x)),
+        Value::Bytes(b) => String::from_utf8_lossy(b),
+        Value::String(s) => Cow::Borrowed(s.as_ref()),
+    };
+    let s = text.trim_start_matches(|c| matches!(c, ' ' | '\t' | '\n' | '\x0b' | '\x0c' | '\r'));
+    let b = s.as_bytes();
+    let digits = |i: usize| i + b[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+    let start = match b.first() {
+        Some(b'+') | Some(b'-') => 1,
+        _ => 0,
+    };
+    let int_end = digits(start);
+    let mut end = int_end;
+    if b.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+    }
+    if end - start == usize::from(end > int_end) {
+        // The text doesn't start with a number.
+        return Some(Value::Int(0));
+    }
+    if let Some(b'e') | Some(b'E') = b.get(end) {
+        let exp = match b.get(end + 1) {
+            Some(b'+') | Some(b'-') => end + 2,
+            _ => end + 1,
+        };
+        if digits(exp) > exp {
+            end = digits(exp);
+        }
+    }
+    let s = &s[..end];
+    if end == int_end {
+        if let Ok(n) = s.parse() {
+            return Some(Value::Int(n));
+        }
+    }
+    Some(Value::Float(s.parse().unwrap_or(0.0)))
+}
+
+impl<'a> PartialOrd for Value<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> PartialEq for Value<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for Value<'a> {}
+
+/// Hashes values so that those that are equal, like 1 and 1.0, have the same hash. Floats with
+/// no fraction that fit an integer are hashed as that integer.
+impl<'a> Hash for Value<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.class_rank().hash(state);
+        match self {
+            Value::Null => {}
+            Value::Int(n) => n.hash(state),
+            Value::Float(x) => match *x as i64 {
+                n if cmp_int_float(n, *x) == Ordering::Equal => n.hash(state),
+                _ => x.to_bits().hash(state),
+            },
+            Value::String(s) => s.hash(state),
+            Value::Bytes(b) => b.hash(state),
+        }
     }
 }
 
@@ -60,8 +267,8 @@ impl<'a> TryFrom<&ColContent<'a>> for Value<'a> {
             | ColContent::Int48(_)
             | ColContent::Int64(_) => Self::Int(i64::try_from(content)?),
             ColContent::Float64(_) => Self::Float(f64::try_from(content)?),
-            ColContent::Blob(bs) => Self::Bytes(bs),
-            ColContent::Text(_) => Self::String(<&str>::try_from(content)?),
+            ColContent::Blob(bs) => Self::Bytes(Cow::Borrowed(bs)),
+            ColContent::Text(..) => Self::String(Cow::try_from(content)?),
         })
     }
 }
@@ -71,7 +278,9 @@ impl<'a> From<&Literal<'a>> for Value<'a> {
         match expr {
             Literal::Null => Self::Null,
             Literal::Int(n) => Self::Int(*n),
-            Literal::String(s) => Self::String(s),
+            Literal::String(s) => Self::String(s.clone()),
+            Literal::Float(x) => Self::Float(*x),
+            Literal::Blob(bytes) => Self::Bytes(bytes.clone()),
         }
     }
 }
@@ -81,15 +290,118 @@ impl<'a> fmt::Display for Value<'a> {
         match self {
             Value::Null => write!(f, "NULL"),
             Value::Int(x) => write!(f, "{}", x),
-            Value::Float(x) => write!(f, "{}", x),
+            Value::Float(x) => write!(f, "{}", float_fmt::generic(*x, 15)),
+            // Like in sqlite3, the text of a blob ends at its first NUL byte.
             Value::Bytes(bytes) => {
-                for byte in *bytes {
-                    write!(f, "{:02X} ", byte)?;
-                }
-                Ok(())
+                let text = bytes.split(|b| *b == 0).next().unwrap_or_default();
+                write!(f, "{}", String::from_utf8_lossy(text))
             }
             Value::String(s) => write!(f, "{}", s),
-            Value::CountPlaceholder => panic!("Unexpected CountPlaceholder"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{arith, Value};
+    use crate::syntax::ArithOp;
+    use std::{
+        cmp::Ordering,
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
+    fn hash(value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn compares_integers_with_floats_exactly() {
+        // 2^53 + 1 is the first integer that has no float.
+        let (int, float) = (
+            Value::Int(9_007_199_254_740_993),
+            Value::Float(9_007_199_254_740_992.0),
+        );
+        assert_eq!(int.cmp(&float), Ordering::Greater);
+        assert_eq!(float.cmp(&int), Ordering::Less);
+        assert_ne!(hash(&int), hash(&float));
+
+        assert_eq!(
+            Value::Int(i64::MAX).cmp(&Value::Float(9.3e18)),
+            Ordering::Less
+        );
+        assert_eq!(
+            Value::Int(i64::MIN).cmp(&Value::Float(-9.3e18)),
+            Ordering::Greater
+        );
+        assert_eq!(Value::Int(2).cmp(&Value::Float(2.5)), Ordering::Less);
+        assert_eq!(Value::Int(-2).cmp(&Value::Float(-2.5)), Ordering::Greater);
+        for (int, float) in [(2, 2.0), (0, -0.0), (-3, -3.0)].iter() {
+            let (int, float) = (Value::Int(*int), Value::Float(*float));
+            assert_eq!(int, float);
+            assert_eq!(hash(&int), hash(&float));
+        }
+    }
+
+    #[test]
+    fn computes_arithmetic_like_sqlite() {
+        let text = |s: &'static str| Value::String(s.into());
+        for (op, l, r, result) in [
+            (ArithOp::Div, Value::Int(-7), Value::Int(2), Value::Int(-3)),
+            (
+                ArithOp::Mul,
+                Value::Int(i64::MAX),
+                Value::Int(2),
+                Value::Float(1.8446744073709552e19),
+            ),
+            (
+                ArithOp::Div,
+                Value::Int(i64::MIN),
+                Value::Int(-1),
+                Value::Float(9.223372036854776e18),
+            ),
+            (
+                ArithOp::Rem,
+                Value::Int(i64::MIN),
+                Value::Int(-1),
+                Value::Int(0),
+            ),
+            (
+                ArithOp::Rem,
+                Value::Float(7.5),
+                Value::Int(2),
+                Value::Float(1.0),
+            ),
+            (ArithOp::Div, Value::Float(1.0), Value::Int(0), Value::Null),
+            (ArithOp::Add, Value::Null, Value::Int(1), Value::Null),
+            (
+                ArithOp::Add,
+                text(" 3.5x"),
+                Value::Int(1),
+                Value::Float(4.5),
+            ),
+            (ArithOp::Add, text("1e"), Value::Int(1), Value::Int(2)),
+            (ArithOp::Mul, text("-.5e1"), text("2"), Value::Float(-10.0)),
+            (
+                ArithOp::Add,
+                text("x"),
+                Value::Bytes((&b"12"[..]).into()),
+                Value::Int(12),
+            ),
+        ]
+        .iter()
+        {
+            // Integers equal floats, so their types are compared too.
+            assert_eq!(
+                format!("{:?}", arith(*op, l, r)),
+                format!("{:?}", result),
+                "{:?} {:?} {:?}",
+                l,
+                op,
+                r
+            );
         }
     }
 }
@@ -1,20 +1,26 @@
 use crate::{
-    interpreter::{dot_cmd, select_stmt},
-    schema::DbSchema,
+    interpreter::{
+        analyze, database::Database, dot_cmd, explain, output::OutputConfig, pragma, select_stmt,
+    },
     syntax::{SqlStmt, Sqlite},
 };
 use anyhow::{bail, Result};
 
-pub fn sqlite(sql: Sqlite, db_schema: &DbSchema, db: &[u8]) -> Result<()> {
+pub fn sqlite(sql: Sqlite, db: &mut Database, config: &mut OutputConfig) -> Result<()> {
     match sql {
-        Sqlite::DotCmd(cmd) => dot_cmd::run(&cmd, db_schema),
-        Sqlite::SqlStmt(stmt) => sql_stmt(stmt, db_schema, db),
+        Sqlite::DotCmd(cmd) => dot_cmd::run(&cmd, db, config),
+        Sqlite::SqlStmt(stmt) => sql_stmt(stmt, db, config),
     }
 }
 
-fn sql_stmt(stmt: SqlStmt, db_schema: &DbSchema, db: &[u8]) -> Result<()> {
+fn sql_stmt(stmt: SqlStmt, db: &mut Database, config: &OutputConfig) -> Result<()> {
     match stmt {
-        SqlStmt::Select(select_stmt) => select_stmt::run(&select_stmt, db_schema, db),
+        SqlStmt::Select(select_stmt) => {
+            select_stmt::run(&select_stmt, &db.schema, &db.bytes, config)
+        }
+        SqlStmt::ExplainQueryPlan(select_stmt) => explain::run(&select_stmt, &db.schema),
+        SqlStmt::Pragma { schema, name, arg } => pragma::run(schema, name, arg, db, config),
+        SqlStmt::Analyze(name) => analyze::run(name, db),
         _ => bail!("Not implemented: {:#?}", stmt),
     }
 }
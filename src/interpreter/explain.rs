@@ -0,0 +1,318 @@
+//! EXPLAIN QUERY PLAN, which shows how a query would find its rows instead of running it.
+
+use crate::{
+    interpreter::{
+        eval::Value,
+        ops::{Op, OpKind},
+        select_stmt,
+    },
+    schema::DbSchema,
+    syntax::{CompoundOp, Expr, Query},
+};
+use anyhow::Result;
+use std::{borrow::Cow, fmt::Write};
+
+/// A step of a query plan. Steps that are part of another step have its id as their parent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub id: i64,
+    pub parent: i64,
+    pub detail: String,
+}
+
+pub fn run(select_stmt: &Query, db_schema: &DbSchema) -> Result<()> {
+    print!("{}", tree(&steps(select_stmt, db_schema)?));
+    Ok(())
+}
+
+/// Returns the steps as rows with the columns SQLite uses.
+pub fn query<'a>(
+    select_stmt: &Query,
+    db_schema: &DbSchema,
+) -> Result<(Vec<String>, Vec<Vec<Value<'a>>>)> {
+    let cols = ["id", "parent", "notused", "detail"]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    let rows = steps(select_stmt, db_schema)?
+        .into_iter()
+        .map(|step| {
+            vec![
+                Value::Int(step.id),
+                Value::Int(step.parent),
+                Value::Int(0),
+                Value::String(Cow::Owned(step.detail)),
+            ]
+        })
+        .collect();
+    Ok((cols, rows))
+}
+
+pub fn steps(select_stmt: &Query, db_schema: &DbSchema) -> Result<Vec<Step>> {
+    fn push(detail: String, parent: i64, steps: &mut Vec<Step>) -> i64 {
+        let id = steps.len() as i64 + 2;
+        steps.push(Step { id, parent, detail });
+        id
+    }
+
+    /// Adds the steps of `op` after those of its inputs, which are run first, and then its
+    /// subqueries. Like in SQLite, the seeks of an OR are numbered below it instead, and a
+    /// recursive CTE is a co-routine that's set up before the rows are scanned. The SELECTs of
+    /// a compound query are shown below it with the operator that adds each one, and each
+    /// count(DISTINCT) has a temporary B-tree that's set up before the rows are read. A table
+    /// read under an alias is named by its `alias`.
+    fn add(op: &Op, parent: i64, alias: Option<&str>, steps: &mut Vec<Step>) {
+        match &op.kind {
+            OpKind::As {
+                input,
+                name: Some(name),
+                ..
+            } if input.reads_tbl() => {
+                add(input, parent, Some(name), steps);
+                return;
+            }
+            OpKind::MultiIndexOr { seeks, .. } => {
+                let id = push(op.label(), parent, steps);
+                for (i, seek) in seeks.iter().enumerate() {
+                    let index = push(format!("INDEX {}", i + 1), id, steps);
+                    add(seek, index, alias, steps);
+                }
+                return;
+            }
+            OpKind::RecursiveCte {
+                name, setup, step, ..
+            } => {
+                let id = push(format!("CO-ROUTINE {}", name), parent, steps);
+                let setup_id = push("SETUP".to_owned(), id, steps);
+                add(setup, setup_id, None, steps);
+                let step_id = push("RECURSIVE STEP".to_owned(), id, steps);
+                add(step, step_id, None, steps);
+                push(op.label(), parent, steps);
+                return;
+            }
+            OpKind::Compound { first, rest } => {
+                let id = push(op.label(), parent, steps);
+                let first_id = push("LEFT-MOST SUBQUERY".to_owned(), id, steps);
+                add(first, first_id, None, steps);
+                for (compound_op, input) in rest {
+                    let detail = match compound_op {
+                        CompoundOp::UnionAll => compound_op.to_string(),
+                        _ => format!("{} USING TEMP B-TREE", compound_op),
+                    };
+                    let input_id = push(detail, id, steps);
+                    add(input, input_id, None, steps);
+                }
+                return;
+            }
+            OpKind::Aggregate { exprs, .. } => {
+                for _ in exprs.iter().filter(|e| matches!(e, Expr::CountDistinct(_))) {
+                    push(
+                        "USE TEMP B-TREE FOR count(DISTINCT)".to_owned(),
+                        parent,
+                        steps,
+                    );
+                }
+            }
+            _ => {}
+        }
+        op.inputs()
+            .into_iter()
+            .for_each(|i| add(i, parent, None, steps));
+        if let Some(detail) = op.explain_as(alias) {
+            push(detail, parent, steps);
+        }
+        for subquery in op.subqueries() {
+            let id = push(subquery.explain(), parent, steps);
+            add(&subquery.op, id, None, steps);
+        }
+    }
+
+    let mut steps = vec![];
+    add(
+        &select_stmt::plan(select_stmt, db_schema)?,
+        0,
+        None,
+        &mut steps,
+    );
+    Ok(steps)
+}
+
+/// Draws every operator of a query that was run together with the number of rows it returned.
+pub fn stats(root: &Op) -> String {
+    fn add(op: &Op, parent: i64, steps: &mut Vec<Step>) {
+        let id = steps.len() as i64 + 1;
+        let detail = format!("{} (rows={})", op.label(), op.rows_returned());
+        steps.push(Step { id, parent, detail });
+        op.inputs().into_iter().for_each(|i| add(i, id, steps));
+        for subquery in op.subqueries() {
+            let sub_id = steps.len() as i64 + 1;
+            let detail = subquery.explain();
+            steps.push(Step {
+                id: sub_id,
+                parent: id,
+                detail,
+            });
+            add(&subquery.op, sub_id, steps);
+        }
+    }
+
+    let mut steps = vec![];
+    add(root, 0, &mut steps);
+    tree(&steps)
+}
+
+/// Draws the steps below their parents like the sqlite3 shell does.
+fn tree(steps: &[Step]) -> String {
+    fn draw(steps: &[Step], parent: i64, prefix: &str, out: &mut String) {
+        let children = steps
+            .iter()
+            .filter(|s| s.parent == parent)
+            .collect::<Vec<_>>();
+        for (i, step) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            let (branch, indent) = if last { ("`--", "   ") } else { ("|--", "|  ") };
+            writeln!(out, "{}{}{}", prefix, branch, step.detail).unwrap();
+            draw(steps, step.id, &format!("{}{}", prefix, indent), out);
+        }
+    }
+
+    let mut out = "QUERY PLAN\n".to_owned();
+    draw(steps, 0, "", &mut out);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{stats, steps, tree, Step};
+    use crate::interpreter::select_stmt;
+    use crate::{
+        format::DbFile,
+        interpreter::test_db::{conn, db},
+        syntax::{parse, SqlStmt},
+    };
+    use anyhow::Result;
+
+    fn step(id: i64, parent: i64, detail: &str) -> Step {
+        Step {
+            id,
+            parent,
+            detail: detail.to_owned(),
+        }
+    }
+
+    #[test]
+    fn draws_nested_steps() {
+        let steps = [
+            step(1, 0, "COMPOUND QUERY"),
+            step(2, 1, "LEFT-MOST SUBQUERY"),
+            step(3, 2, "SCAN t"),
+            step(4, 1, "UNION ALL"),
+            step(5, 4, "SCAN u"),
+            step(6, 0, "USE TEMP B-TREE FOR ORDER BY"),
+        ];
+        assert_eq!(
+            tree(&steps),
+            "QUERY PLAN\n\
+             |--COMPOUND QUERY\n\
+             |  |--LEFT-MOST SUBQUERY\n\
+             |  |  `--SCAN t\n\
+             |  `--UNION ALL\n\
+             |     `--SCAN u\n\
+             `--USE TEMP B-TREE FOR ORDER BY\n"
+        );
+    }
+
+    #[test]
+    fn shows_chosen_access_path() -> Result<()> {
+        let conn = conn()?;
+        for (sql, detail) in [
+            (
+                "SELECT name FROM t WHERE id = 2",
+                "SEARCH t USING INTEGER PRIMARY KEY (rowid=?)",
+            ),
+            (
+                "SELECT name FROM t WHERE id IN (2, 1) OR id = 3",
+                "SEARCH t USING INTEGER PRIMARY KEY (rowid=?)",
+            ),
+            ("SELECT name FROM t WHERE name = 'bob'", "SCAN t"),
+            ("SELECT name FROM t WHERE id = 1 OR name = 'bob'", "SCAN t"),
+            ("SELECT count(*) FROM t", "SCAN t"),
+        ] {
+            let sql = format!("EXPLAIN QUERY PLAN {}", sql);
+            let stmt = conn.prepare(&sql)?;
+            let rows = stmt.query()?.collect::<Result<Vec<_>>>()?;
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0].get_str("detail")?, detail);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shows_recursive_ctes_and_joins() -> Result<()> {
+        let db = db()?;
+        let select = match parse::sql_stmt(
+            "WITH RECURSIVE c(n) AS (SELECT id FROM t WHERE id = 1 \
+             UNION SELECT t.id FROM c JOIN t ON t.id = c.n) SELECT n FROM c",
+        )? {
+            SqlStmt::Select(select) => select,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            tree(&steps(&select, &db.schema)?),
+            "QUERY PLAN\n\
+             |--CO-ROUTINE c\n\
+             |  |--SETUP\n\
+             |  |  `--SEARCH t USING INTEGER PRIMARY KEY (rowid=?)\n\
+             |  `--RECURSIVE STEP\n\
+             |     |--SCAN c\n\
+             |     `--SEARCH t USING INTEGER PRIMARY KEY (rowid=?)\n\
+             `--SCAN c\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn shows_compound_queries() -> Result<()> {
+        let db = db()?;
+        let select = match parse::sql_stmt(
+            "SELECT id FROM t UNION ALL SELECT id FROM t WHERE id = 2 \
+             EXCEPT SELECT id FROM t ORDER BY 1 LIMIT 1",
+        )? {
+            SqlStmt::Select(select) => select,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            tree(&steps(&select, &db.schema)?),
+            "QUERY PLAN\n\
+             |--COMPOUND QUERY\n\
+             |  |--LEFT-MOST SUBQUERY\n\
+             |  |  `--SCAN t\n\
+             |  |--UNION ALL\n\
+             |  |  `--SEARCH t USING INTEGER PRIMARY KEY (rowid=?)\n\
+             |  `--EXCEPT USING TEMP B-TREE\n\
+             |     `--SCAN t\n\
+             `--USE TEMP B-TREE FOR ORDER BY\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn draws_row_counts() -> Result<()> {
+        let db = db()?;
+        let select = match parse::sql_stmt("SELECT name FROM t WHERE name != 'ann'")? {
+            SqlStmt::Select(select) => select,
+            _ => unreachable!(),
+        };
+        let root = select_stmt::plan(&select, &db.schema)?;
+        let file = DbFile::new(&db.bytes, &db.schema.db_header);
+        assert_eq!(root.open(file)?.count(), 1);
+        assert_eq!(
+            stats(&root),
+            "QUERY PLAN\n\
+             `--PROJECT (rows=1)\n   \
+                `--FILTER (rows=1)\n      \
+                   `--SCAN t (rows=2)\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,733 @@
+//! Loading CSV files into tables like sqlite3's `.import` does, down to the messages it prints
+//! about malformed rows. Rows that violate a constraint are reported and skipped.
+
+use crate::{
+    format::{DbFile, Enc},
+    interpreter::{
+        btree,
+        database::Database,
+        eval::Value,
+        output::OutputConfig,
+        row::Row,
+        write::{self, Pager},
+    },
+    schema::{Cols, DbSchema, KeyCol, ObjSchema},
+    syntax::{Affinity, OutputMode},
+    util::IterEither,
+};
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fs,
+};
+
+pub fn run(
+    db: &mut Database,
+    file: &str,
+    tbl: &str,
+    csv: bool,
+    skip: usize,
+    config: &OutputConfig,
+) -> Result<()> {
+    let (col_sep, row_sep) = separators(csv, config)?;
+    let csv = fs::read(file).map_err(|_| anyhow!("cannot open \"{}\"", file))?;
+    let mut reader = CsvReader::new(file, &csv, col_sep, row_sep);
+    for _ in 0..skip {
+        reader.read_row();
+    }
+    if find_obj(&db.schema, tbl).is_none() {
+        if tbl.len() >= 7 && tbl[..7].eq_ignore_ascii_case("sqlite_") {
+            bail!("object name reserved for internal use: {}", tbl);
+        }
+        let header = reader.read_row();
+        if header.is_empty() {
+            bail!("{}: empty file", file);
+        }
+        write::create_tbl(db, tbl, create_tbl_sql(file, tbl, header))?;
+    }
+
+    let bytes = {
+        let tbl = match find_obj(&db.schema, tbl) {
+            Some(obj) if obj.type_ == "table" => obj,
+            Some(obj) if obj.type_ == "view" => {
+                bail!("cannot modify {} because it is a view", obj.name)
+            }
+            Some(obj) => bail!("there is already an {} named {}", obj.type_, obj.name),
+            None => bail!("no such table: {}", tbl),
+        };
+        if db.schema.triggers().any(|t| t.tbl_name == tbl.name) {
+            bail!("Importing into tables with triggers is not supported");
+        }
+        // The parser skips AUTOINCREMENT, as reading a table doesn't depend on it.
+        if tbl
+            .sql
+            .as_deref()
+            .unwrap_or_default()
+            .to_ascii_uppercase()
+            .contains("AUTOINCREMENT")
+        {
+            bail!("Importing into AUTOINCREMENT tables is not supported");
+        }
+        // Which rows a partial index covers depends on its WHERE clause, which isn't evaluated.
+        for idx in db.schema.indexes().filter(|i| i.tbl_name == tbl.name) {
+            if idx.cols()?.is_partial() {
+                bail!("Importing into tables with partial indexes is not supported");
+            }
+        }
+        // The values of STORED columns would have to be computed from their expressions.
+        let cols = tbl.cols()?;
+        if cols.names().any(|c| cols.generated(c).is_some()) {
+            bail!("Importing into tables with generated columns is not supported");
+        }
+
+        let mut pager = Pager::new(db)?;
+        let mut rows = TblRows::load(tbl, &db.schema, &db.bytes)?;
+        let n_cols = cols.names().count();
+
+        loop {
+            let start_line = reader.line;
+            let mut fields = Vec::with_capacity(n_cols);
+            let mut i = 0;
+            while i < n_cols {
+                let field = reader.read_field();
+                if field.is_none() && i == 0 {
+                    break;
+                }
+                // RFC 4180 allows the last row to end without a row separator.
+                let field = match field {
+                    None if i == n_cols - 1 && i > 0 => Some(String::new()),
+                    field => field,
+                };
+                fields.push(field);
+                if i < n_cols - 1 && reader.term != Some(col_sep) {
+                    eprintln!(
+                        "{}:{}: expected {} columns but found {} - filling the rest with NULL",
+                        file,
+                        start_line,
+                        n_cols,
+                        i + 1
+                    );
+                    fields.resize(n_cols, None);
+                    i = n_cols;
+                    break;
+                }
+                i += 1;
+            }
+
+            if reader.term == Some(col_sep) {
+                while reader.term == Some(col_sep) {
+                    reader.read_field();
+                    i += 1;
+                }
+                eprintln!(
+                    "{}:{}: expected {} columns but found {} - extras ignored",
+                    file, start_line, n_cols, i
+                );
+            }
+            if i >= n_cols {
+                if let Err(e) = rows.insert(fields) {
+                    eprintln!("{}:{}: INSERT failed: {}", file, start_line, e);
+                }
+            }
+            if reader.term.is_none() {
+                break;
+            }
+        }
+
+        rows.write(&mut pager)?;
+        pager.finish(false)
+    };
+    db.commit(bytes)
+}
+
+/// The separators of fields and rows. Without `--csv` they are taken from the output mode.
+fn separators(csv: bool, config: &OutputConfig) -> Result<(u8, u8)> {
+    if csv {
+        return Ok((b',', b'\n'));
+    }
+
+    let col_sep = match config.col_sep.as_bytes() {
+        [] => bail!("non-null column separator required for import"),
+        [sep] => *sep,
+        _ => bail!("multi-character column separators not allowed for import"),
+    };
+    // CSV mode writes rows separated by CRLF, which reading handles as part of LF.
+    let row_sep = match config.row_sep.as_str() {
+        "\r\n" if config.mode == OutputMode::Csv => "\n",
+        sep => sep,
+    };
+    let row_sep = match row_sep.as_bytes() {
+        [] => bail!("non-null row separator required for import"),
+        [sep] => *sep,
+        _ => bail!("multi-character row separators not allowed for import"),
+    };
+    Ok((col_sep, row_sep))
+}
+
+/// Finds the table, view or index named `name`, which share a namespace. Like all names in
+/// SQL, it's compared case-insensitively.
+fn find_obj<'a>(db_schema: &'a DbSchema, name: &str) -> Option<&'a ObjSchema> {
+    db_schema
+        .objs
+        .iter()
+        .filter(|o| o.type_ != "trigger")
+        .find(|o| o.name.eq_ignore_ascii_case(name))
+}
+
+/// The CREATE statement of a table with a TEXT column for each field of the `header` row.
+/// Columns without name are called `?` and names that occur more than once get their position
+/// appended.
+fn create_tbl_sql(file: &str, tbl: &str, header: Vec<String>) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+    let names = header
+        .into_iter()
+        .map(|name| {
+            if name.is_empty() {
+                "?".to_owned()
+            } else {
+                name
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut renames = vec![];
+    let col_defs = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let is_dup = names
+                .iter()
+                .filter(|n| n.eq_ignore_ascii_case(name))
+                .count()
+                > 1;
+            if !is_dup {
+                return format!("{} TEXT", quote(name));
+            }
+            let renamed = format!("{}_{}", name, i + 1);
+            renames.push(format!("{} to {}", quote(name), quote(&renamed)));
+            format!("{} TEXT", quote(&renamed))
+        })
+        .collect::<Vec<_>>();
+
+    if !renames.is_empty() {
+        eprintln!(
+            "Columns renamed during .import {} due to duplicates:\n{}",
+            file,
+            renames.join(",\n")
+        );
+    }
+
+    // sqlite3 puts 4 columns on each line.
+    let col_defs = col_defs.chunks(4).map(|defs| defs.join(", ")).join(",\n ");
+    format!("CREATE TABLE {}(\n{})", quote(tbl), col_defs)
+}
+
+/// The rows of a table together with the keys of its unique indexes, which new rows are
+/// checked against before they are added.
+struct TblRows<'a> {
+    tbl: &'a ObjSchema,
+    cols: &'a Cols,
+    idxs: Vec<&'a ObjSchema>,
+    enc: Enc,
+    /// The records by rowid. The rows of WITHOUT ROWID tables are numbered in the order they
+    /// were added instead, as they're sorted by their primary key.
+    records: BTreeMap<i64, Vec<Value<'a>>>,
+    /// The keys of the unique indexes, which are folded by the collations of their columns.
+    unique_keys: Vec<(Vec<KeyCol>, BTreeSet<Vec<Value<'a>>>)>,
+}
+
+impl<'a> TblRows<'a> {
+    fn load(tbl: &'a ObjSchema, db_schema: &'a DbSchema, db: &'a [u8]) -> Result<Self> {
+        let cols = tbl.cols()?;
+        // The primary key of a WITHOUT ROWID table has no index of its own.
+        let idxs = db_schema
+            .indexes()
+            .filter(|i| i.tbl_name == tbl.name && i.rootpage != tbl.rootpage)
+            .collect::<Vec<_>>();
+        let mut unique = vec![];
+        for idx in &idxs {
+            let idx_cols = idx.cols()?;
+            if idx_cols.is_unique() {
+                unique.push((idx.autoindex_num(), idx_cols.key()));
+            }
+        }
+        if cols.is_without_rowid() {
+            // The primary key is numbered like the other automatic indexes, even though it has
+            // no entry in the schema table.
+            let pk_num = cols.pk_autoindex_num();
+            let pos = unique
+                .iter()
+                .position(|(num, _)| match (num, pk_num) {
+                    (Some(num), Some(pk_num)) => *num > pk_num,
+                    _ => true,
+                })
+                .unwrap_or(unique.len());
+            unique.insert(pos, (pk_num, cols.key()));
+        }
+        // Like SQLite, the indexes are checked in the reverse order of their creation.
+        let unique_keys = unique
+            .into_iter()
+            .rev()
+            .map(|(_, key)| {
+                (
+                    key.iter().map(|c| cols.collated(c)).collect(),
+                    BTreeSet::new(),
+                )
+            })
+            .collect();
+
+        let mut rows = Self {
+            tbl,
+            cols,
+            idxs,
+            enc: db_schema.db_header.text_encoding,
+            records: BTreeMap::new(),
+            unique_keys,
+        };
+
+        let db = DbFile::new(db, &db_schema.db_header);
+        let root = db.page(tbl.rootpage)?;
+        let existing = if cols.is_without_rowid() {
+            IterEither::left(btree::idx_seek(vec![], root, db).map_ok(Row::from))
+        } else {
+            IterEither::right(btree::full_tbl_scan(root, db).map_ok(Row::from))
+        };
+        for (i, row) in existing.enumerate() {
+            let row = row?;
+            let record = row
+                .record
+                .0
+                .iter()
+                .map(Value::try_from)
+                .collect::<Result<Vec<_>>>()?;
+            rows.add(row.row_id.unwrap_or(i as i64), record)?;
+        }
+        Ok(rows)
+    }
+
+    /// Inserts a row given the text of its columns in the order they were declared in. Like in
+    /// SQLite, the columns' affinity is applied to the text before the constraints are checked.
+    fn insert(&mut self, fields: Vec<Option<String>>) -> Result<()> {
+        let tbl = &self.tbl.name;
+        let cols = self.cols;
+        let mut record = vec![Value::Null; fields.len()];
+        let mut rowid = None;
+
+        for (name, field) in cols.names().zip(fields) {
+            let val = match field {
+                Some(text) => apply_affinity(text, cols.affinity(name)),
+                None => Value::Null,
+            };
+            if cols.is_int_pk(name) {
+                // The column is stored as the rowid, which is chosen automatically for NULL.
+                rowid = match val {
+                    Value::Int(x) => Some(x),
+                    Value::Null => None,
+                    _ => bail!("datatype mismatch"),
+                };
+            } else {
+                record[cols.record_pos(name)?] = val;
+            }
+        }
+
+        for name in cols.names() {
+            if cols.is_not_null(name)
+                && !cols.is_int_pk(name)
+                && record[cols.record_pos(name)?] == Value::Null
+            {
+                bail!("NOT NULL constraint failed: {}.{}", tbl, name);
+            }
+        }
+
+        let rowid = match rowid {
+            Some(rowid) if self.records.contains_key(&rowid) => {
+                let int_pk = cols.names().find(|name| cols.is_int_pk(name)).unwrap();
+                bail!("UNIQUE constraint failed: {}.{}", tbl, int_pk)
+            }
+            Some(rowid) => rowid,
+            None => match self.records.keys().next_back() {
+                Some(&i64::MAX) => bail!("database or disk is full"),
+                Some(max) => max + 1,
+                None => 1,
+            },
+        };
+
+        for (key_cols, keys) in &self.unique_keys {
+            let key = self.unique_key(rowid, &record, key_cols)?;
+            if !key.contains(&Value::Null) && keys.contains(&key) {
+                let cols = key_cols.iter().map(|c| format!("{}.{}", tbl, c.name));
+                bail!("UNIQUE constraint failed: {}", cols.format(", "));
+            }
+        }
+
+        self.add(rowid, record)
+    }
+
+    fn add(&mut self, rowid: i64, record: Vec<Value<'a>>) -> Result<()> {
+        let keys = self
+            .unique_keys
+            .iter()
+            .map(|(key_cols, _)| self.unique_key(rowid, &record, key_cols))
+            .collect::<Result<Vec<_>>>()?;
+        // Unlike other values, NULLs never equal each other, so any number of keys may contain
+        // them.
+        for ((_, keys), key) in self.unique_keys.iter_mut().zip(keys) {
+            if !key.contains(&Value::Null) {
+                keys.insert(key);
+            }
+        }
+        self.records.insert(rowid, record);
+        Ok(())
+    }
+
+    /// The values of `key_cols` in a row.
+    fn key(&self, rowid: i64, record: &[Value<'a>], key_cols: &[KeyCol]) -> Result<Vec<Value<'a>>> {
+        let cols = self.cols;
+        key_cols
+            .iter()
+            .map(|c| match cols.is_int_pk(&c.name) {
+                true => Ok(Value::Int(rowid)),
+                // Records written before a column was added lack it.
+                false => Ok(record
+                    .get(cols.record_pos(&c.name)?)
+                    .cloned()
+                    .unwrap_or(Value::Null)),
+            })
+            .collect()
+    }
+
+    /// The values of `key_cols` in a row with their text folded by the collations of the
+    /// columns, so that keys that compare equal are equal.
+    fn unique_key(
+        &self,
+        rowid: i64,
+        record: &[Value<'a>],
+        key_cols: &[KeyCol],
+    ) -> Result<Vec<Value<'a>>> {
+        Ok(self
+            .key(rowid, record, key_cols)?
+            .into_iter()
+            .zip(key_cols)
+            .map(|(val, col)| match val {
+                Value::String(s) => {
+                    let folded = btree::fold_text(&s, col.collation.as_deref()).into_owned();
+                    Value::String(Cow::Owned(folded))
+                }
+                val => val,
+            })
+            .collect())
+    }
+
+    /// Rebuilds the B-trees of the table and all of its indexes, whose entries are sorted by the
+    /// collations of their columns.
+    fn write(&self, pager: &mut Pager) -> Result<()> {
+        let cols = self.cols;
+        let pk = cols
+            .key()
+            .iter()
+            .map(|c| cols.collated(c))
+            .collect::<Vec<_>>();
+
+        if cols.is_without_rowid() {
+            // The records of WITHOUT ROWID tables start with the primary key.
+            let mut records = self.records.values().collect::<Vec<_>>();
+            records.sort_by(|a, b| btree::cmp_keys(&a[..pk.len()], &b[..pk.len()], &pk, self.enc));
+            let records = records
+                .into_iter()
+                .map(|r| pager.encode_record(r))
+                .collect::<Vec<_>>();
+            pager.build_idx(self.tbl.rootpage as u32, records)?;
+        } else {
+            let rows = self
+                .records
+                .iter()
+                .map(|(rowid, r)| (*rowid, pager.encode_record(r)))
+                .collect::<Vec<_>>();
+            pager.build_tbl(self.tbl.rootpage as u32, rows)?;
+        }
+
+        for idx in &self.idxs {
+            // Instead of a rowid, the entries of a WITHOUT ROWID table's indexes end with the
+            // primary key columns that aren't already part of them.
+            let idx_key = idx.cols()?.key();
+            let key_cols = idx_key
+                .iter()
+                .chain(
+                    pk.iter()
+                        .filter(|c| !idx_key.iter().any(|k| k.name == c.name)),
+                )
+                .map(|c| cols.collated(c))
+                .collect::<Vec<_>>();
+
+            let mut entries = self
+                .records
+                .iter()
+                .map(|(rowid, record)| {
+                    let mut entry = self.key(*rowid, record, &key_cols)?;
+                    if !cols.is_without_rowid() {
+                        entry.push(Value::Int(*rowid));
+                    }
+                    Ok(entry)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            entries.sort_by(|a, b| btree::cmp_keys(a, b, &key_cols, self.enc));
+            let entries = entries
+                .iter()
+                .map(|e| pager.encode_record(e))
+                .collect::<Vec<_>>();
+            pager.build_idx(idx.rootpage as u32, entries)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts text the way SQLite does when storing it in a column with the given affinity as
+/// described here: [Type Affinity](https://www.sqlite.org/datatype3.html#type_affinity)
+fn apply_affinity(text: String, affinity: Affinity) -> Value<'static> {
+    match affinity {
+        Affinity::Numeric | Affinity::Integer | Affinity::Real => {
+            to_number(&text).unwrap_or(Value::String(Cow::Owned(text)))
+        }
+        Affinity::Text | Affinity::Blob => Value::String(Cow::Owned(text)),
+    }
+}
+
+/// Parses text that is a number apart from surrounding whitespace. Numbers without fractional
+/// part become integers if they fit, which is how SQLite stores them even in REAL columns.
+fn to_number(text: &str) -> Option<Value<'static>> {
+    let s = text.trim_matches(|c| matches!(c, ' ' | '\t' | '\n' | '\x0b' | '\x0c' | '\r'));
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let unsigned = s.strip_prefix(&['+', '-'][..]).unwrap_or(s);
+    let (mantissa, exp) = match unsigned.split_once(&['e', 'E'][..]) {
+        Some((mantissa, exp)) => (
+            mantissa,
+            Some(exp.strip_prefix(&['+', '-'][..]).unwrap_or(exp)),
+        ),
+        None => (unsigned, None),
+    };
+    let (int, frac) = match mantissa.split_once('.') {
+        Some((int, frac)) => (int, Some(frac)),
+        None => (mantissa, None),
+    };
+
+    let is_number = digits(int)
+        && frac.map_or(true, digits)
+        && !(int.is_empty() && frac.map_or(true, str::is_empty))
+        && exp.map_or(true, |e| !e.is_empty() && digits(e));
+    if !is_number {
+        return None;
+    }
+
+    if frac.is_none() && exp.is_none() {
+        if let Ok(x) = s.parse() {
+            return Some(Value::Int(x));
+        }
+    }
+    let r = s.parse::<f64>().ok()?;
+    // The bounds are -2^63 and 2^63, which are the closest floats to i64's bounds.
+    Some(
+        if r.fract() == 0.0 && r > i64::MIN as f64 && r < i64::MAX as f64 {
+            Value::Int(r as i64)
+        } else {
+            Value::Float(r)
+        },
+    )
+}
+
+/// Reads the fields of a CSV file one by one. Quoted fields may contain separators as well as
+/// quotes, which are escaped by doubling them. This is a port of sqlite3's
+/// `csv_read_one_field()`, which is lenient about malformed quoting.
+struct CsvReader<'a> {
+    file: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    col_sep: u8,
+    row_sep: u8,
+    /// The line the next field starts on.
+    line: usize,
+    /// The separator the last field ended at, which is `None` at the end of the file.
+    term: Option<u8>,
+}
+
+impl<'a> CsvReader<'a> {
+    fn new(file: &'a str, bytes: &'a [u8], col_sep: u8, row_sep: u8) -> Self {
+        // A UTF-8 byte order mark at the start of the file is skipped.
+        let pos = if bytes.starts_with(b"\xef\xbb\xbf") {
+            3
+        } else {
+            0
+        };
+        Self {
+            file,
+            bytes,
+            pos,
+            col_sep,
+            row_sep,
+            line: 1,
+            term: None,
+        }
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        let b = self.bytes.get(self.pos).copied();
+        self.pos += 1;
+        b
+    }
+
+    /// Reads the fields of the next row, which is empty at the end of the file.
+    fn read_row(&mut self) -> Vec<String> {
+        let mut fields = vec![];
+        while let Some(field) = self.read_field() {
+            fields.push(field);
+            if self.term != Some(self.col_sep) {
+                break;
+            }
+        }
+        fields
+    }
+
+    /// Reads the next field, or `None` at the end of the file.
+    fn read_field(&mut self) -> Option<String> {
+        const QUOTE: Option<u8> = Some(b'"');
+        let c = self.next_byte();
+        if c.is_none() {
+            self.term = None;
+            return None;
+        }
+
+        let mut field = vec![];
+        if c == QUOTE {
+            let start_line = self.line;
+            let (mut pc, mut ppc) = (None, None);
+            loop {
+                let c = self.next_byte();
+                if c == Some(self.row_sep) {
+                    self.line += 1;
+                }
+                if c == QUOTE && pc == QUOTE {
+                    pc = None;
+                    continue;
+                }
+                // A closing quote is only recognized in front of a separator, so everything
+                // after it has been added to the field so far.
+                if (pc == QUOTE
+                    && (c == Some(self.col_sep) || c == Some(self.row_sep) || c.is_none()))
+                    || (c == Some(self.row_sep) && pc == Some(b'\r') && ppc == QUOTE)
+                {
+                    let end = field.iter().rposition(|&b| b == b'"').unwrap();
+                    field.truncate(end);
+                    self.term = c;
+                    break;
+                }
+                if pc == QUOTE && c != Some(b'\r') {
+                    eprintln!("{}:{}: unescaped \" character", self.file, self.line);
+                }
+                match c {
+                    Some(c) => {
+                        field.push(c);
+                        ppc = pc;
+                        pc = Some(c);
+                    }
+                    None => {
+                        eprintln!("{}:{}: unterminated \"-quoted field", self.file, start_line);
+                        self.term = None;
+                        break;
+                    }
+                }
+            }
+        } else {
+            let mut c = c;
+            while let Some(b) = c.filter(|&b| b != self.col_sep && b != self.row_sep) {
+                field.push(b);
+                c = self.next_byte();
+            }
+            if c == Some(self.row_sep) {
+                self.line += 1;
+                if field.last() == Some(&b'\r') {
+                    field.pop();
+                }
+            }
+            self.term = c;
+        }
+        Some(String::from_utf8_lossy(&field).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{create_tbl_sql, to_number, CsvReader};
+    use crate::interpreter::eval::Value;
+
+    fn read_rows(csv: &str) -> Vec<Vec<String>> {
+        let mut reader = CsvReader::new("test.csv", csv.as_bytes(), b',', b'\n');
+        let mut rows = vec![];
+        loop {
+            let row = reader.read_row();
+            if row.is_empty() {
+                return rows;
+            }
+            rows.push(row);
+        }
+    }
+
+    #[test]
+    fn reads_quoted_fields() {
+        assert_eq!(
+            read_rows("a,\"b,\"\"c\"\"\"\r\n\"multi\nline\",\n"),
+            [vec!["a", "b,\"c\""], vec!["multi\nline", ""]]
+        );
+    }
+
+    #[test]
+    fn keeps_malformed_quotes() {
+        assert_eq!(read_rows("\"a\"b,c\"\n"), [vec!["a\"b,c"]]);
+        assert_eq!(read_rows("a\"b,c"), [vec!["a\"b", "c"]]);
+    }
+
+    #[test]
+    fn skips_bom_and_counts_lines() {
+        let csv = "\u{feff}a\n\"b\nc\"\nd";
+        let mut reader = CsvReader::new("test.csv", csv.as_bytes(), b',', b'\n');
+        assert_eq!(reader.read_row(), ["a"]);
+        assert_eq!(reader.read_row(), ["b\nc"]);
+        assert_eq!(reader.line, 4);
+        assert_eq!(reader.read_row(), ["d"]);
+        assert_eq!(reader.term, None);
+    }
+
+    #[test]
+    fn converts_numeric_text() {
+        let int = |s| match to_number(s) {
+            Some(Value::Int(x)) => Some(x),
+            _ => None,
+        };
+        assert_eq!(int(" 7 "), Some(7));
+        assert_eq!(int("-0.0"), Some(0));
+        assert_eq!(int("1."), Some(1));
+        assert_eq!(int("1e+2"), Some(100));
+        assert_eq!(int("-9223372036854775808"), Some(i64::MIN));
+        assert!(matches!(to_number(".5"), Some(Value::Float(x)) if x == 0.5));
+        assert!(matches!(
+            to_number("9.223372036854775807e18"),
+            Some(Value::Float(_))
+        ));
+        assert!(matches!(
+            to_number("99999999999999999999"),
+            Some(Value::Float(_))
+        ));
+        for s in ["", ".", "5e", "12abc", "0x10", "e5", "1e+", "inf"] {
+            assert!(to_number(s).is_none(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn creates_tbl_with_text_cols() {
+        assert_eq!(
+            create_tbl_sql("f.csv", "t", ["a", "A", "", "b\"", "c"].iter().map(|s| s.to_string()).collect()),
+            "CREATE TABLE \"t\"(\n\"a_1\" TEXT, \"A_2\" TEXT, \"?\" TEXT, \"b\"\"\" TEXT,\n \"c\" TEXT)"
+        );
+    }
+}
@@ -0,0 +1,1058 @@
+//! Verifying a database file the way `PRAGMA integrity_check` does in SQLite. Every B-tree and
+//! the freelist are walked page by page, and the index entries are compared with the rows of
+//! their tables. Problems are reported with SQLite's messages and in the order it finds them,
+//! so that the output of both can be compared.
+
+use crate::{
+    format::{varint, Enc, PageType, Record},
+    interpreter::{btree, database::Database, eval::Value},
+    schema::{Cols, DbSchema, KeyCol, ObjSchema},
+};
+use anyhow::Result;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    fmt,
+};
+
+/// The number of problems that are reported unless a different limit is given.
+pub const MAX_ERRORS: usize = 100;
+
+/// SQLite never stores anything in the page holding the byte at this offset, which is used for
+/// locking on some systems.
+const PENDING_BYTE: usize = 0x4000_0000;
+
+/// The contents of pages beyond the end of the file, which SQLite reads as zeros.
+static ZEROS: [u8; 65536] = [0; 65536];
+
+/// What is checked and how many problems are reported at most.
+pub struct CheckOpts<'a> {
+    /// Only checks the B-trees of this table and its indexes instead of the whole database.
+    pub tbl: Option<&'a ObjSchema>,
+    pub max_errors: usize,
+    /// Skips looking up the rows of tables in their indexes, which takes most of the time.
+    pub quick: bool,
+}
+
+/// Checks a database and returns the problems found, or just "ok" if there are none. Those in the
+/// structure of its B-trees are reported together first.
+pub fn check(database: &Database, opts: &CheckOpts) -> Result<Vec<String>> {
+    let db_schema = &database.schema;
+    let mut ck = Checker::new(&database.bytes, db_schema);
+
+    ck.max_errors = opts.max_errors;
+    if opts.tbl.is_none() {
+        let pending_page = (PENDING_BYTE / ck.page_size + 1) as u32;
+        if pending_page <= ck.page_count {
+            ck.referenced[pending_page as usize] = true;
+        }
+        let h = &db_schema.db_header;
+        ck.check_list(
+            Pfx::Freelist,
+            true,
+            h.first_freelist_page,
+            h.freelist_page_count,
+        );
+    }
+
+    // SQLite visits the tables in the order of its hash table of them, which is the reverse of
+    // the order they were created in as long as there are only a few of them. The schema table
+    // is created before all others.
+    let mut tbls = match opts.tbl {
+        Some(tbl) => vec![tbl],
+        None => db_schema.tables().collect(),
+    };
+    tbls.reverse();
+    let checked = tbls
+        .into_iter()
+        .map(|tbl| {
+            let cols = tbl.cols()?;
+            let tree = match cols.is_without_rowid() {
+                false => Some(ck.check_tree(tbl.rootpage, true)),
+                true => None,
+            };
+            let idxs = indexes(db_schema, tbl)?
+                .into_iter()
+                .map(|idx| {
+                    let tree = ck.check_tree(idx.root, false);
+                    (idx, tree)
+                })
+                .collect::<Vec<_>>();
+            Ok((tbl, tree, idxs))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if opts.tbl.is_none() {
+        ck.check_tree(1, true);
+        for page in 1..=ck.page_count {
+            if !ck.referenced[page as usize] {
+                ck.report(Pfx::None, format!("Page {}: never used", page));
+            }
+        }
+    }
+    let n_tree_errors = ck.msgs.len();
+
+    for (_, tree, idxs) in &checked {
+        let n_rows = match tree {
+            Some(tree) => tree.n_entries,
+            None => idxs
+                .iter()
+                .find(|(i, _)| i.is_pk())
+                .map_or(0, |(_, t)| t.n_entries),
+        };
+        for (idx, idx_tree) in idxs {
+            if !idx.is_partial() && idx_tree.n_entries != n_rows {
+                ck.report(
+                    Pfx::None,
+                    format!("wrong # of entries in index {}", idx.name),
+                );
+            }
+        }
+    }
+
+    for (tbl, tree, idxs) in &checked {
+        let tree = match tree {
+            Some(tree) => tree,
+            None => match idxs.iter().find(|(i, _)| i.is_pk()) {
+                Some((_, tree)) => tree,
+                None => continue,
+            },
+        };
+        // The rows of a B-tree that is broken itself can't be told apart from garbage.
+        if !tree.ok {
+            continue;
+        }
+        ck.check_rows(tbl, tree, idxs, opts.quick)?;
+    }
+
+    let mut msgs = ck.msgs.split_off(n_tree_errors);
+    if n_tree_errors > 0 {
+        let tree_errors = ck.msgs.join("\n");
+        msgs.insert(0, format!("*** in database main ***\n{}", tree_errors));
+    }
+    if msgs.is_empty() {
+        msgs.push("ok".to_owned());
+    }
+    Ok(msgs)
+}
+
+/// An index of a table. The primary key of a WITHOUT ROWID table is the table's B-tree itself,
+/// so it has no entry in the schema table.
+struct Idx<'a> {
+    name: Cow<'a, str>,
+    root: i32,
+    /// The columns of an index in the schema table.
+    cols: Option<&'a Cols>,
+}
+
+impl Idx<'_> {
+    fn is_pk(&self) -> bool {
+        self.cols.is_none()
+    }
+
+    fn is_partial(&self) -> bool {
+        self.cols.map_or(false, Cols::is_partial)
+    }
+
+    fn is_unique(&self) -> bool {
+        self.cols.map_or(true, Cols::is_unique)
+    }
+}
+
+/// The indexes of a table in the order SQLite keeps them in, which starts with the most recently
+/// created one.
+fn indexes<'a>(db_schema: &'a DbSchema, tbl: &'a ObjSchema) -> Result<Vec<Idx<'a>>> {
+    let mut idxs = db_schema
+        .indexes()
+        .filter(|i| i.tbl_name == tbl.name && i.rootpage != tbl.rootpage)
+        .map(|i| {
+            let idx = Idx {
+                name: Cow::Borrowed(i.name.as_str()),
+                root: i.rootpage,
+                cols: Some(i.cols()?),
+            };
+            Ok((i.autoindex_num(), idx))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(pk_num) = tbl.cols()?.pk_autoindex_num() {
+        let pos = idxs
+            .iter()
+            .position(|(num, _)| num.map_or(true, |num| num > pk_num))
+            .unwrap_or(idxs.len());
+        let pk = Idx {
+            name: Cow::Owned(format!("sqlite_autoindex_{}_{}", tbl.name, pk_num)),
+            root: tbl.rootpage,
+            cols: None,
+        };
+        idxs.insert(pos, (Some(pk_num), pk));
+    }
+
+    Ok(idxs.into_iter().rev().map(|(_, idx)| idx).collect())
+}
+
+/// Where a problem was found, which its message starts with.
+#[derive(Debug, Clone, Copy)]
+enum Pfx {
+    None,
+    Freelist,
+    Page { root: u32, page: u32 },
+    Cell { root: u32, page: u32, cell: usize },
+}
+
+impl fmt::Display for Pfx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pfx::None => Ok(()),
+            Pfx::Freelist => write!(f, "Freelist: "),
+            Pfx::Page { root, page } => write!(f, "Tree {} page {}: ", root, page),
+            Pfx::Cell { root, page, cell } => {
+                write!(f, "Tree {} page {} cell {}: ", root, page, cell)
+            }
+        }
+    }
+}
+
+/// A B-tree together with what was found while walking it.
+struct Tree<'a> {
+    root: u32,
+    /// The number of entries, which includes the cells of interior index pages.
+    n_entries: usize,
+    /// The rowids and records of the entries that could be read in the order of the B-tree.
+    /// Records whose overflow pages are broken are missing.
+    entries: Vec<(i64, Option<Cow<'a, [u8]>>)>,
+    /// The pages of an index B-tree that could be read, which are needed to look up entries.
+    nodes: HashMap<u32, Node>,
+    /// Whether the B-tree can be read, which only problems with the layout of its pages
+    /// allow.
+    ok: bool,
+}
+
+/// A page of an index B-tree with the child page and entry of each of its cells. The child
+/// pages of leaves are 0.
+struct Node {
+    cells: Vec<(u32, usize)>,
+    right: u32,
+}
+
+/// The sizes of a cell and its payload, which may partly be stored on overflow pages.
+struct CellInfo {
+    /// The rowid of table cells.
+    key: i64,
+    /// The offset of the payload within the cell.
+    header: usize,
+    payload: u64,
+    local: u64,
+    size: u64,
+}
+
+struct Checker<'a> {
+    bytes: &'a [u8],
+    page_size: usize,
+    usable_size: usize,
+    enc: Enc,
+    page_count: u32,
+    referenced: Vec<bool>,
+    msgs: Vec<String>,
+    /// The number of reported problems with the cells and free blocks of pages, which don't
+    /// keep their B-trees from being read.
+    n_layout_errors: usize,
+    max_errors: usize,
+}
+
+impl<'a> Checker<'a> {
+    fn new(bytes: &'a [u8], db_schema: &DbSchema) -> Self {
+        let h = &db_schema.db_header;
+        let page_count = h.page_count(bytes.len());
+        Self {
+            bytes,
+            page_size: h.page_size as usize,
+            usable_size: h.usable_size(),
+            enc: h.text_encoding,
+            page_count,
+            referenced: vec![false; page_count as usize + 1],
+            msgs: vec![],
+            n_layout_errors: 0,
+            max_errors: MAX_ERRORS,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.msgs.len() >= self.max_errors
+    }
+
+    fn report(&mut self, pfx: Pfx, msg: impl fmt::Display) {
+        if !self.is_full() {
+            self.msgs.push(format!("{}{}", pfx, msg));
+        }
+    }
+
+    fn report_layout(&mut self, msg: String) {
+        if !self.is_full() {
+            self.n_layout_errors += 1;
+        }
+        self.report(Pfx::None, msg);
+    }
+
+    fn page(&self, page: u32) -> &'a [u8] {
+        let offset = (page as usize - 1) * self.page_size;
+        self.bytes
+            .get(offset..offset + self.page_size)
+            .unwrap_or(&ZEROS[..self.page_size])
+    }
+
+    /// Marks a page as used and reports if it doesn't exist or has been used before. Returns
+    /// whether the page can't be used.
+    fn check_ref(&mut self, pfx: Pfx, page: u32) -> bool {
+        if page == 0 || page > self.page_count {
+            self.report(pfx, format!("invalid page number {}", page));
+            return true;
+        }
+        if self.referenced[page as usize] {
+            self.report(pfx, format!("2nd reference to page {}", page));
+            return true;
+        }
+        self.referenced[page as usize] = true;
+        false
+    }
+
+    /// Checks a list of overflow pages or the freelist, whose trunk pages list the pages they
+    /// hold, against the number of pages it should have.
+    fn check_list(&mut self, pfx: Pfx, is_freelist: bool, first: u32, expected: u32) {
+        let n_errors = self.msgs.len();
+        let mut n = i64::from(expected);
+        let mut page = first;
+        while page != 0 && !self.is_full() {
+            if self.check_ref(pfx, page) {
+                break;
+            }
+            n -= 1;
+            let data = self.page(page);
+            if is_freelist {
+                let n_leaves = u32_at(data, 4);
+                if n_leaves as usize > self.usable_size / 4 - 2 {
+                    self.report(pfx, format!("freelist leaf count too big on page {}", page));
+                    n -= 1;
+                } else {
+                    for i in 0..n_leaves as usize {
+                        self.check_ref(pfx, u32_at(data, 8 + i * 4));
+                    }
+                    n -= i64::from(n_leaves);
+                }
+            }
+            page = u32_at(data, 0);
+        }
+        if n != 0 && self.msgs.len() == n_errors {
+            let what = if is_freelist {
+                "size"
+            } else {
+                "overflow list length"
+            };
+            let found = i64::from(expected) - n;
+            self.report(
+                pfx,
+                format!("{} is {} but should be {}", what, found, expected),
+            );
+        }
+    }
+
+    fn check_tree(&mut self, root: i32, is_table: bool) -> Tree<'a> {
+        let n_errors = self.msgs.len() - self.n_layout_errors;
+        let mut tree = Tree {
+            root: root.max(0) as u32,
+            n_entries: 0,
+            entries: vec![],
+            nodes: HashMap::new(),
+            ok: true,
+        };
+        if tree.root > 0 {
+            self.check_page(
+                tree.root,
+                tree.root,
+                Pfx::None,
+                is_table,
+                i64::MAX,
+                &mut tree,
+            );
+        }
+        // The pages are walked from right to left.
+        tree.entries.reverse();
+        let n = tree.entries.len();
+        for node in tree.nodes.values_mut() {
+            for (_, entry) in &mut node.cells {
+                *entry = n - 1 - *entry;
+            }
+        }
+        tree.ok = self.msgs.len() - self.n_layout_errors == n_errors;
+        tree
+    }
+
+    /// Checks a page of a B-tree and its children, whose rowids must be less than `max_key`.
+    /// Returns the depth of the page and the smallest rowid found.
+    fn check_page(
+        &mut self,
+        root: u32,
+        page: u32,
+        pfx: Pfx,
+        is_table: bool,
+        max_key: i64,
+        tree: &mut Tree<'a>,
+    ) -> (i32, i64) {
+        if self.check_ref(pfx, page) {
+            return (0, max_key);
+        }
+        let data = self.page(page);
+        let hdr = if page == 1 { 100 } else { 0 };
+        if let Err(msg) = self.init_page(data, hdr, is_table) {
+            self.report(Pfx::Page { root, page }, msg);
+            return (0, max_key);
+        }
+
+        let is_leaf = data[hdr] & 8 != 0;
+        let n_cells = usize::from(u16_at(data, hdr + 3));
+        let content_start = match u16_at(data, hdr + 5) {
+            0 => 65536,
+            x => usize::from(x),
+        };
+        if is_leaf || !is_table {
+            tree.n_entries += n_cells;
+        }
+        let cell_ptrs = hdr + if is_leaf { 8 } else { 12 };
+
+        // The children of a page have to be as deep as each other. Its right-most child is
+        // checked first, as the rowids are checked from right to left.
+        let mut depth = -1;
+        let mut max_key = max_key;
+        let mut key_can_be_equal = true;
+        if !is_leaf {
+            let cell = match pfx {
+                Pfx::Cell { cell, .. } => cell,
+                _ => 0,
+            };
+            let child_pfx = Pfx::Cell { root, page, cell };
+            let child = u32_at(data, hdr + 8);
+            let (child_depth, child_max_key) =
+                self.check_page(root, child, child_pfx, is_table, max_key, tree);
+            depth = child_depth;
+            max_key = child_max_key;
+            key_can_be_equal = false;
+        }
+
+        let mut node = Node {
+            cells: vec![],
+            right: if is_leaf { 0 } else { u32_at(data, hdr + 8) },
+        };
+        let mut covered = vec![];
+        let mut check_coverage = true;
+        for i in (0..n_cells).rev() {
+            if self.is_full() {
+                break;
+            }
+            let pfx = Pfx::Cell {
+                root,
+                page,
+                cell: i,
+            };
+            let pc = usize::from(u16_at(data, cell_ptrs + 2 * i));
+            if pc < content_start || pc > self.usable_size - 4 {
+                let msg = format!(
+                    "Offset {} out of range {}..{}",
+                    pc,
+                    content_start,
+                    self.usable_size - 4
+                );
+                self.report(pfx, msg);
+                check_coverage = false;
+                continue;
+            }
+            let info = self.parse_cell(&data[pc..], is_leaf, is_table);
+            if pc as u64 + info.size > self.usable_size as u64 {
+                self.report(pfx, "Extends off end of page");
+                check_coverage = false;
+                continue;
+            }
+
+            if is_table {
+                let out_of_order = match key_can_be_equal {
+                    true => info.key > max_key,
+                    false => info.key >= max_key,
+                };
+                if out_of_order {
+                    self.report(pfx, format!("Rowid {} out of order", info.key));
+                }
+                max_key = info.key;
+                key_can_be_equal = false;
+            }
+
+            let local = &data[pc + info.header..pc + info.header + info.local as usize];
+            let payload = if info.payload > info.local {
+                let n_errors = self.msgs.len();
+                let usable = self.usable_size as u64;
+                let n_pages = (info.payload - info.local + usable - 5) / (usable - 4);
+                let first = u32_at(data, pc + info.size as usize - 4);
+                self.check_list(pfx, false, first, n_pages as u32);
+                if self.msgs.len() == n_errors {
+                    Some(Cow::Owned(self.read_overflow(local, info.payload, first)))
+                } else {
+                    None
+                }
+            } else {
+                Some(Cow::Borrowed(local))
+            };
+            if is_leaf || !is_table {
+                tree.entries.push((info.key, payload));
+            }
+            if !is_table {
+                let child = if is_leaf { 0 } else { u32_at(data, pc) };
+                node.cells.push((child, tree.entries.len() - 1));
+            }
+
+            if !is_leaf {
+                let child = u32_at(data, pc);
+                let (child_depth, child_max_key) =
+                    self.check_page(root, child, pfx, is_table, max_key, tree);
+                max_key = child_max_key;
+                key_can_be_equal = false;
+                if child_depth != depth {
+                    self.report(pfx, "Child page depth differs");
+                    depth = child_depth;
+                }
+            } else {
+                covered.push((pc << 16) | (pc + info.size as usize - 1));
+            }
+        }
+        let min_key = max_key;
+        if !is_table {
+            node.cells.reverse();
+            tree.nodes.insert(page, node);
+        }
+
+        if check_coverage && !self.is_full() {
+            self.check_coverage(page, data, hdr, is_leaf, is_table, content_start, covered);
+        }
+        (depth + 1, min_key)
+    }
+
+    /// Checks the header of a page the way SQLite does before it reads any of its cells.
+    fn init_page(&self, data: &[u8], hdr: usize, is_table: bool) -> Result<(), &'static str> {
+        const CORRUPT: &str = "btreeInitPage() returns error code 11";
+        let page_type = match data[hdr] {
+            2 => PageType::IntrIdx,
+            5 => PageType::IntrTbl,
+            10 => PageType::LeafIdx,
+            13 => PageType::LeafTbl,
+            _ => return Err(CORRUPT),
+        };
+        // A page has to be of the same kind as the B-tree it's part of.
+        if matches!(page_type, PageType::IntrTbl | PageType::LeafTbl) != is_table {
+            return Err(CORRUPT);
+        }
+        let is_leaf = matches!(page_type, PageType::LeafTbl | PageType::LeafIdx);
+        let n_cells = usize::from(u16_at(data, hdr + 3));
+        if n_cells > (self.page_size - 8) / 6 {
+            return Err(CORRUPT);
+        }
+
+        const FREE_SPACE_CORRUPT: &str = "free space corruption";
+        let usable = self.usable_size;
+        let top = match u16_at(data, hdr + 5) {
+            0 => 65536,
+            x => usize::from(x),
+        };
+        let first_cell = hdr + 8 + if is_leaf { 0 } else { 4 } + 2 * n_cells;
+        let mut n_free = usize::from(data[hdr + 7]) + top;
+        let mut pc = usize::from(u16_at(data, hdr + 1));
+        if pc > 0 {
+            // Free blocks follow the cells, ordered by their offset.
+            if pc < top {
+                return Err(FREE_SPACE_CORRUPT);
+            }
+            loop {
+                if pc > usable - 4 {
+                    return Err(FREE_SPACE_CORRUPT);
+                }
+                let next = usize::from(u16_at(data, pc));
+                let size = usize::from(u16_at(data, pc + 2));
+                n_free += size;
+                if next <= pc + size + 3 {
+                    if next > 0 || pc + size > usable {
+                        return Err(FREE_SPACE_CORRUPT);
+                    }
+                    break;
+                }
+                pc = next;
+            }
+        }
+        if n_free > usable || n_free < first_cell {
+            return Err(FREE_SPACE_CORRUPT);
+        }
+        Ok(())
+    }
+
+    /// Decodes the header of a cell as described here:
+    /// [B-tree Pages](https://www.sqlite.org/fileformat2.html#b_tree_pages)
+    fn parse_cell(&self, cell: &[u8], is_leaf: bool, is_table: bool) -> CellInfo {
+        let usable = self.usable_size as u64;
+        if is_table && !is_leaf {
+            let (key, n) = varint::parse(cell.get(4..).unwrap_or_default());
+            return CellInfo {
+                key,
+                header: 4 + n,
+                payload: 0,
+                local: 0,
+                size: 4 + n as u64,
+            };
+        }
+
+        let mut header = if is_leaf { 0 } else { 4 };
+        let (payload, n) = varint::parse(cell.get(header..).unwrap_or_default());
+        header += n;
+        let mut key = 0;
+        if is_table {
+            let (rowid, n) = varint::parse(cell.get(header..).unwrap_or_default());
+            key = rowid;
+            header += n;
+        }
+
+        let payload = payload as u64 & u64::from(u32::MAX);
+        let max_local = if is_table {
+            usable - 35
+        } else {
+            (usable - 12) * 64 / 255 - 23
+        };
+        let min_local = (usable - 12) * 32 / 255 - 23;
+        if payload <= max_local {
+            return CellInfo {
+                key,
+                header,
+                payload,
+                local: payload,
+                size: (header as u64 + payload).max(4),
+            };
+        }
+        let surplus = min_local + (payload - min_local) % (usable - 4);
+        let local = if surplus <= max_local {
+            surplus
+        } else {
+            min_local
+        };
+        CellInfo {
+            key,
+            header,
+            payload,
+            local,
+            size: header as u64 + local + 4,
+        }
+    }
+
+    /// Reads the part of a payload that is stored on overflow pages. The list of those pages has
+    /// been checked before.
+    fn read_overflow(&self, local: &[u8], size: u64, first: u32) -> Vec<u8> {
+        let mut payload = local.to_vec();
+        let mut page = first;
+        while (payload.len() as u64) < size && page != 0 {
+            let data = self.page(page);
+            let n = (size - payload.len() as u64).min(self.usable_size as u64 - 4) as usize;
+            payload.extend_from_slice(&data[4..4 + n]);
+            page = u32_at(data, 0);
+        }
+        payload
+    }
+
+    /// Checks that the cells and free blocks of a page don't overlap and that the bytes left
+    /// between them add up to the fragmented bytes in the page header.
+    #[allow(clippy::too_many_arguments)]
+    fn check_coverage(
+        &mut self,
+        page: u32,
+        data: &[u8],
+        hdr: usize,
+        is_leaf: bool,
+        is_table: bool,
+        content_start: usize,
+        mut covered: Vec<usize>,
+    ) {
+        // The ranges are packed into a single number like in SQLite, which sorts them by their
+        // start and then their end.
+        if !is_leaf {
+            let cell_ptrs = hdr + 12;
+            let n_cells = usize::from(u16_at(data, hdr + 3));
+            for i in (0..n_cells).rev() {
+                let pc = usize::from(u16_at(data, cell_ptrs + 2 * i));
+                let size = self.parse_cell(&data[pc..], is_leaf, is_table).size as usize;
+                covered.push((pc << 16) | (pc + size - 1));
+            }
+        }
+        let mut free_block = usize::from(u16_at(data, hdr + 1));
+        while free_block > 0 {
+            let size = usize::from(u16_at(data, free_block + 2));
+            covered.push((free_block << 16) | (free_block + size - 1));
+            free_block = usize::from(u16_at(data, free_block));
+        }
+        covered.sort_unstable();
+
+        let mut n_frag = 0;
+        let mut prev = content_start - 1;
+        for range in covered {
+            if prev & 0xffff >= range >> 16 {
+                let msg = format!("Multiple uses for byte {} of page {}", range >> 16, page);
+                self.report_layout(msg);
+                return;
+            }
+            n_frag += (range >> 16) - (prev & 0xffff) - 1;
+            prev = range;
+        }
+        n_frag += self.usable_size - (prev & 0xffff) - 1;
+        let reported = data[hdr + 7];
+        if n_frag != usize::from(reported) {
+            let msg = format!(
+                "Fragmentation of {} bytes reported as {} on page {}",
+                n_frag, reported, page
+            );
+            self.report_layout(msg);
+        }
+    }
+
+    /// Checks that the NOT NULL columns of a table's rows have values and, unless `quick`, that
+    /// every row has its entry in each index.
+    fn check_rows(
+        &mut self,
+        tbl: &ObjSchema,
+        tree: &Tree,
+        idxs: &[(Idx, Tree)],
+        quick: bool,
+    ) -> Result<()> {
+        let cols = tbl.cols()?;
+        let (col_defs, _) = tbl.parse_create_tbl()?;
+        let enc = self.enc;
+
+        // Instead of a rowid, the entries of a WITHOUT ROWID table's indexes end with the primary
+        // key columns that aren't already part of them. Partial indexes are left out, as telling
+        // which rows they hold would take evaluating their WHERE clause. Keys are compared by the
+        // collations the index sorts its columns by.
+        let idxs = idxs
+            .iter()
+            .filter(|(idx, idx_tree)| !quick && idx_tree.ok && !idx.is_pk() && !idx.is_partial())
+            .map(|(idx, idx_tree)| {
+                let idx_key = idx.cols.map_or(&[][..], Cols::key);
+                let key_cols = idx_key
+                    .iter()
+                    .chain(
+                        cols.pk()
+                            .iter()
+                            .filter(|_| cols.is_without_rowid())
+                            .filter(|c| !idx_key.iter().any(|k| k.name == c.name)),
+                    )
+                    .map(|c| cols.collated(c))
+                    .collect::<Vec<_>>();
+                let entries = idx_tree
+                    .entries
+                    .iter()
+                    .map(|(_, payload)| decode(payload.as_deref()?, enc))
+                    .collect::<Vec<_>>();
+                (idx, idx_tree, idx_key.len(), key_cols, entries)
+            })
+            .collect::<Vec<_>>();
+
+        // Records whose overflow pages are broken were already reported.
+        let rows = tree
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(row, (rowid, payload))| Some((row, rowid, payload.as_deref()?)));
+        for (row, rowid, payload) in rows {
+            let values = decode_cols(payload, enc);
+            // Rows written before a column was added lack it and have its default value, while
+            // VIRTUAL columns aren't stored at all.
+            let value = |col: &str| match cols.is_int_pk(col) {
+                true => ColValue::Known(Value::Int(*rowid)),
+                false if cols.is_virtual(col) => ColValue::Missing,
+                false => match values.as_ref().map(|v| v.get(cols.record_pos(col).ok()?)) {
+                    Some(Some(Some(val))) => ColValue::Known(val.clone()),
+                    Some(None) => ColValue::Missing,
+                    Some(Some(None)) | None => ColValue::Undecodable,
+                },
+            };
+
+            for col in col_defs.iter().filter(|c| !cols.is_virtual(c.name)) {
+                let is_null = match value(col.name) {
+                    ColValue::Known(val) => val == Value::Null,
+                    ColValue::Missing => col
+                        .default_value()
+                        .map_or(true, |v| v.eq_ignore_ascii_case("NULL")),
+                    ColValue::Undecodable => false,
+                };
+                if is_null && cols.is_not_null(col.name) {
+                    let msg = format!("NULL value in {}.{}", tbl.name, col.name);
+                    self.report(Pfx::None, msg);
+                }
+            }
+
+            for (idx, idx_tree, n_key_cols, key_cols, entries) in &idxs {
+                let missing = || format!("row {} missing from index {}", row + 1, idx.name);
+                let mut key = vec![];
+                for col in key_cols {
+                    match value(&col.name) {
+                        ColValue::Known(val) => key.push(val),
+                        // The default value of a missing column isn't known without evaluating
+                        // it.
+                        ColValue::Missing => break,
+                        // Like in SQLite, the malformed value matches none of the entries.
+                        ColValue::Undecodable => {
+                            self.report(Pfx::None, missing());
+                            break;
+                        }
+                    }
+                }
+                if key.len() < key_cols.len() {
+                    continue;
+                }
+                if !cols.is_without_rowid() {
+                    key.push(Value::Int(*rowid));
+                }
+
+                // Index entries that can't be decoded are found by no row, which is reported as
+                // missing from the index instead.
+                let pos = match seek(idx_tree, entries, &key, key_cols, enc) {
+                    Some(pos) => pos,
+                    None => {
+                        self.report(Pfx::None, missing());
+                        continue;
+                    }
+                };
+
+                // A collation other than BINARY finds entries whose text differs from the row's,
+                // but the entry must still hold the same values.
+                let entry = entries[pos].as_deref().unwrap_or_default();
+                let differs = key_cols
+                    .iter()
+                    .enumerate()
+                    .any(|(i, c)| !cols.is_binary(c) && entry.get(i) != key.get(i));
+                if differs {
+                    let msg = format!("row {} values differ from index {}", row + 1, idx.name);
+                    self.report(Pfx::None, msg);
+                }
+
+                // NULLs never equal each other, so keys containing them can't be duplicates.
+                let key = &key[..*n_key_cols];
+                if idx.is_unique() && !key.contains(&Value::Null) {
+                    let is_dup =
+                        entries
+                            .get(pos + 1)
+                            .and_then(Option::as_ref)
+                            .map_or(false, |next| {
+                                next.len() >= key.len()
+                                    && btree::cmp_keys(next, key, key_cols, enc).is_eq()
+                            });
+                    if is_dup {
+                        let msg = format!("non-unique entry in index {}", idx.name);
+                        self.report(Pfx::None, msg);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Looks up an index entry page by page with a binary search like SQLite's, which fails to find
+/// entries if the index is out of order. Returns its position among all entries.
+fn seek(
+    tree: &Tree,
+    entries: &[Option<Vec<Value>>],
+    key: &[Value],
+    key_cols: &[KeyCol],
+    enc: Enc,
+) -> Option<usize> {
+    let mut page = tree.root;
+    loop {
+        let node = tree.nodes.get(&page)?;
+        let (mut lwr, mut upr) = (0, node.cells.len());
+        while lwr < upr {
+            let mid = (lwr + upr - 1) / 2;
+            let (_, entry) = node.cells[mid];
+            let ord = match &entries[entry] {
+                Some(e) => btree::cmp_keys(e, key, key_cols, enc).then(e.len().cmp(&key.len())),
+                None => Ordering::Less,
+            };
+            match ord {
+                Ordering::Less => lwr = mid + 1,
+                Ordering::Greater => upr = mid,
+                Ordering::Equal => return Some(entry),
+            }
+        }
+        page = match node.cells.get(lwr) {
+            Some((child, _)) => *child,
+            None => node.right,
+        };
+    }
+}
+
+/// The value of a column in a row being checked.
+enum ColValue<'a> {
+    Known(Value<'a>),
+    /// Not part of the record, like columns added after the row was written.
+    Missing,
+    /// Malformed, like text that isn't valid in the database's encoding.
+    Undecodable,
+}
+
+/// Decodes a record into the values of its columns, which are `None` if they're malformed. The
+/// whole record is `None` if its header is.
+fn decode_cols(payload: &[u8], enc: Enc) -> Option<Vec<Option<Value<'_>>>> {
+    let record = Record::parse(payload, enc).ok()?;
+    Some(record.0.iter().map(|c| Value::try_from(c).ok()).collect())
+}
+
+/// Decodes a record into its values unless it's malformed.
+fn decode(payload: &[u8], enc: Enc) -> Option<Vec<Value<'_>>> {
+    Record::parse(payload, enc)
+        .ok()?
+        .0
+        .iter()
+        .map(Value::try_from)
+        .collect::<Result<_>>()
+        .ok()
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, CheckOpts, MAX_ERRORS};
+    use crate::{
+        format::Error,
+        interpreter::{
+            database::Database,
+            test_db::{db_with_idx, text},
+        },
+    };
+    use anyhow::Result;
+
+    /// A database with a table `t` of `n` rows and an index `i` on its column `b`, which lacks the
+    /// entries of the rows in `missing`.
+    fn db(n: i64, missing: &[i64]) -> Result<Database> {
+        let b = |i: i64| text(&format!("{:060}", i));
+        let entries = (1..=n)
+            .filter(|i| !missing.contains(i))
+            .map(|i| (b(i), i))
+            .collect();
+        db_with_idx("CREATE INDEX i ON t(b)", (1..=n).map(b).collect(), entries)
+    }
+
+    fn opts(quick: bool) -> CheckOpts<'static> {
+        CheckOpts {
+            tbl: None,
+            max_errors: MAX_ERRORS,
+            quick,
+        }
+    }
+
+    #[test]
+    fn accepts_intact_database() -> Result<()> {
+        assert_eq!(check(&db(2000, &[])?, &opts(false))?, ["ok"]);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_rows_missing_from_index() -> Result<()> {
+        let db = db(2000, &[7, 1500])?;
+        assert_eq!(
+            check(&db, &opts(false))?,
+            [
+                "wrong # of entries in index i",
+                "row 7 missing from index i",
+                "row 1500 missing from index i",
+            ]
+        );
+        assert_eq!(check(&db, &opts(true))?, ["wrong # of entries in index i"]);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_rows_that_cant_be_decoded() -> Result<()> {
+        let mut db = db(5, &[])?;
+        // Makes the text of row 3 invalid UTF-8 in the table, but not in the index.
+        let page_size = db.schema.db_header.page_size as usize;
+        let tbl_page = &mut db.bytes[page_size..2 * page_size];
+        let b = format!("{:060}", 3);
+        let pos = tbl_page
+            .windows(b.len())
+            .position(|w| w == b.as_bytes())
+            .unwrap();
+        tbl_page[pos] = 0xff;
+        assert_eq!(check(&db, &opts(false))?, ["row 3 missing from index i"]);
+        Ok(())
+    }
+
+    #[test]
+    fn fails_on_indexes_with_unknown_columns() -> Result<()> {
+        let mut db = db(5, &[])?;
+        // Like an automatic index whose constraint is missing from its table.
+        db.schema.objs[1].cols = None;
+        let err = check(&db, &opts(false)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::CorruptSchema { name, .. }) if name == "i"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn compares_keys_by_collation_of_index() -> Result<()> {
+        let sql = "CREATE INDEX i ON t(b COLLATE NOCASE)";
+        let b = ["c", "a", "B", "A", "b"]
+            .iter()
+            .map(|s| text(s))
+            .collect::<Vec<_>>();
+        let entries =
+            |entries: &[(&str, i64)]| entries.iter().map(|&(s, rowid)| (text(s), rowid)).collect();
+        let nocase = entries(&[("a", 2), ("A", 4), ("B", 3), ("b", 5), ("c", 1)]);
+        let db = db_with_idx(sql, b.clone(), nocase)?;
+        assert_eq!(check(&db, &opts(false))?, ["ok"]);
+
+        let binary = entries(&[("A", 4), ("B", 3), ("a", 2), ("b", 5), ("c", 1)]);
+        let db = db_with_idx(sql, b.clone(), binary)?;
+        assert_ne!(check(&db, &opts(false))?, ["ok"]);
+
+        // The entries are found, but they need to hold the exact values of their rows.
+        let other_case = entries(&[("a", 2), ("A", 4), ("b", 3), ("b", 5), ("C", 1)]);
+        let db = db_with_idx(sql, b, other_case)?;
+        assert_eq!(
+            check(&db, &opts(false))?,
+            [
+                "row 1 values differ from index i",
+                "row 3 values differ from index i"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reports_pages_referenced_twice() -> Result<()> {
+        let mut db = db(2000, &[])?;
+        // Points the right child of the table's root at the child of its first cell.
+        let page_size = db.schema.db_header.page_size as usize;
+        let root = &mut db.bytes[page_size..2 * page_size];
+        let first_cell = u16::from_be_bytes([root[12], root[13]]) as usize;
+        let child = super::u32_at(root, first_cell);
+        let right = super::u32_at(root, 8);
+        root[8..12].copy_from_slice(&child.to_be_bytes());
+
+        let msgs = check(&db, &opts(false))?;
+        let msgs = msgs[0].lines().collect::<Vec<_>>();
+        assert_eq!(msgs[0], "*** in database main ***");
+        let dup_ref = format!("2nd reference to page {}", child);
+        assert!(msgs.iter().any(|m| m.ends_with(&dup_ref)));
+        assert!(msgs.contains(&format!("Page {}: never used", right).as_str()));
+        Ok(())
+    }
+}
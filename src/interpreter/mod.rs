@@ -1,5 +1,22 @@
+pub mod analyze;
 pub mod btree;
+pub mod connection;
+pub mod convert;
+pub mod database;
+#[cfg(feature = "serde")]
+mod de;
 pub mod dot_cmd;
 pub mod eval;
 pub mod exec;
+pub mod explain;
+pub mod import;
+pub mod integrity;
+pub mod ops;
+pub mod output;
+pub mod pragma;
+pub mod repl;
+pub mod row;
 pub mod select_stmt;
+#[cfg(test)]
+mod test_db;
+pub mod write;
@@ -0,0 +1,1045 @@
+//! Operator trees, which queries are run with. The planner in `select_stmt` builds a tree from a
+//! query, and reading the rows of the tree's root pulls them from its inputs in turn. Rows are
+//! passed between operators as lists of values whose columns are named by `Op::cols`.
+//!
+//! Subqueries in expressions have trees of their own, which are attached to the operators that
+//! evaluate the expressions. A correlated subquery is opened again for every row it's evaluated
+//! on and can read the columns of that row and of the rows of the queries around it.
+//!
+//! A recursive common table expression reruns the tree of its recursive step for each row it
+//! finds, which the step reads through a `WorkingRow` operator that shares the row with it.
+
+use crate::{
+    format::DbFile,
+    interpreter::{
+        btree,
+        eval::{arith, Eval, Value},
+        row::Row,
+        select_stmt::ResultRows,
+    },
+    schema::ObjSchema,
+    syntax::{BoolExpr, CompoundOp, Expr, Query, SortOrder},
+    util::{FlatMapOkAndThenExt, IterEither, MapOkAndThenExt},
+};
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::{BTreeSet, HashSet, VecDeque},
+    convert::TryFrom,
+    iter, ptr,
+    rc::Rc,
+};
+
+/// An operator together with the number of rows it returned so far, for profiling. Clones share
+/// the count.
+#[derive(Debug, Clone)]
+pub struct Op<'a> {
+    pub kind: OpKind<'a>,
+    rows: Rc<Cell<usize>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum OpKind<'a> {
+    /// Reads the columns `cols` of every row of a table.
+    Scan {
+        tbl: &'a ObjSchema,
+        cols: Vec<&'a str>,
+    },
+    /// Reads the columns `cols` of the rows of a table whose rowid is one of `keys`.
+    RowidSeek {
+        tbl: &'a ObjSchema,
+        cols: Vec<&'a str>,
+        keys: Vec<Key<'a>>,
+    },
+    /// Reads the columns `cols` of the rows of a table whose value in the leading column of
+    /// `idx` is one of `keys`. For WITHOUT ROWID tables, `idx` may be the table itself.
+    IndexSeek {
+        tbl: &'a ObjSchema,
+        idx: &'a ObjSchema,
+        cols: Vec<&'a str>,
+        keys: Vec<Key<'a>>,
+    },
+    /// Reads the columns `cols` of every row of a table in the order of the entries of `idx`.
+    IndexScan {
+        tbl: &'a ObjSchema,
+        idx: &'a ObjSchema,
+        cols: Vec<&'a str>,
+    },
+    /// Returns a single row without columns, which a SELECT without FROM computes its columns on.
+    ConstantRow,
+    /// Reads the columns `cols` of the rows any of the seeks finds, each only once. SQLite calls
+    /// this the OR-optimization, as it serves the terms of an OR that are on different columns.
+    MultiIndexOr {
+        tbl: &'a ObjSchema,
+        cols: Vec<&'a str>,
+        seeks: Vec<Op<'a>>,
+    },
+    /// Keeps the rows for which the condition holds.
+    Filter {
+        input: Box<Op<'a>>,
+        cond: &'a BoolExpr<'a>,
+        subqueries: Rc<[Subquery<'a>]>,
+    },
+    /// Computes the result columns of each row.
+    Project {
+        input: Box<Op<'a>>,
+        exprs: &'a [Expr<'a>],
+        subqueries: Rc<[Subquery<'a>]>,
+    },
+    /// Computes a single row in which `count(*)` is the number of rows and `count(DISTINCT x)`
+    /// the number of different values of `x`, whose text is compared by the collation at the
+    /// same position in `collations`. Other columns are computed on the first row like SQLite
+    /// does with bare columns, or are NULL without rows.
+    Aggregate {
+        input: Box<Op<'a>>,
+        exprs: &'a [Expr<'a>],
+        subqueries: Rc<[Subquery<'a>]>,
+        collations: Vec<Option<&'a str>>,
+    },
+    /// Leaves out the rows that were returned before, comparing the text of each column by its
+    /// collation in `collations`. If the input is `ordered` so that equal rows come one after
+    /// another, only the previous row is kept to compare with.
+    Distinct {
+        input: Box<Op<'a>>,
+        ordered: bool,
+        collations: Vec<Option<&'a str>>,
+    },
+    /// Sorts the rows by the columns at the given positions.
+    Sort {
+        input: Box<Op<'a>>,
+        keys: Vec<(usize, SortOrder)>,
+    },
+    /// Skips `offset` rows and then returns at most `limit` rows.
+    Limit {
+        input: Box<Op<'a>>,
+        limit: Option<usize>,
+        offset: usize,
+    },
+    /// Pairs each row of `left` with every row of `right`, which is read again for each row and
+    /// can seek its rows by the columns of that row. A join condition is a filter on top of
+    /// this.
+    Join {
+        left: Box<Op<'a>>,
+        right: Box<Op<'a>>,
+    },
+    /// Combines the rows of `first` with those of each of the others in turn like a compound
+    /// SELECT. Except for UNION ALL, the rows are sorted and found only once.
+    Compound {
+        first: Box<Op<'a>>,
+        rest: Vec<(CompoundOp, Op<'a>)>,
+    },
+    /// Names the columns of a table, subquery or common table expression in FROM `cols` and
+    /// lets them be qualified with `name`.
+    As {
+        input: Box<Op<'a>>,
+        name: Option<&'a str>,
+        cols: Vec<String>,
+    },
+    /// Finds the rows of a recursive common table expression: those of `setup`, and then those
+    /// `step` finds for each row found so far, which it reads as `working`. Unless `union_all`
+    /// is set, rows that were found before are left out.
+    RecursiveCte {
+        name: &'a str,
+        setup: Box<Op<'a>>,
+        step: Box<Op<'a>>,
+        union_all: bool,
+        working: Rc<RefCell<Vec<Value<'a>>>>,
+    },
+    /// Reads the row the step of the recursive common table expression `name` is run for.
+    WorkingRow {
+        name: &'a str,
+        cols: Vec<String>,
+        row: Rc<RefCell<Vec<Value<'a>>>>,
+    },
+}
+
+/// A value a seek looks for.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Key<'a> {
+    Value(Value<'a>),
+    /// The value of a column of the rows the seek is run for, like the current row of the left
+    /// side of a join or of a query around a correlated subquery.
+    Col(Option<&'a str>, &'a str),
+}
+
+/// A subquery of an operator's expressions together with the tree that runs it.
+#[derive(Debug, Clone)]
+pub struct Subquery<'a> {
+    pub select: &'a Query<'a>,
+    pub kind: SubqueryKind,
+    /// Subqueries are numbered in the order they appear in the statement.
+    pub num: usize,
+    /// Whether the subquery refers to columns of the queries it's nested in, so that it has to
+    /// be run again for each of their rows. The rows of other subqueries are read only once.
+    pub correlated: bool,
+    pub op: Op<'a>,
+    rows: Cache<'a>,
+}
+
+/// The rows of a subquery that are kept once they've been read. Clones share them.
+type Cache<'a> = Rc<RefCell<Option<Rc<[Vec<Value<'a>>]>>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubqueryKind {
+    /// Used as a value.
+    Scalar,
+    /// The right-hand side of IN.
+    List,
+    Exists,
+}
+
+/// The name of a column together with the name of the table or subquery in FROM it's from, which
+/// it can be qualified with.
+pub type QualifiedCol<'a> = (Option<&'a str>, String);
+
+/// The current rows of the queries a subquery is nested in, outermost first, together with the
+/// names of their columns.
+#[derive(Debug, Clone, Default)]
+struct Outer<'a>(Vec<(Rc<[QualifiedCol<'a>]>, Vec<Value<'a>>)>);
+
+/// What the expressions of an operator are evaluated with besides a row: the names of its
+/// columns, the rows of the queries around it and the subqueries of the expressions.
+struct Env<'a> {
+    db: DbFile<'a>,
+    cols: Rc<[QualifiedCol<'a>]>,
+    outer: Outer<'a>,
+    subqueries: Rc<[Subquery<'a>]>,
+}
+
+impl<'a> Op<'a> {
+    pub fn new(kind: OpKind<'a>) -> Self {
+        Self {
+            kind,
+            rows: Rc::default(),
+        }
+    }
+
+    /// Makes this operator the input of an operator built by `kind`.
+    pub fn then(self, kind: impl FnOnce(Box<Self>) -> OpKind<'a>) -> Self {
+        Self::new(kind(Box::new(self)))
+    }
+
+    /// Lets the columns be qualified with `name`, renaming them to `cols` if given.
+    pub fn named(self, name: Option<&'a str>, cols: Option<Vec<String>>) -> Self {
+        let cols = cols.unwrap_or_else(|| self.cols());
+        self.then(|input| OpKind::As { input, name, cols })
+    }
+
+    /// Whether the operator reads the rows of a table itself.
+    pub fn reads_tbl(&self) -> bool {
+        matches!(
+            self.kind,
+            OpKind::Scan { .. }
+                | OpKind::RowidSeek { .. }
+                | OpKind::IndexSeek { .. }
+                | OpKind::IndexScan { .. }
+                | OpKind::MultiIndexOr { .. }
+        )
+    }
+
+    /// How many rows the operator returned, adding up all the times it was read.
+    pub fn rows_returned(&self) -> usize {
+        self.rows.get()
+    }
+
+    /// The names of the columns of the rows the operator returns.
+    pub fn cols(&self) -> Vec<String> {
+        match &self.kind {
+            OpKind::Scan { cols, .. }
+            | OpKind::RowidSeek { cols, .. }
+            | OpKind::IndexSeek { cols, .. }
+            | OpKind::IndexScan { cols, .. }
+            | OpKind::MultiIndexOr { cols, .. } => cols.iter().map(|c| c.to_string()).collect(),
+            OpKind::ConstantRow => vec![],
+            OpKind::Filter { input, .. }
+            | OpKind::Distinct { input, .. }
+            | OpKind::Sort { input, .. }
+            | OpKind::Limit { input, .. }
+            | OpKind::Compound { first: input, .. } => input.cols(),
+            OpKind::Project { exprs, .. } | OpKind::Aggregate { exprs, .. } => {
+                exprs.iter().map(Expr::result_col_name).collect()
+            }
+            OpKind::Join { left, right } => {
+                let mut cols = left.cols();
+                cols.extend(right.cols());
+                cols
+            }
+            OpKind::As { cols, .. } | OpKind::WorkingRow { cols, .. } => cols.clone(),
+            OpKind::RecursiveCte { setup, .. } => setup.cols(),
+        }
+    }
+
+    /// The names of the columns together with the names they can be qualified with.
+    pub fn qualified_cols(&self) -> Vec<QualifiedCol<'a>> {
+        let tbl = match &self.kind {
+            OpKind::Scan { tbl, .. }
+            | OpKind::RowidSeek { tbl, .. }
+            | OpKind::IndexSeek { tbl, .. }
+            | OpKind::IndexScan { tbl, .. }
+            | OpKind::MultiIndexOr { tbl, .. } => Some(tbl.name.as_str()),
+            OpKind::Filter { input, .. }
+            | OpKind::Distinct { input, .. }
+            | OpKind::Sort { input, .. }
+            | OpKind::Limit { input, .. } => return input.qualified_cols(),
+            OpKind::Join { left, right } => {
+                let mut cols = left.qualified_cols();
+                cols.extend(right.qualified_cols());
+                return cols;
+            }
+            OpKind::As { name, .. } => *name,
+            OpKind::ConstantRow
+            | OpKind::Project { .. }
+            | OpKind::Aggregate { .. }
+            | OpKind::Compound { .. }
+            | OpKind::RecursiveCte { .. }
+            | OpKind::WorkingRow { .. } => None,
+        };
+        self.cols().into_iter().map(|col| (tbl, col)).collect()
+    }
+
+    pub fn inputs(&self) -> Vec<&Op<'a>> {
+        match &self.kind {
+            OpKind::Scan { .. }
+            | OpKind::RowidSeek { .. }
+            | OpKind::IndexSeek { .. }
+            | OpKind::IndexScan { .. }
+            | OpKind::ConstantRow
+            | OpKind::WorkingRow { .. } => vec![],
+            OpKind::Filter { input, .. }
+            | OpKind::Project { input, .. }
+            | OpKind::Aggregate { input, .. }
+            | OpKind::Distinct { input, .. }
+            | OpKind::Sort { input, .. }
+            | OpKind::Limit { input, .. }
+            | OpKind::As { input, .. } => vec![input],
+            OpKind::Join { left, right } => vec![left, right],
+            OpKind::RecursiveCte { setup, step, .. } => vec![setup, step],
+            OpKind::Compound { first, rest } => iter::once(first.as_ref())
+                .chain(rest.iter().map(|(_, op)| op))
+                .collect(),
+            OpKind::MultiIndexOr { seeks, .. } => seeks.iter().collect(),
+        }
+    }
+
+    /// The subqueries of the operator's expressions.
+    pub fn subqueries(&self) -> &[Subquery<'a>] {
+        match &self.kind {
+            OpKind::Filter { subqueries, .. }
+            | OpKind::Project { subqueries, .. }
+            | OpKind::Aggregate { subqueries, .. } => subqueries,
+            _ => &[],
+        }
+    }
+
+    /// Describes the operator like SQLite's EXPLAIN QUERY PLAN, which only shows how tables are
+    /// read and where temporary B-trees are needed.
+    pub fn explain(&self) -> Option<String> {
+        self.explain_as(None)
+    }
+
+    /// Describes the operator like `explain`, naming the table it reads by its `alias`.
+    pub fn explain_as(&self, alias: Option<&str>) -> Option<String> {
+        let name = |tbl: &ObjSchema| alias.unwrap_or(&tbl.name).to_owned();
+        Some(match &self.kind {
+            OpKind::Scan { tbl, .. } => format!("SCAN {}", name(tbl)),
+            OpKind::RowidSeek { tbl, .. } => {
+                format!("SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)", name(tbl))
+            }
+            OpKind::IndexSeek { tbl, idx, .. } => {
+                let col = idx.cols.as_ref().and_then(|c| c.leading());
+                let col = col.map_or("", |c| c.name.as_str());
+                match idx.is_table() {
+                    true => format!("SEARCH {} USING PRIMARY KEY ({}=?)", name(tbl), col),
+                    false => format!("SEARCH {} USING INDEX {} ({}=?)", name(tbl), idx.name, col),
+                }
+            }
+            OpKind::IndexScan { tbl, idx, .. } => {
+                format!("SCAN {} USING INDEX {}", name(tbl), idx.name)
+            }
+            OpKind::ConstantRow => "SCAN CONSTANT ROW".to_owned(),
+            OpKind::MultiIndexOr { .. } => "MULTI-INDEX OR".to_owned(),
+            OpKind::Distinct { ordered: false, .. } => "USE TEMP B-TREE FOR DISTINCT".to_owned(),
+            OpKind::Sort { .. } => "USE TEMP B-TREE FOR ORDER BY".to_owned(),
+            OpKind::RecursiveCte { name, .. } | OpKind::WorkingRow { name, .. } => {
+                format!("SCAN {}", name)
+            }
+            _ => return None,
+        })
+    }
+
+    /// Names the operator for profiling.
+    pub fn label(&self) -> String {
+        self.explain().unwrap_or_else(|| {
+            match &self.kind {
+                OpKind::Filter { .. } => "FILTER",
+                OpKind::Project { .. } => "PROJECT",
+                OpKind::Aggregate { .. } => "AGGREGATE",
+                OpKind::Distinct { .. } => "DISTINCT",
+                OpKind::Limit { .. } => "LIMIT",
+                OpKind::As { .. } => "AS",
+                OpKind::Compound { .. } => "COMPOUND QUERY",
+                _ => "JOIN",
+            }
+            .to_owned()
+        })
+    }
+
+    /// Starts reading the rows of the operator.
+    pub fn open(&self, db: DbFile<'a>) -> Result<ResultRows<'a>> {
+        self.open_in(db, &Outer::default())
+    }
+
+    /// Starts reading the rows of the operator as part of a subquery run for the `outer` rows.
+    fn open_in(&self, db: DbFile<'a>, outer: &Outer<'a>) -> Result<ResultRows<'a>> {
+        let rows: ResultRows<'a> = match &self.kind {
+            OpKind::Scan { tbl, cols } => {
+                let page = db.page(tbl.rootpage)?;
+                let rows = if tbl.cols()?.is_without_rowid() {
+                    IterEither::left(btree::idx_seek(vec![], page, db).map_ok(Row::from))
+                } else {
+                    IterEither::right(btree::full_tbl_scan(page, db).map_ok(Row::from))
+                };
+                Box::new(read_cols(rows, tbl, cols.clone()))
+            }
+            OpKind::RowidSeek { tbl, cols, .. } | OpKind::IndexSeek { tbl, cols, .. } => {
+                Box::new(read_cols(self.seek(db, outer)?, tbl, cols.clone()))
+            }
+            OpKind::IndexScan { tbl, idx, cols } => {
+                Box::new(read_cols(idx_seek(tbl, idx, None, db)?, tbl, cols.clone()))
+            }
+            OpKind::MultiIndexOr { tbl, cols, seeks } => {
+                let rows = seeks
+                    .iter()
+                    .map(|seek| Ok(seek.counted(seek.seek(db, outer)?)))
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .flatten();
+                let mut found = BTreeSet::new();
+                let tbl = *tbl;
+                let rows = rows.filter_map(move |row| {
+                    row.and_then(|row| Ok(found.insert(row_key(&row, tbl)?).then(|| row)))
+                        .transpose()
+                });
+                Box::new(read_cols(rows, tbl, cols.clone()))
+            }
+            OpKind::Filter {
+                input,
+                cond,
+                subqueries,
+            } => {
+                let (env, cond) = (Env::new(db, input, outer, subqueries), *cond);
+                Box::new(input.open_in(db, outer)?.filter_map(move |row| {
+                    row.and_then(|row| Ok(holds(cond, &row, &env)?.then(|| row)))
+                        .transpose()
+                }))
+            }
+            OpKind::Project {
+                input,
+                exprs,
+                subqueries,
+            } => {
+                let (env, exprs) = (Env::new(db, input, outer, subqueries), *exprs);
+                Box::new(input.open_in(db, outer)?.map_ok_and_then(move |row| {
+                    exprs.iter().map(|e| eval(e, &row, &env)).collect()
+                }))
+            }
+            OpKind::Aggregate {
+                input,
+                exprs,
+                subqueries,
+                collations,
+            } => {
+                let collations = collations.clone();
+                let (env, exprs) = (Env::new(db, input, outer, subqueries), *exprs);
+                let rows = input.open_in(db, outer)?;
+                Box::new(iter::once_with(move || {
+                    let (mut first, mut count) = (None, 0_usize);
+                    let mut distinct = vec![HashSet::new(); exprs.len()];
+                    for row in rows {
+                        let row = row?;
+                        for ((expr, values), collation) in
+                            exprs.iter().zip(&mut distinct).zip(&collations)
+                        {
+                            if let Expr::CountDistinct(expr) = expr {
+                                match eval(expr, &row, &env)? {
+                                    Value::Null => {}
+                                    value => {
+                                        values.insert(collate(value, *collation));
+                                    }
+                                }
+                            }
+                        }
+                        count += 1;
+                        first.get_or_insert(row);
+                    }
+                    let first = first.unwrap_or_else(|| vec![Value::Null; env.cols.len()]);
+                    exprs
+                        .iter()
+                        .zip(distinct)
+                        .map(|(expr, values)| match expr {
+                            Expr::Count => Ok(Value::Int(i64::try_from(count)?)),
+                            Expr::CountDistinct(_) => Ok(Value::Int(i64::try_from(values.len())?)),
+                            _ => eval(expr, &first, &env),
+                        })
+                        .collect()
+                }))
+            }
+            OpKind::Distinct {
+                input,
+                ordered,
+                collations,
+            } => {
+                let (rows, collations) = (input.open_in(db, outer)?, collations.clone());
+                match ordered {
+                    true => {
+                        let mut last = None;
+                        Box::new(rows.filter_ok(move |row| {
+                            let key = collate_row(row, &collations);
+                            let new = last.as_ref() != Some(&key);
+                            if new {
+                                last = Some(key);
+                            }
+                            new
+                        }))
+                    }
+                    false => {
+                        let mut found = HashSet::new();
+                        Box::new(
+                            rows.filter_ok(move |row| found.insert(collate_row(row, &collations))),
+                        )
+                    }
+                }
+            }
+            OpKind::Sort { input, keys } => {
+                let (rows, keys) = (input.open_in(db, outer)?, keys.clone());
+                Box::new(
+                    iter::once_with(move || {
+                        let mut rows = rows.collect::<Result<Vec<_>>>()?;
+                        rows.sort_by(|a, b| cmp_rows(a, b, &keys));
+                        Ok(rows)
+                    })
+                    .flatten_ok(),
+                )
+            }
+            OpKind::Limit {
+                input,
+                limit,
+                offset,
+            } => Box::new(
+                input
+                    .open_in(db, outer)?
+                    .skip(*offset)
+                    .take(limit.unwrap_or(usize::MAX)),
+            ),
+            OpKind::Join { left, right } => {
+                let (right, outer) = (Op::clone(right), outer.clone());
+                let left_cols = Rc::from(left.qualified_cols());
+                Box::new(left.open_in(db, &outer)?.flat_map_ok_and_then(move |l| {
+                    let mut outer = outer.clone();
+                    outer.0.push((Rc::clone(&left_cols), l.clone()));
+                    let rows = match right.open_in(db, &outer) {
+                        Ok(rows) => rows,
+                        Err(e) => return IterEither::left(iter::once(Err(e))),
+                    };
+                    IterEither::right(rows.map_ok(move |r| l.iter().cloned().chain(r).collect()))
+                }))
+            }
+            OpKind::Compound { first, rest } => {
+                let mut rows = first.open_in(db, outer)?;
+                for (op, input) in rest {
+                    let (op, other) = (*op, input.open_in(db, outer)?);
+                    rows = match op {
+                        CompoundOp::UnionAll => Box::new(rows.chain(other)),
+                        _ => Box::new(
+                            iter::once_with(move || {
+                                let mut rows = rows.collect::<Result<BTreeSet<_>>>()?;
+                                match op {
+                                    CompoundOp::Union => {
+                                        for row in other {
+                                            rows.insert(row?);
+                                        }
+                                    }
+                                    _ => {
+                                        let other = other.collect::<Result<BTreeSet<_>>>()?;
+                                        let keep = op == CompoundOp::Intersect;
+                                        rows.retain(|row| other.contains(row) == keep);
+                                    }
+                                }
+                                Ok(rows)
+                            })
+                            .flatten_ok(),
+                        ),
+                    };
+                }
+                rows
+            }
+            OpKind::As { input, .. } => input.open_in(db, outer)?,
+            OpKind::RecursiveCte {
+                setup,
+                step,
+                union_all,
+                working,
+                ..
+            } => {
+                let mut found = BTreeSet::new();
+                let mut queue = VecDeque::new();
+                for row in setup.open_in(db, outer)? {
+                    let row = row?;
+                    if *union_all || found.insert(row.clone()) {
+                        queue.push_back(row);
+                    }
+                }
+                let (step, working, union_all) = (Op::clone(step), Rc::clone(working), *union_all);
+                let outer = outer.clone();
+                Box::new(iter::from_fn(move || {
+                    let row = queue.pop_front()?;
+                    *working.borrow_mut() = row.clone();
+                    let next = step.open_in(db, &outer).and_then(|rows| {
+                        rows.map_ok(|next| {
+                            if union_all || found.insert(next.clone()) {
+                                queue.push_back(next);
+                            }
+                        })
+                        .collect::<Result<()>>()
+                    });
+                    Some(next.map(|()| row))
+                }))
+            }
+            OpKind::ConstantRow => Box::new(iter::once(Ok(vec![]))),
+            OpKind::WorkingRow { row, .. } => Box::new(iter::once(Ok(row.borrow().clone()))),
+        };
+
+        Ok(Box::new(self.counted(rows)))
+    }
+
+    /// Finds the rows of a seek, which are yet to be read. Each key is sought once, so that the
+    /// rows of different keys are kept apart.
+    fn seek(
+        &self,
+        db: DbFile<'a>,
+        outer: &Outer<'a>,
+    ) -> Result<Box<dyn Iterator<Item = Result<Row<'a>>> + 'a>> {
+        Ok(match &self.kind {
+            OpKind::RowidSeek { tbl, keys, .. } => {
+                let page = db.page(tbl.rootpage)?;
+                let rowids = key_values(keys, outer)?
+                    .into_iter()
+                    .filter_map(|key| match key {
+                        Value::Int(rowid) => Some(rowid),
+                        Value::Float(x) if x.fract() == 0.0 => Some(x as i64),
+                        _ => None,
+                    });
+                Box::new(rowids.filter_map(move |rowid| {
+                    btree::pk_scan(rowid, &page, db)
+                        .map(|cell| cell.map(Row::from))
+                        .transpose()
+                }))
+            }
+            OpKind::IndexSeek { tbl, idx, keys, .. } => Box::new(
+                key_values(keys, outer)?
+                    .into_iter()
+                    .map(|key| idx_seek(tbl, idx, Some(key), db))
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .flatten(),
+            ),
+            _ => bail!("{} is not a seek", self.label()),
+        })
+    }
+
+    /// Counts the rows as they're returned.
+    fn counted<T>(
+        &self,
+        rows: impl Iterator<Item = Result<T>> + 'a,
+    ) -> impl Iterator<Item = Result<T>> + 'a {
+        let count = Rc::clone(&self.rows);
+        rows.inspect(move |row| {
+            if row.is_ok() {
+                count.set(count.get() + 1)
+            }
+        })
+    }
+}
+
+impl<'a> Subquery<'a> {
+    pub fn new(
+        select: &'a Query<'a>,
+        kind: SubqueryKind,
+        num: usize,
+        correlated: bool,
+        op: Op<'a>,
+    ) -> Self {
+        Self {
+            select,
+            kind,
+            num,
+            correlated,
+            op,
+            rows: Rc::default(),
+        }
+    }
+
+    /// Describes the subquery like SQLite's EXPLAIN QUERY PLAN.
+    pub fn explain(&self) -> String {
+        format!(
+            "{}{} SUBQUERY {}",
+            if self.correlated { "CORRELATED " } else { "" },
+            match self.kind {
+                SubqueryKind::List => "LIST",
+                SubqueryKind::Scalar | SubqueryKind::Exists => "SCALAR",
+            },
+            self.num
+        )
+    }
+
+    /// Reads the rows of the subquery for the current rows of the queries it's nested in. The
+    /// rows of an uncorrelated subquery are kept after they've been read the first time.
+    fn open(&self, db: DbFile<'a>, outer: &Outer<'a>) -> Result<ResultRows<'a>> {
+        if self.correlated {
+            return self.op.open_in(db, outer);
+        }
+        let cached = self.rows.borrow().clone();
+        let rows = match cached {
+            Some(rows) => rows,
+            None => {
+                let rows = self.op.open_in(db, outer)?.collect::<Result<Rc<[_]>>>()?;
+                *self.rows.borrow_mut() = Some(Rc::clone(&rows));
+                rows
+            }
+        };
+        Ok(Box::new((0..rows.len()).map(move |i| Ok(rows[i].clone()))))
+    }
+}
+
+impl<'a> Outer<'a> {
+    /// The value of the column in the innermost row that has it.
+    fn get(&self, tbl: Option<&str>, col: &str) -> Option<&Value<'a>> {
+        self.0.iter().rev().find_map(|(cols, row)| {
+            let i = col_positions(cols, tbl, col).next()?;
+            row.get(i)
+        })
+    }
+}
+
+impl<'a> Env<'a> {
+    fn new(
+        db: DbFile<'a>,
+        input: &Op<'a>,
+        outer: &Outer<'a>,
+        subqueries: &Rc<[Subquery<'a>]>,
+    ) -> Self {
+        Self {
+            db,
+            cols: input.qualified_cols().into(),
+            outer: outer.clone(),
+            subqueries: Rc::clone(subqueries),
+        }
+    }
+
+    /// Reads the rows of the subquery `select` for `row`.
+    fn subquery(&self, select: &Query<'a>, row: &[Value<'a>]) -> Result<ResultRows<'a>> {
+        let subquery = self
+            .subqueries
+            .iter()
+            .find(|s| ptr::eq(s.select, select))
+            .ok_or_else(|| anyhow!("Subquery was not planned"))?;
+        if !subquery.correlated {
+            return subquery.open(self.db, &self.outer);
+        }
+        let mut outer = self.outer.clone();
+        outer.0.push((Rc::clone(&self.cols), row.to_vec()));
+        subquery.open(self.db, &outer)
+    }
+}
+
+/// The values of the keys of a seek for the rows it's run for, in order and without duplicates.
+/// NULLs are left out, as they're equal to nothing.
+fn key_values<'a>(keys: &[Key<'a>], outer: &Outer<'a>) -> Result<Vec<Value<'a>>> {
+    let values = keys
+        .iter()
+        .map(|key| match key {
+            Key::Value(value) => Ok(value.clone()),
+            Key::Col(tbl, col) => outer
+                .get(*tbl, col)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown column '{}'", col)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(values
+        .into_iter()
+        .filter(|value| *value != Value::Null)
+        .sorted()
+        .dedup()
+        .collect())
+}
+
+/// Finds the rows of `tbl` with `key` in the leading column of `idx`, or all of them in the
+/// order of its entries without a key.
+fn idx_seek<'a>(
+    tbl: &'a ObjSchema,
+    idx: &'a ObjSchema,
+    key: Option<Value<'a>>,
+    db: DbFile<'a>,
+) -> Result<impl Iterator<Item = Result<Row<'a>>> + 'a> {
+    let tbl_page = db.page(tbl.rootpage)?;
+    let idx_page = db.page(idx.rootpage)?;
+    let order = idx.cols()?.leading().map_or(SortOrder::Asc, |c| c.order);
+    let key = key.map(|key| (key, order)).into_iter().collect::<Vec<_>>();
+
+    Ok(if idx.is_table() {
+        IterEither::left(IterEither::left(
+            btree::idx_seek(key, idx_page, db).map_ok(Row::from),
+        ))
+    } else if tbl.cols()?.is_without_rowid() {
+        let pk_pos = tbl.cols()?.pk_pos_in_idx(idx.cols()?);
+        let pk_orders = tbl.cols()?.pk().iter().map(|c| c.order);
+        let pk_pos = pk_pos.into_iter().zip(pk_orders).collect::<Vec<_>>();
+
+        let rows = btree::idx_seek(key, idx_page, db)
+            .map_ok_and_then(move |entry| {
+                let pk = pk_pos
+                    .iter()
+                    .map(|(pos, order)| Ok((Value::try_from(&entry[*pos])?, *order)))
+                    .collect::<Result<Vec<_>>>()?;
+                btree::idx_seek(pk, tbl_page.clone(), db).next().transpose()
+            })
+            .filter_map(Result::transpose)
+            .map_ok(Row::from);
+
+        IterEither::left(IterEither::right(rows))
+    } else {
+        IterEither::right(btree::idx_scan(key, idx_page, tbl_page, db).map_ok(Row::from))
+    })
+}
+
+/// What tells the rows of `tbl` apart: their rowid, or the primary key of a WITHOUT ROWID table.
+fn row_key<'a>(row: &Row<'a>, tbl: &'a ObjSchema) -> Result<Vec<Value<'a>>> {
+    match row.row_id {
+        Some(rowid) => Ok(vec![Value::Int(rowid)]),
+        None => tbl
+            .cols()?
+            .pk()
+            .iter()
+            .map(|c| Expr::ColName(&c.name).eval(row, tbl))
+            .collect(),
+    }
+}
+
+/// Reads the columns `cols` of rows of `tbl`.
+fn read_cols<'a>(
+    rows: impl Iterator<Item = Result<Row<'a>>> + 'a,
+    tbl: &'a ObjSchema,
+    cols: Vec<&'a str>,
+) -> impl Iterator<Item = Result<Vec<Value<'a>>>> + 'a {
+    rows.map_ok_and_then(move |row| {
+        cols.iter()
+            .map(|col| Expr::ColName(col).eval(&row, tbl))
+            .collect()
+    })
+}
+
+/// The positions of the columns a column reference can be to, which are those with its name and
+/// with the table or alias it's qualified with if any.
+pub fn col_positions<'c>(
+    cols: &'c [QualifiedCol],
+    tbl: Option<&'c str>,
+    col: &'c str,
+) -> impl Iterator<Item = usize> + 'c {
+    cols.iter().positions(move |(t, c)| {
+        let same_tbl = match (tbl, t) {
+            (None, _) => true,
+            (Some(tbl), Some(t)) => tbl.eq_ignore_ascii_case(t),
+            (Some(_), None) => false,
+        };
+        same_tbl && c.eq_ignore_ascii_case(col)
+    })
+}
+
+/// Evaluates `expr` on a row. Columns the row doesn't have are looked up in the rows of the
+/// queries around it.
+fn eval<'a>(expr: &Expr<'a>, row: &[Value<'a>], env: &Env<'a>) -> Result<Value<'a>> {
+    Ok(match expr {
+        Expr::Literal(lit) | Expr::Param { value: lit, .. } => lit.into(),
+        Expr::ColName(_) | Expr::QualifiedColName { .. } => {
+            let (tbl, col) = expr.as_col_ref().unwrap();
+            col_positions(&env.cols, tbl, col)
+                .next()
+                .and_then(|i| row.get(i))
+                .or_else(|| env.outer.get(tbl, col))
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown column '{}'", expr))?
+        }
+        Expr::Count | Expr::CountDistinct(_) => {
+            bail!("{} can only be used as a result column", expr)
+        }
+        Expr::Subquery { select, .. } => env
+            .subquery(select, row)?
+            .next()
+            .transpose()?
+            .map_or(Value::Null, |mut row| row.swap_remove(0)),
+        Expr::Arith { op, l, r, .. } => arith(*op, &eval(l, row, env)?, &eval(r, row, env)?),
+    })
+}
+
+fn holds<'a>(cond: &BoolExpr<'a>, row: &[Value<'a>], env: &Env<'a>) -> Result<bool> {
+    Ok(match cond {
+        BoolExpr::Equals { l, r } => equals(&eval(l, row, env)?, &eval(r, row, env)?),
+        BoolExpr::NotEquals { l, r } => {
+            let (l, r) = (eval(l, row, env)?, eval(r, row, env)?);
+            l != Value::Null && r != Value::Null && l != r
+        }
+        BoolExpr::In { expr, list } => {
+            let val = eval(expr, row, env)?;
+            list.iter()
+                .map(|e| eval(e, row, env))
+                .collect::<Result<Vec<_>>>()?
+                .iter()
+                .any(|v| equals(&val, v))
+        }
+        BoolExpr::InSubquery { expr, select } => {
+            let val = eval(expr, row, env)?;
+            env.subquery(select, row)?
+                .map_ok(|row| equals(&row[0], &val))
+                .find(|found| !matches!(found, Ok(false)))
+                .transpose()?
+                .is_some()
+        }
+        BoolExpr::Exists(select) => env.subquery(select, row)?.next().transpose()?.is_some(),
+        BoolExpr::And(l, r) => holds(l, row, env)? && holds(r, row, env)?,
+        BoolExpr::Or(l, r) => holds(l, row, env)? || holds(r, row, env)?,
+    })
+}
+
+/// Folds the text of a value by a collation, so that texts it considers equal are equal.
+fn collate<'a>(value: Value<'a>, collation: Option<&str>) -> Value<'a> {
+    match (value, collation) {
+        (Value::String(s), Some(c)) => {
+            Value::String(btree::fold_text(&s, Some(c)).into_owned().into())
+        }
+        (value, _) => value,
+    }
+}
+
+/// A row with the text of each column folded by its collation in `collations`.
+fn collate_row<'a>(row: &[Value<'a>], collations: &[Option<&str>]) -> Vec<Value<'a>> {
+    row.iter()
+        .zip(collations)
+        .map(|(value, collation)| collate(value.clone(), *collation))
+        .collect()
+}
+
+/// Whether two values are equal in a condition, which NULL never is to anything.
+fn equals(a: &Value, b: &Value) -> bool {
+    *a != Value::Null && a == b
+}
+
+fn cmp_rows(a: &[Value], b: &[Value], keys: &[(usize, SortOrder)]) -> Ordering {
+    keys.iter()
+        .map(|(i, order)| match order {
+            SortOrder::Asc => a[*i].cmp(&b[*i]),
+            SortOrder::Desc => b[*i].cmp(&a[*i]),
+        })
+        .find(|ord| ord.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Key, Op, OpKind};
+    use crate::{
+        format::DbFile, interpreter::eval::Value, interpreter::test_db::db, syntax::SortOrder,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn composes_operators() -> Result<()> {
+        let db = db()?;
+        let file = DbFile::new(&db.bytes, &db.schema.db_header);
+        let t = db.schema.table("t").unwrap();
+        let scan = || {
+            Op::new(OpKind::Scan {
+                tbl: t,
+                cols: vec!["id", "name"],
+            })
+        };
+
+        let sorted = scan().then(|input| OpKind::Sort {
+            input,
+            keys: vec![(1, SortOrder::Desc)],
+        });
+        let limited = sorted.then(|input| OpKind::Limit {
+            input,
+            limit: Some(1),
+            offset: 0,
+        });
+        let rows = limited.open(file)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(rows, [vec![Value::Int(2), Value::String("bob".into())]]);
+
+        let joined = Op::new(OpKind::Join {
+            left: Box::new(scan()),
+            right: Box::new(scan()),
+        });
+        assert_eq!(joined.cols(), ["id", "name", "id", "name"]);
+        let rows = joined.open(file)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[1][0], Value::Int(1));
+        assert_eq!(rows[1][2], Value::Int(2));
+        Ok(())
+    }
+
+    #[test]
+    fn counts_rows_of_each_operator() -> Result<()> {
+        let db = db()?;
+        let file = DbFile::new(&db.bytes, &db.schema.db_header);
+        let t = db.schema.table("t").unwrap();
+        let scan = Op::new(OpKind::Scan {
+            tbl: t,
+            cols: vec!["id"],
+        });
+        let limited = scan.then(|input| OpKind::Limit {
+            input,
+            limit: None,
+            offset: 1,
+        });
+
+        assert_eq!(limited.open(file)?.count(), 1);
+        assert_eq!(limited.open(file)?.count(), 1);
+        assert_eq!(limited.rows_returned(), 2);
+        assert_eq!(limited.inputs()[0].rows_returned(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn combines_seeks_without_duplicates() -> Result<()> {
+        let db = db()?;
+        let file = DbFile::new(&db.bytes, &db.schema.db_header);
+        let t = db.schema.table("t").unwrap();
+        let seek = |rowids: &[i64]| {
+            Op::new(OpKind::RowidSeek {
+                tbl: t,
+                cols: vec!["name"],
+                keys: rowids
+                    .iter()
+                    .map(|&id| Key::Value(Value::Int(id)))
+                    .collect(),
+            })
+        };
+        let or = Op::new(OpKind::MultiIndexOr {
+            tbl: t,
+            cols: vec!["name"],
+            seeks: vec![seek(&[2, 3]), seek(&[1, 2])],
+        });
+        let rows = or.open(file)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            rows,
+            [
+                vec![Value::String("bob".into())],
+                vec![Value::String("ann".into())]
+            ]
+        );
+        assert_eq!(or.inputs()[1].rows_returned(), 2);
+        Ok(())
+    }
+}
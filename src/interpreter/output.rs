@@ -0,0 +1,773 @@
+use crate::{
+    interpreter::eval::Value,
+    syntax::{is_keyword, OutputMode},
+    util::float_fmt,
+};
+use anyhow::Result;
+use std::{borrow::Cow, io::Write, iter::once, str};
+
+/// The settings of the shell that determine how the results of queries are printed.
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+    pub mode: OutputMode,
+    /// Whether to print the column names. If they were never turned on or off explicitly, only
+    /// column mode shows them.
+    pub headers: Option<bool>,
+    pub null_value: String,
+    pub col_sep: String,
+    pub row_sep: String,
+    /// The table that insert mode generates INSERT statements for.
+    pub insert_tbl: String,
+    /// Whether to print how many rows each operator of a query returned after its result.
+    pub scan_stats: bool,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            mode: OutputMode::List,
+            headers: None,
+            null_value: String::new(),
+            col_sep: "|".to_owned(),
+            row_sep: "\n".to_owned(),
+            insert_tbl: "table".to_owned(),
+            scan_stats: false,
+        }
+    }
+}
+
+impl OutputConfig {
+    /// Switches to `mode`, which like in sqlite3 also resets the separators of list, tabs and
+    /// csv mode.
+    pub fn set_mode(&mut self, mode: OutputMode, tbl: Option<String>) {
+        let seps = match mode {
+            OutputMode::List => Some(("|", "\n")),
+            OutputMode::Tabs => Some(("\t", "\n")),
+            OutputMode::Csv => Some((",", "\r\n")),
+            _ => None,
+        };
+        if let Some((col_sep, row_sep)) = seps {
+            self.col_sep = col_sep.to_owned();
+            self.row_sep = row_sep.to_owned();
+        }
+        if mode == OutputMode::Insert {
+            self.insert_tbl = tbl.unwrap_or_else(|| "table".to_owned());
+        }
+        self.mode = mode;
+    }
+
+    fn shows_headers(&self) -> bool {
+        match self.mode {
+            OutputMode::Box | OutputMode::Markdown | OutputMode::Table => true,
+            OutputMode::Column => self.headers.unwrap_or(true),
+            _ => self.headers.unwrap_or(false),
+        }
+    }
+
+    /// Creates a sink that prints results to `out` in the current mode.
+    pub fn sink<'a>(&'a self, out: impl Write + 'a) -> Box<dyn ResultSink + 'a> {
+        let config = self;
+        let out = Box::new(out);
+        match self.mode {
+            OutputMode::List | OutputMode::Tabs | OutputMode::Csv => Box::new(Delimited {
+                out,
+                config,
+                cols: vec![],
+                rows: 0,
+            }),
+            OutputMode::Json => Box::new(Json {
+                out,
+                cols: vec![],
+                rows: 0,
+            }),
+            OutputMode::Line => Box::new(Line {
+                out,
+                config,
+                cols: vec![],
+                rows: 0,
+            }),
+            OutputMode::Html => Box::new(Html {
+                out,
+                config,
+                cols: vec![],
+                rows: 0,
+            }),
+            OutputMode::Insert => Box::new(Insert {
+                out,
+                config,
+                cols: vec![],
+            }),
+            OutputMode::Column | OutputMode::Box | OutputMode::Markdown | OutputMode::Table => {
+                Box::new(Grid {
+                    out,
+                    config,
+                    cols: vec![],
+                    rows: vec![],
+                })
+            }
+        }
+    }
+}
+
+/// Receives the result of a query and prints it. Rows are printed as soon as they arrive,
+/// unless the layout depends on all of them like for the aligned columns of column mode.
+pub trait ResultSink {
+    /// Starts a result with the given column names. Nothing is printed for an empty result.
+    fn begin(&mut self, cols: &[String]) -> Result<()>;
+
+    fn row(&mut self, row: &[Value]) -> Result<()>;
+
+    fn finish(&mut self) -> Result<()>;
+}
+
+type Out<'a> = Box<dyn Write + 'a>;
+
+/// The value as it's shown in modes that print plain text.
+fn text<'v>(val: &'v Value, null_value: &'v str) -> Cow<'v, str> {
+    match val {
+        Value::Null => Cow::Borrowed(null_value),
+        Value::String(s) => Cow::Borrowed(s),
+        _ => Cow::Owned(val.to_string()),
+    }
+}
+
+/// The list, tabs and csv modes, which print the values of a row separated by a delimiter.
+struct Delimited<'a> {
+    out: Out<'a>,
+    config: &'a OutputConfig,
+    cols: Vec<String>,
+    rows: usize,
+}
+
+impl<'a> Delimited<'a> {
+    fn field<'v>(&self, val: Cow<'v, str>) -> Cow<'v, str> {
+        if self.config.mode == OutputMode::Csv && needs_csv_quotes(&val, &self.config.col_sep) {
+            Cow::Owned(format!("\"{}\"", val.replace('"', "\"\"")))
+        } else {
+            val
+        }
+    }
+
+    fn write_line(&mut self, fields: &[Cow<'_, str>]) -> Result<()> {
+        let config = self.config;
+        write!(
+            self.out,
+            "{}{}",
+            fields.join(&config.col_sep),
+            config.row_sep
+        )?;
+        Ok(())
+    }
+}
+
+impl<'a> ResultSink for Delimited<'a> {
+    fn begin(&mut self, cols: &[String]) -> Result<()> {
+        self.cols = cols.to_vec();
+        Ok(())
+    }
+
+    fn row(&mut self, row: &[Value]) -> Result<()> {
+        if self.rows == 0 && self.config.shows_headers() {
+            let cols = std::mem::take(&mut self.cols);
+            let header = cols.iter().map(|c| self.field(Cow::Borrowed(c)));
+            let header = header.collect::<Vec<_>>();
+            self.write_line(&header)?;
+        }
+        self.rows += 1;
+
+        // NULLs are the only values that are never quoted in csv mode.
+        let null_value = &self.config.null_value;
+        let fields = row.iter().map(|v| match v {
+            Value::Null => Cow::Borrowed(null_value.as_str()),
+            v => self.field(text(v, "")),
+        });
+        let fields = fields.collect::<Vec<_>>();
+        self.write_line(&fields)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Whether a value has to be quoted in csv mode, because it contains the separator or any of
+/// the characters sqlite3 considers in need of quoting.
+fn needs_csv_quotes(val: &str, col_sep: &str) -> bool {
+    let needs_quote = |c: char| c < ' ' || matches!(c, ' ' | '"' | '\'') || c as u32 >= 0x7f;
+    val.is_empty() || val.contains(needs_quote) || val.contains(col_sep)
+}
+
+/// Prints the result as a JSON array with an object per row.
+struct Json<'a> {
+    out: Out<'a>,
+    cols: Vec<String>,
+    rows: usize,
+}
+
+impl<'a> ResultSink for Json<'a> {
+    fn begin(&mut self, cols: &[String]) -> Result<()> {
+        self.cols = cols.iter().map(|c| json_str(c)).collect();
+        Ok(())
+    }
+
+    fn row(&mut self, row: &[Value]) -> Result<()> {
+        write!(self.out, "{}", if self.rows == 0 { "[{" } else { ",\n{" })?;
+        self.rows += 1;
+
+        for (i, (col, val)) in self.cols.iter().zip(row).enumerate() {
+            let val = match val {
+                Value::Null => Cow::Borrowed("null"),
+                Value::Int(_) => text(val, ""),
+                Value::Float(r) => Cow::Owned(float_literal(*r)),
+                Value::Bytes(bytes) => Cow::Owned(json_bytes(bytes)),
+                _ => Cow::Owned(json_str(&text(val, ""))),
+            };
+            let sep = if i == 0 { "" } else { "," };
+            write!(self.out, "{}{}:{}", sep, col, val)?;
+        }
+        write!(self.out, "}}")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.rows > 0 {
+            writeln!(self.out, "]")?;
+        }
+        Ok(())
+    }
+}
+
+fn json_str(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    s.chars().for_each(|c| push_json_char(&mut json, c));
+    json.push('"');
+    json
+}
+
+/// A blob as a JSON string of its text, where bytes that aren't valid UTF-8 are escaped.
+fn json_bytes(bytes: &[u8]) -> String {
+    let mut json = String::with_capacity(bytes.len() + 2);
+    json.push('"');
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (valid, invalid) = match str::from_utf8(rest) {
+            Ok(valid) => (valid, &rest[rest.len()..]),
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                let invalid_len = e.error_len().unwrap_or(after.len());
+                // The prefix was just checked to be valid UTF-8.
+                let valid = str::from_utf8(valid).unwrap_or_default();
+                (valid, &after[..invalid_len])
+            }
+        };
+        valid.chars().for_each(|c| push_json_char(&mut json, c));
+        for b in invalid {
+            json.push_str(&format!("\\u{:04x}", b));
+        }
+        rest = &rest[valid.len() + invalid.len()..];
+    }
+    json.push('"');
+    json
+}
+
+fn push_json_char(json: &mut String, c: char) {
+    match c {
+        '"' => json.push_str("\\\""),
+        '\\' => json.push_str("\\\\"),
+        '\u{8}' => json.push_str("\\b"),
+        '\u{c}' => json.push_str("\\f"),
+        '\n' => json.push_str("\\n"),
+        '\r' => json.push_str("\\r"),
+        '\t' => json.push_str("\\t"),
+        c if c < ' ' || c == '\u{7f}' => json.push_str(&format!("\\u{:04x}", c as u32)),
+        c => json.push(c),
+    }
+}
+
+/// Prints every value on a line of its own next to its column name.
+struct Line<'a> {
+    out: Out<'a>,
+    config: &'a OutputConfig,
+    cols: Vec<String>,
+    rows: usize,
+}
+
+impl<'a> ResultSink for Line<'a> {
+    fn begin(&mut self, cols: &[String]) -> Result<()> {
+        self.cols = cols.to_vec();
+        Ok(())
+    }
+
+    fn row(&mut self, row: &[Value]) -> Result<()> {
+        let row_sep = &self.config.row_sep;
+        if self.rows > 0 {
+            write!(self.out, "{}", row_sep)?;
+        }
+        self.rows += 1;
+
+        // sqlite3 aligns the names to a width of at least 5.
+        let width = self
+            .cols
+            .iter()
+            .map(|c| c.chars().count())
+            .fold(5, usize::max);
+        for (col, val) in self.cols.iter().zip(row) {
+            let val = text(val, &self.config.null_value);
+            write!(self.out, "{:>w$} = {}{}", col, val, row_sep, w = width)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Prints the rows of an HTML table without the surrounding `<TABLE>` tags.
+struct Html<'a> {
+    out: Out<'a>,
+    config: &'a OutputConfig,
+    cols: Vec<String>,
+    rows: usize,
+}
+
+impl<'a> ResultSink for Html<'a> {
+    fn begin(&mut self, cols: &[String]) -> Result<()> {
+        self.cols = cols.to_vec();
+        Ok(())
+    }
+
+    fn row(&mut self, row: &[Value]) -> Result<()> {
+        if self.rows == 0 && self.config.shows_headers() {
+            write!(self.out, "<TR>")?;
+            for col in &self.cols {
+                writeln!(self.out, "<TH>{}</TH>", html_escape(col))?;
+            }
+            writeln!(self.out, "</TR>")?;
+        }
+        self.rows += 1;
+
+        write!(self.out, "<TR>")?;
+        for val in row {
+            let val = text(val, &self.config.null_value);
+            writeln!(self.out, "<TD>{}</TD>", html_escape(&val))?;
+        }
+        writeln!(self.out, "</TR>")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn html_escape(s: &str) -> Cow<'_, str> {
+    if !s.contains(['<', '>', '&', '"', '\''].as_ref()) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut html = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '&' => html.push_str("&amp;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    Cow::Owned(html)
+}
+
+/// Prints an INSERT statement per row that recreates it in the table `insert_tbl`.
+struct Insert<'a> {
+    out: Out<'a>,
+    config: &'a OutputConfig,
+    cols: Vec<String>,
+}
+
+impl<'a> ResultSink for Insert<'a> {
+    fn begin(&mut self, cols: &[String]) -> Result<()> {
+        self.cols = cols.to_vec();
+        Ok(())
+    }
+
+    fn row(&mut self, row: &[Value]) -> Result<()> {
+        write!(
+            self.out,
+            "INSERT INTO {}",
+            quote_ident(&self.config.insert_tbl)
+        )?;
+        if self.config.shows_headers() {
+            let cols = self.cols.iter().map(|c| quote_ident(c));
+            write!(self.out, "({})", cols.collect::<Vec<_>>().join(","))?;
+        }
+
+        let vals = row.iter().map(sql_literal).collect::<Vec<_>>();
+        writeln!(self.out, " VALUES({});", vals.join(","))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Quotes an identifier with double quotes unless it can be used as is.
+pub fn quote_ident(ident: &str) -> Cow<'_, str> {
+    let is_plain = ident.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && ident.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_plain && !is_keyword(ident) {
+        Cow::Borrowed(ident)
+    } else {
+        Cow::Owned(format!("\"{}\"", ident.replace('"', "\"\"")))
+    }
+}
+
+/// The value as an SQL literal like sqlite3 writes it in insert mode.
+pub fn sql_literal(val: &Value) -> String {
+    match val {
+        Value::Null => "NULL".to_owned(),
+        Value::Float(r) => float_literal(*r),
+        // Control characters can only be written as escapes of the `unistr()` function.
+        Value::String(s) if s.contains(|c: char| c < ' ') => {
+            let mut escaped = String::with_capacity(s.len());
+            for c in s.chars() {
+                match c {
+                    '\'' => escaped.push_str("''"),
+                    '\\' => escaped.push_str("\\\\"),
+                    c if c < ' ' => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => escaped.push(c),
+                }
+            }
+            format!("unistr('{}')", escaped)
+        }
+        Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+        Value::Bytes(bytes) => {
+            let hex = bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>();
+            format!("X'{}'", hex)
+        }
+        _ => val.to_string(),
+    }
+}
+
+/// A REAL value with enough digits to read it back exactly. Integral values keep a `.0` to
+/// stay REAL, and infinities are written as a number too large for a double.
+fn float_literal(r: f64) -> String {
+    const I64_RANGE: std::ops::Range<f64> =
+        -9_223_372_036_854_775_808.0..9_223_372_036_854_775_808.0;
+    if r.is_infinite() {
+        if r > 0.0 { "9.0e+999" } else { "-9.0e+999" }.to_owned()
+    } else if I64_RANGE.contains(&r) && r.trunc() == r {
+        format!("{}.0", r as i64)
+    } else {
+        float_fmt::generic(r, 20)
+    }
+}
+
+/// The column, box, markdown and table modes, which align the values of each column. Values
+/// spanning multiple lines are split across lines of the output.
+struct Grid<'a> {
+    out: Out<'a>,
+    config: &'a OutputConfig,
+    cols: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+struct Borders {
+    top: Option<[&'static str; 3]>,
+    mid: Option<[&'static str; 3]>,
+    bottom: Option<[&'static str; 3]>,
+    line: &'static str,
+    vertical: [&'static str; 3],
+}
+
+const BOX: Borders = Borders {
+    top: Some(["┌─", "─┬─", "─┐"]),
+    mid: Some(["├─", "─┼─", "─┤"]),
+    bottom: Some(["└─", "─┴─", "─┘"]),
+    line: "─",
+    vertical: ["│ ", " │ ", " │"],
+};
+
+const TABLE: Borders = Borders {
+    top: Some(["+-", "-+-", "-+"]),
+    mid: Some(["+-", "-+-", "-+"]),
+    bottom: Some(["+-", "-+-", "-+"]),
+    line: "-",
+    vertical: ["| ", " | ", " |"],
+};
+
+const MARKDOWN: Borders = Borders {
+    top: None,
+    mid: Some(["|-", "-|-", "-|"]),
+    bottom: None,
+    line: "-",
+    vertical: ["| ", " | ", " |"],
+};
+
+const COLUMN: Borders = Borders {
+    top: None,
+    mid: Some(["", "  ", ""]),
+    bottom: None,
+    line: "-",
+    vertical: ["", "  ", ""],
+};
+
+impl<'a> Grid<'a> {
+    fn borders(&self) -> &'static Borders {
+        match self.config.mode {
+            OutputMode::Box => &BOX,
+            OutputMode::Table => &TABLE,
+            OutputMode::Markdown => &MARKDOWN,
+            _ => &COLUMN,
+        }
+    }
+
+    fn write_rule(&mut self, rule: [&str; 3], widths: &[usize]) -> Result<()> {
+        let line = self.borders().line;
+        let segments = widths.iter().map(|w| line.repeat(*w)).collect::<Vec<_>>();
+        writeln!(self.out, "{}{}{}", rule[0], segments.join(rule[1]), rule[2])?;
+        Ok(())
+    }
+
+    fn write_cells(&mut self, cells: &[String], widths: &[usize], centered: bool) -> Result<()> {
+        let [left, sep, right] = self.borders().vertical;
+        let lines = cells.iter().map(|c| c.split('\n').collect::<Vec<_>>());
+        let lines = lines.collect::<Vec<_>>();
+        let height = lines.iter().map(Vec::len).max().unwrap_or(1);
+
+        for i in 0..height {
+            let padded = lines.iter().zip(widths).map(|(cell, w)| {
+                let line = cell.get(i).copied().unwrap_or("");
+                let pad = w.saturating_sub(line.chars().count());
+                let pad_left = if centered { pad / 2 } else { 0 };
+                format!(
+                    "{}{}{}",
+                    " ".repeat(pad_left),
+                    line,
+                    " ".repeat(pad - pad_left)
+                )
+            });
+            let padded = padded.collect::<Vec<_>>();
+            writeln!(self.out, "{}{}{}", left, padded.join(sep), right)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ResultSink for Grid<'a> {
+    fn begin(&mut self, cols: &[String]) -> Result<()> {
+        self.cols = cols.iter().map(|c| expand_tabs(c)).collect();
+        Ok(())
+    }
+
+    fn row(&mut self, row: &[Value]) -> Result<()> {
+        let null_value = &self.config.null_value;
+        let row = row.iter().map(|v| expand_tabs(&text(v, null_value)));
+        self.rows.push(row.collect());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let rows = std::mem::take(&mut self.rows);
+        let cols = std::mem::take(&mut self.cols);
+        let line_width = |cell: &String| cell.split('\n').map(|l| l.chars().count()).max();
+
+        let widths = (0..cols.len())
+            .map(|i| {
+                rows.iter()
+                    .map(|row| &row[i])
+                    .chain(once(&cols[i]))
+                    .filter_map(line_width)
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        let is_multi_line = rows.iter().flatten().any(|cell| cell.contains('\n'));
+
+        let borders = self.borders();
+        if let Some(top) = borders.top {
+            self.write_rule(top, &widths)?;
+        }
+        if self.config.shows_headers() {
+            let centered = self.config.mode != OutputMode::Column;
+            self.write_cells(&cols, &widths, centered)?;
+            if let Some(mid) = borders.mid {
+                self.write_rule(mid, &widths)?;
+            }
+        }
+
+        for (i, row) in rows.iter().enumerate() {
+            if i > 0 && is_multi_line {
+                match self.config.mode {
+                    OutputMode::Column => writeln!(self.out)?,
+                    OutputMode::Box | OutputMode::Table => {
+                        if let Some(mid) = borders.mid {
+                            self.write_rule(mid, &widths)?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            self.write_cells(row, &widths, false)?;
+        }
+
+        if let Some(bottom) = borders.bottom {
+            self.write_rule(bottom, &widths)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces tabs with the spaces up to the next multiple of 8 within a line, as sqlite3 does to
+/// keep columns aligned.
+fn expand_tabs(s: &str) -> String {
+    let mut expanded = String::with_capacity(s.len());
+    let mut col = 0;
+    for c in s.chars() {
+        match c {
+            '\t' => {
+                let n = 8 - col % 8;
+                expanded.push_str(&" ".repeat(n));
+                col += n;
+            }
+            '\n' => {
+                expanded.push(c);
+                col = 0;
+            }
+            c => {
+                expanded.push(c);
+                col += 1;
+            }
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn print(config: &OutputConfig, cols: &[&str], rows: &[Vec<Value>]) -> String {
+        let mut out = vec![];
+        {
+            let mut sink = config.sink(&mut out);
+            let cols = cols.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            sink.begin(&cols).unwrap();
+            for row in rows {
+                sink.row(row).unwrap();
+            }
+            sink.finish().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn config(mode: OutputMode) -> OutputConfig {
+        let mut config = OutputConfig::default();
+        config.set_mode(mode, None);
+        config
+    }
+
+    fn rows() -> Vec<Vec<Value<'static>>> {
+        vec![
+            vec![Value::Int(1), Value::String("a,\"b\"".into())],
+            vec![Value::Int(2), Value::Null],
+        ]
+    }
+
+    #[test]
+    fn csv_quotes_values_and_uses_crlf() {
+        let mut config = config(OutputMode::Csv);
+        config.headers = Some(true);
+        config.null_value = "NULL".to_owned();
+        assert_eq!(
+            print(&config, &["id", "x y"], &rows()),
+            "id,\"x y\"\r\n1,\"a,\"\"b\"\"\"\r\n2,NULL\r\n"
+        );
+    }
+
+    #[test]
+    fn json_has_an_object_per_row() {
+        assert_eq!(
+            print(&config(OutputMode::Json), &["id", "x"], &rows()),
+            "[{\"id\":1,\"x\":\"a,\\\"b\\\"\"},\n{\"id\":2,\"x\":null}]\n"
+        );
+        assert_eq!(print(&config(OutputMode::Json), &["id"], &[]), "");
+    }
+
+    #[test]
+    fn box_centers_headers() {
+        assert_eq!(
+            print(&config(OutputMode::Box), &["id", "x"], &rows()),
+            "┌────┬───────┐\n\
+             │ id │   x   │\n\
+             ├────┼───────┤\n\
+             │ 1  │ a,\"b\" │\n\
+             │ 2  │       │\n\
+             └────┴───────┘\n"
+        );
+    }
+
+    #[test]
+    fn column_separates_multi_line_rows() {
+        let rows = vec![
+            vec![Value::String("a\tb".into())],
+            vec![Value::String("c\nd".into())],
+        ];
+        assert_eq!(
+            print(&config(OutputMode::Column), &["x"], &rows),
+            "x        \n---------\na       b\n\nc        \nd        \n"
+        );
+    }
+
+    #[test]
+    fn insert_quotes_identifiers_and_literals() {
+        let mut config = OutputConfig::default();
+        config.set_mode(OutputMode::Insert, Some("my tbl".to_owned()));
+        let rows = vec![vec![
+            Value::String("it's".into()),
+            Value::Bytes(Cow::Borrowed(&[0, 255])),
+        ]];
+        assert_eq!(
+            print(&config, &["a", "b"], &rows),
+            "INSERT INTO \"my tbl\" VALUES('it''s',X'00ff');\n"
+        );
+    }
+
+    #[test]
+    fn insert_escapes_control_chars_and_keeps_floats_exact() {
+        let rows = vec![vec![
+            Value::String("a\\'b\n".into()),
+            Value::Float(2.0),
+            Value::Float(-0.1),
+            Value::Float(f64::INFINITY),
+        ]];
+        assert_eq!(
+            print(&config(OutputMode::Insert), &["a", "b", "c", "d"], &rows),
+            "INSERT INTO \"table\" VALUES(unistr('a\\\\''b\\u000a'),2.0,\
+             -0.1000000000000000055,9.0e+999);\n"
+        );
+    }
+
+    #[test]
+    fn json_escapes_invalid_utf8_of_blobs() {
+        let rows = vec![vec![
+            Value::Bytes(Cow::Borrowed(&[0, 0xc3, 0xa9, 0xff])),
+            Value::Float(1e20),
+        ]];
+        assert_eq!(
+            print(&config(OutputMode::Json), &["b", "f"], &rows),
+            "[{\"b\":\"\\u0000é\\u00ff\",\"f\":1.0e+20}]\n"
+        );
+    }
+}
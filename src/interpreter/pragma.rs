@@ -0,0 +1,600 @@
+use crate::{
+    format::{Enc, Error},
+    interpreter::{
+        database::Database,
+        eval::Value,
+        integrity::{self, CheckOpts},
+        output::OutputConfig,
+    },
+    schema::{DbSchema, Generated, KeyCol, ObjSchema},
+    syntax::{parse, ColConstraint, SortOrder, SqlStmt, TblConstraint},
+};
+use anyhow::{bail, Result};
+use std::{borrow::Cow, collections::HashMap, env, fs, io};
+
+/// The settings this implementation would have been compiled with if it were SQLite.
+const COMPILE_OPTIONS: &[&str] = &[
+    "DEFAULT_PAGE_SIZE=4096",
+    "OMIT_LOAD_EXTENSION",
+    "THREADSAFE=0",
+];
+
+type Rows<'a> = Vec<Vec<Value<'a>>>;
+
+pub fn run(
+    schema: Option<&str>,
+    name: &str,
+    arg: Option<&str>,
+    database: &Database,
+    config: &OutputConfig,
+) -> Result<()> {
+    let (cols, rows) = query(schema, name, arg, database)?;
+    if cols.is_empty() {
+        return Ok(());
+    }
+
+    let stdout = io::stdout();
+    let mut sink = config.sink(stdout.lock());
+    sink.begin(&cols)?;
+    rows.iter().try_for_each(|row| sink.row(row))?;
+    sink.finish()
+}
+
+/// Returns the names of the result columns and the result rows. Unknown pragmas have neither.
+pub fn query<'a>(
+    schema: Option<&str>,
+    name: &str,
+    arg: Option<&str>,
+    database: &'a Database,
+) -> Result<(Vec<String>, Rows<'a>)> {
+    if let Some(schema) = schema.filter(|s| !s.eq_ignore_ascii_case("main")) {
+        bail!("unknown database {}", schema);
+    }
+
+    let db_schema = &database.schema;
+    let h = &db_schema.db_header;
+    let name = name.to_ascii_lowercase();
+    let (cols, rows): (&[&str], Rows) = match (name.as_str(), arg) {
+        ("table_info", Some(tbl)) => (
+            &["cid", "name", "type", "notnull", "dflt_value", "pk"],
+            table_info(db_schema, tbl, false)?,
+        ),
+        ("table_xinfo", Some(tbl)) => (
+            &[
+                "cid",
+                "name",
+                "type",
+                "notnull",
+                "dflt_value",
+                "pk",
+                "hidden",
+            ],
+            table_info(db_schema, tbl, true)?,
+        ),
+        ("index_list", Some(tbl)) => (
+            &["seq", "name", "unique", "origin", "partial"],
+            index_list(db_schema, tbl)?,
+        ),
+        ("index_info", Some(idx)) => (
+            &["seqno", "cid", "name"],
+            index_info(db_schema, idx, false)?,
+        ),
+        ("index_xinfo", Some(idx)) => (
+            &["seqno", "cid", "name", "desc", "coll", "key"],
+            index_info(db_schema, idx, true)?,
+        ),
+        ("foreign_key_list", Some(tbl)) => (
+            &[
+                "id",
+                "seq",
+                "table",
+                "from",
+                "to",
+                "on_update",
+                "on_delete",
+                "match",
+            ],
+            foreign_key_list(db_schema, tbl)?,
+        ),
+        (
+            "page_size" | "user_version" | "application_id" | "schema_version" | "encoding",
+            Some(_),
+        ) => bail!("Setting PRAGMA {} is not supported", name),
+        ("page_size", None) => (&["page_size"], vec![vec![int(h.page_size)]]),
+        ("page_count", None) => (
+            &["page_count"],
+            vec![vec![int(h.page_count(database.bytes.len()))]],
+        ),
+        ("freelist_count", None) => (&["freelist_count"], vec![vec![int(h.freelist_page_count)]]),
+        ("encoding", None) => {
+            let enc = match h.text_encoding {
+                Enc::Utf8 => "UTF-8",
+                Enc::Utf16Le => "UTF-16le",
+                Enc::Utf16Be => "UTF-16be",
+            };
+            (&["encoding"], vec![vec![text(enc)]])
+        }
+        ("user_version", None) => (&["user_version"], vec![vec![int(h.user_version)]]),
+        ("application_id", None) => (&["application_id"], vec![vec![int(h.application_id)]]),
+        ("schema_version", None) => (&["schema_version"], vec![vec![int(h.schema_cookie)]]),
+        ("database_list", None) => {
+            // Like sqlite3, the file is shown with its full path even if it doesn't exist yet.
+            let path = fs::canonicalize(database.path())
+                .or_else(|_| env::current_dir().map(|dir| dir.join(database.path())))?;
+            let file = text(path.to_string_lossy().into_owned());
+            (
+                &["seq", "name", "file"],
+                vec![vec![int(0), text("main"), file]],
+            )
+        }
+        ("compile_options", None) => (
+            &["compile_options"],
+            COMPILE_OPTIONS.iter().map(|o| vec![text(*o)]).collect(),
+        ),
+        ("integrity_check" | "quick_check", arg) => {
+            let mut opts = CheckOpts {
+                tbl: None,
+                max_errors: integrity::MAX_ERRORS,
+                quick: name == "quick_check",
+            };
+            match arg.map(|a| (a, a.parse::<i64>())) {
+                None => {}
+                Some((_, Ok(n))) if n > 0 => opts.max_errors = n as usize,
+                Some((_, Ok(_))) => {}
+                Some((tbl, Err(_))) => match db_schema
+                    .tables()
+                    .find(|t| t.name.eq_ignore_ascii_case(tbl))
+                {
+                    Some(tbl) => opts.tbl = Some(tbl),
+                    None => bail!("no such table: {}", tbl),
+                },
+            }
+            let msgs = integrity::check(database, &opts)?;
+            (
+                if opts.quick {
+                    &["quick_check"]
+                } else {
+                    &["integrity_check"]
+                },
+                msgs.into_iter().map(|m| vec![text(m)]).collect(),
+            )
+        }
+        // COUNT(*) is the only function that can be used in queries so far.
+        ("function_list", None) => (
+            &["name", "builtin", "type", "enc", "narg", "flags"],
+            vec![vec![
+                text("count"),
+                int(1),
+                text("w"),
+                text("utf8"),
+                int(0),
+                int(0x200000),
+            ]],
+        ),
+        // Like in SQLite, unknown pragmas are no error.
+        _ => return Ok((vec![], vec![])),
+    };
+
+    Ok((cols.iter().map(|c| c.to_string()).collect(), rows))
+}
+
+fn int<'a>(x: impl Into<i64>) -> Value<'a> {
+    Value::Int(x.into())
+}
+
+fn text<'a>(s: impl Into<Cow<'a, str>>) -> Value<'a> {
+    Value::String(s.into())
+}
+
+fn find_tbl<'a>(db_schema: &'a DbSchema, name: &str) -> Result<Option<&'a ObjSchema>> {
+    let obj = db_schema
+        .objs
+        .iter()
+        .find(|o| (o.is_table() || o.is_view()) && o.name.eq_ignore_ascii_case(name));
+    match obj {
+        Some(view) if view.is_view() => bail!("Columns of views are not supported"),
+        obj => Ok(obj),
+    }
+}
+
+/// Lists a table's columns in the order they were declared in. Like in SQLite, generated
+/// columns are hidden from `table_info`, while `table_xinfo` marks them as hidden 2 if they're
+/// VIRTUAL and 3 if they're STORED.
+fn table_info<'a>(db_schema: &'a DbSchema, tbl: &str, xinfo: bool) -> Result<Rows<'a>> {
+    let tbl = match find_tbl(db_schema, tbl)? {
+        Some(tbl) => tbl,
+        None => return Ok(vec![]),
+    };
+    let (col_defs, _) = tbl.parse_create_tbl()?;
+    let cols = tbl.cols()?;
+    Ok(col_defs
+        .iter()
+        .map(|col| {
+            let hidden = match cols.generated(col.name) {
+                None => 0,
+                Some(Generated::Virtual(_)) => 2,
+                Some(Generated::Stored) => 3,
+            };
+            (col, hidden)
+        })
+        .filter(|(_, hidden)| xinfo || *hidden == 0)
+        .enumerate()
+        .map(|(cid, (col, hidden))| {
+            let pk = cols.pk().iter().position(|c| c.name == col.name);
+            let mut row = vec![
+                int(cid as i64),
+                text(col.name),
+                text(col.display_type()),
+                int(cols.is_not_null(col.name)),
+                col.default_value().map_or(Value::Null, text),
+                int(pk.map_or(0, |pos| pos as i64 + 1)),
+            ];
+            if xinfo {
+                row.push(int(hidden));
+            }
+            row
+        })
+        .collect())
+}
+
+/// Lists a table's indexes starting with the most recently created one. `origin` tells if an
+/// index was created by CREATE INDEX (c), a UNIQUE constraint (u) or the PRIMARY KEY (pk).
+fn index_list<'a>(db_schema: &'a DbSchema, tbl: &str) -> Result<Rows<'a>> {
+    let tbl = match db_schema
+        .tables()
+        .find(|t| t.name.eq_ignore_ascii_case(tbl))
+    {
+        Some(tbl) => tbl,
+        None => return Ok(vec![]),
+    };
+    let tbl_cols = tbl.cols()?;
+
+    let mut idxs = db_schema
+        .indexes()
+        .filter(|i| i.tbl_name.eq_ignore_ascii_case(&tbl.name))
+        .map(|i| {
+            let cols = i.cols()?;
+            let origin = match i.autoindex_num() {
+                None => "c",
+                Some(_) if cols.key() == tbl_cols.pk() => "pk",
+                Some(_) => "u",
+            };
+            let row = vec![
+                text(i.name.as_str()),
+                int(cols.is_unique()),
+                text(origin),
+                int(cols.is_partial()),
+            ];
+            Ok((i.autoindex_num(), row))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // The primary key index of a WITHOUT ROWID table is the table itself, so it's missing from
+    // the schema table.
+    if let Some(pk_num) = tbl_cols.pk_autoindex_num() {
+        let pos = idxs
+            .iter()
+            .position(|(num, _)| num.map_or(true, |num| num > pk_num))
+            .unwrap_or(idxs.len());
+        let name = format!("sqlite_autoindex_{}_{}", tbl.name, pk_num);
+        let row = vec![text(name), int(1), text("pk"), int(0)];
+        idxs.insert(pos, (Some(pk_num), row));
+    }
+
+    Ok(idxs
+        .into_iter()
+        .rev()
+        .enumerate()
+        .map(|(seq, (_, row))| {
+            let mut row = row;
+            row.insert(0, int(seq as i64));
+            row
+        })
+        .collect())
+}
+
+/// Lists the key columns of an index. The extended list also has the columns that follow the
+/// key in the index's entries, which is the rowid or the rest of a WITHOUT ROWID table's primary
+/// key. For the primary key of a WITHOUT ROWID table those are the remaining columns.
+fn index_info<'a>(db_schema: &'a DbSchema, idx: &str, xinfo: bool) -> Result<Rows<'a>> {
+    let (tbl, key, idx) = match find_idx(db_schema, idx)? {
+        Some(found) => found,
+        None => return Ok(vec![]),
+    };
+    let tbl_cols = tbl.cols()?;
+
+    // The columns of an index use the collation of the table's columns unless the index or
+    // the constraint it was created for overrides it.
+    let (col_defs, constraints) = tbl.parse_create_tbl()?;
+    let mut collations = col_defs
+        .iter()
+        .filter_map(|c| Some((c.name, c.collation()?)))
+        .collect::<HashMap<_, _>>();
+    let idx_sql = idx.and_then(|i| i.sql.as_deref());
+    let idx_cols = match idx_sql.map(parse::sql_stmt).transpose()? {
+        Some(SqlStmt::CreateIdx { target_cols, .. }) => target_cols,
+        _ => constraints
+            .into_iter()
+            .find_map(|c| match c {
+                TblConstraint::PrimaryKey(cols) | TblConstraint::Unique(cols)
+                    if cols
+                        .iter()
+                        .map(|c| c.name)
+                        .eq(key.iter().map(|c| c.name.as_str())) =>
+                {
+                    Some(cols)
+                }
+                _ => None,
+            })
+            .unwrap_or_default(),
+    };
+    collations.extend(idx_cols.iter().filter_map(|c| Some((c.name, c.collation?))));
+    let cid = |name: &str| {
+        let cid = tbl_cols.names().position(|n| n == name);
+        cid.map(|cid| cid as i64)
+            .ok_or_else(|| Error::CorruptSchema {
+                name: idx.map_or(&tbl.name, |i| &i.name).clone(),
+                reason: format!("no such column: {}", name),
+            })
+    };
+
+    let key_cols = key.iter().map(|c| (c, true));
+    let rest = if !tbl_cols.is_without_rowid() {
+        vec![]
+    } else if key == tbl_cols.pk() {
+        tbl_cols
+            .names()
+            .filter(|n| !key.iter().any(|c| c.name == *n))
+            .map(|n| KeyCol {
+                name: n.to_owned(),
+                order: SortOrder::Asc,
+                collation: None,
+            })
+            .collect()
+    } else {
+        // Like in SQLite, the primary key columns that follow the key of an automatic index are
+        // listed in ascending order.
+        tbl_cols
+            .pk()
+            .iter()
+            .filter(|pk_col| !key.iter().any(|c| c.name == pk_col.name))
+            .map(|c| KeyCol {
+                name: c.name.clone(),
+                order: if idx_sql.is_some() {
+                    c.order
+                } else {
+                    SortOrder::Asc
+                },
+                collation: None,
+            })
+            .collect()
+    };
+
+    let mut rows = key_cols
+        .chain(rest.iter().map(|c| (c, false)))
+        .take(if xinfo { usize::MAX } else { key.len() })
+        .enumerate()
+        .map(|(seqno, (col, is_key))| {
+            let mut row = vec![
+                int(seqno as i64),
+                int(cid(&col.name)?),
+                text(col.name.clone()),
+            ];
+            if xinfo {
+                row.extend([
+                    int(col.order == SortOrder::Desc),
+                    text(
+                        collations
+                            .get(col.name.as_str())
+                            .copied()
+                            .unwrap_or("BINARY"),
+                    ),
+                    int(is_key),
+                ]);
+            }
+            Ok(row)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if xinfo && !tbl_cols.is_without_rowid() {
+        let seqno = int(rows.len() as i64);
+        rows.push(vec![
+            seqno,
+            int(-1),
+            Value::Null,
+            int(0),
+            text("BINARY"),
+            int(0),
+        ]);
+    }
+    Ok(rows)
+}
+
+/// An index with its table and key. The primary key of a WITHOUT ROWID table has no object of
+/// its own.
+type FoundIdx<'a> = (&'a ObjSchema, &'a [KeyCol], Option<&'a ObjSchema>);
+
+/// Finds an index by its name together with its table and key. WITHOUT ROWID tables can be
+/// referred to by their own name or that of their primary key's automatic index.
+fn find_idx<'a>(db_schema: &'a DbSchema, name: &str) -> Result<Option<FoundIdx<'a>>> {
+    if let Some(idx) = db_schema
+        .indexes()
+        .find(|i| i.name.eq_ignore_ascii_case(name))
+    {
+        let tbl = db_schema.tables().find(|t| t.name == idx.tbl_name);
+        return Ok(match tbl {
+            Some(tbl) => Some((tbl, idx.cols()?.key(), Some(idx))),
+            None => None,
+        });
+    }
+
+    for tbl in db_schema.tables() {
+        let cols = tbl.cols()?;
+        if !cols.is_without_rowid() {
+            continue;
+        }
+        let is_pk_name = cols.pk_autoindex_num().map_or(false, |n| {
+            format!("sqlite_autoindex_{}_{}", tbl.name, n).eq_ignore_ascii_case(name)
+        });
+        if tbl.name.eq_ignore_ascii_case(name) || is_pk_name {
+            return Ok(Some((tbl, cols.key(), None)));
+        }
+    }
+    Ok(None)
+}
+
+/// Lists the foreign keys of a table, numbered in the reverse order of their declaration, with a
+/// row for each of their columns. SQLite doesn't support MATCH clauses, so `match` is always
+/// NONE.
+fn foreign_key_list<'a>(db_schema: &'a DbSchema, tbl: &str) -> Result<Rows<'a>> {
+    let tbl = match find_tbl(db_schema, tbl)? {
+        Some(tbl) => tbl,
+        None => return Ok(vec![]),
+    };
+    let (col_defs, constraints) = tbl.parse_create_tbl()?;
+
+    let col_fks = col_defs.iter().flat_map(|col| {
+        col.constraints.iter().filter_map(move |c| match c {
+            ColConstraint::ForeignKey(fk) => Some((vec![col.name], fk)),
+            _ => None,
+        })
+    });
+    let tbl_fks = constraints.iter().filter_map(|c| match c {
+        TblConstraint::ForeignKey { cols, fk } => Some((cols.clone(), fk)),
+        _ => None,
+    });
+    let fks = col_fks.chain(tbl_fks).collect::<Vec<_>>();
+
+    Ok(fks
+        .iter()
+        .rev()
+        .enumerate()
+        .flat_map(|(id, (from, fk))| {
+            from.iter().enumerate().map(move |(seq, from)| {
+                vec![
+                    int(id as i64),
+                    int(seq as i64),
+                    text(fk.tbl),
+                    text(*from),
+                    fk.cols.get(seq).map_or(Value::Null, |to| text(*to)),
+                    text(fk.on_update.to_string()),
+                    text(fk.on_delete.to_string()),
+                    text("NONE"),
+                ]
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schema::Cols;
+
+    fn schema(sqls: &[&str]) -> DbSchema {
+        let mut db_schema = DbSchema::parse(&Database::empty()).unwrap();
+        db_schema.objs = sqls
+            .iter()
+            .zip(2..)
+            .map(|(sql, rootpage)| {
+                let (type_, name, tbl_name) = match parse::sql_stmt(sql).unwrap() {
+                    SqlStmt::CreateTbl { name, .. } => ("table", name, name),
+                    SqlStmt::CreateIdx {
+                        name, target_tbl, ..
+                    } => ("index", name, target_tbl),
+                    _ => unreachable!(),
+                };
+                ObjSchema {
+                    type_: type_.to_owned(),
+                    name: name.to_owned(),
+                    tbl_name: tbl_name.to_owned(),
+                    rootpage,
+                    sql: Some(sql.to_string()),
+                    cols: Some(Cols::parse(sql).unwrap()),
+                }
+            })
+            .collect();
+        db_schema
+    }
+
+    fn strings(rows: Rows) -> Vec<String> {
+        rows.iter()
+            .map(|row| {
+                row.iter()
+                    .map(|v| format!("{}", v))
+                    .collect::<Vec<_>>()
+                    .join("|")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lists_cols_of_tbl() {
+        let db_schema = schema(&[
+            "create table t (a int not null default 5, b varchar(10) default (1 + 2), c, \
+             primary key (c, a))",
+        ]);
+        assert_eq!(
+            strings(table_info(&db_schema, "T", false).unwrap()),
+            [
+                "0|a|INT|1|5|2",
+                "1|b|varchar(10)|0|1 + 2|0",
+                "2|c||0|NULL|1"
+            ]
+        );
+        assert!(table_info(&db_schema, "u", false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn hides_generated_cols() {
+        let db_schema = schema(&["create table t (a, v as (a) virtual, b, s int as (a) stored)"]);
+        assert_eq!(
+            strings(table_info(&db_schema, "t", false).unwrap()),
+            ["0|a||0|NULL|0", "1|b||0|NULL|0"]
+        );
+        assert_eq!(
+            strings(table_info(&db_schema, "t", true).unwrap()),
+            [
+                "0|a||0|NULL|0|0",
+                "1|v||0|NULL|0|2",
+                "2|b||0|NULL|0|0",
+                "3|s|INT|0|NULL|0|3",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_foreign_keys_in_reverse() {
+        let db_schema = schema(&[
+            "create table t (a references p (x) on delete cascade, b, c, \
+             foreign key (b, c) references q on update set null)",
+        ]);
+        assert_eq!(
+            strings(foreign_key_list(&db_schema, "t").unwrap()),
+            [
+                "0|0|q|b|NULL|SET NULL|NO ACTION|NONE",
+                "0|1|q|c|NULL|SET NULL|NO ACTION|NONE",
+                "1|0|p|a|x|NO ACTION|CASCADE|NONE",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_pk_after_key_of_without_rowid_idx() {
+        let db_schema = schema(&[
+            "create table t (a, b collate nocase, c, primary key (c, a desc)) without rowid",
+            "create index i on t (b desc)",
+        ]);
+        assert_eq!(
+            strings(index_info(&db_schema, "i", true).unwrap()),
+            ["0|1|b|1|nocase|1", "1|2|c|0|BINARY|0", "2|0|a|1|BINARY|0"]
+        );
+        assert_eq!(
+            strings(index_info(&db_schema, "t", false).unwrap()),
+            ["0|2|c", "1|0|a"]
+        );
+        assert_eq!(
+            strings(index_list(&db_schema, "t").unwrap()),
+            ["0|i|0|c|0", "1|sqlite_autoindex_t_1|1|pk|0"]
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    interpreter::{database::Database, exec, output::OutputConfig},
+    syntax::{parse, DotCmd, Sqlite},
+};
+use anyhow::{anyhow, Result};
+use rustyline::{error::ReadlineError, Editor};
+use std::{
+    env,
+    io::{self, Stdin},
+    path::PathBuf,
+};
+
+const PROMPT: &str = "sqlite> ";
+const CONTINUATION_PROMPT: &str = "   ...> ";
+const HISTORY_FILE: &str = ".sqlite_starter_history";
+
+/// Reads SQL statements and dot-commands from stdin and runs them one after another. A statement
+/// may span multiple lines and is only run once it's terminated by a `;`, while dot-commands
+/// always fit on a single line. Errors are reported without ending the session, and like in
+/// sqlite3 the returned flag tells whether there were none.
+pub fn run(db: &mut Database) -> Result<bool> {
+    let mut input = Input::open()?;
+    let mut config = OutputConfig::default();
+    let mut buf = String::new();
+    let mut ok = true;
+    let mut report = |result: Result<()>| {
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            ok = false;
+        }
+    };
+
+    while let Some(line) = input.read_line(&buf)? {
+        if buf.trim().is_empty() && line.trim_start().starts_with('.') {
+            input.add_history(&line);
+            match parse::sqlite(line.trim()) {
+                Ok(Sqlite::DotCmd(DotCmd::Quit)) => break,
+                Ok(cmd) => report(exec::sqlite(cmd, db, &mut config)),
+                Err(e) => report(Err(anyhow!("Invalid command: {}", e))),
+            }
+            buf.clear();
+            continue;
+        }
+
+        buf.push_str(&line);
+        buf.push('\n');
+
+        let (stmts, rest) = split_stmts(&buf);
+        if stmts.is_empty() {
+            continue;
+        }
+
+        input.add_history(buf[..buf.len() - rest.len()].trim());
+        for stmt in stmts {
+            match parse::sqlite(stmt) {
+                Ok(stmt) => report(exec::sqlite(stmt, db, &mut config)),
+                Err(e) => report(Err(anyhow!("Invalid SQL: {}", e))),
+            }
+        }
+        buf = rest.to_owned();
+    }
+
+    if !buf.trim().is_empty() {
+        report(Err(anyhow!("Incomplete input: {}", buf.trim())));
+    }
+
+    input.close()?;
+    Ok(ok)
+}
+
+/// Splits off all statements terminated by a `;` from the start of `sql` and returns them
+/// without their `;` together with the unterminated rest. Semicolons inside of quotes and
+/// comments don't end a statement.
+fn split_stmts(sql: &str) -> (Vec<&str>, &str) {
+    let mut stmts = vec![];
+    let mut start = 0;
+    let mut chars = sql.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let closing = match c {
+            ';' => {
+                let stmt = sql[start..i].trim();
+                if !stmt.is_empty() {
+                    stmts.push(stmt);
+                }
+                start = i + 1;
+                continue;
+            }
+            '\'' | '"' | '`' => c,
+            '[' => ']',
+            '-' if matches!(chars.peek(), Some((_, '-'))) => '\n',
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                while let Some((_, c)) = chars.next() {
+                    if c == '*' && matches!(chars.peek(), Some((_, '/'))) {
+                        chars.next();
+                        break;
+                    }
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        // Doubled quotes are escapes, which are skipped as two separate quoted strings.
+        for (_, c) in chars.by_ref() {
+            if c == closing {
+                break;
+            }
+        }
+    }
+
+    (stmts, &sql[start..])
+}
+
+/// The source of the lines to run, which is a line editor with history when a user types them
+/// and the plain stdin otherwise.
+enum Input {
+    Terminal {
+        editor: Box<Editor<()>>,
+        history: Option<PathBuf>,
+    },
+    Piped(Stdin),
+}
+
+impl Input {
+    fn open() -> Result<Self> {
+        if !stdin_is_terminal() {
+            return Ok(Self::Piped(io::stdin()));
+        }
+
+        let mut editor = Box::new(Editor::<()>::new()?);
+        let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(history) = &history {
+            // There's no history yet when the shell is started for the first time.
+            let _ = editor.load_history(history);
+        }
+
+        Ok(Self::Terminal { editor, history })
+    }
+
+    /// Reads the next line, or `None` at the end of the input. `buf` holds the lines of an
+    /// unterminated statement read so far.
+    fn read_line(&mut self, buf: &str) -> Result<Option<String>> {
+        match self {
+            Self::Terminal { editor, .. } => {
+                let prompt = if buf.trim().is_empty() {
+                    PROMPT
+                } else {
+                    CONTINUATION_PROMPT
+                };
+                match editor.readline(prompt) {
+                    Ok(line) => Ok(Some(line)),
+                    // Like sqlite3, Ctrl-C only discards the current line.
+                    Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+                    Err(ReadlineError::Eof) => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Self::Piped(stdin) => {
+                let mut line = String::new();
+                if stdin.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Ok(Some(line))
+            }
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        if let Self::Terminal { editor, .. } = self {
+            editor.add_history_entry(entry);
+        }
+    }
+
+    fn close(mut self) -> Result<()> {
+        if let Self::Terminal {
+            editor,
+            history: Some(history),
+        } = &mut self
+        {
+            editor.save_history(history)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rust 1.54 has no `IsTerminal`, and the line editor keeps its own check private, so this asks
+/// the C library, which every Unix program links.
+#[cfg(unix)]
+fn stdin_is_terminal() -> bool {
+    extern "C" {
+        fn isatty(fd: i32) -> i32;
+    }
+    // SAFETY: `isatty` only inspects the file descriptor.
+    unsafe { isatty(0) == 1 }
+}
+
+/// Other platforms leave it to the line editor to fall back to plain reads.
+#[cfg(not(unix))]
+fn stdin_is_terminal() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::split_stmts;
+
+    #[test]
+    fn splits_terminated_stmts() {
+        let (stmts, rest) = split_stmts("select a from t;\n select b\nfrom u ; select");
+        assert_eq!(stmts, ["select a from t", "select b\nfrom u"]);
+        assert_eq!(rest, " select");
+
+        let (stmts, rest) = split_stmts(" ;; select 1;");
+        assert_eq!(stmts, ["select 1"]);
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn ignores_semicolons_in_quotes_and_comments() {
+        let sql = "select 'a;''b' from \"t;\" -- c;\n where [x;] = `y;` /* ; */;";
+        let (stmts, rest) = split_stmts(sql);
+        assert_eq!(stmts, [&sql[..sql.len() - 1]]);
+        assert_eq!(rest, "");
+
+        let (stmts, rest) = split_stmts("select 'a;\n");
+        assert!(stmts.is_empty());
+        assert_eq!(rest, "select 'a;\n");
+    }
+}
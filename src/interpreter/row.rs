@@ -0,0 +1,27 @@
+use crate::format::{LeafTblCell, Record};
+
+/// A row of a table. Rows of ordinary tables are cells of a table B-tree and have a rowid, while
+/// the rows of WITHOUT ROWID tables are entries of an index B-tree keyed by the primary key.
+#[derive(Debug)]
+pub struct Row<'a> {
+    pub row_id: Option<i64>,
+    pub record: Record<'a>,
+}
+
+impl<'a> From<LeafTblCell<'a>> for Row<'a> {
+    fn from(cell: LeafTblCell<'a>) -> Self {
+        Self {
+            row_id: Some(cell.row_id),
+            record: cell.payload,
+        }
+    }
+}
+
+impl<'a> From<Record<'a>> for Row<'a> {
+    fn from(record: Record<'a>) -> Self {
+        Self {
+            row_id: None,
+            record,
+        }
+    }
+}
@@ -1,193 +1,1717 @@
 use crate::{
-    format::{LeafTblCell, Page},
+    format::DbFile,
     interpreter::{
-        btree,
-        eval::{Eval, Value},
+        eval::Value,
+        explain,
+        ops::{col_positions, Key, Op, OpKind, QualifiedCol, Subquery, SubqueryKind},
+        output::OutputConfig,
     },
     schema::{DbSchema, ObjSchema},
-    syntax::{BoolExpr, Expr, Literal, Select},
-    util::{str_sim, IterEither, JoinOkExt},
+    syntax::{BoolExpr, CompoundOp, Cte, Expr, Limit, Literal, Query, Select, SortOrder, Source},
+    util::{str_sim, MapOkAndThenExt},
 };
 use anyhow::{anyhow, bail, Result};
 use itertools::Itertools;
-use std::convert::TryInto;
-
-pub fn run(select_stmt: &Select, db_schema: &DbSchema, db: &[u8]) -> Result<()> {
-    let page_size = db_schema.db_header.page_size.into();
-    let tbl_schema = db_schema
-        .table(select_stmt.tbl)
-        .ok_or_else(|| anyhow!("Table '{}' not found", select_stmt.tbl))?;
-    let rootpage = Page::parse(tbl_schema.rootpage, page_size, db)?;
-
-    validate_col_names(select_stmt, tbl_schema)?;
-
-    if let Some(pk) = by_int_pk(select_stmt, tbl_schema) {
-        int_pk_search(pk, select_stmt, &rootpage, tbl_schema, page_size, db)?;
-    } else if let Some((idx_schema, key)) = by_idx_key(select_stmt, db_schema) {
-        idx_search(
-            key,
-            idx_schema,
-            select_stmt,
-            &rootpage,
-            tbl_schema,
-            page_size,
-            db,
-        )?;
-    } else {
-        full_tbl_search(select_stmt, rootpage, tbl_schema, page_size, db)?;
-    }
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    convert::TryFrom,
+    io, iter,
+    ops::Range,
+    rc::Rc,
+};
 
-    Ok(())
-}
+/// The rows of a query's result, which are computed as they're needed.
+pub type ResultRows<'a> = Box<dyn Iterator<Item = Result<Vec<Value<'a>>>> + 'a>;
+
+/// Like SQLite, tables that haven't been analyzed are assumed to have about a million rows…
+const DEFAULT_ROWS: f64 = 1_000_000.0;
+/// …and each key of an index to match about 10 of them.
+const DEFAULT_AVG_EQ: f64 = 10.0;
+/// How many rows of a full scan reading a row through an index is worth. With it, an index is
+/// passed over about when SQLite would: once a key matches more than half of the rows.
+const LOOKUP_COST: f64 = 1.75;
 
-fn by_int_pk(select_stmt: &Select, schema: &ObjSchema) -> Option<i64> {
-    select_stmt
-        .filter
-        .as_ref()
-        .and_then(BoolExpr::is_int_pk_servable)
-        .filter(|(col, _)| schema.cols().is_int_pk(col))
-        .map(|(_, pk)| pk)
+/// A way of finding the rows of a table. Seeks look for the values of literals, parameters or
+/// columns of rows found before.
+enum Access<'a> {
+    Scan,
+    RowidSeek(Vec<&'a Expr<'a>>),
+    IndexSeek(&'a ObjSchema, Vec<&'a Expr<'a>>),
+    /// Every entry of an index in order, each leading to its row.
+    IndexScan(&'a ObjSchema),
+    /// A seek for each term of an OR, whose rows are combined.
+    MultiIndexOr(Vec<Access<'a>>),
 }
 
-fn by_idx_key<'a>(
-    select_stmt: &'a Select,
-    db_schema: &'a DbSchema,
-) -> Option<(&'a ObjSchema<'a>, &'a Literal<'a>)> {
-    select_stmt
-        .filter
-        .as_ref()
-        .and_then(BoolExpr::is_index_servable)
-        .and_then(|(col, key)| db_schema.index(select_stmt.tbl, col).map(|idx| (idx, key)))
-}
-
-fn int_pk_search(
-    pk: i64,
-    select_stmt: &Select,
-    tbl_page: &Page,
-    tbl_schema: &ObjSchema,
-    page_size: usize,
+pub fn run(
+    select_stmt: &Query,
+    db_schema: &DbSchema,
     db: &[u8],
+    config: &OutputConfig,
 ) -> Result<()> {
-    let row = btree::pk_scan(pk, tbl_page, page_size, db)?
-        .map(|cell| eval_row(cell, select_stmt, tbl_schema))
-        .ok_or(select_stmt);
+    let root = plan(select_stmt, db_schema)?;
+    let rows = root.open(DbFile::new(db, &db_schema.db_header))?;
 
-    if select_stmt.has_count_expr() {
-        println!("{}", replace_count(row, 1)?.join_ok("|")?);
-    } else if let Ok(mut row) = row {
-        println!("{}", row.join_ok("|")?);
-    }
+    let stdout = io::stdout();
+    let mut sink = config.sink(stdout.lock());
+    sink.begin(&root.cols())?;
+    rows.map_ok_and_then(|row| sink.row(&row))
+        .collect::<Result<()>>()?;
+    sink.finish()?;
 
+    if config.scan_stats {
+        print!("{}", explain::stats(&root));
+    }
     Ok(())
 }
 
-fn idx_search(
-    key: &Literal,
-    idx_schema: &ObjSchema,
-    select_stmt: &Select,
-    tbl_page: &Page,
-    tbl_schema: &ObjSchema,
-    page_size: usize,
-    db: &[u8],
-) -> Result<()> {
-    let idx_page = Page::parse(idx_schema.rootpage, page_size, db)?;
-    let mut rows = btree::idx_scan(key.into(), idx_page, tbl_page, page_size, db)
-        .map_ok(|cell| eval_row(cell, select_stmt, tbl_schema));
+/// Returns the names of the result columns and the result rows.
+pub fn query<'a>(
+    select_stmt: &'a Query,
+    db_schema: &'a DbSchema,
+    db: &'a [u8],
+) -> Result<(Vec<String>, ResultRows<'a>)> {
+    let root = plan(select_stmt, db_schema)?;
+    let rows = root.open(DbFile::new(db, &db_schema.db_header))?;
+    Ok((root.cols(), rows))
+}
+
+/// A table or subquery in FROM.
+enum Input<'a> {
+    /// A table, which may have an alias.
+    Tbl(&'a ObjSchema, Option<&'a str>),
+    /// The rows of a subquery or common table expression.
+    Rows(Op<'a>),
+}
+
+/// A planned query or subquery.
+struct Plan<'a> {
+    op: Op<'a>,
+    /// The columns of the queries around it that the query refers to, by how many levels
+    /// further out they are.
+    outer_refs: Vec<(usize, Option<&'a str>, &'a str)>,
+}
+
+/// What a query is planned in.
+#[derive(Clone, Default)]
+struct Scope<'a> {
+    /// The columns of the sources of the queries around it, innermost first.
+    outer: Vec<Vec<QualifiedCol<'a>>>,
+    /// The common table expressions it can read, of which the last one with a name counts.
+    ctes: Vec<(&'a str, CteDef<'a>)>,
+}
+
+/// A common table expression that queries can read like a table.
+#[derive(Clone)]
+enum CteDef<'a> {
+    /// It's planned again wherever it's read.
+    Query(&'a Cte<'a>),
+    /// The row the step of a recursive CTE is run for, with the columns of the CTE. `reads`
+    /// counts how often the step reads it.
+    Working {
+        cols: Vec<String>,
+        row: Rc<RefCell<Vec<Value<'a>>>>,
+        reads: Rc<Cell<usize>>,
+    },
+    /// The working row of a recursive CTE in a subquery of its step, which can't read it.
+    Hidden,
+}
+
+/// Builds the operator tree that runs the query.
+pub fn plan<'a>(query: &'a Query, db_schema: &'a DbSchema) -> Result<Op<'a>> {
+    Ok(plan_query(query, db_schema, &Scope::default(), &mut 0)?.op)
+}
+
+/// Plans a query nested in the given scope. Its subqueries are numbered after `num`.
+fn plan_query<'a>(
+    query: &'a Query,
+    db_schema: &'a DbSchema,
+    scope: &Scope<'a>,
+    num: &mut usize,
+) -> Result<Plan<'a>> {
+    let mut scope = scope.clone();
+    let ctes = query.with.iter().map(|cte| (cte.name, CteDef::Query(cte)));
+    scope.ctes.extend(ctes);
 
-    if select_stmt.has_count_expr() {
-        let first = rows.next().transpose()?.ok_or(select_stmt);
-        println!("{}", replace_count(first, rows.count() + 1)?.join_ok("|")?);
-    } else {
-        for row in rows {
-            println!("{}", row?.join_ok("|")?);
+    // The ORDER BY of a compound query can only sort by its result columns.
+    let mut plan = match query.compound.is_empty() {
+        true => plan_select(&query.select, db_schema, &scope, num, &query.order_by)?,
+        false => {
+            let plan = plan_compound(&query.select, &query.compound, db_schema, &scope, num)?;
+            let selects = query.selects().collect::<Vec<_>>();
+            let keys = query
+                .order_by
+                .iter()
+                .enumerate()
+                .map(
+                    |(i, (term, order))| match result_col(&selects, i + 1, term)? {
+                        Some(col) => Ok((col, *order)),
+                        None => bail!(
+                            "{} ORDER BY term does not match any column in the result set",
+                            ordinal(i + 1)
+                        ),
+                    },
+                )
+                .collect::<Result<Vec<_>>>()?;
+            match keys.is_empty() {
+                true => plan,
+                false => Plan {
+                    op: plan.op.then(|input| OpKind::Sort { input, keys }),
+                    ..plan
+                },
+            }
         }
+    };
+    if let Some(limit) = &query.limit {
+        let (limit, offset) = limit_values(limit)?;
+        plan.op = plan.op.then(|input| OpKind::Limit {
+            input,
+            limit,
+            offset,
+        });
     }
+    Ok(plan)
+}
 
-    Ok(())
+/// Plans the SELECTs of a compound query and combines their rows from left to right.
+fn plan_compound<'a>(
+    first: &'a Select,
+    rest: &'a [(CompoundOp, Select)],
+    db_schema: &'a DbSchema,
+    scope: &Scope<'a>,
+    num: &mut usize,
+) -> Result<Plan<'a>> {
+    let Plan { op, mut outer_refs } = plan_select(first, db_schema, scope, num, &[])?;
+    if rest.is_empty() {
+        return Ok(Plan { op, outer_refs });
+    }
+    let n = op.cols().len();
+    let rest = rest
+        .iter()
+        .map(|(compound_op, select)| {
+            let plan = plan_select(select, db_schema, scope, num, &[])?;
+            if plan.op.cols().len() != n {
+                bail!(
+                    "SELECTs to the left and right of {} do not have the same number of result \
+                     columns",
+                    compound_op
+                );
+            }
+            outer_refs.extend(plan.outer_refs);
+            Ok((*compound_op, plan.op))
+        })
+        .collect::<Result<_>>()?;
+    let first = Box::new(op);
+    let op = Op::new(OpKind::Compound { first, rest });
+    Ok(Plan { op, outer_refs })
 }
 
-fn full_tbl_search(
-    select_stmt: &Select,
-    tbl_page: Page,
-    tbl_schema: &ObjSchema,
-    page_size: usize,
-    db: &[u8],
-) -> Result<()> {
-    let mut rows = btree::full_tbl_scan(tbl_page, page_size, db)
-        .filter_ok(move |cell| match &select_stmt.filter {
-            Some(expr) => match expr.eval(cell, tbl_schema).unwrap() {
-                Value::Int(b) => b == 1,
-                _ => panic!("BoolExpr didn't return a Value::Int"),
-            },
-            None => true,
+/// Plans a SELECT that's sorted by the ORDER BY terms `order_by`. Terms that are result columns
+/// sort its result rows, while otherwise the rows of its source are sorted before the result
+/// columns are computed.
+fn plan_select<'a>(
+    select_stmt: &'a Select,
+    db_schema: &'a DbSchema,
+    scope: &Scope<'a>,
+    num: &mut usize,
+    order_by: &'a [(Expr<'a>, SortOrder)],
+) -> Result<Plan<'a>> {
+    let mut result_keys = vec![];
+    let mut sorts_source = false;
+    for (i, (term, order)) in order_by.iter().enumerate() {
+        match result_col(&[select_stmt], i + 1, term)? {
+            Some(col) => result_keys.push((col, *order)),
+            None => sorts_source |= term.as_literal().is_none(),
+        }
+    }
+    let sort_refs = order_by
+        .iter()
+        .filter(|_| sorts_source)
+        .flat_map(|(term, _)| term.col_refs());
+
+    // A subquery in FROM can't refer to the query it's in.
+    let items = match &select_stmt.from {
+        Some(from) => source_items(from, None),
+        None => vec![],
+    };
+    let mut inputs = vec![];
+    for (item, _) in &items {
+        inputs.push(match item {
+            Source::Tbl { name, alias } => {
+                let cte = scope
+                    .ctes
+                    .iter()
+                    .rev()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name));
+                match cte {
+                    Some((_, def)) => {
+                        Input::Rows(cte_op(name, *alias, def, db_schema, scope, num)?)
+                    }
+                    None => Input::Tbl(
+                        db_schema
+                            .table(name)
+                            .ok_or_else(|| anyhow!("Table '{}' not found", name))?,
+                        *alias,
+                    ),
+                }
+            }
+            Source::Subquery { select, alias } => {
+                let op = plan_query(select, db_schema, &scope.nested(None), num)?.op;
+                Input::Rows(match alias {
+                    Some(_) => op.named(*alias, None),
+                    None => op,
+                })
+            }
+            Source::Join { .. } => unreachable!(),
+        });
+    }
+    let input_cols = inputs
+        .iter()
+        .map(|input| match input {
+            Input::Tbl(tbl, alias) => {
+                let name = alias.unwrap_or(&tbl.name);
+                Ok(tbl
+                    .cols()?
+                    .names()
+                    .map(|c| (Some(name), c.to_owned()))
+                    .collect())
+            }
+            Input::Rows(rows) => Ok(rows.qualified_cols()),
+        })
+        .collect::<Result<Vec<Vec<_>>>>()?;
+    let source_cols = input_cols.concat();
+    // Text is compared by the collation of the table column it's from, which the columns of
+    // subqueries don't keep.
+    let source_collations = inputs
+        .iter()
+        .map(|input| match input {
+            Input::Tbl(tbl, _) => {
+                let cols = tbl.cols()?;
+                Ok(cols.names().map(|c| cols.col_collation(c)).collect())
+            }
+            Input::Rows(rows) => Ok(vec![None; rows.cols().len()]),
         })
-        .map_ok(|cell| eval_row(cell, select_stmt, tbl_schema));
+        .collect::<Result<Vec<Vec<_>>>>()?
+        .concat();
 
-    if select_stmt.has_count_expr() {
-        let first = rows.next().transpose()?.ok_or(select_stmt);
-        println!("{}", replace_count(first, rows.count() + 1)?.join_ok("|")?);
-    } else {
-        for row in rows {
-            println!("{}", row?.join_ok("|")?);
+    // Columns that the source doesn't have belong to the innermost query around it that does.
+    let position = |tbl: Option<&str>, col: &str| -> Result<Option<usize>> {
+        match col_positions(&source_cols, tbl, col)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [] => Ok(None),
+            [i] => Ok(Some(*i)),
+            _ => bail!("Ambiguous column '{}'", col),
         }
+    };
+    // The collations DISTINCT compares the result columns by, or the argument of count(DISTINCT)
+    // in an aggregate, where BINARY is none.
+    let mut collations = vec![];
+    for expr in &select_stmt.cols {
+        let expr = match expr {
+            Expr::CountDistinct(arg) => arg,
+            expr => expr,
+        };
+        let i = match expr.as_col_ref() {
+            Some((tbl, col)) => position(tbl, col)?,
+            None => None,
+        };
+        let collation = i.and_then(|i| source_collations[i]);
+        collations.push(collation.filter(|c| !c.eq_ignore_ascii_case("BINARY")));
     }
+    // Text sorted by BINARY isn't sorted by other collations, whose equal texts may be apart.
+    let binary = collations.iter().all(Option::is_none);
 
-    Ok(())
+    let mut read = vec![];
+    let mut outer_refs = vec![];
+    for (tbl, col) in select_stmt.col_refs().into_iter().chain(sort_refs) {
+        if let Some(i) = position(tbl, col)? {
+            read.push(i);
+            continue;
+        }
+        let level = scope
+            .outer
+            .iter()
+            .position(|cols| col_positions(cols, tbl, col).next().is_some());
+        match level {
+            Some(level) => outer_refs.push((level + 1, tbl, col)),
+            None => bail!(
+                "Unknown column '{}'. Did you mean '{}'?",
+                tbl.map_or_else(|| col.to_owned(), |tbl| format!("{}.{}", tbl, col)),
+                str_sim::most_similar(col, source_cols.iter().map(|(_, c)| c.as_str())).unwrap()
+            ),
+        }
+    }
+
+    let sub_scope = scope.nested(Some(source_cols.clone()));
+    let mut plan_subqueries = |subqueries: Vec<(&'a Query<'a>, SubqueryKind)>| {
+        subqueries
+            .into_iter()
+            .map(|(select, kind)| {
+                *num += 1;
+                let sub_num = *num;
+                let plan = plan_query(select, db_schema, &sub_scope, num)?;
+                let n = plan.op.cols().len();
+                if kind != SubqueryKind::Exists && n != 1 {
+                    bail!("sub-select returns {} columns - expected 1", n);
+                }
+                let correlated = !plan.outer_refs.is_empty();
+                let refs = plan.outer_refs.iter();
+                outer_refs.extend(refs.map(|(level, tbl, col)| (level - 1, *tbl, *col)));
+                Ok(Subquery::new(select, kind, sub_num, correlated, plan.op))
+            })
+            .collect::<Result<Rc<[_]>>>()
+    };
+    let col_subqueries = plan_subqueries(subqueries_of_exprs(&select_stmt.cols))?;
+    let mut on_subqueries = select_stmt
+        .join_conds()
+        .into_iter()
+        .map(|cond| plan_subqueries(subqueries_of_cond(cond)))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter();
+    let filter_subqueries = plan_subqueries(
+        select_stmt
+            .filter
+            .as_ref()
+            .map_or_else(Vec::new, subqueries_of_cond),
+    )?;
+
+    // Correlated subqueries read their columns of the source from its rows too.
+    for (_, tbl, col) in outer_refs.iter().filter(|(level, ..)| *level == 0) {
+        read.extend(position(*tbl, col)?);
+    }
+    outer_refs.retain(|(level, ..)| *level > 0);
+
+    // The tables of a join are read in the order that's estimated to cost the least. A table
+    // can be sought by the values of the filter or of a join condition that are known before
+    // its rows, which are those of the tables and subqueries read before it and of the queries
+    // around it.
+    let ranges = input_cols
+        .iter()
+        .scan(0, |start, cols| {
+            let range = *start..*start + cols.len();
+            *start = range.end;
+            Some(range)
+        })
+        .collect::<Vec<_>>();
+    let conds = select_stmt.join_conds();
+    let seek_conds = conds
+        .iter()
+        .copied()
+        .chain(&select_stmt.filter)
+        .flat_map(BoolExpr::conjuncts)
+        .collect::<Vec<_>>();
+    let tbls = inputs
+        .iter()
+        .zip(&ranges)
+        .map(|(input, range)| match input {
+            Input::Tbl(tbl, _) => Some((*tbl, range.clone())),
+            Input::Rows(_) => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    let order = match tbls {
+        Some(tbls) => join_order(&tbls, &seek_conds, &source_cols, db_schema)?,
+        None => (0..inputs.len()).collect(),
+    };
+
+    let mut ops = vec![];
+    let mut needs_filter = true;
+    let mut sorted_by = vec![];
+    for (i, (input, range)) in inputs.into_iter().zip(&ranges).enumerate() {
+        ops.push(match input {
+            Input::Tbl(tbl, alias) => {
+                let cols = tbl
+                    .cols()?
+                    .names()
+                    .enumerate()
+                    .filter(|(i, _)| read.contains(&(range.start + i)))
+                    .map(|(_, col)| col)
+                    .collect::<Vec<_>>();
+                let before = &order[..order.iter().position(|&j| j == i).unwrap_or(0)];
+                let known = |pos: usize| before.iter().any(|&j| ranges[j].contains(&pos));
+                let mut access =
+                    tbl_access(tbl, range, known, &seek_conds, &source_cols, db_schema)?;
+                let joined = !matches!(select_stmt.from, Some(Source::Tbl { .. }));
+                // A DISTINCT that reads the whole table can read it in the order of an index
+                // instead, so that its equal rows come one after another.
+                let distinct = select_stmt.distinct && !select_stmt.has_count_expr();
+                if distinct && binary && !joined && !sorts_source && matches!(access, Access::Scan)
+                {
+                    access = distinct_scan(&select_stmt.cols, tbl, db_schema)?.unwrap_or(access);
+                }
+                // A seek finds exactly the rows the filter is looking for, unless there are
+                // other tables whose columns it refers to or it only looks for those of one of
+                // the conditions the filter joins with AND.
+                let conjuncts = select_stmt
+                    .filter
+                    .as_ref()
+                    .map_or(0, |f| f.conjuncts().len());
+                needs_filter = joined
+                    || conjuncts > 1
+                    || matches!(access, Access::Scan | Access::IndexScan(_));
+                if !joined {
+                    sorted_by = sorted_by_cols(&access, tbl)?;
+                }
+                let op = access_op(access, tbl, cols);
+                match alias {
+                    Some(_) => op.named(alias, None),
+                    None => op,
+                }
+            }
+            Input::Rows(rows) => rows,
+        });
+    }
+    let rows = match (&select_stmt.from, order.iter().copied().eq(0..order.len())) {
+        (None, _) => Op::new(OpKind::ConstantRow),
+        (Some(from), true) => join_ops(from, &mut ops.into_iter(), &mut on_subqueries),
+        (Some(_), false) => {
+            let conds = conds.into_iter().zip(on_subqueries).collect();
+            join_in_order(ops, &order, &ranges, conds, &source_cols)
+        }
+    };
+
+    let rows = match &select_stmt.filter {
+        Some(cond) if needs_filter => rows.then(|input| OpKind::Filter {
+            input,
+            cond,
+            subqueries: filter_subqueries,
+        }),
+        _ => rows,
+    };
+
+    // An aggregate has a single row, so there's nothing to sort.
+    let aggregate = select_stmt.has_count_expr();
+    let rows = match sorts_source && !aggregate {
+        true => {
+            let cols = rows.qualified_cols();
+            let mut keys = vec![];
+            for (i, (term, order)) in order_by.iter().enumerate() {
+                let term = match term {
+                    Expr::Literal(Literal::Int(n)) => &select_stmt.cols[*n as usize - 1],
+                    _ => term,
+                };
+                match (term.as_col_ref(), term.as_literal()) {
+                    (Some((tbl, col)), _) => {
+                        keys.extend(col_positions(&cols, tbl, col).next().map(|i| (i, *order)))
+                    }
+                    (None, Some(_)) => {}
+                    (None, None) => bail!("{} ORDER BY term is not supported", ordinal(i + 1)),
+                }
+            }
+            rows.then(|input| OpKind::Sort { input, keys })
+        }
+        false => rows,
+    };
+
+    let exprs = &select_stmt.cols;
+    let subqueries = col_subqueries;
+    let op = match aggregate {
+        true => rows.then(|input| OpKind::Aggregate {
+            input,
+            exprs,
+            subqueries,
+            collations: collations.clone(),
+        }),
+        false => rows.then(|input| OpKind::Project {
+            input,
+            exprs,
+            subqueries,
+        }),
+    };
+    // Equal result rows come one after another if the rows of the source are sorted by the
+    // columns they're computed from.
+    let op = match select_stmt.distinct && !aggregate {
+        true => {
+            let ordered =
+                binary && !sorts_source && groups_equal_rows(&select_stmt.cols, &sorted_by);
+            op.then(|input| OpKind::Distinct {
+                input,
+                ordered,
+                collations,
+            })
+        }
+        false => op,
+    };
+    let op = match sorts_source || aggregate || result_keys.is_empty() {
+        true => op,
+        false => op.then(|input| OpKind::Sort {
+            input,
+            keys: result_keys,
+        }),
+    };
+    Ok(Plan { op, outer_refs })
 }
 
-fn eval_row<'a>(
-    cell: LeafTblCell<'a>,
-    select_stmt: &'a Select,
-    tbl_schema: &'a ObjSchema,
-) -> impl Iterator<Item = Result<Value<'a>>> {
-    select_stmt
-        .cols
+/// The position of the result column an ORDER BY term is, which is given by its number or as
+/// one of the result columns of the SELECTs. `nth` is the term's number for errors.
+fn result_col(selects: &[&Select], nth: usize, term: &Expr) -> Result<Option<usize>> {
+    let n = selects[0].cols.len();
+    if let Expr::Literal(Literal::Int(i)) = term {
+        return match usize::try_from(*i) {
+            Ok(i) if (1..=n).contains(&i) => Ok(Some(i - 1)),
+            _ => bail!(
+                "{} ORDER BY term out of range - should be between 1 and {}",
+                ordinal(nth),
+                n
+            ),
+        };
+    }
+    let is_term = |col: &Expr| match (col.as_col_ref(), term.as_col_ref()) {
+        (Some((tbl, col)), Some((term_tbl, term_col))) => {
+            col.eq_ignore_ascii_case(term_col)
+                && term_tbl.map_or(true, |t| {
+                    tbl.map_or(false, |tbl| tbl.eq_ignore_ascii_case(t))
+                })
+        }
+        _ => col == term,
+    };
+    Ok(selects
         .iter()
-        .map(move |col| col.eval(&cell, tbl_schema))
-}
-
-fn replace_count<'a>(
-    row: Result<impl Iterator<Item = Result<Value<'a>>> + 'a, &'a Select>,
-    count: usize,
-) -> Result<impl Iterator<Item = Result<Value<'a>>> + 'a>
-where
-{
-    match row {
-        Ok(row) => {
-            let count = count.try_into()?;
-            let row = row.map_ok(move |col| match col {
-                Value::CountPlaceholder => Value::Int(count),
-                _ => col,
-            });
-            Ok(IterEither::left(row))
+        .find_map(|select| select.cols.iter().position(is_term)))
+}
+
+/// The number of rows to return at most and how many to skip first. Like in SQLite, a negative
+/// limit means there's none and a negative offset skips nothing.
+fn limit_values(limit: &Limit) -> Result<(Option<usize>, usize)> {
+    let int = |expr: &Expr| match expr.as_literal() {
+        Some(Literal::Int(n)) => Ok(*n),
+        _ => bail!("datatype mismatch"),
+    };
+    let offset = limit.offset.as_ref().map_or(Ok(0), int)?;
+    Ok((
+        usize::try_from(int(&limit.limit)?).ok(),
+        usize::try_from(offset).unwrap_or(0),
+    ))
+}
+
+/// Writes a number like "1st" or "12th".
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+impl<'a> Scope<'a> {
+    /// The scope of the subqueries of a query in this scope whose source has the columns
+    /// `cols`, or of those in its FROM if there are none. Like in SQLite, subqueries can't read
+    /// the working row of a recursive common table expression.
+    fn nested(&self, cols: Option<Vec<QualifiedCol<'a>>>) -> Self {
+        let outer = match cols {
+            Some(cols) => iter::once(cols).chain(self.outer.iter().cloned()).collect(),
+            None => vec![],
+        };
+        let ctes = self
+            .ctes
+            .iter()
+            .map(|(name, def)| match def {
+                CteDef::Working { .. } => (*name, CteDef::Hidden),
+                _ => (*name, def.clone()),
+            })
+            .collect();
+        Self { outer, ctes }
+    }
+}
+
+/// Plans reading the common table expression `def` under its name or `alias`. A CTE can read
+/// the others that are in scope where it's read, but only itself if it's recursive. Whether it
+/// is shows when its last SELECT is planned with itself in scope.
+fn cte_op<'a>(
+    name: &'a str,
+    alias: Option<&'a str>,
+    def: &CteDef<'a>,
+    db_schema: &'a DbSchema,
+    scope: &Scope<'a>,
+    num: &mut usize,
+) -> Result<Op<'a>> {
+    let qualifier = Some(alias.unwrap_or(name));
+    let cte = match def {
+        CteDef::Query(cte) => cte,
+        CteDef::Working { cols, row, reads } => {
+            reads.set(reads.get() + 1);
+            let row = Rc::clone(row);
+            let cols = cols.clone();
+            return Ok(Op::new(OpKind::WorkingRow { name, cols, row }).named(qualifier, None));
         }
-        Err(select_stmt) => {
-            let empty_row = select_stmt.cols.iter().map(|col| match col {
-                Expr::Count => Ok(Value::Int(0)),
-                Expr::Literal(lit) => Ok(lit.into()),
-                Expr::ColName(_) => Ok(Value::String("")),
-            });
-            Ok(IterEither::right(empty_row))
+        CteDef::Hidden => bail!("recursive reference in a subquery: {}", name),
+    };
+
+    let mut body_scope = scope.nested(None);
+    body_scope
+        .ctes
+        .retain(|(n, _)| !n.eq_ignore_ascii_case(cte.name));
+    let start = *num;
+    let op = match recursive_cte_op(cte, db_schema, &body_scope, num)? {
+        Some(op) => op,
+        None => {
+            *num = start;
+            plan_query(&cte.query, db_schema, &body_scope, num)?.op
+        }
+    };
+    let cols = cte_cols(cte, &op)?;
+    Ok(op.named(qualifier, Some(cols)))
+}
+
+/// Plans a common table expression whose last SELECT is added by UNION or UNION ALL as a
+/// recursive one, unless that SELECT doesn't read it.
+fn recursive_cte_op<'a>(
+    cte: &'a Cte<'a>,
+    db_schema: &'a DbSchema,
+    body_scope: &Scope<'a>,
+    num: &mut usize,
+) -> Result<Option<Op<'a>>> {
+    let query = &cte.query;
+    let (union, step, rest) = match query.compound.split_last() {
+        Some(((op @ (CompoundOp::Union | CompoundOp::UnionAll), step), rest)) => (*op, step, rest),
+        _ => return Ok(None),
+    };
+    let mut scope = body_scope.clone();
+    let ctes = query.with.iter().map(|cte| (cte.name, CteDef::Query(cte)));
+    scope.ctes.extend(ctes);
+    let setup = plan_compound(&query.select, rest, db_schema, &scope, num)?.op;
+    let cols = cte_cols(cte, &setup)?;
+
+    let working = Rc::<RefCell<_>>::default();
+    let reads = Rc::<Cell<_>>::default();
+    scope.ctes.push((
+        cte.name,
+        CteDef::Working {
+            cols: cols.clone(),
+            row: Rc::clone(&working),
+            reads: Rc::clone(&reads),
+        },
+    ));
+    let step = plan_select(step, db_schema, &scope, num, &[])?.op;
+    if step.cols().len() != cols.len() {
+        bail!(
+            "SELECTs to the left and right of {} do not have the same number of result columns",
+            union
+        );
+    }
+    match reads.get() {
+        0 => return Ok(None),
+        1 => {}
+        _ => bail!("multiple references to recursive table: {}", cte.name),
+    }
+    if !query.order_by.is_empty() {
+        bail!("ORDER BY in a recursive common table expression is not supported");
+    }
+    let op = Op::new(OpKind::RecursiveCte {
+        name: cte.name,
+        setup: Box::new(setup),
+        step: Box::new(step),
+        union_all: union == CompoundOp::UnionAll,
+        working,
+    });
+    Ok(Some(match &query.limit {
+        Some(limit) => {
+            let (limit, offset) = limit_values(limit)?;
+            op.then(|input| OpKind::Limit {
+                input,
+                limit,
+                offset,
+            })
+        }
+        None => op,
+    }))
+}
+
+/// The names of the columns of a common table expression whose rows `op` finds.
+fn cte_cols(cte: &Cte, op: &Op) -> Result<Vec<String>> {
+    let n = op.cols().len();
+    let cols = match cte.cols.is_empty() {
+        true => op.cols(),
+        false => cte.cols.iter().map(|c| c.to_string()).collect(),
+    };
+    if cols.len() != n {
+        bail!(
+            "table {} has {} values for {} columns",
+            cte.name,
+            n,
+            cols.len()
+        );
+    }
+    Ok(cols)
+}
+
+/// The columns the rows found the given way are sorted by. The entries of an index are sorted by
+/// its columns and then by the rowid or primary key of their rows.
+fn sorted_by_cols<'a>(access: &Access<'a>, tbl: &'a ObjSchema) -> Result<Vec<&'a str>> {
+    let tbl_cols = tbl.cols()?;
+    let key = |obj: &'a ObjSchema| -> Result<_> {
+        Ok(obj
+            .cols()?
+            .key()
+            .iter()
+            .map(move |c| (c.name.as_str(), tbl_cols.is_binary(c))))
+    };
+    let int_pk = tbl_cols.names().filter(|col| tbl_cols.is_int_pk(col));
+    let row_key = key(tbl)?.chain(int_pk.map(|col| (col, true)));
+    let sorted_by = match access {
+        Access::Scan | Access::RowidSeek(_) => row_key.collect(),
+        Access::IndexSeek(idx, _) | Access::IndexScan(idx) => key(idx)?.chain(row_key).collect(),
+        Access::MultiIndexOr(_) => vec![],
+    };
+    // Text sorted by another collation than BINARY isn't in the order values compare in, and
+    // the columns after it aren't either.
+    Ok(sorted_by
+        .into_iter()
+        .take_while(|(_, binary)| *binary)
+        .map(|(col, _)| col)
+        .collect())
+}
+
+/// Whether rows sorted by the columns `sorted_by` have the same values of `exprs` one after
+/// another, which holds if the columns `exprs` refer to are the first ones of `sorted_by` in
+/// any order. Constants have the same value in every row.
+fn groups_equal_rows(exprs: &[Expr], sorted_by: &[&str]) -> bool {
+    let mut cols = vec![];
+    for expr in exprs {
+        match (expr.as_col_ref(), expr.as_literal()) {
+            (Some((_, col)), _) => cols.push(col),
+            (None, Some(_)) => {}
+            (None, None) => return false,
+        }
+    }
+    let has = |cols: &[&str], col: &str| cols.iter().any(|c| c.eq_ignore_ascii_case(col));
+    (0..=sorted_by.len()).any(|n| {
+        let first = &sorted_by[..n];
+        cols.iter().all(|col| has(first, col)) && first.iter().all(|col| has(&cols, col))
+    })
+}
+
+/// Picks an index to read every row of a table in the order of, so that rows with the same
+/// values of `exprs` come one after another, unless the table is already read in such an order.
+/// Partial indexes lack some of the rows.
+fn distinct_scan<'a>(
+    exprs: &[Expr],
+    tbl: &'a ObjSchema,
+    db_schema: &'a DbSchema,
+) -> Result<Option<Access<'a>>> {
+    if groups_equal_rows(exprs, &sorted_by_cols(&Access::Scan, tbl)?) {
+        return Ok(None);
+    }
+    for idx in db_schema.indexes().filter(|idx| idx.tbl_name == tbl.name) {
+        let access = Access::IndexScan(idx);
+        if !idx.cols()?.is_partial() && groups_equal_rows(exprs, &sorted_by_cols(&access, tbl)?) {
+            return Ok(Some(access));
+        }
+    }
+    Ok(None)
+}
+
+/// The tables and subqueries of a FROM clause in the order they appear, each with the condition
+/// of the join it's the last one of, starting with `on`.
+fn source_items<'a>(
+    source: &'a Source<'a>,
+    on: Option<&'a BoolExpr<'a>>,
+) -> Vec<(&'a Source<'a>, Option<&'a BoolExpr<'a>>)> {
+    match source {
+        Source::Join { l, r, on: cond } => {
+            let mut items = source_items(l, None);
+            items.extend(source_items(r, cond.as_deref().or(on)));
+            items
+        }
+        _ => vec![(source, on)],
+    }
+}
+
+/// Joins of more tables than this are read in the order of FROM, as the number of orders to
+/// compare grows too fast with them.
+const MAX_REORDERED_TBLS: usize = 6;
+
+/// Picks the order to read the tables of a join in, given with the positions of their columns
+/// in the source. Each table is read again for every row found before it, but may seek its rows
+/// by the values of those rows. Of the orders that are estimated to cost the same, the one of
+/// FROM is preferred.
+fn join_order<'a>(
+    tbls: &[(&'a ObjSchema, Range<usize>)],
+    conds: &[&'a BoolExpr<'a>],
+    source_cols: &[QualifiedCol<'a>],
+    db_schema: &'a DbSchema,
+) -> Result<Vec<usize>> {
+    let n = tbls.len();
+    if !(2..=MAX_REORDERED_TBLS).contains(&n) {
+        return Ok((0..n).collect());
+    }
+    let mut cheapest: Option<(f64, Vec<usize>)> = None;
+    for order in (0..n).permutations(n) {
+        let (mut total, mut rows) = (0.0, 1.0);
+        for (k, &i) in order.iter().enumerate() {
+            let (tbl, range) = &tbls[i];
+            let known = |pos: usize| order[..k].iter().any(|&j| tbls[j].1.contains(&pos));
+            let access = tbl_access(tbl, range, known, conds, source_cols, db_schema)?;
+            total += rows * cost(&access, tbl, db_schema)?;
+            rows *= found_rows(&access, tbl, db_schema)?;
+        }
+        if cheapest.as_ref().map_or(true, |(min, _)| total < *min) {
+            cheapest = Some((total, order));
         }
     }
+    Ok(cheapest.map_or_else(Vec::new, |(_, order)| order))
 }
 
-fn validate_col_names(select_stmt: &Select, tbl_schema: &ObjSchema) -> Result<()> {
-    let selected_cols = select_stmt.selected_col_names();
-    let filtered_cols = select_stmt
-        .filter
+/// Picks the cheapest way of finding the rows of a table whose columns are at `range` in the
+/// source. It can seek by the values of literals, of the columns at positions `known` holds for
+/// and of the queries around it.
+fn tbl_access<'a>(
+    tbl: &'a ObjSchema,
+    range: &Range<usize>,
+    known: impl Fn(usize) -> bool,
+    conds: &[&'a BoolExpr<'a>],
+    source_cols: &[QualifiedCol<'a>],
+    db_schema: &'a DbSchema,
+) -> Result<Access<'a>> {
+    let col = |expr: &Expr<'a>| {
+        let (tbl, col) = expr.as_col_ref()?;
+        let i = col_positions(source_cols, tbl, col).next()?;
+        range.contains(&i).then(|| col)
+    };
+    let is_key = |expr: &Expr<'a>| match expr.as_col_ref() {
+        Some((tbl, col)) => col_positions(source_cols, tbl, col)
+            .next()
+            .map_or(true, &known),
+        None => expr.as_literal().is_some(),
+    };
+    let paths = conds
         .iter()
-        .flat_map(BoolExpr::referenced_col_names);
+        .map(|cond| access_paths(cond.seek_terms(&col, &is_key), tbl, db_schema))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+    Ok(cheapest(paths, tbl, db_schema)?.unwrap_or(Access::Scan))
+}
+
+/// Joins the operators of the tables of a FROM clause, whose columns are at `ranges` in the
+/// source, in the given order. Each join condition is checked as soon as the tables it refers to
+/// are joined, or once all of them are if it has subqueries, which may refer to any of them.
+fn join_in_order<'a>(
+    ops: Vec<Op<'a>>,
+    order: &[usize],
+    ranges: &[Range<usize>],
+    conds: Vec<(&'a BoolExpr<'a>, Rc<[Subquery<'a>]>)>,
+    source_cols: &[QualifiedCol<'a>],
+) -> Op<'a> {
+    let step_of = |pos: usize| {
+        order
+            .iter()
+            .position(|&i| ranges[i].contains(&pos))
+            .unwrap_or(0)
+    };
+    let mut conds = conds
+        .into_iter()
+        .map(|(cond, subqueries)| {
+            let step = match subqueries.is_empty() {
+                true => cond
+                    .exprs()
+                    .into_iter()
+                    .flat_map(Expr::col_refs)
+                    .filter_map(|(tbl, col)| col_positions(source_cols, tbl, col).next())
+                    .map(step_of)
+                    .max()
+                    .unwrap_or(0),
+                false => order.len() - 1,
+            };
+            (step, cond, subqueries)
+        })
+        .collect::<Vec<_>>();
 
-    selected_cols.chain(filtered_cols).try_for_each(|col| {
-        if tbl_schema.cols().has(col) {
-            return Ok(());
+    let mut ops = ops.into_iter().map(Some).collect::<Vec<_>>();
+    let mut joined: Option<Op<'a>> = None;
+    for (step, &i) in order.iter().enumerate() {
+        let right = ops[i].take().unwrap();
+        let mut op = match joined {
+            Some(left) => Op::new(OpKind::Join {
+                left: Box::new(left),
+                right: Box::new(right),
+            }),
+            None => right,
+        };
+        for (_, cond, subqueries) in conds.iter_mut().filter(|(s, ..)| *s == step) {
+            let (cond, subqueries) = (*cond, Rc::clone(subqueries));
+            op = op.then(|input| OpKind::Filter {
+                input,
+                cond,
+                subqueries,
+            });
         }
+        joined = Some(op);
+    }
+    joined.unwrap()
+}
 
-        bail!(
-            "Unknown column '{}'. Did you mean '{}'?",
-            col,
-            str_sim::most_similar(col, tbl_schema.cols().names()).unwrap()
-        )
+/// Joins the operators of the tables and subqueries of a FROM clause like it does, filtering
+/// each join by its condition.
+fn join_ops<'a>(
+    source: &'a Source<'a>,
+    items: &mut impl Iterator<Item = Op<'a>>,
+    on_subqueries: &mut impl Iterator<Item = Rc<[Subquery<'a>]>>,
+) -> Op<'a> {
+    let (l, r, on) = match source {
+        Source::Join { l, r, on } => (l, r, on),
+        _ => return items.next().unwrap(),
+    };
+    let left = Box::new(join_ops(l, items, on_subqueries));
+    let right = Box::new(join_ops(r, items, on_subqueries));
+    let join = Op::new(OpKind::Join { left, right });
+    match on {
+        Some(cond) => join.then(|input| OpKind::Filter {
+            input,
+            cond,
+            subqueries: on_subqueries.next().unwrap(),
+        }),
+        None => join,
+    }
+}
+
+/// The subqueries of expressions that are used as values.
+fn subqueries_of_exprs<'a, 'e>(
+    exprs: impl IntoIterator<Item = &'e Expr<'a>>,
+) -> Vec<(&'e Query<'a>, SubqueryKind)> {
+    exprs
+        .into_iter()
+        .map(Expr::operand)
+        .flat_map(Expr::subqueries)
+        .map(|select| (select, SubqueryKind::Scalar))
+        .collect()
+}
+
+/// The subqueries of a condition in the order they appear.
+fn subqueries_of_cond<'a, 'e>(cond: &'e BoolExpr<'a>) -> Vec<(&'e Query<'a>, SubqueryKind)> {
+    let mut subqueries = match cond {
+        BoolExpr::And(l, r) | BoolExpr::Or(l, r) => {
+            let mut subqueries = subqueries_of_cond(l);
+            subqueries.extend(subqueries_of_cond(r));
+            return subqueries;
+        }
+        _ => subqueries_of_exprs(cond.exprs()),
+    };
+    match cond {
+        BoolExpr::InSubquery { select, .. } => subqueries.push((select, SubqueryKind::List)),
+        BoolExpr::Exists(select) => subqueries.push((select, SubqueryKind::Exists)),
+        _ => {}
+    }
+    subqueries
+}
+
+/// Builds the operator that reads the columns `cols` of the rows found the given way.
+fn access_op<'a>(access: Access<'a>, tbl: &'a ObjSchema, cols: Vec<&'a str>) -> Op<'a> {
+    let key = |expr: &Expr<'a>| match (expr.as_literal(), expr.as_col_ref()) {
+        (Some(lit), _) => Key::Value(lit.into()),
+        (None, Some((tbl, col))) => Key::Col(tbl, col),
+        (None, None) => unreachable!(),
+    };
+    match access {
+        Access::Scan => Op::new(OpKind::Scan { tbl, cols }),
+        Access::RowidSeek(keys) => Op::new(OpKind::RowidSeek {
+            tbl,
+            cols,
+            keys: keys.into_iter().map(key).collect(),
+        }),
+        Access::IndexSeek(idx, keys) => Op::new(OpKind::IndexSeek {
+            tbl,
+            idx,
+            cols,
+            keys: keys.into_iter().map(key).collect(),
+        }),
+        Access::IndexScan(idx) => Op::new(OpKind::IndexScan { tbl, idx, cols }),
+        Access::MultiIndexOr(seeks) => Op::new(OpKind::MultiIndexOr {
+            tbl,
+            cols: cols.clone(),
+            seeks: seeks
+                .into_iter()
+                .map(|seek| access_op(seek, tbl, cols.clone()))
+                .collect(),
+        }),
+    }
+}
+
+/// Lists the ways of finding the rows of a condition's seek terms, seeks before the full scan,
+/// which is chosen only if it's cheaper. An OR of terms on different columns is served by a
+/// seek for each term, while terms on the same column become a single seek for all of their
+/// keys, like in SQLite.
+fn access_paths<'a>(
+    terms: Option<Vec<(&'a str, Vec<&'a Expr<'a>>)>>,
+    tbl_schema: &'a ObjSchema,
+    db_schema: &'a DbSchema,
+) -> Result<Vec<Access<'a>>> {
+    let mut terms = match terms {
+        Some(terms) => terms,
+        None => return Ok(vec![Access::Scan]),
+    };
+    if terms.iter().all(|(col, _)| *col == terms[0].0) {
+        let col = terms[0].0;
+        let keys = terms.into_iter().flat_map(|(_, keys)| keys).collect();
+        terms = vec![(col, keys)];
+    }
+
+    let mut paths = match terms.as_slice() {
+        [(col, keys)] => seeks(col, keys, tbl_schema, db_schema)?,
+        _ => terms
+            .iter()
+            .map(|(col, keys)| {
+                let seeks = seeks(col, keys, tbl_schema, db_schema)?;
+                cheapest(seeks, tbl_schema, db_schema)
+            })
+            .collect::<Result<Option<Vec<_>>>>()?
+            .map(Access::MultiIndexOr)
+            .into_iter()
+            .collect(),
+    };
+    paths.push(Access::Scan);
+    Ok(paths)
+}
+
+/// Lists the seeks that find the rows with one of `keys` in `col`. The B-tree of a WITHOUT
+/// ROWID table is an index on its primary key itself. Rowids are only sought by integers or
+/// by the values of columns.
+fn seeks<'a>(
+    col: &'a str,
+    keys: &[&'a Expr<'a>],
+    tbl_schema: &'a ObjSchema,
+    db_schema: &'a DbSchema,
+) -> Result<Vec<Access<'a>>> {
+    let cols = tbl_schema.cols()?;
+    let rowids = Some(keys.to_vec()).filter(|keys| {
+        cols.is_int_pk(col)
+            && keys
+                .iter()
+                .all(|key| matches!(key.as_literal(), Some(Literal::Int(_)) | None))
+    });
+    let idxs = Some(tbl_schema)
+        .filter(|_| {
+            cols.leading()
+                .map_or(false, |c| c.name == col && cols.is_binary(c))
+        })
+        .into_iter()
+        .chain(db_schema.indexes_by(&tbl_schema.name, col));
+    Ok(rowids
+        .map(Access::RowidSeek)
+        .into_iter()
+        .chain(idxs.map(|idx| Access::IndexSeek(idx, keys.to_vec())))
+        .collect())
+}
+
+/// Picks the way of finding rows that's estimated to cost the least, preferring the first one
+/// of those that cost the same.
+fn cheapest<'a>(
+    paths: Vec<Access<'a>>,
+    tbl_schema: &ObjSchema,
+    db_schema: &DbSchema,
+) -> Result<Option<Access<'a>>> {
+    let costs = paths
+        .into_iter()
+        .map(|access| Ok((cost(&access, tbl_schema, db_schema)?, access)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(costs
+        .into_iter()
+        .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        .map(|(_, access)| access))
+}
+
+/// Estimates how many rows of a table are found the given way.
+fn found_rows(access: &Access, tbl_schema: &ObjSchema, db_schema: &DbSchema) -> Result<f64> {
+    Ok(match access {
+        Access::Scan | Access::IndexScan(_) => db_schema
+            .row_count(&tbl_schema.name)
+            .map_or(DEFAULT_ROWS, |n| n as f64),
+        Access::RowidSeek(rowids) => rowids.len() as f64,
+        Access::IndexSeek(idx, keys) => keys.len() as f64 * avg_eq(idx, db_schema)?,
+        Access::MultiIndexOr(seeks) => seeks
+            .iter()
+            .map(|seek| found_rows(seek, tbl_schema, db_schema))
+            .sum::<Result<f64>>()?,
+    })
+}
+
+/// Estimates how many rows of its table an index finds for each key.
+fn avg_eq(idx: &ObjSchema, db_schema: &DbSchema) -> Result<f64> {
+    let cols = idx.cols()?;
+    let unique = idx.is_table() || cols.is_unique();
+    Ok(match db_schema.avg_eq(idx) {
+        Some(n) => n as f64,
+        None if unique && cols.key().len() == 1 => 1.0,
+        None => DEFAULT_AVG_EQ,
     })
 }
+
+/// Estimates how many rows reading a table the given way is worth, based on the statistics
+/// ANALYZE collected. Seeking a B-tree takes about log2(n) steps, and every entry an index
+/// finds leads to its row in the table unless the index is the table itself. Each key is sought
+/// separately.
+fn cost(access: &Access, tbl_schema: &ObjSchema, db_schema: &DbSchema) -> Result<f64> {
+    let rows = db_schema
+        .row_count(&tbl_schema.name)
+        .map_or(DEFAULT_ROWS, |n| n as f64);
+    let seek = rows.max(2.0).log2();
+    Ok(match access {
+        Access::Scan => rows,
+        Access::IndexScan(_) => rows * LOOKUP_COST,
+        Access::RowidSeek(rowids) => rowids.len() as f64 * seek,
+        Access::IndexSeek(idx, keys) => {
+            let avg_eq = avg_eq(idx, db_schema)?;
+            let per_key = match idx.is_table() {
+                true => seek + avg_eq,
+                false => seek + avg_eq * LOOKUP_COST,
+            };
+            keys.len() as f64 * per_key
+        }
+        Access::MultiIndexOr(seeks) => seeks
+            .iter()
+            .map(|seek| cost(seek, tbl_schema, db_schema))
+            .sum::<Result<f64>>()?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::plan;
+    use crate::{
+        format::DbFile,
+        interpreter::{
+            database::Database,
+            eval::Value,
+            explain,
+            ops::Op,
+            test_db::{db, db_with_idx, db_with_tbl, text},
+        },
+        schema::{Cols, DbSchema, ObjSchema, Stat},
+        syntax::{parse, Query, SqlStmt},
+    };
+    use anyhow::Result;
+    use itertools::Itertools;
+
+    fn obj(type_: &str, name: &str, rootpage: i32, sql: &str) -> Result<ObjSchema> {
+        let tbl_name = match parse::sql_stmt(sql)? {
+            SqlStmt::CreateIdx { target_tbl, .. } => target_tbl,
+            _ => name,
+        };
+        Ok(ObjSchema {
+            type_: type_.to_owned(),
+            name: name.to_owned(),
+            tbl_name: tbl_name.to_owned(),
+            rootpage,
+            sql: Some(sql.to_owned()),
+            cols: Some(Cols::parse(sql)?),
+        })
+    }
+
+    fn stat(idx: &str, counts: &[u64]) -> Stat {
+        Stat {
+            tbl: "t".to_owned(),
+            idx: Some(idx.to_owned()),
+            counts: counts.to_vec(),
+        }
+    }
+
+    fn select(sql: &str) -> Result<Query<'_>> {
+        match parse::sql_stmt(sql)? {
+            SqlStmt::Select(select) => Ok(select),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Reads the rows of a planned query with the values of each joined by "|".
+    fn rows<'a>(root: &Op<'a>, db: &'a Database) -> Result<Vec<String>> {
+        let file = DbFile::new(&db.bytes, &db.schema.db_header);
+        let rows = root.open(file)?;
+        rows.map_ok(|row| row.iter().join("|")).collect()
+    }
+
+    /// Runs a query and returns its rows like `rows`.
+    fn run(sql: &str, db: &Database) -> Result<Vec<String>> {
+        rows(&plan(&select(sql)?, &db.schema)?, db)
+    }
+
+    /// The steps of a query's plan as EXPLAIN QUERY PLAN shows them, each with the id of the
+    /// step it's part of.
+    fn steps(sql: &str, db_schema: &DbSchema) -> Result<Vec<(i64, String)>> {
+        let steps = explain::steps(&select(sql)?, db_schema)?;
+        Ok(steps.into_iter().map(|s| (s.parent, s.detail)).collect())
+    }
+
+    fn access_path(sql: &str, db_schema: &DbSchema) -> Result<String> {
+        Ok(steps(sql, db_schema)?.remove(0).1)
+    }
+
+    #[test]
+    fn picks_cheapest_access_path() -> Result<()> {
+        let mut db_schema = DbSchema::parse(&Database::empty())?;
+        db_schema.objs = vec![
+            obj(
+                "table",
+                "t",
+                2,
+                "CREATE TABLE t(a INTEGER PRIMARY KEY, b, c)",
+            )?,
+            obj("index", "tb", 3, "CREATE INDEX tb ON t(b)")?,
+            obj("index", "tbc", 4, "CREATE INDEX tbc ON t(b, c)")?,
+        ];
+        let by_b = "SELECT a FROM t WHERE b = 1";
+        assert_eq!(
+            access_path(by_b, &db_schema)?,
+            "SEARCH t USING INDEX tb (b=?)"
+        );
+
+        db_schema.stats = vec![stat("tb", &[1000, 400]), stat("tbc", &[1000, 20, 1])];
+        assert_eq!(
+            access_path(by_b, &db_schema)?,
+            "SEARCH t USING INDEX tbc (b=?)"
+        );
+        assert_eq!(
+            steps("SELECT c FROM t WHERE b = 1 OR a IN (2, 3)", &db_schema)?,
+            [
+                (0, "MULTI-INDEX OR".to_owned()),
+                (2, "INDEX 1".to_owned()),
+                (3, "SEARCH t USING INDEX tbc (b=?)".to_owned()),
+                (2, "INDEX 2".to_owned()),
+                (5, "SEARCH t USING INTEGER PRIMARY KEY (rowid=?)".to_owned()),
+            ]
+        );
+
+        // An index that leaves most of the rows is slower than reading all of them.
+        db_schema.stats = vec![stat("tb", &[100, 80]), stat("tbc", &[100, 80, 1])];
+        assert_eq!(access_path(by_b, &db_schema)?, "SCAN t");
+        assert_eq!(
+            access_path("SELECT a FROM t WHERE a = 1", &db_schema)?,
+            "SEARCH t USING INTEGER PRIMARY KEY (rowid=?)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn picks_cheapest_join_order() -> Result<()> {
+        let mut db_schema = DbSchema::parse(&Database::empty())?;
+        db_schema.objs = vec![
+            obj("table", "a", 2, "CREATE TABLE a(x, n)")?,
+            obj("table", "b", 3, "CREATE TABLE b(y, m)")?,
+            obj("table", "c", 4, "CREATE TABLE c(z)")?,
+            obj("index", "by", 5, "CREATE INDEX by ON b(y)")?,
+        ];
+        let details = |sql| -> Result<Vec<_>> {
+            Ok(steps(sql, &db_schema)?
+                .into_iter()
+                .map(|(_, d)| d)
+                .collect())
+        };
+
+        // `b` can only be sought once the rows of `a` are known.
+        for sql in &[
+            "SELECT n, m FROM b, a WHERE b.y = a.x",
+            "SELECT n, m FROM b JOIN a ON a.x = b.y",
+        ] {
+            assert_eq!(details(sql)?, ["SCAN a", "SEARCH b USING INDEX by (y=?)"]);
+        }
+        assert_eq!(
+            details("SELECT n FROM c JOIN b ON b.m = c.z JOIN a ON a.x = b.y")?,
+            ["SCAN a", "SEARCH b USING INDEX by (y=?)", "SCAN c"]
+        );
+        // Orders that cost the same keep the one of FROM.
+        assert_eq!(
+            details("SELECT n, m FROM b, a WHERE b.m = a.n")?,
+            ["SCAN b", "SCAN a"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn names_tbls_by_their_aliases() -> Result<()> {
+        let mut db_schema = DbSchema::parse(&Database::empty())?;
+        db_schema.objs = vec![
+            obj(
+                "table",
+                "emp",
+                2,
+                "CREATE TABLE emp(id INTEGER PRIMARY KEY, a, b)",
+            )?,
+            obj("index", "ia", 3, "CREATE INDEX ia ON emp(a)")?,
+            obj("index", "ib", 4, "CREATE INDEX ib ON emp(b)")?,
+        ];
+        assert_eq!(access_path("SELECT a FROM emp e", &db_schema)?, "SCAN e");
+        assert_eq!(
+            access_path("SELECT a FROM emp AS e WHERE id = 3", &db_schema)?,
+            "SEARCH e USING INTEGER PRIMARY KEY (rowid=?)"
+        );
+        assert_eq!(
+            steps("SELECT e.a FROM emp e WHERE a = 1 OR b = 2", &db_schema)?
+                .into_iter()
+                .map(|(_, d)| d)
+                .collect::<Vec<_>>(),
+            [
+                "MULTI-INDEX OR",
+                "INDEX 1",
+                "SEARCH e USING INDEX ia (a=?)",
+                "INDEX 2",
+                "SEARCH e USING INDEX ib (b=?)"
+            ]
+        );
+        // The tables of a subquery keep their own names.
+        assert_eq!(
+            access_path("SELECT a FROM (SELECT a FROM emp) e", &db_schema)?,
+            "SCAN emp"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_indexes_with_other_collations() -> Result<()> {
+        let mut db_schema = DbSchema::parse(&Database::empty())?;
+        db_schema.objs = vec![
+            obj("table", "t", 2, "CREATE TABLE t(a, b COLLATE NOCASE, c)")?,
+            obj("index", "ta", 3, "CREATE INDEX ta ON t(a COLLATE NOCASE)")?,
+            obj("index", "tb", 4, "CREATE INDEX tb ON t(b)")?,
+            obj("index", "tc", 5, "CREATE INDEX tc ON t(c COLLATE RTRIM)")?,
+        ];
+        for col in &["a", "b", "c"] {
+            let sql = format!("SELECT a FROM t WHERE {} = 'x'", col);
+            assert_eq!(access_path(&sql, &db_schema)?, "SCAN t");
+        }
+
+        db_schema.objs[1] = obj("index", "ta", 3, "CREATE INDEX ta ON t(a COLLATE binary)")?;
+        db_schema.objs[2] = obj("index", "tb", 4, "CREATE INDEX tb ON t(b COLLATE BINARY)")?;
+        assert_eq!(
+            access_path("SELECT a FROM t WHERE a = 'x'", &db_schema)?,
+            "SEARCH t USING INDEX ta (a=?)"
+        );
+        assert_eq!(
+            access_path("SELECT a FROM t WHERE b = 'x'", &db_schema)?,
+            "SEARCH t USING INDEX tb (b=?)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn seeks_by_one_of_the_conditions_joined_with_and() -> Result<()> {
+        let db = db()?;
+        let sql = "SELECT name FROM t WHERE name = 'bob' AND id = 2";
+        assert_eq!(
+            access_path(sql, &db.schema)?,
+            "SEARCH t USING INTEGER PRIMARY KEY (rowid=?)"
+        );
+        assert_eq!(run(sql, &db)?, ["bob"]);
+        // The rows found are filtered by the other conditions.
+        assert!(run("SELECT name FROM t WHERE id = 2 AND name = 'ann'", &db)?.is_empty());
+        assert_eq!(
+            run(
+                "SELECT id FROM t WHERE id = 1 AND name = 'x' OR id = 2",
+                &db
+            )?,
+            ["2"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn never_finds_nulls_equal() -> Result<()> {
+        let db = db()?;
+        let values = "WITH c(n) AS (SELECT NULL FROM t UNION ALL SELECT id FROM t)";
+        let run_where =
+            |cond: &str| run(&format!("{} SELECT n FROM c WHERE {}", values, cond), &db);
+
+        assert!(run_where("n IN (NULL)")?.is_empty());
+        assert_eq!(run_where("n IN (NULL, 1)")?, ["1"]);
+        assert!(run_where("n IN (SELECT NULL FROM t)")?.is_empty());
+        assert!(run_where("n = (SELECT NULL FROM t)")?.is_empty());
+        assert_eq!(run_where("n != 1")?, ["2"]);
+        assert!(run_where("n = NULL OR NULL != n")?.is_empty());
+
+        // Seeks don't look for NULLs either.
+        let db = db_with_idx(
+            "CREATE INDEX i ON t(b)",
+            vec![Value::Null, Value::Int(1), Value::Null],
+            vec![(Value::Null, 1), (Value::Null, 3), (Value::Int(1), 2)],
+        )?;
+        let sql = "SELECT a FROM t WHERE b IN (NULL, 1)";
+        assert_eq!(
+            access_path(sql, &db.schema)?,
+            "SEARCH t USING INDEX i (b=?)"
+        );
+        assert_eq!(run(sql, &db)?, ["2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn runs_subqueries_once_unless_correlated() -> Result<()> {
+        let db = db()?;
+        let run_subquery = |sql| -> Result<_> {
+            let select = select(sql)?;
+            let root = plan(&select, &db.schema)?;
+            let rows = rows(&root, &db)?;
+            let subquery = &root.subqueries()[0];
+            Ok((rows, subquery.explain(), subquery.op.rows_returned()))
+        };
+
+        let (rows, explain, runs) =
+            run_subquery("SELECT id, (SELECT count(*) FROM t WHERE name != 'x') FROM t")?;
+        assert_eq!(rows, ["1|2", "2|2"]);
+        assert_eq!((explain.as_str(), runs), ("SCALAR SUBQUERY 1", 1));
+
+        // `id` isn't a column of the subquery's source, so it's the one of the outer row.
+        let (rows, explain, runs) = run_subquery(
+            "SELECT id, (SELECT count(*) FROM (SELECT name FROM t) WHERE name = 'ann' OR id = 2) \
+             FROM t",
+        )?;
+        assert_eq!(rows, ["1|1", "2|2"]);
+        assert_eq!(
+            (explain.as_str(), runs),
+            ("CORRELATED SCALAR SUBQUERY 1", 2)
+        );
+
+        assert_eq!(
+            run("SELECT id FROM t WHERE id IN (SELECT id, name FROM t)", &db)
+                .unwrap_err()
+                .to_string(),
+            "sub-select returns 2 columns - expected 1"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn walks_recursive_ctes() -> Result<()> {
+        let db = db()?;
+
+        // The step finds the other row from 1 and nothing from 2.
+        let walk = |union| {
+            format!(
+                "WITH RECURSIVE c(n) AS (SELECT id FROM t {} SELECT t.id FROM c JOIN t \
+                 ON t.id != c.n WHERE c.n = 1) SELECT n FROM c",
+                union
+            )
+        };
+        assert_eq!(run(&walk("UNION ALL"), &db)?, ["1", "2", "2"]);
+        assert_eq!(run(&walk("UNION"), &db)?, ["1", "2"]);
+        // With UNION, rows that were found before aren't walked again, so this ends.
+        assert_eq!(
+            run(
+                "WITH RECURSIVE c(n) AS (SELECT id FROM t WHERE id = 1 UNION \
+                 SELECT t.id FROM c, t WHERE t.id != c.n) SELECT n FROM c",
+                &db
+            )?,
+            ["1", "2"]
+        );
+        // Without reading itself, it's a plain compound query, which LIMIT can end too.
+        assert_eq!(
+            run(
+                "WITH c(n) AS (SELECT id FROM t UNION SELECT id FROM t) SELECT n FROM c",
+                &db
+            )?,
+            ["1", "2"]
+        );
+        assert_eq!(
+            run(
+                "WITH RECURSIVE c(n) AS (SELECT id FROM t UNION ALL SELECT n FROM c LIMIT 5) \
+                 SELECT n FROM c",
+                &db
+            )?,
+            ["1", "2", "1", "2", "1"]
+        );
+        // A SELECT without FROM has a single row to compute its columns on.
+        let from_constant = "WITH RECURSIVE c(n) AS (SELECT 2 UNION SELECT t.id FROM c, t \
+                             WHERE t.id != c.n) SELECT n FROM c";
+        assert_eq!(run(from_constant, &db)?, ["2", "1"]);
+        assert_eq!(
+            steps(from_constant, &db.schema)?[2],
+            (3, "SCAN CONSTANT ROW".to_owned())
+        );
+        assert_eq!(
+            run(
+                "WITH RECURSIVE cnt(x) AS (SELECT 1 UNION ALL SELECT x+1 FROM cnt LIMIT 5) \
+                 SELECT x FROM cnt",
+                &db
+            )?,
+            ["1", "2", "3", "4", "5"]
+        );
+        // CTEs hide tables of the same name and can be read under another one.
+        assert_eq!(
+            run(
+                "WITH t(a, b) AS (SELECT name, id FROM t WHERE id = 2) \
+                 SELECT x.a, t.b FROM t x, t",
+                &db
+            )?,
+            ["bob|2"]
+        );
+
+        for (sql, err) in [
+            (
+                "WITH c(a, b) AS (SELECT id FROM t) SELECT a FROM c",
+                "table c has 1 values for 2 columns",
+            ),
+            (
+                "WITH c(n) AS (SELECT id FROM t UNION SELECT x.n FROM c x, c y) SELECT n FROM c",
+                "multiple references to recursive table: c",
+            ),
+            (
+                "WITH c(n) AS (SELECT id FROM t UNION SELECT id FROM t WHERE id IN \
+                 (SELECT n FROM c)) SELECT n FROM c",
+                "recursive reference in a subquery: c",
+            ),
+            ("SELECT id FROM t, t x", "Ambiguous column 'id'"),
+        ] {
+            assert_eq!(run(sql, &db).unwrap_err().to_string(), err);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn combines_sorts_and_limits_rows() -> Result<()> {
+        let db = db()?;
+
+        assert_eq!(
+            run("SELECT name FROM t UNION SELECT name FROM t", &db)?,
+            ["ann", "bob"]
+        );
+        assert_eq!(
+            run(
+                "SELECT id FROM t UNION ALL SELECT id FROM t ORDER BY 1 DESC LIMIT 3",
+                &db
+            )?,
+            ["2", "2", "1"]
+        );
+        assert_eq!(
+            run(
+                "SELECT id FROM t INTERSECT SELECT id FROM t WHERE name = 'bob'",
+                &db
+            )?,
+            ["2"]
+        );
+        assert_eq!(
+            run(
+                "SELECT id FROM t EXCEPT SELECT id FROM t WHERE name = 'bob' ORDER BY id",
+                &db
+            )?,
+            ["1"]
+        );
+        // Rows can be sorted by columns that aren't in the result.
+        assert_eq!(
+            run("SELECT name FROM t ORDER BY score DESC", &db)?,
+            ["bob", "ann"]
+        );
+        assert_eq!(run("SELECT id FROM t LIMIT 1 OFFSET 1", &db)?, ["2"]);
+        assert_eq!(run("SELECT id FROM t LIMIT 5 OFFSET 2", &db)?, [""; 0]);
+
+        for (sql, err) in [
+            (
+                "SELECT id FROM t ORDER BY id, 2",
+                "2nd ORDER BY term out of range - should be between 1 and 1",
+            ),
+            (
+                "SELECT id FROM t UNION SELECT id FROM t ORDER BY name",
+                "1st ORDER BY term does not match any column in the result set",
+            ),
+            (
+                "SELECT id FROM t INTERSECT SELECT id, name FROM t",
+                "SELECTs to the left and right of INTERSECT do not have the same number of \
+                 result columns",
+            ),
+            ("SELECT id FROM t LIMIT 'x'", "datatype mismatch"),
+        ] {
+            assert_eq!(run(sql, &db).unwrap_err().to_string(), err);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn removes_duplicate_rows() -> Result<()> {
+        let db = db()?;
+
+        // NULLs are the same for DISTINCT, and so are 2 and 2.0, but count(DISTINCT) leaves
+        // NULLs out.
+        let values = "WITH c(n) AS (SELECT NULL FROM t UNION ALL SELECT id FROM t \
+                      UNION ALL SELECT score FROM t)";
+        assert_eq!(
+            run(&format!("{} SELECT DISTINCT n FROM c", values), &db)?,
+            ["NULL", "1", "2", "1.5"]
+        );
+        assert_eq!(
+            run(
+                &format!("{} SELECT count(DISTINCT n), count(*) FROM c", values),
+                &db
+            )?,
+            ["3|6"]
+        );
+        assert_eq!(
+            run("SELECT DISTINCT t.name, 1 FROM t, t x ORDER BY 1 DESC", &db)?,
+            ["bob|1", "ann|1"]
+        );
+
+        // Rows read in the order of the selected columns only need to be compared with the
+        // previous one.
+        assert_eq!(
+            steps("SELECT DISTINCT id FROM t WHERE id != 3", &db.schema)?,
+            [(0, "SCAN t".to_owned())]
+        );
+        assert_eq!(
+            steps("SELECT DISTINCT name FROM t", &db.schema)?,
+            [
+                (0, "SCAN t".to_owned()),
+                (0, "USE TEMP B-TREE FOR DISTINCT".to_owned())
+            ]
+        );
+        assert_eq!(run("SELECT DISTINCT 'x' FROM t", &db)?, ["x"]);
+        Ok(())
+    }
+
+    #[test]
+    fn compares_distinct_text_by_collation() -> Result<()> {
+        let rows = [("Foo", "a"), ("FOO", "a "), ("foo", "b")];
+        let db = db_with_tbl(
+            "CREATE TABLE t(a COLLATE NOCASE, b COLLATE RTRIM, c)",
+            rows.iter()
+                .map(|&(a, b)| vec![text(a), text(b), text(a)])
+                .collect(),
+        )?;
+
+        assert_eq!(
+            run(
+                "SELECT count(DISTINCT a), count(DISTINCT b), count(DISTINCT c) FROM t",
+                &db
+            )?,
+            ["1|2|3"]
+        );
+        assert_eq!(run("SELECT DISTINCT a FROM t", &db)?, ["Foo"]);
+        assert_eq!(run("SELECT DISTINCT a, b FROM t", &db)?, ["Foo|a", "foo|b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_distinct_rows_in_index_order() -> Result<()> {
+        let b = [2, 1, 2, 3, 1];
+        let mut entries = b.iter().copied().zip(1..).collect::<Vec<_>>();
+        entries.sort_unstable();
+        let db = db_with_idx(
+            "CREATE INDEX i ON t(b)",
+            b.iter().map(|&b| Value::Int(b)).collect(),
+            entries
+                .into_iter()
+                .map(|(b, i)| (Value::Int(b), i))
+                .collect(),
+        )?;
+
+        // The index sorts the rows by `b`, so equal ones come one after another.
+        assert_eq!(
+            steps("SELECT DISTINCT b FROM t", &db.schema)?,
+            [(0, "SCAN t USING INDEX i".to_owned())]
+        );
+        assert_eq!(run("SELECT DISTINCT b FROM t", &db)?, ["1", "2", "3"]);
+        assert_eq!(
+            run("SELECT DISTINCT b FROM t WHERE a != 4", &db)?,
+            ["1", "2"]
+        );
+        // Rows aren't sorted by the columns after those of the index.
+        assert_eq!(
+            steps("SELECT DISTINCT b, a FROM t", &db.schema)?,
+            [
+                (0, "SCAN t".to_owned()),
+                (0, "USE TEMP B-TREE FOR DISTINCT".to_owned())
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+//! Databases that tests build in memory, with a table `t` and the indexes they need.
+
+use crate::{
+    format::PageType,
+    interpreter::{connection::Connection, database::Database, eval::Value, write::Pager},
+    schema::DbSchema,
+};
+use anyhow::Result;
+use std::borrow::Cow;
+
+pub(crate) fn text(s: &str) -> Value<'static> {
+    Value::String(Cow::Owned(s.to_owned()))
+}
+
+/// A connection to the database of `db`.
+pub(crate) fn conn() -> Result<Connection> {
+    Ok(Connection::with_db(db()?))
+}
+
+/// A database with the table `t`.
+pub(crate) fn db() -> Result<Database> {
+    let rows = [("ann", 1.5), ("bob", 2.0)]
+        .iter()
+        .map(|&(name, score)| vec![Value::Null, text(name), Value::Float(score)])
+        .collect();
+    db_with_tbl(
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT, score REAL)",
+        rows,
+    )
+}
+
+/// A database with a table `t` created by `tbl_sql` that has the given rows, whose rowids count
+/// from 1.
+pub(crate) fn db_with_tbl(tbl_sql: &str, rows: Vec<Vec<Value>>) -> Result<Database> {
+    let db = Database::open("/nonexistent/db")?;
+    let mut pager = Pager::new(&db)?;
+    let root = pager.create_tree(PageType::LeafTbl);
+    let records = rows
+        .iter()
+        .map(|row| pager.encode_record(row))
+        .collect::<Vec<_>>();
+    pager.build_tbl(root, (1..).zip(records))?;
+    with_schema(db, pager, &[("table", "t", root, tbl_sql)])
+}
+
+/// A database with a table `t` whose column `b` has the values `b`, and an index `i` with
+/// the given entries of values and rowids.
+pub(crate) fn db_with_idx(
+    idx_sql: &str,
+    b: Vec<Value>,
+    idx_entries: Vec<(Value, i64)>,
+) -> Result<Database> {
+    let db = Database::open("/nonexistent/db")?;
+    let mut pager = Pager::new(&db)?;
+    let tbl_root = pager.create_tree(PageType::LeafTbl);
+    let idx_root = pager.create_tree(PageType::LeafIdx);
+    let rows = (1..)
+        .zip(&b)
+        .map(|(i, b)| pager.encode_record(&[Value::Int(i), b.clone()]))
+        .collect::<Vec<_>>();
+    pager.build_tbl(tbl_root, (1..).zip(rows))?;
+    let entries = idx_entries
+        .into_iter()
+        .map(|(entry, rowid)| pager.encode_record(&[entry, Value::Int(rowid)]))
+        .collect::<Vec<_>>();
+    pager.build_idx(idx_root, entries)?;
+    let schema = [
+        ("table", "t", tbl_root, "CREATE TABLE t(a, b NOT NULL)"),
+        ("index", "i", idx_root, idx_sql),
+    ];
+    with_schema(db, pager, &schema)
+}
+
+/// Finishes a database whose trees `pager` built by writing the schema of the objects of `t`,
+/// each given by its type, name, root page and SQL.
+fn with_schema(
+    mut db: Database,
+    mut pager: Pager,
+    objs: &[(&str, &str, u32, &str)],
+) -> Result<Database> {
+    let records = objs
+        .iter()
+        .map(|&(type_, name, root, sql)| {
+            let record = [
+                text(type_),
+                text(name),
+                text("t"),
+                Value::Int(root.into()),
+                text(sql),
+            ];
+            pager.encode_record(&record)
+        })
+        .collect::<Vec<_>>();
+    pager.build_tbl(1, (1..).zip(records))?;
+    db.bytes = pager.finish(true);
+    db.schema = DbSchema::parse(&db.bytes)?;
+    Ok(db)
+}
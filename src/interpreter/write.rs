@@ -0,0 +1,574 @@
+//! Writing B-trees. Instead of inserting cells one by one, a changed B-tree is rebuilt from all
+//! of its cells in sorted order. That fills its pages from left to right, so they never have to
+//! be split or balanced.
+
+use crate::{
+    format::{varint, DbFile, DbHeader, Enc, Page, PageType},
+    interpreter::{btree, database::Database, eval::Value},
+    util::MapOkAndThenExt,
+};
+use anyhow::{bail, Result};
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    convert::{TryFrom, TryInto},
+    mem,
+};
+
+/// A copy of the database file that B-trees are written to page by page. Pages that are no
+/// longer used by any B-tree are reused before the file grows, and the rest of them ends up in
+/// the freelist once writing is done.
+pub struct Pager {
+    bytes: Vec<u8>,
+    page_size: usize,
+    usable_size: usize,
+    enc: Enc,
+    schema_format: u32,
+    free: BTreeSet<u32>,
+}
+
+/// The contents of a page that has yet to be written.
+struct PageContent {
+    page_type: PageType,
+    cells: Vec<Vec<u8>>,
+    right_most_ptr: Option<u32>,
+}
+
+/// A page of a B-tree level together with the key its parent refers to it by. That's the largest
+/// rowid of a table page, while index pages are followed by an entry that is moved up into their
+/// parent, which the last page of a level has none of.
+type Node<T> = (T, Option<Vec<u8>>);
+
+impl Pager {
+    pub fn new(db: &Database) -> Result<Self> {
+        let header = &db.schema.db_header;
+        if header.autovacuum_top_root != 0 {
+            bail!("Writing to auto-vacuum databases is not supported");
+        }
+        if header.write_format != 1 {
+            bail!("Writing to WAL databases is not supported");
+        }
+
+        Ok(Self {
+            bytes: db.bytes.clone(),
+            page_size: header.page_size as usize,
+            usable_size: header.usable_size(),
+            enc: header.text_encoding,
+            schema_format: header.schema_format,
+            free: BTreeSet::new(),
+        })
+    }
+
+    /// Creates an empty B-tree of the given type and returns its root page.
+    pub fn create_tree(&mut self, leaf_type: PageType) -> u32 {
+        let root = self.allocate();
+        self.write_page(root, &PageContent::new(leaf_type, vec![], None));
+        root
+    }
+
+    /// Replaces the contents of the table B-tree at `root` with `rows`, which are pairs of a
+    /// rowid and an encoded record sorted by rowid.
+    pub fn build_tbl(
+        &mut self,
+        root: u32,
+        rows: impl IntoIterator<Item = (i64, Vec<u8>)>,
+    ) -> Result<()> {
+        self.free_tree(root)?;
+        let capacity = self.capacity(root);
+        // Larger records would need overflow pages.
+        let max_local = self.usable_size - 35;
+
+        let mut leaves = vec![];
+        let mut cells = vec![];
+        let mut size = PageContent::LEAF_HEADER_SIZE;
+        let mut last_rowid = 0;
+        for (rowid, record) in rows {
+            if record.len() > max_local {
+                bail!("Rows of more than {} bytes are not supported", max_local);
+            }
+            let mut cell = varint::serialize(record.len() as i64);
+            cell.extend(varint::serialize(rowid));
+            cell.extend(record);
+
+            if size + 2 + cell.len() > capacity && !cells.is_empty() {
+                let leaf = PageContent::new(PageType::LeafTbl, mem::take(&mut cells), None);
+                leaves.push((leaf, Some(varint::serialize(last_rowid))));
+                size = PageContent::LEAF_HEADER_SIZE;
+            }
+            size += 2 + cell.len();
+            cells.push(cell);
+            last_rowid = rowid;
+        }
+        let leaf = PageContent::new(PageType::LeafTbl, cells, None);
+        leaves.push((leaf, Some(varint::serialize(last_rowid))));
+
+        self.build_tree(root, leaves, PageType::IntrTbl);
+        Ok(())
+    }
+
+    /// Replaces the contents of the index B-tree at `root` with `entries`, which are encoded
+    /// records in the order of the index.
+    pub fn build_idx(
+        &mut self,
+        root: u32,
+        entries: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Result<()> {
+        self.free_tree(root)?;
+        let capacity = self.capacity(root);
+        let max_local = self.max_local_idx();
+
+        let mut leaves = vec![];
+        let mut cells = vec![];
+        let mut size = PageContent::LEAF_HEADER_SIZE;
+        for entry in entries {
+            if entry.len() > max_local {
+                bail!(
+                    "Index entries of more than {} bytes are not supported",
+                    max_local
+                );
+            }
+            let mut cell = varint::serialize(entry.len() as i64);
+            cell.extend(entry);
+
+            // The entry that doesn't fit anymore separates this leaf from the next one.
+            if size + 2 + cell.len() > capacity && !cells.is_empty() {
+                let leaf = PageContent::new(PageType::LeafIdx, mem::take(&mut cells), None);
+                leaves.push((leaf, Some(cell)));
+                size = PageContent::LEAF_HEADER_SIZE;
+                continue;
+            }
+            size += 2 + cell.len();
+            cells.push(cell);
+        }
+
+        // If the last entry ended up as a separator, the entry before it has to take its place.
+        if cells.is_empty() {
+            if let Some((mut leaf, Some(separator))) = leaves.pop() {
+                let prev = leaf
+                    .cells
+                    .pop()
+                    .expect("Leaf pages hold at least one entry");
+                leaves.push((leaf, Some(prev)));
+                cells.push(separator);
+            }
+        }
+        leaves.push((PageContent::new(PageType::LeafIdx, cells, None), None));
+
+        self.build_tree(root, leaves, PageType::IntrIdx);
+        Ok(())
+    }
+
+    /// Encodes `values` as a record as described here:
+    /// [Record Format](https://www.sqlite.org/fileformat2.html#record_format)
+    pub fn encode_record(&self, values: &[Value]) -> Vec<u8> {
+        let mut serial_types = vec![];
+        let mut body = vec![];
+        for val in values {
+            let serial_type = match val {
+                Value::Null => 0,
+                // Schema format 4 added serial types for the integers 0 and 1.
+                Value::Int(x @ (0 | 1)) if self.schema_format >= 4 => 8 + x,
+                Value::Int(x) => {
+                    let (serial_type, len) = match x {
+                        -0x80..=0x7f => (1, 1),
+                        -0x8000..=0x7fff => (2, 2),
+                        -0x80_0000..=0x7f_ffff => (3, 3),
+                        -0x8000_0000..=0x7fff_ffff => (4, 4),
+                        -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                        _ => (6, 8),
+                    };
+                    body.extend(&x.to_be_bytes()[8 - len..]);
+                    serial_type
+                }
+                Value::Float(x) => {
+                    body.extend(x.to_be_bytes());
+                    7
+                }
+                Value::Bytes(bytes) => {
+                    body.extend(bytes.iter());
+                    12 + 2 * bytes.len() as i64
+                }
+                Value::String(s) => {
+                    let bytes = self.enc.encode(s);
+                    body.extend(&*bytes);
+                    13 + 2 * bytes.len() as i64
+                }
+            };
+            serial_types.extend(varint::serialize(serial_type));
+        }
+
+        // The size of the header includes the varint it is stored in.
+        let mut header_size = serial_types.len() + 1;
+        while serial_types.len() + varint::serialize(header_size as i64).len() != header_size {
+            header_size += 1;
+        }
+
+        let mut record = varint::serialize(header_size as i64);
+        record.extend(serial_types);
+        record.extend(body);
+        record
+    }
+
+    /// Finishes writing and returns the new contents of the database file. Pages that weren't
+    /// reused are added to the freelist. Changing the schema cookie makes other connections
+    /// reload the schema.
+    pub fn finish(mut self, schema_changed: bool) -> Vec<u8> {
+        let leaves_per_trunk = self.usable_size / 4 - 8;
+        let mut first_trunk = self.header_field(32);
+        let mut free_count = self.header_field(36);
+
+        let free = mem::take(&mut self.free).into_iter().collect::<Vec<_>>();
+        for pages in free.chunks(leaves_per_trunk + 1).rev() {
+            let (trunk, leaves) = (pages[0], &pages[1..]);
+            let offset = self.page_offset(trunk);
+            let page = &mut self.bytes[offset..offset + self.page_size];
+            page.fill(0);
+            page[..4].copy_from_slice(&first_trunk.to_be_bytes());
+            page[4..8].copy_from_slice(&(leaves.len() as u32).to_be_bytes());
+            for (i, leaf) in leaves.iter().enumerate() {
+                page[8 + 4 * i..12 + 4 * i].copy_from_slice(&leaf.to_be_bytes());
+            }
+            first_trunk = trunk;
+            free_count += pages.len() as u32;
+        }
+
+        let change_counter = self.header_field(24).wrapping_add(1);
+        let page_count = (self.bytes.len() / self.page_size) as u32;
+        self.set_header_field(24, change_counter);
+        self.set_header_field(28, page_count);
+        self.set_header_field(32, first_trunk);
+        self.set_header_field(36, free_count);
+        self.set_header_field(92, change_counter);
+        if schema_changed {
+            let schema_cookie = self.header_field(40).wrapping_add(1);
+            self.set_header_field(40, schema_cookie);
+        }
+        self.bytes
+    }
+
+    /// Builds the levels of a B-tree from the bottom up until a single page remains, which is
+    /// written to the root page.
+    fn build_tree(&mut self, root: u32, mut level: Vec<Node<PageContent>>, intr_type: PageType) {
+        let capacity = self.capacity(root);
+        while level.len() > 1 {
+            let children = level
+                .into_iter()
+                .map(|(page, key)| {
+                    let page_num = self.allocate();
+                    self.write_page(page_num, &page);
+                    (page_num, key)
+                })
+                .collect();
+            level = pack_intr_pages(children, intr_type, capacity);
+        }
+
+        let (page, _) = level.pop().expect("A B-tree has at least one page");
+        self.write_page(root, &page);
+    }
+
+    /// Frees all pages of the B-tree at `root` except for the root page itself.
+    fn free_tree(&mut self, root: u32) -> Result<()> {
+        let mut pages = BTreeSet::new();
+        let mut stack = vec![root];
+        while let Some(page_num) = stack.pop() {
+            let page = self.page(page_num)?;
+            // Pages of a corrupt file might point back up their B-tree.
+            if !pages.insert(page_num) {
+                return Err(page.corrupt(page.header_offset(), "Page is referenced twice"));
+            }
+            for ptr in page.cell_ptrs() {
+                let mut cell = page.parse_cell(ptr, Ok)?;
+                if !page.header.is_leaf() {
+                    match cell.get(..4) {
+                        Some(child) => stack.push(u32::from_be_bytes(child.try_into()?)),
+                        None => return Err(page.corrupt(ptr, "Cell extends past end of page")),
+                    }
+                    cell = &cell[4..];
+                }
+                let (payload_size, max_local) = match page.header.page_type {
+                    PageType::LeafTbl => (varint::parse(cell).0, self.usable_size - 35),
+                    PageType::IntrTbl => continue,
+                    PageType::LeafIdx | PageType::IntrIdx => {
+                        (varint::parse(cell).0, self.max_local_idx())
+                    }
+                };
+                if payload_size > max_local as i64 {
+                    bail!("Rewriting B-trees with overflow pages is not supported");
+                }
+            }
+            stack.extend(page.header.right_most_ptr.map(|p| p as u32));
+        }
+
+        pages.remove(&root);
+        self.free.extend(pages);
+        Ok(())
+    }
+
+    fn allocate(&mut self) -> u32 {
+        if let Some(page_num) = self.free.iter().next().copied() {
+            self.free.remove(&page_num);
+            return page_num;
+        }
+        self.bytes.resize(self.bytes.len() + self.page_size, 0);
+        (self.bytes.len() / self.page_size) as u32
+    }
+
+    fn write_page(&mut self, page_num: u32, page: &PageContent) {
+        let header_offset = header_offset(page_num);
+        let start = self.page_offset(page_num);
+        let data = &mut self.bytes[start..start + self.page_size];
+        data[header_offset..].fill(0);
+
+        let header_size = page.header_size();
+        let mut content_start = self.usable_size;
+        for (i, cell) in page.cells.iter().enumerate() {
+            content_start -= cell.len();
+            data[content_start..content_start + cell.len()].copy_from_slice(cell);
+            let ptr = header_offset + header_size + 2 * i;
+            data[ptr..ptr + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+        }
+
+        let header = &mut data[header_offset..];
+        header[0] = page.page_type as u8;
+        header[3..5].copy_from_slice(&(page.cells.len() as u16).to_be_bytes());
+        // A content area starting at 65536 is stored as 0.
+        header[5..7].copy_from_slice(&(content_start as u16).to_be_bytes());
+        if let Some(ptr) = page.right_most_ptr {
+            header[8..12].copy_from_slice(&ptr.to_be_bytes());
+        }
+    }
+
+    fn page(&self, page_num: u32) -> Result<Page<'_>> {
+        if page_num == 1 {
+            Page::parse_schema(self.page_size, &self.bytes)
+        } else {
+            Page::parse(page_num as i32, self.page_size, &self.bytes)
+        }
+    }
+
+    /// The space available to the pages of the B-tree at `root`. Every page has to fit into the
+    /// root page, which has less space on page 1.
+    fn capacity(&self, root: u32) -> usize {
+        self.usable_size - header_offset(root)
+    }
+
+    /// The size of the largest payload an index page stores without overflow pages.
+    fn max_local_idx(&self) -> usize {
+        (self.usable_size - 12) * 64 / 255 - 23
+    }
+
+    fn page_offset(&self, page_num: u32) -> usize {
+        (page_num as usize - 1) * self.page_size
+    }
+
+    fn header_field(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn set_header_field(&mut self, offset: usize, v: u32) {
+        self.bytes[offset..offset + 4].copy_from_slice(&v.to_be_bytes());
+    }
+}
+
+/// Adds a table to the schema table and creates its empty B-tree.
+pub fn create_tbl(db: &mut Database, name: &str, sql: String) -> Result<()> {
+    let bytes = {
+        let mut pager = Pager::new(db)?;
+        let rootpage = pager.create_tree(PageType::LeafTbl);
+
+        let db_file = DbFile::new(&db.bytes, &db.schema.db_header);
+        let schema_page = Page::parse_schema(db_file.page_size, &db.bytes)?;
+        let mut rows = btree::full_tbl_scan(schema_page, db_file)
+            .map_ok_and_then(|cell| {
+                let record = cell
+                    .payload
+                    .0
+                    .iter()
+                    .map(Value::try_from)
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((cell.row_id, pager.encode_record(&record)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let rowid = rows.last().map_or(1, |(rowid, _)| rowid + 1);
+        let record = [
+            Value::String(Cow::Borrowed("table")),
+            Value::String(Cow::Borrowed(name)),
+            Value::String(Cow::Borrowed(name)),
+            Value::Int(rootpage.into()),
+            Value::String(Cow::Owned(sql)),
+        ];
+        rows.push((rowid, pager.encode_record(&record)));
+
+        pager.build_tbl(1, rows)?;
+        pager.finish(true)
+    };
+    db.commit(bytes)
+}
+
+impl PageContent {
+    const LEAF_HEADER_SIZE: usize = 8;
+    const INTR_HEADER_SIZE: usize = 12;
+
+    fn new(page_type: PageType, cells: Vec<Vec<u8>>, right_most_ptr: Option<u32>) -> Self {
+        Self {
+            page_type,
+            cells,
+            right_most_ptr,
+        }
+    }
+
+    fn header_size(&self) -> usize {
+        match self.right_most_ptr {
+            Some(_) => Self::INTR_HEADER_SIZE,
+            None => Self::LEAF_HEADER_SIZE,
+        }
+    }
+}
+
+/// Page 1 starts with the database header.
+fn header_offset(page_num: u32) -> usize {
+    if page_num == 1 {
+        DbHeader::SIZE
+    } else {
+        0
+    }
+}
+
+/// Distributes the child pages of a level among as few interior pages as possible. Each child
+/// except for the right-most one of a page is referred to by a cell holding its key.
+fn pack_intr_pages(
+    children: Vec<Node<u32>>,
+    page_type: PageType,
+    capacity: usize,
+) -> Vec<Node<PageContent>> {
+    let key_len = |(_, key): &Node<u32>| key.as_ref().map_or(0, Vec::len);
+
+    let mut groups = vec![vec![]];
+    let mut size = PageContent::INTR_HEADER_SIZE;
+    for child in children {
+        let group = groups.last_mut().unwrap();
+        // Adding a child turns the right-most child so far into a cell.
+        if let Some(last) = group.last() {
+            let cell_size = 2 + 4 + key_len(last);
+            if size + cell_size > capacity {
+                groups.push(vec![child]);
+                size = PageContent::INTR_HEADER_SIZE;
+                continue;
+            }
+            size += cell_size;
+        }
+        group.push(child);
+    }
+
+    // Every interior page needs at least one cell besides its right-most child.
+    if let [.., prev, last] = groups.as_mut_slice() {
+        if last.len() == 1 {
+            last.insert(0, prev.pop().unwrap());
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|mut group| {
+            let (right_most_ptr, key) = group.pop().unwrap();
+            let cells = group
+                .into_iter()
+                .map(|(page_num, key)| {
+                    let mut cell = page_num.to_be_bytes().to_vec();
+                    cell.extend(key.expect("Only the last page of a level has no key"));
+                    cell
+                })
+                .collect();
+            (
+                PageContent::new(page_type, cells, Some(right_most_ptr)),
+                key,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Pager;
+    use crate::{
+        format::{DbFile, PageType},
+        interpreter::{btree, database::Database, eval::Value},
+        schema::DbSchema,
+        util::MapOkAndThenExt,
+    };
+    use anyhow::Result;
+    use itertools::Itertools;
+    use std::{borrow::Cow, convert::TryFrom};
+
+    fn rows(n: i64) -> impl Iterator<Item = Vec<Value<'static>>> {
+        (0..n).map(|i| {
+            vec![
+                Value::Int(i * 1000),
+                Value::String(Cow::Owned("x".repeat(50))),
+            ]
+        })
+    }
+
+    #[test]
+    fn builds_multi_level_trees() -> Result<()> {
+        let db = Database::open("/nonexistent/db")?;
+        let mut pager = Pager::new(&db)?;
+        let tbl_root = pager.create_tree(PageType::LeafTbl);
+        let idx_root = pager.create_tree(PageType::LeafIdx);
+        let records = rows(5000)
+            .map(|r| pager.encode_record(&r))
+            .collect::<Vec<_>>();
+        pager.build_tbl(tbl_root, (1..).zip(records.clone()))?;
+        pager.build_idx(idx_root, records)?;
+        let bytes = pager.finish(false);
+
+        let db_schema = DbSchema::parse(&bytes)?;
+        let db = DbFile::new(&bytes, &db_schema.db_header);
+        let rowids = btree::full_tbl_scan(db.page(tbl_root as i32)?, db)
+            .map_ok(|cell| cell.row_id)
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(rowids, (1..=5000).collect::<Vec<_>>());
+
+        let entries = btree::idx_seek(vec![], db.page(idx_root as i32)?, db)
+            .map_ok_and_then(|entry| i64::try_from(&entry[0]))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(entries, (0..5000).map(|i| i * 1000).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn frees_pages_of_rebuilt_trees() -> Result<()> {
+        let db = Database::open("/nonexistent/db")?;
+        let mut pager = Pager::new(&db)?;
+        let root = pager.create_tree(PageType::LeafTbl);
+        let records = rows(500)
+            .map(|r| pager.encode_record(&r))
+            .collect::<Vec<_>>();
+        pager.build_tbl(root, (1..).zip(records))?;
+        pager.build_tbl(root, vec![])?;
+        let bytes = pager.finish(false);
+
+        let header = DbSchema::parse(&bytes)?.db_header;
+        assert_eq!(header.db_page_count as usize, bytes.len() / 4096);
+        assert_eq!(header.freelist_page_count, header.db_page_count - 2);
+        Ok(())
+    }
+
+    #[test]
+    fn encodes_smallest_serial_types() -> Result<()> {
+        let pager = Pager::new(&Database::open("/nonexistent/db")?)?;
+        let record = pager.encode_record(&[
+            Value::Null,
+            Value::Int(1),
+            Value::Int(-129),
+            Value::Int(1 << 40),
+            Value::Float(0.5),
+            Value::String(Cow::Borrowed("ab")),
+            Value::Bytes(Cow::Borrowed(&[7])),
+        ]);
+        assert_eq!(record[..8], [8, 0, 9, 2, 5, 7, 17, 14]);
+        assert_eq!(record.len(), 8 + 2 + 6 + 8 + 2 + 1);
+        Ok(())
+    }
+}
@@ -3,3 +3,8 @@ pub mod interpreter;
 pub mod schema;
 pub mod syntax;
 pub mod util;
+
+pub use interpreter::{
+    connection::{ColIndex, Connection, ParamIndex, Row, Rows, Statement},
+    convert::{Date, DateTime, FromSql, OwnedValue, Time, ToSql},
+};
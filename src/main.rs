@@ -1,31 +1,36 @@
 use anyhow::{anyhow, bail, Result};
-use sqlite_starter_rust::{interpreter::exec, schema::DbSchema, syntax::parse};
-use std::{env::args, fs::File, io::Read};
+use sqlite_starter_rust::{
+    interpreter::{database::Database, exec, output::OutputConfig, repl},
+    syntax::parse,
+};
+use std::{env::args, process};
 
 fn main() -> Result<()> {
     let args = args().collect::<Vec<_>>();
     let (db_file, sql) = parse_args(&args)?;
 
-    let db = read_db(db_file)?;
+    let mut db = Database::open(db_file)?;
 
-    let sql = parse::sqlite(sql).map_err(|e| anyhow!("Invalid SQL: {}", e))?;
-    let schema = DbSchema::parse(&db)?;
-    exec::sqlite(sql, &schema, &db)?;
-
-    Ok(())
+    match sql {
+        Some(sql) => {
+            let sql = parse::sqlite(sql).map_err(|e| anyhow!("Invalid SQL: {}", e))?;
+            exec::sqlite(sql, &mut db, &mut OutputConfig::default())
+        }
+        None => {
+            // Failed statements don't end the session, but they still make it fail.
+            if !repl::run(&mut db)? {
+                process::exit(1);
+            }
+            Ok(())
+        }
+    }
 }
 
-fn parse_args(args: &[String]) -> Result<(&str, &str)> {
+/// Without a command an interactive shell is started.
+fn parse_args(args: &[String]) -> Result<(&str, Option<&str>)> {
     match args.len() {
-        0 | 1 => bail!("Missing <database path> and <command>"),
-        2 => bail!("Missing <command>"),
-        _ => Ok((&args[1], &args[2])),
+        0 | 1 => bail!("Missing <database path>"),
+        2 => Ok((&args[1], None)),
+        _ => Ok((&args[1], Some(&args[2]))),
     }
 }
-
-fn read_db(file: &str) -> Result<Vec<u8>> {
-    let mut file = File::open(file)?;
-    let mut db = Vec::new();
-    file.read_to_end(&mut db)?;
-    Ok(db)
-}
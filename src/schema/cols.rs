@@ -1,67 +1,522 @@
 use crate::{
-    syntax::{parse, ColDef, SqlStmt},
-    util::{flip, IterEither},
+    syntax::{
+        parse, Affinity, ColConstraint, ColDef, IndexedCol, SortOrder, SqlStmt, TblConstraint,
+    },
+    util::{flip, ContainsExt, IterEither},
 };
 use anyhow::{anyhow, bail, Result};
-use std::{collections::HashMap, iter::once};
+use std::collections::HashMap;
 
 #[derive(Debug)]
-pub enum Cols<'a> {
+pub enum Cols {
     TblCols {
-        int_pk: Option<&'a str>,
-        name_to_pos: HashMap<&'a str, usize>,
+        int_pk: Option<String>,
+        pk: Vec<KeyCol>,
+        without_rowid: bool,
+        /// The column names in the order they were declared in.
+        names: Vec<String>,
+        name_to_pos: HashMap<String, usize>,
+        types: HashMap<String, ColType>,
+        autoindexes: Vec<Vec<KeyCol>>,
+    },
+    IdxCols {
+        cols: Vec<KeyCol>,
+        /// Whether no two entries may have the same key, which holds for the automatic indexes
+        /// of UNIQUE and PRIMARY KEY constraints as well.
+        unique: bool,
+        partial: bool,
     },
-    IdxCol(&'a str),
 }
 
-impl<'a> Cols<'a> {
-    pub fn parse(create_sql: &'a str) -> Result<Self> {
+/// What a table's column declaration says about the values it may hold.
+#[derive(Debug)]
+pub struct ColType {
+    affinity: Affinity,
+    not_null: bool,
+    generated: Option<Generated>,
+    collation: Option<String>,
+}
+
+/// How the value of a generated column is kept.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Generated {
+    /// Computed from the text of an expression whenever the column is read.
+    Virtual(String),
+    /// Written to the record like the value of any other column.
+    Stored,
+}
+
+/// A column of an index key and the order it is sorted in. Without a collation of its own the
+/// column uses the one of the table's column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyCol {
+    pub name: String,
+    pub order: SortOrder,
+    pub collation: Option<String>,
+}
+
+impl From<&IndexedCol<'_>> for KeyCol {
+    fn from(col: &IndexedCol) -> Self {
+        Self {
+            name: col.name.to_owned(),
+            order: col.order,
+            collation: col.collation.map(String::from),
+        }
+    }
+}
+
+impl Cols {
+    pub fn parse(create_sql: &str) -> Result<Self> {
         let sql = parse::sql_stmt(create_sql)
             .map_err(|e| anyhow!("Failed to parse CREATE statement: {}", e))?;
 
         Ok(match sql {
-            SqlStmt::CreateTbl { col_defs, .. } => Self::TblCols {
-                int_pk: col_defs.iter().find(ColDef::is_int_pk).map(ColDef::name),
-                name_to_pos: col_defs
+            SqlStmt::CreateTbl {
+                col_defs,
+                constraints,
+                without_rowid,
+                ..
+            } => {
+                let rowid_alias = int_pk(&col_defs, &constraints);
+                let int_pk = if without_rowid { None } else { rowid_alias };
+                let pk = pk(&col_defs, &constraints);
+
+                // The records of WITHOUT ROWID tables start with the primary key columns.
+                // VIRTUAL columns aren't part of the records at all.
+                let record_order = if without_rowid { pk.as_slice() } else { &[] }
                     .iter()
-                    .map(ColDef::name)
+                    .map(|c| c.name.as_str())
+                    .chain(
+                        col_defs
+                            .iter()
+                            .filter(|c| !matches!(c.generated(), Some((_, false))))
+                            .map(ColDef::name)
+                            .filter(|n| !without_rowid || !pk.iter().any(|c| c.name == *n)),
+                    );
+
+                let name_to_pos = record_order
+                    .map(String::from)
                     .enumerate()
                     .map(flip)
-                    .collect::<HashMap<_, _>>(),
+                    .collect::<HashMap<_, _>>();
+
+                // The primary key of a WITHOUT ROWID table can't be NULL without being declared
+                // NOT NULL.
+                let types = col_defs
+                    .iter()
+                    .map(|c| {
+                        let not_null = c.constraints.contains(&ColConstraint::NotNull)
+                            || without_rowid && pk.iter().any(|k| k.name == c.name());
+                        let affinity = c.affinity();
+                        let generated = c.generated().map(|(expr, stored)| match stored {
+                            true => Generated::Stored,
+                            false => Generated::Virtual(expr.to_owned()),
+                        });
+                        let col_type = ColType {
+                            affinity,
+                            not_null,
+                            generated,
+                            collation: c.collation().map(String::from),
+                        };
+                        (c.name.to_owned(), col_type)
+                    })
+                    .collect();
+
+                // An INTEGER PRIMARY KEY of a WITHOUT ROWID table is no rowid alias, but its
+                // index is only created after all others.
+                let mut autoindexes = autoindexes(&col_defs, &constraints, rowid_alias);
+                if without_rowid && rowid_alias.is_some() {
+                    autoindexes.push(pk.clone());
+                }
+
+                Self::TblCols {
+                    int_pk: int_pk.map(String::from),
+                    pk,
+                    without_rowid,
+                    names: col_defs.iter().map(|c| c.name.to_owned()).collect(),
+                    name_to_pos,
+                    types,
+                    autoindexes,
+                }
+            }
+            SqlStmt::CreateIdx {
+                target_cols,
+                unique,
+                partial,
+                ..
+            } => Self::IdxCols {
+                cols: target_cols.iter().map(KeyCol::from).collect(),
+                unique,
+                partial,
             },
-            SqlStmt::CreateIdx { target_col, .. } => Self::IdxCol(target_col),
             _ => bail!("Expected CREATE statement but got:\n{}", create_sql),
         })
     }
 
     pub fn names(&self) -> impl Iterator<Item = &str> {
         match self {
-            Self::TblCols { name_to_pos, .. } => IterEither::left(name_to_pos.keys().copied()),
-            Self::IdxCol(col) => IterEither::right(once(*col)),
+            Self::TblCols { names, .. } => IterEither::left(names.iter().map(String::as_str)),
+            Self::IdxCols { cols, .. } => IterEither::right(cols.iter().map(|c| c.name.as_str())),
         }
     }
 
     pub fn has(&self, col: &str) -> bool {
         match self {
-            Self::TblCols { name_to_pos, .. } => name_to_pos.contains_key(col),
-            Self::IdxCol(c) => col == *c,
+            Self::TblCols { types, .. } => types.contains_key(col),
+            Self::IdxCols { cols, .. } => cols.iter().any(|c| c.name == col),
+        }
+    }
+
+    /// The columns the entries of an index are sorted by. WITHOUT ROWID tables are stored as an
+    /// index on their primary key, while the rows of other tables are sorted by their rowid.
+    pub fn key(&self) -> &[KeyCol] {
+        match self {
+            Self::TblCols {
+                without_rowid: true,
+                pk,
+                ..
+            } => pk,
+            Self::TblCols { .. } => &[],
+            Self::IdxCols { cols, .. } => cols,
         }
     }
 
+    /// The left-most column of an index, which is the column its entries are sorted by first.
+    pub fn leading(&self) -> Option<&KeyCol> {
+        self.key().first()
+    }
+
+    /// The columns of the n-th (starting at 1) automatic index SQLite created for the
+    /// `UNIQUE` and `PRIMARY KEY` constraints of a table. Those indexes are named
+    /// `sqlite_autoindex_<table>_<n>` and have no CREATE statement in the schema table.
+    pub fn autoindex(&self, n: usize) -> Option<Self> {
+        match self {
+            Self::TblCols { autoindexes, .. } => n
+                .checked_sub(1)
+                .and_then(|i| autoindexes.get(i))
+                .map(|cols| Self::IdxCols {
+                    cols: cols.clone(),
+                    unique: true,
+                    partial: false,
+                }),
+            Self::IdxCols { .. } => None,
+        }
+    }
+
+    pub fn is_without_rowid(&self) -> bool {
+        matches!(
+            self,
+            Self::TblCols {
+                without_rowid: true,
+                ..
+            }
+        )
+    }
+
+    /// The primary key columns of a table in the order they were declared in.
+    pub fn pk(&self) -> &[KeyCol] {
+        match self {
+            Self::TblCols { pk, .. } => pk,
+            Self::IdxCols { .. } => &[],
+        }
+    }
+
+    /// The positions of a WITHOUT ROWID table's primary key columns in the entries of one of
+    /// its indexes. Instead of a rowid those entries end with the primary key columns that
+    /// aren't already part of the index.
+    pub fn pk_pos_in_idx(&self, idx_cols: &Cols) -> Vec<usize> {
+        let idx_cols = match idx_cols {
+            Cols::IdxCols { cols, .. } => cols.as_slice(),
+            Cols::TblCols { .. } => &[],
+        };
+
+        let mut suffix_pos = idx_cols.len();
+        self.pk()
+            .iter()
+            .map(|pk_col| {
+                idx_cols
+                    .iter()
+                    .position(|c| c.name == pk_col.name)
+                    .unwrap_or_else(|| {
+                        suffix_pos += 1;
+                        suffix_pos - 1
+                    })
+            })
+            .collect()
+    }
+
     pub fn is_int_pk(&self, col: &str) -> bool {
         matches!(
             self,
             Self::TblCols {
                 int_pk: Some(c),
                 ..
-            } if *c == col
+            } if c == col
         )
     }
 
-    pub fn record_pos(&self, col: &str) -> usize {
+    /// The affinity of a table's column. The columns of indexes have no affinity of their own.
+    pub fn affinity(&self, col: &str) -> Affinity {
+        match self {
+            Self::TblCols { types, .. } => types[col].affinity,
+            Self::IdxCols { .. } => Affinity::Blob,
+        }
+    }
+
+    pub fn is_not_null(&self, col: &str) -> bool {
+        matches!(self, Self::TblCols { types, .. } if types.get(col).map_or(false, |t| t.not_null))
+    }
+
+    /// The collation a table's column names for its text, if any.
+    pub fn col_collation(&self, col: &str) -> Option<&str> {
+        match self {
+            Self::TblCols { types, .. } => types.get(col)?.collation.as_deref(),
+            Self::IdxCols { .. } => None,
+        }
+    }
+
+    /// The collation text in an index column of a table is compared with, which is BINARY unless
+    /// the index or the table's column names another one.
+    pub fn collation<'c>(&'c self, key_col: &'c KeyCol) -> &'c str {
+        key_col
+            .collation
+            .as_deref()
+            .or_else(|| self.col_collation(&key_col.name))
+            .unwrap_or("BINARY")
+    }
+
+    /// An index column of a table with the collation it uses made explicit, which is how
+    /// [cmp_keys](crate::interpreter::btree::cmp_keys) expects it.
+    pub fn collated(&self, key_col: &KeyCol) -> KeyCol {
+        KeyCol {
+            collation: Some(self.collation(key_col).to_owned()),
+            ..key_col.clone()
+        }
+    }
+
+    /// Whether an index column of a table is sorted in the order values compare in.
+    pub fn is_binary(&self, key_col: &KeyCol) -> bool {
+        self.collation(key_col).eq_ignore_ascii_case("BINARY")
+    }
+
+    /// How a generated column of a table is computed, or `None` for other columns.
+    pub fn generated(&self, col: &str) -> Option<&Generated> {
         match self {
-            Self::TblCols { name_to_pos, .. } => name_to_pos[col],
-            Self::IdxCol(_) => 0,
+            Self::TblCols { types, .. } => types.get(col)?.generated.as_ref(),
+            Self::IdxCols { .. } => None,
         }
     }
+
+    /// Whether a column of a table is computed when it's read instead of being stored.
+    pub fn is_virtual(&self, col: &str) -> bool {
+        matches!(self.generated(col), Some(Generated::Virtual(_)))
+    }
+
+    /// Whether no two entries of an index may have the same key.
+    pub fn is_unique(&self) -> bool {
+        matches!(self, Self::IdxCols { unique: true, .. })
+    }
+
+    /// Whether an index only has entries for the rows matching its WHERE clause.
+    pub fn is_partial(&self) -> bool {
+        matches!(self, Self::IdxCols { partial: true, .. })
+    }
+
+    /// The number n of the automatic index `sqlite_autoindex_<table>_<n>` that a WITHOUT ROWID
+    /// table's primary key would have. The table's B-tree is that index, so it has no entry in
+    /// the schema table, but the other automatic indexes are still numbered around it.
+    pub fn pk_autoindex_num(&self) -> Option<usize> {
+        if !self.is_without_rowid() {
+            return None;
+        }
+        (1..)
+            .map(|n| self.autoindex(n))
+            .take_while(Option::is_some)
+            .flatten()
+            .position(|idx| idx.key() == self.key())
+            .map(|i| i + 1)
+    }
+
+    pub fn record_pos(&self, col: &str) -> Result<usize> {
+        match self {
+            Self::TblCols { name_to_pos, .. } => name_to_pos.get(col).copied(),
+            Self::IdxCols { cols, .. } => cols.iter().position(|c| c.name == col),
+        }
+        .ok_or_else(|| anyhow!("No column {} in record", col))
+    }
+}
+
+fn int_pk<'a>(col_defs: &[ColDef<'a>], constraints: &[TblConstraint<'a>]) -> Option<&'a str> {
+    let tbl_pk = constraints.iter().find_map(|c| match c {
+        TblConstraint::PrimaryKey(cols) if cols.len() == 1 => Some(cols[0].name),
+        _ => None,
+    });
+
+    col_defs
+        .iter()
+        .find(ColDef::is_int_pk)
+        .or_else(|| {
+            col_defs
+                .iter()
+                .find(|c| tbl_pk.contains_(&c.name) && c.has_int_type())
+        })
+        .map(ColDef::name)
+}
+
+fn pk(col_defs: &[ColDef], constraints: &[TblConstraint]) -> Vec<KeyCol> {
+    let col_pk = col_defs.iter().find_map(|col| {
+        col.constraints.iter().find_map(|c| match c {
+            ColConstraint::PrimaryKey(order) => Some(vec![KeyCol {
+                name: col.name.to_owned(),
+                order: *order,
+                collation: None,
+            }]),
+            _ => None,
+        })
+    });
+
+    let tbl_pk = || {
+        constraints.iter().find_map(|c| match c {
+            TblConstraint::PrimaryKey(cols) => Some(cols.iter().map(KeyCol::from).collect()),
+            _ => None,
+        })
+    };
+
+    col_pk.or_else(tbl_pk).unwrap_or_default()
+}
+
+/// Lists the columns of the indexes SQLite creates automatically for a table in the order they
+/// are numbered. Constraints are numbered in order of appearance, while column constraints
+/// precede table constraints. An INTEGER PRIMARY KEY doesn't need an index and constraints
+/// targeting the same columns as a previous one share its index.
+fn autoindexes(
+    col_defs: &[ColDef],
+    constraints: &[TblConstraint],
+    int_pk: Option<&str>,
+) -> Vec<Vec<KeyCol>> {
+    let col_constraints = col_defs.iter().flat_map(|col| {
+        col.constraints.iter().filter_map(move |c| match c {
+            ColConstraint::PrimaryKey(_) if int_pk.contains_(&col.name) => None,
+            ColConstraint::PrimaryKey(order) => Some(vec![KeyCol {
+                name: col.name.to_owned(),
+                order: *order,
+                collation: None,
+            }]),
+            ColConstraint::Unique => Some(vec![KeyCol {
+                name: col.name.to_owned(),
+                order: SortOrder::Asc,
+                collation: None,
+            }]),
+            _ => None,
+        })
+    });
+
+    let tbl_constraints = constraints
+        .iter()
+        .filter_map(|c| match c {
+            TblConstraint::PrimaryKey(cols)
+                if cols.len() == 1 && int_pk.contains_(&cols[0].name) =>
+            {
+                None
+            }
+            TblConstraint::PrimaryKey(cols) | TblConstraint::Unique(cols) => Some(cols),
+            TblConstraint::ForeignKey { .. } => None,
+        })
+        .map(|cols| cols.iter().map(KeyCol::from).collect());
+
+    col_constraints
+        .chain(tbl_constraints)
+        .fold(vec![], |mut idxs: Vec<Vec<KeyCol>>, cols| {
+            let is_dup = idxs
+                .iter()
+                .any(|idx| idx.iter().map(|c| &c.name).eq(cols.iter().map(|c| &c.name)));
+            if !is_dup {
+                idxs.push(cols);
+            }
+            idxs
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cols, Generated};
+
+    fn autoindex_names(create_sql: &str, n: usize) -> Option<Vec<String>> {
+        Cols::parse(create_sql)
+            .unwrap()
+            .autoindex(n)
+            .map(|cols| cols.names().map(String::from).collect())
+    }
+
+    #[test]
+    fn numbers_col_constraints_before_tbl_constraints() {
+        let sql = "create table t (a text unique, b integer, c, d, unique (c, d), \
+                   unique (a), primary key (b, c))";
+        assert_eq!(autoindex_names(sql, 1).unwrap(), ["a"]);
+        assert_eq!(autoindex_names(sql, 2).unwrap(), ["c", "d"]);
+        assert_eq!(autoindex_names(sql, 3).unwrap(), ["b", "c"]);
+        assert_eq!(autoindex_names(sql, 4), None);
+    }
+
+    #[test]
+    fn int_pk_has_no_autoindex() {
+        let sql = "create table t (id integer primary key, x unique)";
+        assert_eq!(autoindex_names(sql, 1).unwrap(), ["x"]);
+        assert_eq!(autoindex_names(sql, 2), None);
+
+        let sql = "create table t (id integer, y, primary key (id desc))";
+        assert_eq!(autoindex_names(sql, 1), None);
+    }
+
+    #[test]
+    fn desc_int_pk_has_autoindex() {
+        let sql = "create table t (id integer primary key desc, z unique)";
+        assert_eq!(autoindex_names(sql, 1).unwrap(), ["id"]);
+        assert_eq!(autoindex_names(sql, 2).unwrap(), ["z"]);
+    }
+
+    #[test]
+    fn without_rowid_records_start_with_pk() {
+        let tbl = Cols::parse(
+            "create table t (a text, b unique, c, d, primary key (c, a desc)) without rowid",
+        )
+        .unwrap();
+        assert_eq!(
+            ["a", "b", "c", "d"]
+                .iter()
+                .map(|c| tbl.record_pos(c).unwrap())
+                .collect::<Vec<_>>(),
+            [1, 2, 0, 3]
+        );
+        assert!(!tbl.is_int_pk("c"));
+
+        let idx = Cols::parse("create index i on t (d, a)").unwrap();
+        assert_eq!(tbl.pk_pos_in_idx(&idx), [2, 1]);
+        assert_eq!(tbl.pk_pos_in_idx(&tbl.autoindex(1).unwrap()), [1, 2]);
+    }
+
+    #[test]
+    fn virtual_cols_are_not_in_records() {
+        let tbl =
+            Cols::parse("create table q (a, v as (a) virtual, b, s as (a) stored, c)").unwrap();
+        let pos = ["a", "b", "s", "c"]
+            .iter()
+            .map(|c| tbl.record_pos(c).unwrap());
+        assert_eq!(pos.collect::<Vec<_>>(), [0, 1, 2, 3]);
+        assert_eq!(
+            tbl.generated("v"),
+            Some(&Generated::Virtual("a".to_owned()))
+        );
+        assert_eq!(tbl.generated("s"), Some(&Generated::Stored));
+        assert!(tbl.has("v"));
+    }
+
+    #[test]
+    fn without_rowid_int_pk_is_no_rowid_alias() {
+        let sql = "create table t (x integer primary key, y unique) without rowid";
+        assert!(!Cols::parse(sql).unwrap().is_int_pk("x"));
+        // the primary key's index is numbered last in this case
+        assert_eq!(autoindex_names(sql, 1).unwrap(), ["y"]);
+        assert_eq!(autoindex_names(sql, 2).unwrap(), ["x"]);
+    }
 }
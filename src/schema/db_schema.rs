@@ -1,62 +1,143 @@
 use crate::{
-    format::{DbHeader, Page},
+    format::{DbFile, DbHeader, Page},
     interpreter::btree,
-    schema::ObjSchema,
+    schema::{Cols, ObjSchema, Stat},
     util::MapOkAndThenExt,
 };
 use anyhow::Result;
+use itertools::Itertools;
 
 #[derive(Debug)]
-pub struct DbSchema<'a> {
+pub struct DbSchema {
     pub db_header: DbHeader,
-    pub objs: Vec<ObjSchema<'a>>,
+    pub objs: Vec<ObjSchema>,
     pub size: usize,
+    /// The statistics ANALYZE collected for the query planner.
+    pub stats: Vec<Stat>,
 }
 
-impl<'a> DbSchema<'a> {
-    pub fn parse(db: &'a [u8]) -> Result<DbSchema<'a>> {
-        let db_header = DbHeader::parse(&db[..DbHeader::SIZE])?;
-        let page_size = db_header.page_size.into();
+impl DbSchema {
+    pub fn parse(db: &[u8]) -> Result<DbSchema> {
+        let db_header = DbHeader::parse(db)?;
+        let page_size = db_header.page_size as usize;
         let rootpage = Page::parse_schema(page_size, db)?;
-        let page_content_offset: usize = rootpage.header.start_of_content_area.into();
+
+        let db_file = DbFile::new(db, &db_header);
+        let mut objs = btree::full_tbl_scan(rootpage, db_file)
+            .map_ok_and_then(|c| ObjSchema::parse(&c))
+            .collect::<Result<Vec<_>>>()?;
+        resolve_autoindex_cols(&mut objs);
+
+        let stats = match objs
+            .iter()
+            .find(|o| o.type_ == "table" && o.name == "sqlite_stat1")
+        {
+            Some(tbl) => btree::full_tbl_scan(db_file.page(tbl.rootpage)?, db_file)
+                .map_ok(|c| Stat::parse(&c))
+                .filter_map(Result::transpose)
+                .collect::<Result<Vec<_>>>()?,
+            None => vec![],
+        };
+
+        // sqlite3 reports the total length of all CREATE statements as the size of the schema.
+        let size = objs
+            .iter()
+            .filter_map(|o| o.sql.as_ref())
+            .map(|sql| sql.chars().count())
+            .sum();
 
         Ok(DbSchema {
             db_header,
-            objs: btree::full_tbl_scan(rootpage, page_size, db)
-                .map_ok_and_then(|c| ObjSchema::parse(&c))
-                .collect::<Result<Vec<_>>>()?,
-            size: page_size - page_content_offset - DbHeader::SIZE,
+            objs,
+            size,
+            stats,
         })
     }
 
-    pub fn tables(&self) -> impl Iterator<Item = &ObjSchema<'a>> {
+    pub fn tables(&self) -> impl Iterator<Item = &ObjSchema> {
         self.objs.iter().filter(ObjSchema::is_table)
     }
 
-    pub fn indexes(&self) -> impl Iterator<Item = &ObjSchema<'a>> {
+    pub fn indexes(&self) -> impl Iterator<Item = &ObjSchema> {
         self.objs.iter().filter(ObjSchema::is_index)
     }
 
-    pub fn views(&self) -> impl Iterator<Item = &ObjSchema<'a>> {
+    pub fn views(&self) -> impl Iterator<Item = &ObjSchema> {
         self.objs.iter().filter(ObjSchema::is_view)
     }
 
-    pub fn triggers(&self) -> impl Iterator<Item = &ObjSchema<'a>> {
+    pub fn triggers(&self) -> impl Iterator<Item = &ObjSchema> {
         self.objs.iter().filter(ObjSchema::is_trigger)
     }
 
-    pub fn table(&self, name: &str) -> Option<&ObjSchema<'a>> {
+    pub fn table(&self, name: &str) -> Option<&ObjSchema> {
         self.tables().find(|t| t.name == name)
     }
 
-    pub fn index(&self, tbl: &str, col: &str) -> Option<&ObjSchema<'a>> {
-        self.indexes().find(|s| {
-            !s.name.starts_with("sqlite_autoindex_") && s.tbl_name == tbl && s.cols().has(col)
+    /// Finds the indexes on `tbl` that can be used to look up rows by `col`. Partial indexes
+    /// are left out, as they may lack some of the rows, and so are indexes that sort the text of
+    /// `col` by another collation than the BINARY one values are compared with.
+    pub fn indexes_by<'a>(
+        &'a self,
+        tbl: &'a str,
+        col: &'a str,
+    ) -> impl Iterator<Item = &'a ObjSchema> + 'a {
+        let tbl_cols = self.table(tbl).and_then(|t| t.cols.as_ref());
+        self.indexes().filter(move |s| {
+            s.tbl_name == tbl
+                && matches!((&s.cols, tbl_cols), (Some(cols), Some(tbl_cols)) if !cols.is_partial()
+                    && cols.leading().map_or(false, |c| c.name == col && tbl_cols.is_binary(c)))
         })
     }
 
+    /// The number of rows of a table according to ANALYZE. Partial indexes don't count, as they
+    /// only have entries for some of the rows.
+    pub fn row_count(&self, tbl: &str) -> Option<u64> {
+        self.stats
+            .iter()
+            .filter(|s| s.tbl == tbl)
+            .find(|s| match &s.idx {
+                Some(idx) => !self
+                    .indexes()
+                    .any(|i| &i.name == idx && i.cols.as_ref().map_or(false, Cols::is_partial)),
+                None => true,
+            })
+            .map(|s| s.counts[0])
+    }
+
+    /// The average number of entries of an index that share the value of its leading column
+    /// according to ANALYZE. The primary key of a WITHOUT ROWID table is recorded under the
+    /// name of the table.
+    pub fn avg_eq(&self, idx: &ObjSchema) -> Option<u64> {
+        self.stats
+            .iter()
+            .find(|s| s.tbl == idx.tbl_name && s.idx.as_ref() == Some(&idx.name))
+            .and_then(|s| s.counts.get(1).copied())
+    }
+
     pub fn has_index(&self, tbl: &str, col: &str) -> bool {
         self.indexes()
-            .any(|s| s.tbl_name == tbl && s.cols().has(col))
+            .any(|s| s.tbl_name == tbl && s.cols.as_ref().map_or(false, |c| c.has(col)))
+    }
+}
+
+/// Automatic indexes have no CREATE statement, so their columns have to be derived from the
+/// constraints of the table they belong to.
+fn resolve_autoindex_cols(objs: &mut [ObjSchema]) {
+    let resolved = objs
+        .iter()
+        .enumerate()
+        .filter(|(_, obj)| obj.cols.is_none())
+        .filter_map(|(i, idx)| {
+            let n = idx.autoindex_num()?;
+            let tbl = objs
+                .iter()
+                .find(|o| o.is_table() && o.name == idx.tbl_name)?;
+            Some((i, tbl.cols.as_ref()?.autoindex(n)?))
+        })
+        .collect::<Vec<_>>();
+
+    for (i, cols) in resolved {
+        objs[i].cols = Some(cols);
     }
 }
@@ -1,7 +1,9 @@
 mod cols;
 mod db_schema;
 mod obj_schema;
+mod stat;
 
 pub use cols::*;
 pub use db_schema::*;
 pub use obj_schema::*;
+pub use stat::*;
@@ -1,30 +1,39 @@
-use crate::{format::LeafTblCell, schema::Cols};
-use anyhow::{anyhow, Result};
+use crate::{
+    format::{Error, LeafTblCell},
+    schema::Cols,
+    syntax::{parse, ColDef, SqlStmt, TblConstraint},
+};
+use anyhow::{anyhow, bail, Result};
 use std::convert::TryFrom;
 
 #[derive(Debug)]
-pub struct ObjSchema<'a> {
-    pub type_: &'a str,
-    pub name: &'a str,
-    pub tbl_name: &'a str,
+pub struct ObjSchema {
+    pub type_: String,
+    pub name: String,
+    pub tbl_name: String,
     pub rootpage: i32,
-    pub sql: Option<&'a str>,
-    pub cols: Option<Cols<'a>>,
+    pub sql: Option<String>,
+    pub cols: Option<Cols>,
 }
 
-impl<'a> ObjSchema<'a> {
-    pub fn parse(record: &LeafTblCell<'a>) -> Result<Self> {
-        let type_ = <&str>::try_from(&record.payload[0])
+impl ObjSchema {
+    pub fn parse(record: &LeafTblCell) -> Result<Self> {
+        let type_ = String::try_from(&record.payload[0])
             .map_err(|e| anyhow!("Unexpected value in column 'type': {}", e))?;
-        let name = <&str>::try_from(&record.payload[1])
+        let name = String::try_from(&record.payload[1])
             .map_err(|e| anyhow!("Unexpected value in column 'name': {}", e))?;
-        let tbl_name = <&str>::try_from(&record.payload[2])
+        let tbl_name = String::try_from(&record.payload[2])
             .map_err(|e| anyhow!("Unexpected value in column 'tbl_name': {}", e))?;
         let rootpage = i32::try_from(&record.payload[3])
             .map_err(|e| anyhow!("Unexpected value in column 'rootpage': {}", e))?;
-        let sql = Option::<&str>::try_from(&record.payload[4])
+        let sql = Option::<String>::try_from(&record.payload[4])
             .map_err(|e| anyhow!("Unexpected value in column 'sql': {}", e))?;
-        let cols = sql.map(Cols::parse).transpose()?;
+        // Only the columns of tables and indexes are needed to read their B-trees.
+        let cols = sql
+            .as_deref()
+            .filter(|_| type_ == "table" || type_ == "index")
+            .map(Cols::parse)
+            .transpose()?;
 
         Ok(Self {
             type_,
@@ -36,31 +45,57 @@ impl<'a> ObjSchema<'a> {
         })
     }
 
-    pub fn cols(&self) -> &Cols {
-        self.cols
-            .as_ref()
-            .unwrap_or_else(|| panic!(
-                "Columns of object '{}' are unknown, because no CREATE statement was found in schema record",
-                self.name))
+    /// The columns of a table or index, which are unknown if its schema record has no usable
+    /// CREATE statement.
+    pub fn cols(&self) -> Result<&Cols> {
+        self.cols.as_ref().ok_or_else(|| {
+            Error::CorruptSchema {
+                name: self.name.clone(),
+                reason: "no CREATE statement for its columns".to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// The column definitions and constraints of a table's CREATE statement.
+    pub fn parse_create_tbl(&self) -> Result<(Vec<ColDef<'_>>, Vec<TblConstraint<'_>>)> {
+        match parse::sql_stmt(self.sql.as_deref().unwrap_or_default())? {
+            SqlStmt::CreateTbl {
+                col_defs,
+                constraints,
+                ..
+            } => Ok((col_defs, constraints)),
+            _ => bail!("Expected CREATE TABLE statement for table {}", self.name),
+        }
     }
 
-    pub fn is_table(self: &&ObjSchema<'a>) -> bool {
+    pub fn is_table(self: &&ObjSchema) -> bool {
         self.type_ == "table"
     }
 
-    pub fn is_index(self: &&ObjSchema<'a>) -> bool {
+    pub fn is_index(self: &&ObjSchema) -> bool {
         self.type_ == "index"
     }
 
-    pub fn is_view(self: &&ObjSchema<'a>) -> bool {
+    pub fn is_view(self: &&ObjSchema) -> bool {
         self.type_ == "view"
     }
 
-    pub fn is_trigger(self: &&ObjSchema<'a>) -> bool {
+    pub fn is_trigger(self: &&ObjSchema) -> bool {
         self.type_ == "trigger"
     }
 
-    pub fn is_sequence_tbl(self: &ObjSchema<'a>) -> bool {
+    pub fn is_sequence_tbl(self: &ObjSchema) -> bool {
         self.name == "sqlite_sequence"
     }
+
+    /// Extracts the n from the name `sqlite_autoindex_<table>_<n>` of an automatic index.
+    pub fn autoindex_num(&self) -> Option<usize> {
+        self.name
+            .strip_prefix("sqlite_autoindex_")?
+            .strip_prefix(self.tbl_name.as_str())?
+            .strip_prefix('_')?
+            .parse()
+            .ok()
+    }
 }
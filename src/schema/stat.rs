@@ -0,0 +1,49 @@
+use crate::format::LeafTblCell;
+use itertools::Itertools;
+use std::convert::TryFrom;
+
+/// A row of `sqlite_stat1`, in which ANALYZE records how many rows a table has and how well its
+/// indexes narrow them down.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stat {
+    pub tbl: String,
+    /// The index the row is about, which is the table itself for the primary key of a WITHOUT
+    /// ROWID table. Tables without indexes are recorded without one.
+    pub idx: Option<String>,
+    /// The number of entries, followed by the average number of entries that share the values
+    /// of the first 1, 2, … columns of the index.
+    pub counts: Vec<u64>,
+}
+
+impl Stat {
+    /// Parses a row of `sqlite_stat1`. Like SQLite, rows that don't hold text are ignored, as
+    /// is anything after the numbers of the `stat` column, like `unordered` or `sz=N`.
+    pub fn parse(record: &LeafTblCell) -> Option<Self> {
+        let text = |i: usize| {
+            Option::<String>::try_from(&record.payload[i])
+                .ok()
+                .flatten()
+        };
+        let (tbl, stat) = (text(0)?, text(2)?);
+        let counts = stat
+            .split_whitespace()
+            .map(|n| n.parse().ok())
+            .take_while(Option::is_some)
+            .flatten()
+            .collect::<Vec<_>>();
+        if counts.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            tbl,
+            idx: text(1),
+            counts,
+        })
+    }
+
+    /// The `stat` column as ANALYZE writes it.
+    pub fn stat(&self) -> String {
+        self.counts.iter().join(" ")
+    }
+}
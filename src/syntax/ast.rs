@@ -1,3 +1,5 @@
+use std::{borrow::Cow, fmt, iter::once};
+
 #[derive(Debug, PartialEq)]
 pub enum Sqlite<'a> {
     DotCmd(DotCmd),
@@ -7,14 +9,135 @@ pub enum Sqlite<'a> {
 #[derive(Debug, PartialEq, Clone)]
 pub enum DotCmd {
     DbInfo,
-    Tables,
-    Schema,
+    /// Lists the tables whose names match the LIKE pattern.
+    Tables(Option<String>),
+    /// Lists the indexes of the tables whose names match the LIKE pattern.
+    Indexes(Option<String>),
+    /// Prints the CREATE statements of the objects whose tables match the LIKE pattern.
+    Schema {
+        pattern: Option<String>,
+        indent: bool,
+    },
+    /// Prints an SQL script that recreates the tables and other objects whose names match any
+    /// of the LIKE patterns, or the whole database if there are none.
+    Dump(Vec<String>),
+    /// Loads the rows of a CSV file into a table, which is created from the file's header row if
+    /// it doesn't exist yet. Without `csv` the separators of the output mode are used.
+    Import {
+        file: String,
+        tbl: String,
+        csv: bool,
+        skip: usize,
+    },
+    /// Prints the whole schema including the statistics collected by ANALYZE.
+    FullSchema {
+        indent: bool,
+    },
+    Quit,
+    Mode {
+        mode: OutputMode,
+        tbl: Option<String>,
+    },
+    Headers(bool),
+    /// Whether to print the number of rows each operator of a query returned.
+    ScanStats(bool),
+    NullValue(String),
+    Separator {
+        col: String,
+        row: Option<String>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputMode {
+    List,
+    Csv,
+    Json,
+    Line,
+    Column,
+    Box,
+    Markdown,
+    Table,
+    Tabs,
+    Html,
+    Insert,
 }
 
 #[derive(Debug, PartialEq)]
-pub enum ColDef<'a> {
-    IntPk(&'a str),
-    Col(&'a str),
+pub struct ColDef<'a> {
+    pub name: &'a str,
+    pub type_name: Option<&'a str>,
+    pub constraints: Vec<ColConstraint<'a>>,
+}
+
+/// The type a column prefers to store its values as.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ColConstraint<'a> {
+    PrimaryKey(SortOrder),
+    Unique,
+    NotNull,
+    /// The default value as the text of its expression.
+    Default(&'a str),
+    Collate(&'a str),
+    ForeignKey(ForeignKey<'a>),
+    /// A column computed from the text of an expression. Only STORED columns are written to the
+    /// records, VIRTUAL ones are computed when they're read.
+    Generated {
+        expr: &'a str,
+        stored: bool,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TblConstraint<'a> {
+    PrimaryKey(Vec<IndexedCol<'a>>),
+    Unique(Vec<IndexedCol<'a>>),
+    ForeignKey {
+        cols: Vec<&'a str>,
+        fk: ForeignKey<'a>,
+    },
+}
+
+/// The REFERENCES clause of a foreign key constraint.
+#[derive(Debug, PartialEq)]
+pub struct ForeignKey<'a> {
+    pub tbl: &'a str,
+    /// The referenced columns, which are the primary key of `tbl` if none are given.
+    pub cols: Vec<&'a str>,
+    pub on_update: FkAction,
+    pub on_delete: FkAction,
+}
+
+/// What happens to the rows referring to a row of the parent table that is updated or deleted.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FkAction {
+    NoAction,
+    Restrict,
+    SetNull,
+    SetDefault,
+    Cascade,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct IndexedCol<'a> {
+    pub name: &'a str,
+    pub order: SortOrder,
+    pub collation: Option<&'a str>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortOrder {
+    Asc,
+    Desc,
 }
 
 #[derive(Debug, PartialEq)]
@@ -22,107 +145,559 @@ pub enum SqlStmt<'a> {
     CreateTbl {
         name: &'a str,
         col_defs: Vec<ColDef<'a>>,
+        constraints: Vec<TblConstraint<'a>>,
+        without_rowid: bool,
     },
     CreateIdx {
         name: &'a str,
+        unique: bool,
         target_tbl: &'a str,
-        target_col: &'a str,
+        target_cols: Vec<IndexedCol<'a>>,
+        /// Whether the index has a WHERE clause and only covers some of the table's rows.
+        partial: bool,
     },
-    Select(Select<'a>),
+    Select(Query<'a>),
+    /// Shows how the query would find its rows instead of running it.
+    ExplainQueryPlan(Query<'a>),
+    /// Queries a setting or information about the database. The argument is either given in
+    /// parentheses or after an equals sign.
+    Pragma {
+        schema: Option<&'a str>,
+        name: &'a str,
+        arg: Option<&'a str>,
+    },
+    /// Collects statistics for the query planner about the given table or index, or about all
+    /// of them.
+    Analyze(Option<&'a str>),
 }
 
-#[derive(Debug, PartialEq)]
+/// A query, which combines the rows of SELECTs with compound operators from left to right and
+/// then sorts and limits them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Query<'a> {
+    /// The common table expressions of the WITH clause, which the query and its subqueries can
+    /// read like tables.
+    pub with: Vec<Cte<'a>>,
+    pub select: Select<'a>,
+    /// The SELECTs whose rows are combined with those of the SELECTs before them.
+    pub compound: Vec<(CompoundOp, Select<'a>)>,
+    /// The result columns to sort by, which are given as expressions or by their number.
+    pub order_by: Vec<(Expr<'a>, SortOrder)>,
+    pub limit: Option<Box<Limit<'a>>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Select<'a> {
+    /// Whether each result row is returned only once, as with SELECT DISTINCT.
+    pub distinct: bool,
     pub cols: Vec<Expr<'a>>,
-    pub tbl: &'a str,
+    /// What the rows are read from. Without FROM there's a single row, which has no columns.
+    pub from: Option<Source<'a>>,
     pub filter: Option<BoolExpr<'a>>,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CompoundOp {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+/// LIMIT and OFFSET, which are integers or parameters.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Limit<'a> {
+    pub limit: Expr<'a>,
+    pub offset: Option<Expr<'a>>,
+}
+
+/// A common table expression, which names the rows of a query for the statement it's part of.
+/// It's recursive if the last SELECT of the query is added by UNION or UNION ALL and reads the
+/// CTE, in which case that SELECT is run again for each row found and reads it as the CTE.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cte<'a> {
+    pub name: &'a str,
+    /// Names for the columns, which are named like the result columns of `query` otherwise.
+    pub cols: Vec<&'a str>,
+    pub query: Query<'a>,
+}
+
+/// What a query reads its rows from.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Source<'a> {
+    /// A table or common table expression, which may be given another name.
+    Tbl {
+        name: &'a str,
+        alias: Option<&'a str>,
+    },
+    /// A subquery in parentheses, whose result is read like a table.
+    Subquery {
+        select: Box<Query<'a>>,
+        alias: Option<&'a str>,
+    },
+    /// Every row of `l` paired with every row of `r`, or only the pairs for which `on` holds.
+    Join {
+        l: Box<Source<'a>>,
+        r: Box<Source<'a>>,
+        on: Option<Box<BoolExpr<'a>>>,
+    },
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr<'a> {
     Literal(Literal<'a>),
+    /// A parameter like `?`, `?2` or `:name`, which is NULL unless a value is bound to it.
+    Param {
+        name: &'a str,
+        value: Literal<'a>,
+    },
     ColName(&'a str),
+    /// A column of the table or subquery in FROM that has the name or alias `tbl`.
+    QualifiedColName {
+        tbl: &'a str,
+        col: &'a str,
+    },
     Count,
+    /// The number of different values the expression has in the rows, leaving out NULL.
+    CountDistinct(Box<Expr<'a>>),
+    /// A subquery in parentheses, whose value is the first column of its first row or NULL if
+    /// it has none. `text` is how it's written, which names the result column it's used as.
+    Subquery {
+        select: Box<Query<'a>>,
+        text: &'a str,
+    },
+    /// An arithmetic operation on two expressions. `text` is how it's written, which names the
+    /// result column it's used as.
+    Arith {
+        op: ArithOp,
+        l: Box<Expr<'a>>,
+        r: Box<Expr<'a>>,
+        text: &'a str,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Literal<'a> {
     Null,
-    String(&'a str),
+    String(Cow<'a, str>),
     Int(i64),
+    Float(f64),
+    Blob(Cow<'a, [u8]>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum BoolExpr<'a> {
-    Equals { l: Expr<'a>, r: Expr<'a> },
-    NotEquals { l: Expr<'a>, r: Expr<'a> },
+    Equals {
+        l: Expr<'a>,
+        r: Expr<'a>,
+    },
+    NotEquals {
+        l: Expr<'a>,
+        r: Expr<'a>,
+    },
+    /// Holds if `expr` equals any of the values in `list`.
+    In {
+        expr: Expr<'a>,
+        list: Vec<Expr<'a>>,
+    },
+    /// Holds if `expr` equals any of the values in the first column of the subquery's rows.
+    InSubquery {
+        expr: Expr<'a>,
+        select: Box<Query<'a>>,
+    },
+    /// Holds if the subquery has any rows.
+    Exists(Box<Query<'a>>),
+    /// Holds if both conditions do.
+    And(Box<BoolExpr<'a>>, Box<BoolExpr<'a>>),
+    /// Holds if either condition does.
+    Or(Box<BoolExpr<'a>>, Box<BoolExpr<'a>>),
 }
 
 impl<'a> ColDef<'a> {
-    pub const fn is_int_pk(self: &&ColDef<'a>) -> bool {
-        matches!(self, ColDef::IntPk(_))
+    /// Whether the column is an alias for the rowid as described here:
+    /// [ROWIDs and the INTEGER PRIMARY KEY](https://www.sqlite.org/lang_createtable.html#rowid)
+    ///
+    /// Note the quirk that "INTEGER PRIMARY KEY DESC" does not create an alias.
+    pub fn is_int_pk(self: &&ColDef<'a>) -> bool {
+        self.has_int_type()
+            && self
+                .constraints
+                .contains(&ColConstraint::PrimaryKey(SortOrder::Asc))
+    }
+
+    pub fn has_int_type(&self) -> bool {
+        matches!(self.type_name, Some(t) if t.eq_ignore_ascii_case("INTEGER"))
+    }
+
+    /// The column's affinity as determined by these rules:
+    /// [Determination Of Column Affinity](https://www.sqlite.org/datatype3.html#determination_of_column_affinity)
+    pub fn affinity(&self) -> Affinity {
+        let type_name = self.type_name.unwrap_or_default().to_ascii_uppercase();
+        let has = |s: &[&str]| s.iter().any(|s| type_name.contains(s));
+        if has(&["INT"]) {
+            Affinity::Integer
+        } else if has(&["CHAR", "CLOB", "TEXT"]) {
+            Affinity::Text
+        } else if self.type_name.is_none() || has(&["BLOB"]) {
+            Affinity::Blob
+        } else if has(&["REAL", "FLOA", "DOUB"]) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
     }
 
     pub const fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn default_value(&self) -> Option<&'a str> {
+        self.constraints.iter().find_map(|c| match c {
+            ColConstraint::Default(val) => Some(*val),
+            _ => None,
+        })
+    }
+
+    /// The expression of a generated column and whether its value is stored.
+    pub fn generated(&self) -> Option<(&'a str, bool)> {
+        self.constraints.iter().find_map(|c| match c {
+            ColConstraint::Generated { expr, stored } => Some((*expr, *stored)),
+            _ => None,
+        })
+    }
+
+    pub fn collation(&self) -> Option<&'a str> {
+        self.constraints.iter().find_map(|c| match c {
+            ColConstraint::Collate(collation) => Some(*collation),
+            _ => None,
+        })
+    }
+
+    /// The declared type as SQLite shows it. The names of the types that STRICT tables allow
+    /// are normalized to upper case.
+    pub fn display_type(&self) -> Cow<'a, str> {
+        let type_name = self.type_name.unwrap_or_default();
+        let is_std = ["ANY", "BLOB", "INT", "INTEGER", "REAL", "TEXT"]
+            .iter()
+            .any(|t| type_name.eq_ignore_ascii_case(t));
+        if is_std {
+            Cow::Owned(type_name.to_ascii_uppercase())
+        } else {
+            Cow::Borrowed(type_name)
+        }
+    }
+}
+
+impl fmt::Display for CompoundOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CompoundOp::Union => "UNION",
+            CompoundOp::UnionAll => "UNION ALL",
+            CompoundOp::Intersect => "INTERSECT",
+            CompoundOp::Except => "EXCEPT",
+        })
+    }
+}
+
+impl fmt::Display for FkAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FkAction::NoAction => "NO ACTION",
+            FkAction::Restrict => "RESTRICT",
+            FkAction::SetNull => "SET NULL",
+            FkAction::SetDefault => "SET DEFAULT",
+            FkAction::Cascade => "CASCADE",
+        })
+    }
+}
+
+impl<'a> IndexedCol<'a> {
+    pub const fn asc(name: &'a str) -> Self {
+        Self {
+            name,
+            order: SortOrder::Asc,
+            collation: None,
+        }
+    }
+}
+
+impl<'a> fmt::Display for Expr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ColDef::IntPk(n) | ColDef::Col(n) => *n,
+            Expr::Literal(Literal::Null) => write!(f, "NULL"),
+            Expr::Literal(Literal::String(s)) => write!(f, "'{}'", s),
+            Expr::Literal(Literal::Int(n)) => write!(f, "{}", n),
+            Expr::Literal(Literal::Float(x)) => write!(f, "{}", x),
+            Expr::Literal(Literal::Blob(bytes)) => {
+                write!(f, "X'")?;
+                bytes.iter().try_for_each(|b| write!(f, "{:02X}", b))?;
+                write!(f, "'")
+            }
+            Expr::Param { name, .. } => write!(f, "{}", name),
+            Expr::ColName(col) => write!(f, "{}", col),
+            Expr::QualifiedColName { tbl, col } => write!(f, "{}.{}", tbl, col),
+            Expr::Count => write!(f, "count(*)"),
+            Expr::CountDistinct(expr) => write!(f, "count(DISTINCT {})", expr),
+            Expr::Subquery { text, .. } | Expr::Arith { text, .. } => write!(f, "{}", text),
         }
     }
 }
 
 impl<'a> Expr<'a> {
-    pub const fn as_col_name(&self) -> Option<&str> {
+    /// The column a column reference is to, with the table it's qualified with if any.
+    pub const fn as_col_ref(&self) -> Option<(Option<&'a str>, &'a str)> {
         match self {
-            Expr::ColName(c) => Some(c),
+            Expr::ColName(col) => Some((None, col)),
+            Expr::QualifiedColName { tbl, col } => Some((Some(tbl), col)),
             _ => None,
         }
     }
+
+    /// The name of the result column the expression is used as, which is how it's written
+    /// except that qualified columns are named without their table like in SQLite.
+    pub fn result_col_name(&self) -> String {
+        match self {
+            Expr::QualifiedColName { col, .. } => col.to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// The expression whose values a count is of, or the expression itself otherwise.
+    pub fn operand(&self) -> &Self {
+        match self {
+            Expr::CountDistinct(expr) => expr,
+            _ => self,
+        }
+    }
+
+    pub fn as_subquery(&self) -> Option<&Query<'a>> {
+        match self {
+            Expr::Subquery { select, .. } => Some(select.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// The columns the expression refers to, leaving out those of subqueries.
+    pub fn col_refs(&self) -> Vec<(Option<&'a str>, &'a str)> {
+        match self {
+            Expr::Arith { l, r, .. } => l.col_refs().into_iter().chain(r.col_refs()).collect(),
+            _ => self.as_col_ref().into_iter().collect(),
+        }
+    }
+
+    /// The subqueries of the expression in the order they appear.
+    pub fn subqueries(&self) -> Vec<&Query<'a>> {
+        match self {
+            Expr::Arith { l, r, .. } => l.subqueries().into_iter().chain(r.subqueries()).collect(),
+            _ => self.as_subquery().into_iter().collect(),
+        }
+    }
+
+    /// The value of a literal or of a parameter.
+    pub const fn as_literal(&self) -> Option<&Literal<'a>> {
+        match self {
+            Expr::Literal(lit) | Expr::Param { value: lit, .. } => Some(lit),
+            _ => None,
+        }
+    }
+
+    fn params_mut(&mut self) -> Vec<(&'a str, &mut Literal<'a>)> {
+        match self {
+            Expr::Param { name, value } => vec![(*name, value)],
+            Expr::Subquery { select, .. } => select.params_mut(),
+            Expr::CountDistinct(expr) => expr.params_mut(),
+            Expr::Arith { l, r, .. } => {
+                let mut params = l.params_mut();
+                params.extend(r.params_mut());
+                params
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl<'a> Query<'a> {
+    /// The SELECTs of the query from left to right.
+    pub fn selects(&self) -> impl Iterator<Item = &Select<'a>> {
+        once(&self.select).chain(self.compound.iter().map(|(_, select)| select))
+    }
+
+    /// The names of the parameters and their values in the order they appear in the statement,
+    /// including those of subqueries.
+    pub fn params_mut(&mut self) -> Vec<(&'a str, &mut Literal<'a>)> {
+        let mut params = self
+            .with
+            .iter_mut()
+            .flat_map(|cte| cte.query.params_mut())
+            .collect::<Vec<_>>();
+        params.extend(self.select.params_mut());
+        for (_, select) in &mut self.compound {
+            params.extend(select.params_mut());
+        }
+        let order_by = self.order_by.iter_mut().map(|(expr, _)| expr);
+        let limit = self
+            .limit
+            .iter_mut()
+            .flat_map(|l| once(&mut l.limit).chain(&mut l.offset));
+        params.extend(order_by.chain(limit).flat_map(Expr::params_mut));
+        params
+    }
 }
 
 impl<'a> Select<'a> {
-    pub fn selected_col_names(&self) -> impl Iterator<Item = &str> {
-        self.cols.iter().filter_map(Expr::as_col_name)
+    /// The columns the result columns and the conditions of the query refer to, leaving out
+    /// those of subqueries.
+    pub fn col_refs(&self) -> Vec<(Option<&'a str>, &'a str)> {
+        let conds = self.join_conds().into_iter().chain(&self.filter);
+        self.cols
+            .iter()
+            .map(Expr::operand)
+            .chain(conds.flat_map(BoolExpr::exprs))
+            .flat_map(Expr::col_refs)
+            .collect()
+    }
+
+    /// The join conditions of FROM in the order they appear.
+    pub fn join_conds(&self) -> Vec<&BoolExpr<'a>> {
+        self.from.as_ref().map_or_else(Vec::new, Source::conds)
     }
 
     pub fn has_count_expr(&self) -> bool {
-        self.cols.iter().any(|c| matches!(c, Expr::Count))
+        self.cols
+            .iter()
+            .any(|c| matches!(c, Expr::Count | Expr::CountDistinct(_)))
+    }
+
+    fn params_mut(&mut self) -> Vec<(&'a str, &mut Literal<'a>)> {
+        let mut params = self
+            .cols
+            .iter_mut()
+            .flat_map(Expr::params_mut)
+            .collect::<Vec<_>>();
+        params.extend(self.from.iter_mut().flat_map(Source::params_mut));
+        params.extend(self.filter.iter_mut().flat_map(BoolExpr::params_mut));
+        params
+    }
+}
+
+impl<'a> Source<'a> {
+    /// The join conditions in the order they appear.
+    pub fn conds(&self) -> Vec<&BoolExpr<'a>> {
+        match self {
+            Source::Join { l, r, on } => {
+                let mut conds = l.conds();
+                conds.extend(r.conds());
+                conds.extend(on.as_deref());
+                conds
+            }
+            _ => vec![],
+        }
+    }
+
+    fn params_mut(&mut self) -> Vec<(&'a str, &mut Literal<'a>)> {
+        match self {
+            Source::Tbl { .. } => vec![],
+            Source::Subquery { select, .. } => select.params_mut(),
+            Source::Join { l, r, on } => {
+                let mut params = l.params_mut();
+                params.extend(r.params_mut());
+                params.extend(on.iter_mut().flat_map(|on| on.params_mut()));
+                params
+            }
+        }
     }
 }
 
 impl<'a> BoolExpr<'a> {
-    pub fn referenced_col_names(&self) -> impl Iterator<Item = &str> {
+    /// The expressions of the condition in the order they appear.
+    pub fn exprs(&self) -> Vec<&Expr<'a>> {
         match self {
-            BoolExpr::Equals { l, r } | BoolExpr::NotEquals { l, r } => l
-                .as_col_name()
-                .into_iter()
-                .chain(r.as_col_name().into_iter()),
+            BoolExpr::Equals { l, r } | BoolExpr::NotEquals { l, r } => vec![l, r],
+            BoolExpr::In { expr, list } => once(expr).chain(list).collect(),
+            BoolExpr::InSubquery { expr, .. } => vec![expr],
+            BoolExpr::Exists(_) => vec![],
+            BoolExpr::And(l, r) | BoolExpr::Or(l, r) => {
+                l.exprs().into_iter().chain(r.exprs()).collect()
+            }
         }
     }
 
-    pub const fn is_int_pk_servable(&self) -> Option<(&str, i64)> {
+    pub fn exprs_mut(&mut self) -> Vec<&mut Expr<'a>> {
         match self {
-            BoolExpr::Equals {
-                l: Expr::ColName(c),
-                r: Expr::Literal(Literal::Int(pk)),
+            BoolExpr::Equals { l, r } | BoolExpr::NotEquals { l, r } => vec![l, r],
+            BoolExpr::In { expr, list } => once(expr).chain(list).collect(),
+            BoolExpr::InSubquery { expr, .. } => vec![expr],
+            BoolExpr::Exists(_) => vec![],
+            BoolExpr::And(l, r) | BoolExpr::Or(l, r) => {
+                l.exprs_mut().into_iter().chain(r.exprs_mut()).collect()
             }
-            | BoolExpr::Equals {
-                l: Expr::Literal(Literal::Int(pk)),
-                r: Expr::ColName(c),
-            } => Some((c, *pk)),
-            _ => None,
         }
     }
 
-    pub const fn is_index_servable(&self) -> Option<(&str, &Literal)> {
+    fn params_mut(&mut self) -> Vec<(&'a str, &mut Literal<'a>)> {
+        match self {
+            BoolExpr::InSubquery { expr, select } => {
+                let mut params = expr.params_mut();
+                params.extend(select.params_mut());
+                params
+            }
+            BoolExpr::Exists(select) => select.params_mut(),
+            BoolExpr::And(l, r) | BoolExpr::Or(l, r) => {
+                let mut params = l.params_mut();
+                params.extend(r.params_mut());
+                params
+            }
+            _ => self
+                .exprs_mut()
+                .into_iter()
+                .flat_map(Expr::params_mut)
+                .collect(),
+        }
+    }
+
+    /// The conditions joined by AND at the top of the condition, which holds if all of them do.
+    pub fn conjuncts(&self) -> Vec<&Self> {
         match self {
-            BoolExpr::Equals {
-                l: Expr::ColName(c),
-                r: Expr::Literal(literal),
+            BoolExpr::And(l, r) => l.conjuncts().into_iter().chain(r.conjuncts()).collect(),
+            _ => vec![self],
+        }
+    }
+
+    /// Splits the condition into terms that each look for the rows with one of some values in a
+    /// column, if it consists of nothing else. `col` names the columns of the rows looked for
+    /// and `is_key` tells which expressions have values that are known before them. The
+    /// condition holds for the rows of all terms together.
+    pub fn seek_terms<'e>(
+        &'e self,
+        col: &impl Fn(&Expr<'a>) -> Option<&'a str>,
+        is_key: &impl Fn(&Expr<'a>) -> bool,
+    ) -> Option<Vec<(&'a str, Vec<&'e Expr<'a>>)>> {
+        match self {
+            BoolExpr::Equals { l, r } => match (col(l), col(r)) {
+                (Some(c), _) if is_key(r) => Some(vec![(c, vec![r])]),
+                (_, Some(c)) if is_key(l) => Some(vec![(c, vec![l])]),
+                _ => None,
+            },
+            BoolExpr::In { expr, list } => {
+                let c = col(expr)?;
+                list.iter()
+                    .all(is_key)
+                    .then(|| vec![(c, list.iter().collect())])
+            }
+            BoolExpr::Or(l, r) => {
+                let mut terms = l.seek_terms(col, is_key)?;
+                terms.extend(r.seek_terms(col, is_key)?);
+                Some(terms)
             }
-            | BoolExpr::Equals {
-                l: Expr::Literal(literal),
-                r: Expr::ColName(c),
-            } => Some((c, literal)),
             _ => None,
         }
     }
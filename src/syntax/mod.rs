@@ -3,3 +3,4 @@ pub mod parse;
 mod util;
 
 pub use ast::*;
+pub use util::is_keyword;
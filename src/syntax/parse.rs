@@ -2,73 +2,237 @@ use crate::syntax::ast::*;
 use anyhow::{anyhow, Result};
 use nom::{error::convert_error, Finish};
 
-pub fn sqlite(sql: &str) -> Result<Sqlite> {
+pub fn sqlite(sql: &str) -> Result<Sqlite<'_>> {
     parsers::sqlite(sql)
         .finish()
         .map(|r| r.1)
         .map_err(|e| anyhow!(convert_error(sql, e)))
 }
 
-pub fn sql_stmt(sql: &str) -> Result<SqlStmt> {
+pub fn sql_stmt(sql: &str) -> Result<SqlStmt<'_>> {
     parsers::sql_stmt(sql)
         .finish()
         .map(|r| r.1)
         .map_err(|e| anyhow!(convert_error(sql, e)))
 }
 
+/// Parses the text of a single expression, like the one of a generated column.
+pub fn expr(sql: &str) -> Result<Expr<'_>> {
+    parsers::whole_expr(sql)
+        .finish()
+        .map(|r| r.1)
+        .map_err(|e| anyhow!(convert_error(sql, e)))
+}
+
 mod parsers {
     use crate::syntax::{ast::*, util::*};
+    use itertools::Either;
     use nom::{
         branch::*, bytes::complete::*, character::complete::*, combinator::*, error::*, multi::*,
         sequence::*, IResult, Parser,
     };
 
+    use std::{borrow::Cow, convert::TryFrom};
+
     type R<'a, O> = IResult<&'a str, O, VerboseError<&'a str>>;
 
-    pub fn sqlite(i: &str) -> R<Sqlite> {
+    pub fn sqlite(i: &str) -> R<'_, Sqlite<'_>> {
         terminated(
             alt((dot_cmd.map(Sqlite::DotCmd), sql_stmt.map(Sqlite::SqlStmt))),
             eof,
         )(i)
     }
 
-    fn dot_cmd(i: &str) -> R<DotCmd> {
+    fn dot_cmd(i: &str) -> R<'_, DotCmd> {
         delimited(
             char('.'),
             alt((
                 value(DotCmd::DbInfo, tag("dbinfo")),
-                value(DotCmd::Tables, tag("tables")),
-                value(DotCmd::Schema, tag("schema")),
+                preceded(tag("tables"), opt(preceded(space1, dot_arg))).map(DotCmd::Tables),
+                preceded(
+                    alt((tag("indexes"), tag("indices"))),
+                    opt(preceded(space1, dot_arg)),
+                )
+                .map(DotCmd::Indexes),
+                preceded(tag("schema"), schema_args)
+                    .map(|(pattern, indent)| DotCmd::Schema { pattern, indent }),
+                map_opt(
+                    preceded(tag("fullschema"), schema_args),
+                    |args| match args {
+                        (None, indent) => Some(DotCmd::FullSchema { indent }),
+                        (Some(_), _) => None,
+                    },
+                ),
+                preceded(
+                    tag("dump"),
+                    many0(preceded(
+                        space1,
+                        verify(dot_arg, |a: &str| !a.starts_with("--")),
+                    )),
+                )
+                .map(DotCmd::Dump),
+                preceded(tag("import"), import_args),
+                value(DotCmd::Quit, alt((tag("quit"), tag("exit")))),
+                tuple((
+                    tag("mode"),
+                    preceded(space1, output_mode),
+                    opt(preceded(space1, dot_arg)),
+                ))
+                .map(|(_, mode, tbl)| DotCmd::Mode { mode, tbl }),
+                preceded(pair(tag("headers"), space1), bool_arg).map(DotCmd::Headers),
+                preceded(pair(tag("scanstats"), space1), bool_arg).map(DotCmd::ScanStats),
+                preceded(pair(tag("nullvalue"), space1), dot_arg).map(DotCmd::NullValue),
+                tuple((
+                    tag("separator"),
+                    preceded(space1, dot_arg),
+                    opt(preceded(space1, dot_arg)),
+                ))
+                .map(|(_, col, row)| DotCmd::Separator { col, row }),
             )),
             multispace0,
         )(i)
     }
 
-    pub fn sql_stmt(i: &str) -> R<SqlStmt> {
-        terminated(alt((create_idx_stmt, create_tbl_stmt, select_stmt)), eof)(i)
+    /// The optional pattern and `--indent` flag of `.schema`, which may come in any order.
+    fn schema_args(i: &str) -> R<'_, (Option<String>, bool)> {
+        map_opt(many0(preceded(space1, dot_arg)), |args| {
+            let (flags, patterns): (Vec<_>, Vec<_>) =
+                args.into_iter().partition(|a| a.starts_with("--"));
+            let indent = match flags.as_slice() {
+                [] => false,
+                [flag] if flag == "--indent" => true,
+                _ => return None,
+            };
+            match patterns.len() {
+                0 | 1 => Some((patterns.into_iter().next(), indent)),
+                _ => None,
+            }
+        })(i)
+    }
+
+    /// The file and table of `.import` together with its options, which may come anywhere.
+    fn import_args(i: &str) -> R<'_, DotCmd> {
+        map_opt(many1(preceded(space1, dot_arg)), |args| {
+            let mut csv = false;
+            let mut skip = 0;
+            let mut positional = vec![];
+            let mut args = args.into_iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--csv" => csv = true,
+                    "--skip" => skip = args.next()?.parse().ok()?,
+                    a if a.starts_with('-') => return None,
+                    _ => positional.push(arg),
+                }
+            }
+            match <[String; 2]>::try_from(positional) {
+                Ok([file, tbl]) => Some(DotCmd::Import {
+                    file,
+                    tbl,
+                    csv,
+                    skip,
+                }),
+                Err(_) => None,
+            }
+        })(i)
+    }
+
+    fn output_mode(i: &str) -> R<'_, OutputMode> {
+        alt((
+            value(OutputMode::List, keyword("list")),
+            value(OutputMode::Csv, keyword("csv")),
+            value(OutputMode::Json, keyword("json")),
+            value(OutputMode::Line, keyword("line")),
+            value(OutputMode::Column, keyword("column")),
+            value(OutputMode::Box, keyword("box")),
+            value(OutputMode::Markdown, keyword("markdown")),
+            value(OutputMode::Table, keyword("table")),
+            value(OutputMode::Tabs, keyword("tabs")),
+            value(OutputMode::Html, keyword("html")),
+            value(OutputMode::Insert, keyword("insert")),
+        ))(i)
+    }
+
+    fn bool_arg(i: &str) -> R<'_, bool> {
+        alt((
+            value(
+                true,
+                alt((keyword("on"), keyword("yes"), keyword("true"), tag("1"))),
+            ),
+            value(
+                false,
+                alt((keyword("off"), keyword("no"), keyword("false"), tag("0"))),
+            ),
+        ))(i)
+    }
+
+    /// An argument of a dot-command, which has to be quoted to contain whitespace. Like in
+    /// sqlite3, backslash escapes are resolved unless the argument is in single quotes.
+    fn dot_arg(i: &str) -> R<'_, String> {
+        fn escape(i: &str) -> R<'_, &str> {
+            alt((
+                value("\t", char('t')),
+                value("\n", char('n')),
+                value("\r", char('r')),
+                value("\\", char('\\')),
+                value("\"", char('"')),
+            ))(i)
+        }
+
+        alt((
+            delimited(char('\''), take_while(|c| c != '\''), char('\'')).map(String::from),
+            delimited(
+                char('"'),
+                opt(escaped_transform(none_of("\\\""), '\\', escape)),
+                char('"'),
+            )
+            .map(Option::unwrap_or_default),
+            verify(
+                escaped_transform(none_of("\\ \t\r\n"), '\\', escape),
+                |arg: &str| !arg.is_empty(),
+            ),
+        ))(i)
     }
 
-    fn create_idx_stmt(i: &str) -> R<SqlStmt> {
+    pub fn sql_stmt(i: &str) -> R<'_, SqlStmt<'_>> {
+        terminated(
+            alt((
+                create_idx_stmt,
+                create_tbl_stmt,
+                select_stmt,
+                explain_stmt,
+                pragma_stmt,
+                analyze_stmt,
+            )),
+            eof,
+        )(i)
+    }
+
+    fn create_idx_stmt(i: &str) -> R<'_, SqlStmt<'_>> {
         tuple((
             skip(preceded_ws0(tag_no_case("CREATE"))),
+            opt(preceded_ws1(keyword("UNIQUE"))),
             skip(preceded_ws1(tag_no_case("INDEX"))),
             skip(opt(preceded_ws1(if_not_exists_clause))),
             preceded_ws1(identifier),
             skip(preceded_ws1(tag_no_case("ON"))),
             preceded_ws1(identifier),
             skip(delimited_ws0(char('('))),
-            identifier,
+            comma_separated_list1(indexed_col),
             skip(terminated_ws0(char(')'))),
+            opt(preceded(keyword("WHERE"), rest)),
         ))
         .map(|x| SqlStmt::CreateIdx {
-            name: x.3,
-            target_tbl: x.5,
-            target_col: x.7,
+            name: x.4,
+            unique: x.1.is_some(),
+            target_tbl: x.6,
+            target_cols: x.8,
+            partial: x.10.is_some(),
         })
         .parse(i)
     }
 
-    fn create_tbl_stmt(i: &str) -> R<SqlStmt> {
+    fn create_tbl_stmt(i: &str) -> R<'_, SqlStmt<'_>> {
         tuple((
             skip(preceded_ws0(tag_no_case("CREATE"))),
             skip(preceded_ws1(tag_no_case("TABLE"))),
@@ -76,39 +240,290 @@ mod parsers {
             preceded_ws1(identifier),
             skip(delimited_ws0(char('('))),
             comma_separated_list1(create_tbl_coldef),
-            skip(terminated_ws0(char(')'))),
+            many0(preceded(delimited_ws0(char(',')), tbl_constraint)),
+            skip(delimited_ws0(char(')'))),
+            opt(terminated_ws0(comma_separated_list1(tbl_option))),
         ))
         .map(|x| SqlStmt::CreateTbl {
             name: x.3,
             col_defs: x.5,
+            constraints: x.6.into_iter().flatten().collect(),
+            without_rowid: x.8.unwrap_or_default().contains(&TblOption::WithoutRowid),
         })
         .parse(i)
     }
 
-    fn create_tbl_coldef(i: &str) -> R<ColDef> {
-        let int_pk_col = tuple((
+    #[derive(Clone, PartialEq)]
+    enum TblOption {
+        WithoutRowid,
+        Strict,
+    }
+
+    fn tbl_option(i: &str) -> R<'_, TblOption> {
+        alt((
+            value(
+                TblOption::WithoutRowid,
+                pair(keyword("WITHOUT"), preceded_ws1(keyword("ROWID"))),
+            ),
+            value(TblOption::Strict, keyword("STRICT")),
+        ))(i)
+    }
+
+    fn create_tbl_coldef(i: &str) -> R<'_, ColDef<'_>> {
+        tuple((
+            verify(identifier, |id: &str| !is_tbl_constraint_keyword(id)),
+            opt(preceded_ws1(type_name)),
+            many0(preceded_ws1(col_constraint)),
+        ))
+        .map(|x| ColDef {
+            name: x.0,
+            type_name: x.1,
+            constraints: x.2.into_iter().flatten().collect(),
+        })
+        .parse(i)
+    }
+
+    fn type_name(i: &str) -> R<'_, &str> {
+        let word = verify(regular_identifier, |w: &str| !is_col_constraint_keyword(w));
+
+        recognize(pair(
+            separated_list1(multispace1, word),
+            opt(preceded_ws0(parenthesized)),
+        ))(i)
+    }
+
+    /// Parses a column constraint. Only the constraints relevant for query planning, inserting
+    /// rows and describing the table are captured, all others are recognized and skipped.
+    fn col_constraint(i: &str) -> R<'_, Option<ColConstraint<'_>>> {
+        let pk = tuple((
+            keyword("PRIMARY"),
+            preceded_ws1(keyword("KEY")),
+            opt(preceded_ws1(sort_order)),
+            skip(opt(preceded_ws1(conflict_clause))),
+            skip(opt(preceded_ws1(keyword("AUTOINCREMENT")))),
+        ))
+        .map(|x| Some(ColConstraint::PrimaryKey(x.2.unwrap_or(SortOrder::Asc))));
+
+        let unique = terminated(keyword("UNIQUE"), opt(preceded_ws1(conflict_clause)))
+            .map(|_| Some(ColConstraint::Unique));
+
+        let not_null = tuple((
+            keyword("NOT"),
+            preceded_ws1(keyword("NULL")),
+            opt(preceded_ws1(conflict_clause)),
+        ));
+
+        // Like SQLite, the parentheses around an expression aren't part of the default value.
+        let default = preceded(
+            keyword("DEFAULT"),
+            preceded_ws0(alt((
+                parenthesized.map(|p: &str| p[1..p.len() - 1].trim()),
+                quoted('\''),
+                recognize(pair(opt(one_of("+-")), number_lit)),
+                identifier,
+            ))),
+        );
+
+        let generated = tuple((
+            opt(pair(keyword("GENERATED"), preceded_ws1(keyword("ALWAYS")))),
+            preceded_ws0(keyword("AS")),
+            preceded_ws0(parenthesized),
+            opt(preceded_ws1(alt((keyword("STORED"), keyword("VIRTUAL"))))),
+        ))
+        .map(|x| ColConstraint::Generated {
+            expr: x.2[1..x.2.len() - 1].trim(),
+            stored: x.3.map_or(false, |kw| kw.eq_ignore_ascii_case("STORED")),
+        });
+
+        preceded(
+            opt(terminated(constraint_name, multispace1)),
+            alt((
+                pk,
+                unique,
+                not_null.map(|_| Some(ColConstraint::NotNull)),
+                keyword("NULL").map(|_| None),
+                preceded(keyword("CHECK"), preceded_ws0(parenthesized)).map(|_| None),
+                default.map(|val| Some(ColConstraint::Default(val))),
+                collation.map(|c| Some(ColConstraint::Collate(c))),
+                fk_clause.map(|fk| Some(ColConstraint::ForeignKey(fk))),
+                generated.map(Some),
+            )),
+        )(i)
+    }
+
+    /// Parses a table constraint. Like with [col_constraint] only the constraints relevant for
+    /// query planning are captured.
+    fn tbl_constraint(i: &str) -> R<'_, Option<TblConstraint<'_>>> {
+        let indexed_cols = || {
+            delimited(
+                delimited_ws0(char('(')),
+                comma_separated_list1(indexed_col),
+                preceded_ws0(char(')')),
+            )
+        };
+
+        let pk = tuple((
+            keyword("PRIMARY"),
+            preceded_ws1(keyword("KEY")),
+            indexed_cols(),
+            opt(preceded_ws1(conflict_clause)),
+        ))
+        .map(|x| Some(TblConstraint::PrimaryKey(x.2)));
+
+        let unique = tuple((
+            keyword("UNIQUE"),
+            indexed_cols(),
+            opt(preceded_ws1(conflict_clause)),
+        ))
+        .map(|x| Some(TblConstraint::Unique(x.1)));
+
+        let fk = tuple((
+            keyword("FOREIGN"),
+            preceded_ws1(keyword("KEY")),
+            preceded_ws0(col_names),
+            preceded_ws0(fk_clause),
+        ))
+        .map(|x| Some(TblConstraint::ForeignKey { cols: x.2, fk: x.3 }));
+
+        preceded(
+            opt(terminated(constraint_name, multispace1)),
+            alt((
+                pk,
+                unique,
+                preceded(keyword("CHECK"), preceded_ws0(parenthesized)).map(|_| None),
+                fk,
+            )),
+        )(i)
+    }
+
+    fn constraint_name(i: &str) -> R<'_, &str> {
+        preceded(keyword("CONSTRAINT"), preceded_ws1(identifier))(i)
+    }
+
+    fn indexed_col(i: &str) -> R<'_, IndexedCol<'_>> {
+        tuple((
             identifier,
-            skip(preceded_ws1(tag_no_case("INTEGER"))),
-            skip(preceded_ws1(tag_no_case("PRIMARY"))),
-            skip(preceded_ws1(tag_no_case("KEY"))),
-            skip_col_constraints,
+            opt(preceded_ws1(collation)),
+            opt(preceded_ws1(sort_order)),
         ))
-        .map(|x| ColDef::IntPk(x.0));
+        .map(|x| IndexedCol {
+            name: x.0,
+            order: x.2.unwrap_or(SortOrder::Asc),
+            collation: x.1,
+        })
+        .parse(i)
+    }
 
-        let other_col = terminated(identifier, skip_col_constraints).map(ColDef::Col);
+    fn collation(i: &str) -> R<'_, &str> {
+        preceded(keyword("COLLATE"), preceded_ws1(identifier))(i)
+    }
 
-        alt((int_pk_col, other_col))(i)
+    fn sort_order(i: &str) -> R<'_, SortOrder> {
+        alt((
+            value(SortOrder::Asc, keyword("ASC")),
+            value(SortOrder::Desc, keyword("DESC")),
+        ))(i)
     }
 
-    fn skip_col_constraints(i: &str) -> R<()> {
-        skip(alt((
-            pair(multispace0, peek(is_a(",)"))),
-            pair(multispace1, take_while(|c| c != ',' && c != ')')),
+    fn conflict_clause(i: &str) -> R<'_, ()> {
+        skip(tuple((
+            keyword("ON"),
+            preceded_ws1(keyword("CONFLICT")),
+            preceded_ws1(alt((
+                keyword("ROLLBACK"),
+                keyword("ABORT"),
+                keyword("FAIL"),
+                keyword("IGNORE"),
+                keyword("REPLACE"),
+            ))),
         )))
         .parse(i)
     }
 
-    fn if_not_exists_clause(i: &str) -> R<()> {
+    fn col_names(i: &str) -> R<'_, Vec<&str>> {
+        delimited(
+            terminated_ws0(char('(')),
+            comma_separated_list1(identifier),
+            preceded_ws0(char(')')),
+        )(i)
+    }
+
+    fn fk_clause(i: &str) -> R<'_, ForeignKey<'_>> {
+        let action = alt((
+            value(
+                FkAction::SetNull,
+                pair(keyword("SET"), preceded_ws1(keyword("NULL"))),
+            ),
+            value(
+                FkAction::SetDefault,
+                pair(keyword("SET"), preceded_ws1(keyword("DEFAULT"))),
+            ),
+            value(FkAction::Cascade, keyword("CASCADE")),
+            value(FkAction::Restrict, keyword("RESTRICT")),
+            value(
+                FkAction::NoAction,
+                pair(keyword("NO"), preceded_ws1(keyword("ACTION"))),
+            ),
+        ));
+
+        let on_action = tuple((
+            keyword("ON"),
+            preceded_ws1(alt((keyword("DELETE"), keyword("UPDATE")))),
+            preceded_ws1(action),
+        ));
+
+        let deferrable = tuple((
+            opt(terminated(keyword("NOT"), multispace1)),
+            keyword("DEFERRABLE"),
+            opt(preceded_ws1(pair(
+                keyword("INITIALLY"),
+                preceded_ws1(alt((keyword("DEFERRED"), keyword("IMMEDIATE")))),
+            ))),
+        ));
+
+        tuple((
+            keyword("REFERENCES"),
+            preceded_ws1(identifier),
+            opt(preceded_ws0(col_names)),
+            many0(preceded_ws1(alt((
+                on_action.map(|x| Some((x.1, x.2))),
+                pair(keyword("MATCH"), preceded_ws1(identifier)).map(|_| None),
+                deferrable.map(|_| None),
+            )))),
+        ))
+        .map(|x| {
+            let action = |event: &str| {
+                x.3.iter()
+                    .flatten()
+                    .rfind(|(e, _)| e.eq_ignore_ascii_case(event))
+                    .map_or(FkAction::NoAction, |(_, action)| *action)
+            };
+            let (on_update, on_delete) = (action("UPDATE"), action("DELETE"));
+            ForeignKey {
+                tbl: x.1,
+                cols: x.2.unwrap_or_default(),
+                on_update,
+                on_delete,
+            }
+        })
+        .parse(i)
+    }
+
+    /// Recognizes a parenthesized expression with balanced parentheses without parsing it.
+    fn parenthesized(i: &str) -> R<'_, &str> {
+        recognize(delimited(
+            char('('),
+            many0_count(alt((
+                is_not("()'\""),
+                quoted('\''),
+                quoted('"'),
+                parenthesized,
+            ))),
+            char(')'),
+        ))(i)
+    }
+
+    fn if_not_exists_clause(i: &str) -> R<'_, ()> {
         skip(tuple((
             tag_no_case("IF"),
             preceded_ws1(tag_no_case("NOT")),
@@ -117,117 +532,711 @@ mod parsers {
         .parse(i)
     }
 
-    fn select_stmt(i: &str) -> R<SqlStmt> {
+    fn select_stmt(i: &str) -> R<'_, SqlStmt<'_>> {
+        query.map(SqlStmt::Select).parse(i)
+    }
+
+    fn explain_stmt(i: &str) -> R<'_, SqlStmt<'_>> {
+        preceded(
+            tuple((
+                preceded_ws0(keyword("EXPLAIN")),
+                preceded_ws1(keyword("QUERY")),
+                preceded_ws1(keyword("PLAN")),
+                multispace1,
+            )),
+            query,
+        )
+        .map(SqlStmt::ExplainQueryPlan)
+        .parse(i)
+    }
+
+    /// SELECTs combined by compound operators, which ORDER BY and LIMIT apply to as a whole.
+    fn query(i: &str) -> R<'_, Query<'_>> {
+        let compound_op = alt((
+            value(
+                CompoundOp::UnionAll,
+                pair(keyword("UNION"), preceded_ws1(keyword("ALL"))),
+            ),
+            value(CompoundOp::Union, keyword("UNION")),
+            value(CompoundOp::Intersect, keyword("INTERSECT")),
+            value(CompoundOp::Except, keyword("EXCEPT")),
+        ));
+        tuple((
+            skip(multispace0),
+            opt(terminated(with_clause, multispace0)),
+            select,
+            many0(pair(compound_op, select)),
+            opt(terminated(order_by, multispace0)),
+            opt(terminated(limit.map(Box::new), multispace0)),
+        ))
+        .map(|x| Query {
+            with: x.1.unwrap_or_default(),
+            select: x.2,
+            compound: x.3,
+            order_by: x.4.unwrap_or_default(),
+            limit: x.5,
+        })
+        .parse(i)
+    }
+
+    fn select(i: &str) -> R<'_, Select<'_>> {
         tuple((
             skip(multispace0),
             skip(tag_no_case("SELECT")),
             skip(multispace1),
+            opt(terminated(
+                alt((
+                    value(true, keyword("DISTINCT")),
+                    value(false, keyword("ALL")),
+                )),
+                multispace1,
+            )),
             select_result_cols,
-            skip(delimited_ws1(tag_no_case("FROM"))),
-            identifier,
+            opt(preceded(
+                preceded_ws1(keyword("FROM")),
+                preceded_ws0(source),
+            )),
             opt(select_filter),
             skip(multispace0),
         ))
-        .map(|x| {
-            SqlStmt::Select(Select {
-                cols: x.3,
-                tbl: x.5,
-                filter: x.6,
+        .map(|x| Select {
+            distinct: x.3.unwrap_or_default(),
+            cols: x.4,
+            from: x.5,
+            filter: x.6,
+        })
+        .parse(i)
+    }
+
+    fn order_by(i: &str) -> R<'_, Vec<(Expr<'_>, SortOrder)>> {
+        preceded(
+            tuple((keyword("ORDER"), preceded_ws1(keyword("BY")), multispace1)),
+            comma_separated_list1(pair(
+                expr,
+                opt(preceded_ws1(sort_order)).map(|order| order.unwrap_or(SortOrder::Asc)),
+            )),
+        )(i)
+    }
+
+    /// LIMIT with an optional OFFSET, which may also be given before the limit and a comma.
+    fn limit(i: &str) -> R<'_, Limit<'_>> {
+        preceded(
+            pair(keyword("LIMIT"), multispace1),
+            alt((
+                separated_pair(expr, delimited_ws0(char(',')), expr).map(|(offset, limit)| Limit {
+                    limit,
+                    offset: Some(offset),
+                }),
+                pair(expr, opt(preceded(delimited_ws1(keyword("OFFSET")), expr)))
+                    .map(|(limit, offset)| Limit { limit, offset }),
+            )),
+        )(i)
+    }
+
+    /// WITH and its common table expressions. Like in SQLite, they may refer to themselves
+    /// whether or not RECURSIVE is given.
+    fn with_clause(i: &str) -> R<'_, Vec<Cte<'_>>> {
+        preceded(
+            tuple((
+                keyword("WITH"),
+                opt(preceded_ws1(keyword("RECURSIVE"))),
+                multispace1,
+            )),
+            comma_separated_list1(cte),
+        )(i)
+    }
+
+    fn cte(i: &str) -> R<'_, Cte<'_>> {
+        tuple((
+            identifier,
+            opt(preceded_ws0(col_names)),
+            skip(delimited_ws0(keyword("AS"))),
+            subquery,
+        ))
+        .map(|(name, cols, (), query)| Cte {
+            name,
+            cols: cols.unwrap_or_default(),
+            query,
+        })
+        .parse(i)
+    }
+
+    fn subquery(i: &str) -> R<'_, Query<'_>> {
+        delimited(char('('), query, char(')'))(i)
+    }
+
+    /// Tables and subqueries joined with commas or JOIN, which may be followed by a condition.
+    fn source(i: &str) -> R<'_, Source<'_>> {
+        let comma_join = preceded(delimited_ws0(char(',')), source_item).map(|r| (r, None));
+        let join = pair(
+            preceded(
+                tuple((
+                    multispace1,
+                    opt(terminated(keyword("INNER"), multispace1)),
+                    keyword("JOIN"),
+                )),
+                preceded_ws0(source_item),
+            ),
+            opt(preceded(delimited_ws1(keyword("ON")), bool_expr).map(Box::new)),
+        );
+        pair(source_item, many0(alt((comma_join, join))))
+            .map(|(first, joins)| {
+                joins.into_iter().fold(first, |l, (r, on)| Source::Join {
+                    l: Box::new(l),
+                    r: Box::new(r),
+                    on,
+                })
             })
+            .parse(i)
+    }
+
+    /// A table or a subquery, which may be given a name with or without AS.
+    fn source_item(i: &str) -> R<'_, Source<'_>> {
+        let alias = || {
+            opt(preceded(
+                opt(preceded_ws1(keyword("AS"))),
+                preceded_ws1(alt((
+                    delimited_identifier,
+                    verify(regular_identifier, |id: &str| !is_keyword(id)),
+                ))),
+            ))
+        };
+        alt((
+            pair(subquery, alias()).map(|(select, alias)| Source::Subquery {
+                select: Box::new(select),
+                alias,
+            }),
+            pair(identifier, alias()).map(|(name, alias)| Source::Tbl { name, alias }),
+        ))(i)
+    }
+
+    fn pragma_stmt(i: &str) -> R<'_, SqlStmt<'_>> {
+        let arg = || {
+            alt((
+                identifier,
+                str_lit,
+                recognize(pair(opt(one_of("+-")), number_lit)),
+            ))
+        };
+
+        tuple((
+            skip(preceded_ws0(keyword("PRAGMA"))),
+            preceded_ws1(opt(terminated(identifier, delimited_ws0(char('.'))))),
+            identifier,
+            opt(alt((
+                preceded(delimited_ws0(char('=')), arg()),
+                delimited(delimited_ws0(char('(')), arg(), preceded_ws0(char(')'))),
+            ))),
+            skip(multispace0),
+        ))
+        .map(|x| SqlStmt::Pragma {
+            schema: x.1,
+            name: x.2,
+            arg: x.3,
         })
         .parse(i)
     }
 
-    fn select_result_cols(i: &str) -> R<Vec<Expr>> {
-        comma_separated_list1(alt((value(Expr::Count, tag_no_case("COUNT(*)")), expr))).parse(i)
+    fn analyze_stmt(i: &str) -> R<'_, SqlStmt<'_>> {
+        delimited(
+            preceded_ws0(keyword("ANALYZE")),
+            opt(preceded_ws1(identifier)),
+            multispace0,
+        )
+        .map(SqlStmt::Analyze)
+        .parse(i)
     }
 
-    fn select_filter(i: &str) -> R<BoolExpr> {
+    fn select_result_cols(i: &str) -> R<'_, Vec<Expr<'_>>> {
+        let count_distinct = delimited(
+            tuple((
+                keyword("COUNT"),
+                delimited_ws0(char('(')),
+                keyword("DISTINCT"),
+                multispace1,
+            )),
+            expr,
+            preceded_ws0(char(')')),
+        );
+        comma_separated_list1(alt((
+            value(Expr::Count, tag_no_case("COUNT(*)")),
+            count_distinct.map(|expr| Expr::CountDistinct(Box::new(expr))),
+            expr,
+        )))
+        .parse(i)
+    }
+
+    fn select_filter(i: &str) -> R<'_, BoolExpr<'_>> {
+        preceded(delimited_ws1(tag_no_case("WHERE")), bool_expr)(i)
+    }
+
+    /// Conditions joined by AND and OR, which bind weaker than the comparisons and AND stronger
+    /// than OR.
+    fn bool_expr(i: &str) -> R<'_, BoolExpr<'_>> {
+        let term = alt((
+            delimited(
+                terminated_ws0(char('(')),
+                bool_expr,
+                preceded_ws0(char(')')),
+            ),
+            preceded(terminated_ws0(keyword("EXISTS")), subquery)
+                .map(|select| BoolExpr::Exists(Box::new(select))),
+            in_expr,
+            comparison,
+        ));
+        let conjunction = separated_list1(delimited_ws0(keyword("AND")), term).map(|terms| {
+            terms
+                .into_iter()
+                .reduce(|l, r| BoolExpr::And(Box::new(l), Box::new(r)))
+                .unwrap()
+        });
+        separated_list1(delimited_ws0(keyword("OR")), conjunction)
+            .map(|terms| {
+                terms
+                    .into_iter()
+                    .reduce(|l, r| BoolExpr::Or(Box::new(l), Box::new(r)))
+                    .unwrap()
+            })
+            .parse(i)
+    }
+
+    fn comparison(i: &str) -> R<'_, BoolExpr<'_>> {
         tuple((
-            skip(delimited_ws1(tag_no_case("WHERE"))),
             expr,
             delimited_ws0(alt((tag("=="), tag("="), tag("!="), tag("<>")))),
             expr,
         ))
-        .map(|x| match x.2 {
-            "==" | "=" => BoolExpr::Equals { l: x.1, r: x.3 },
-            "!=" | "<>" => BoolExpr::NotEquals { l: x.1, r: x.3 },
-            _ => panic!("Unsupported operator {}", x.2),
+        .map(|x| match x.1 {
+            "==" | "=" => BoolExpr::Equals { l: x.0, r: x.2 },
+            "!=" | "<>" => BoolExpr::NotEquals { l: x.0, r: x.2 },
+            _ => panic!("Unsupported operator {}", x.1),
         })
         .parse(i)
     }
 
-    fn lit(i: &str) -> R<Literal> {
+    /// A value compared to a list of values or to the rows of a subquery.
+    fn in_expr(i: &str) -> R<'_, BoolExpr<'_>> {
+        let list = delimited(
+            terminated_ws0(char('(')),
+            comma_separated_list1(expr),
+            preceded_ws0(char(')')),
+        );
+        pair(
+            terminated(expr, preceded_ws1(keyword("IN"))),
+            preceded_ws0(alt((subquery.map(Either::Left), list.map(Either::Right)))),
+        )
+        .map(|(expr, rhs)| match rhs {
+            Either::Left(select) => BoolExpr::InSubquery {
+                expr,
+                select: Box::new(select),
+            },
+            Either::Right(list) => BoolExpr::In { expr, list },
+        })
+        .parse(i)
+    }
+
+    fn lit(i: &str) -> R<'_, Literal<'_>> {
         alt((
             value(Literal::Null, tag_no_case("NULL")),
-            str_lit.map(Literal::String),
+            str_lit.map(|s| Literal::String(Cow::Borrowed(s))),
             num.map(Literal::Int),
         ))(i)
     }
 
-    fn expr(i: &str) -> R<Expr> {
-        alt((lit.map(Expr::Literal), identifier.map(Expr::ColName)))(i)
+    pub fn whole_expr(i: &str) -> R<'_, Expr<'_>> {
+        terminated(expr, eof)(i)
+    }
+
+    /// Operands joined by arithmetic operators, of which `*`, `/` and `%` bind stronger than `+`
+    /// and `-`.
+    fn expr(i: &str) -> R<'_, Expr<'_>> {
+        arith("+-", product)(i)
+    }
+
+    fn product(i: &str) -> R<'_, Expr<'_>> {
+        arith("*/%", operand)(i)
+    }
+
+    /// Operands joined by any of the operators `ops`, which apply from left to right.
+    fn arith<'a>(
+        ops: &'static str,
+        operand: fn(&'a str) -> R<'a, Expr<'a>>,
+    ) -> impl FnMut(&'a str) -> R<'a, Expr<'a>> {
+        move |i| {
+            let (mut rest, mut expr) = operand(i)?;
+            loop {
+                let (after, (op, r)) = match pair(delimited_ws0(one_of(ops)), operand)(rest) {
+                    Ok(x) => x,
+                    Err(nom::Err::Error(_)) => return Ok((rest, expr)),
+                    Err(e) => return Err(e),
+                };
+                let op = match op {
+                    '+' => ArithOp::Add,
+                    '-' => ArithOp::Sub,
+                    '*' => ArithOp::Mul,
+                    '/' => ArithOp::Div,
+                    _ => ArithOp::Rem,
+                };
+                expr = Expr::Arith {
+                    op,
+                    l: Box::new(expr),
+                    r: Box::new(r),
+                    text: &i[..i.len() - after.len()],
+                };
+                rest = after;
+            }
+        }
+    }
+
+    fn operand(i: &str) -> R<'_, Expr<'_>> {
+        alt((
+            consumed(subquery).map(|(text, select)| Expr::Subquery {
+                select: Box::new(select),
+                text,
+            }),
+            // The parentheses are part of how an operation in them is written.
+            consumed(delimited(
+                terminated_ws0(char('(')),
+                expr,
+                preceded_ws0(char(')')),
+            ))
+            .map(|(text, expr)| match expr {
+                Expr::Arith { op, l, r, .. } => Expr::Arith { op, l, r, text },
+                expr => expr,
+            }),
+            lit.map(Expr::Literal),
+            param.map(|name| Expr::Param {
+                name,
+                value: Literal::Null,
+            }),
+            separated_pair(identifier, char('.'), identifier)
+                .map(|(tbl, col)| Expr::QualifiedColName { tbl, col }),
+            alt((
+                delimited_identifier,
+                verify(regular_identifier, |id: &str| !is_select_clause_keyword(id)),
+            ))
+            .map(Expr::ColName),
+        ))(i)
+    }
+
+    /// A parameter, which is numbered or named after its prefix.
+    fn param(i: &str) -> R<'_, &str> {
+        alt((
+            recognize(pair(char('?'), digit0)),
+            recognize(pair(one_of(":@$"), many1(alt((alphanumeric1, tag("_")))))),
+        ))(i)
     }
 
-    fn identifier(i: &str) -> R<&str> {
+    fn identifier(i: &str) -> R<'_, &str> {
         alt((delimited_identifier, regular_identifier))(i)
     }
 
-    fn regular_identifier(i: &str) -> R<&str> {
+    fn regular_identifier(i: &str) -> R<'_, &str> {
         recognize(pair(
             alt((alpha1, tag("_"))),
             many0(alt((alphanumeric1, tag("_")))),
         ))(i)
     }
 
-    fn delimited_identifier(i: &str) -> R<&str> {
+    fn delimited_identifier(i: &str) -> R<'_, &str> {
         delimited(char('"'), is_not("\""), char('"'))(i)
     }
 
-    fn str_lit(i: &str) -> R<&str> {
+    fn str_lit(i: &str) -> R<'_, &str> {
         delimited(char('\''), is_not("'"), char('\''))(i)
     }
 
-    fn num(i: &str) -> R<i64> {
+    fn number_lit(i: &str) -> R<'_, &str> {
+        recognize(pair(digit1, opt(pair(char('.'), digit0))))(i)
+    }
+
+    fn num(i: &str) -> R<'_, i64> {
         map_res(digit1, str::parse)(i)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::syntax::{Query, Select, Source};
+
+    fn tbl(name: &str) -> Source<'_> {
+        Source::Tbl { name, alias: None }
+    }
+
+    /// A query of just the SELECT.
+    fn simple(select: Select<'_>) -> Query<'_> {
+        Query {
+            with: vec![],
+            select,
+            compound: vec![],
+            order_by: vec![],
+            limit: None,
+        }
+    }
+
     mod create_tbl {
         use super::super::*;
 
+        fn col<'a>(
+            name: &'a str,
+            type_name: Option<&'a str>,
+            constraints: Vec<ColConstraint<'a>>,
+        ) -> ColDef<'a> {
+            ColDef {
+                name,
+                type_name,
+                constraints,
+            }
+        }
+
         #[test]
         fn basic() {
             assert_eq!(
                 sql_stmt("create table foo (bar, qux)").unwrap(),
                 SqlStmt::CreateTbl {
                     name: "foo",
-                    col_defs: vec![ColDef::Col("bar"), ColDef::Col("qux")],
+                    col_defs: vec![col("bar", None, vec![]), col("qux", None, vec![])],
+                    constraints: vec![],
+                    without_rowid: false,
                 }
             )
         }
 
         #[test]
-        fn ignores_types_and_constraints_on_cols() {
+        fn ignores_irrelevant_constraints_on_cols() {
             assert_eq!(
-                sql_stmt("create table foo (bar text default 'bar', qux blob unique not null)")
+                sql_stmt("create table foo (bar text check (bar != ''), qux blob unique not null)")
                     .unwrap(),
                 SqlStmt::CreateTbl {
                     name: "foo",
-                    col_defs: vec![ColDef::Col("bar"), ColDef::Col("qux")],
+                    col_defs: vec![
+                        col("bar", Some("text"), vec![]),
+                        col(
+                            "qux",
+                            Some("blob"),
+                            vec![ColConstraint::Unique, ColConstraint::NotNull]
+                        )
+                    ],
+                    constraints: vec![],
+                    without_rowid: false,
                 }
             )
         }
 
         #[test]
         fn captures_int_pk_constraint() {
+            let stmt = sql_stmt("create table foo (bar integer primary key, qux)").unwrap();
+            assert_eq!(
+                stmt,
+                SqlStmt::CreateTbl {
+                    name: "foo",
+                    col_defs: vec![
+                        col(
+                            "bar",
+                            Some("integer"),
+                            vec![ColConstraint::PrimaryKey(SortOrder::Asc)]
+                        ),
+                        col("qux", None, vec![])
+                    ],
+                    constraints: vec![],
+                    without_rowid: false,
+                }
+            );
+            match stmt {
+                SqlStmt::CreateTbl { col_defs, .. } => assert_eq!(
+                    col_defs.iter().find(ColDef::is_int_pk).map(ColDef::name),
+                    Some("bar")
+                ),
+                _ => unreachable!(),
+            }
+        }
+
+        #[test]
+        fn desc_int_pk_is_no_rowid_alias() {
+            match sql_stmt("create table foo (bar integer primary key desc)").unwrap() {
+                SqlStmt::CreateTbl { col_defs, .. } => {
+                    assert_eq!(col_defs.iter().find(ColDef::is_int_pk), None)
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        #[test]
+        fn captures_multi_word_types_with_size() {
             assert_eq!(
-                sql_stmt("create table foo (bar integer primary key, qux)").unwrap(),
+                sql_stmt("create table foo (bar varchar(255) not null, qux unsigned big int)")
+                    .unwrap(),
+                SqlStmt::CreateTbl {
+                    name: "foo",
+                    col_defs: vec![
+                        col("bar", Some("varchar(255)"), vec![ColConstraint::NotNull]),
+                        col("qux", Some("unsigned big int"), vec![])
+                    ],
+                    constraints: vec![],
+                    without_rowid: false,
+                }
+            )
+        }
+
+        #[test]
+        fn skips_constraints_with_nested_parentheses() {
+            assert_eq!(
+                sql_stmt(
+                    "create table foo (bar int check (bar > (1 + 2)) default (')'), \
+                     qux constraint q references baz (id) on delete cascade unique)"
+                )
+                .unwrap(),
+                SqlStmt::CreateTbl {
+                    name: "foo",
+                    col_defs: vec![
+                        col("bar", Some("int"), vec![ColConstraint::Default("')'")]),
+                        col(
+                            "qux",
+                            None,
+                            vec![
+                                ColConstraint::ForeignKey(ForeignKey {
+                                    tbl: "baz",
+                                    cols: vec!["id"],
+                                    on_update: FkAction::NoAction,
+                                    on_delete: FkAction::Cascade,
+                                }),
+                                ColConstraint::Unique
+                            ]
+                        )
+                    ],
+                    constraints: vec![],
+                    without_rowid: false,
+                }
+            )
+        }
+
+        #[test]
+        fn captures_generated_cols() {
+            assert_eq!(
+                sql_stmt(
+                    "create table foo (bar, qux as (bar + 1), \
+                     baz text generated always as ( (bar) ) stored)"
+                )
+                .unwrap(),
+                SqlStmt::CreateTbl {
+                    name: "foo",
+                    col_defs: vec![
+                        col("bar", None, vec![]),
+                        col(
+                            "qux",
+                            None,
+                            vec![ColConstraint::Generated {
+                                expr: "bar + 1",
+                                stored: false
+                            }]
+                        ),
+                        col(
+                            "baz",
+                            Some("text"),
+                            vec![ColConstraint::Generated {
+                                expr: "(bar)",
+                                stored: true
+                            }]
+                        )
+                    ],
+                    constraints: vec![],
+                    without_rowid: false,
+                }
+            )
+        }
+
+        #[test]
+        fn captures_defaults_and_foreign_keys() {
+            assert_eq!(
+                sql_stmt(
+                    "create table foo (bar default -1.5, qux default 'a''b', baz default null, \
+                     foreign key (bar, qux) references quux on update set null on delete \
+                     restrict match full deferrable initially deferred)"
+                )
+                .unwrap(),
                 SqlStmt::CreateTbl {
                     name: "foo",
-                    col_defs: vec![ColDef::IntPk("bar"), ColDef::Col("qux")],
+                    col_defs: vec![
+                        col("bar", None, vec![ColConstraint::Default("-1.5")]),
+                        col("qux", None, vec![ColConstraint::Default("'a''b'")]),
+                        col("baz", None, vec![ColConstraint::Default("null")])
+                    ],
+                    constraints: vec![TblConstraint::ForeignKey {
+                        cols: vec!["bar", "qux"],
+                        fk: ForeignKey {
+                            tbl: "quux",
+                            cols: vec![],
+                            on_update: FkAction::SetNull,
+                            on_delete: FkAction::Restrict,
+                        }
+                    }],
+                    without_rowid: false,
+                }
+            )
+        }
+
+        #[test]
+        fn captures_tbl_constraints() {
+            assert_eq!(
+                sql_stmt(
+                    "create table foo (bar, qux, primary key (bar, qux desc), \
+                     check (bar != qux), constraint u unique (qux collate nocase))"
+                )
+                .unwrap(),
+                SqlStmt::CreateTbl {
+                    name: "foo",
+                    col_defs: vec![col("bar", None, vec![]), col("qux", None, vec![])],
+                    constraints: vec![
+                        TblConstraint::PrimaryKey(vec![
+                            IndexedCol::asc("bar"),
+                            IndexedCol {
+                                name: "qux",
+                                order: SortOrder::Desc,
+                                collation: None,
+                            }
+                        ]),
+                        TblConstraint::Unique(vec![IndexedCol {
+                            name: "qux",
+                            order: SortOrder::Asc,
+                            collation: Some("nocase"),
+                        }]),
+                    ],
+                    without_rowid: false,
+                }
+            )
+        }
+
+        #[test]
+        fn captures_without_rowid_option() {
+            assert_eq!(
+                sql_stmt("create table foo (bar int primary key, qux int) strict, without rowid")
+                    .unwrap(),
+                SqlStmt::CreateTbl {
+                    name: "foo",
+                    col_defs: vec![
+                        col(
+                            "bar",
+                            Some("int"),
+                            vec![ColConstraint::PrimaryKey(SortOrder::Asc)]
+                        ),
+                        col("qux", Some("int"), vec![])
+                    ],
+                    constraints: vec![],
+                    without_rowid: true,
+                }
+            )
+        }
+
+        #[test]
+        fn unique() {
+            assert_eq!(
+                sql_stmt("create unique index foo on bar (qux)").unwrap(),
+                SqlStmt::CreateIdx {
+                    name: "foo",
+                    unique: true,
+                    target_tbl: "bar",
+                    target_cols: vec![IndexedCol::asc("qux")],
+                    partial: false,
                 }
             )
         }
@@ -238,7 +1247,9 @@ mod test {
                 sql_stmt("create table if not exists foo (bar, qux)").unwrap(),
                 SqlStmt::CreateTbl {
                     name: "foo",
-                    col_defs: vec![ColDef::Col("bar"), ColDef::Col("qux")],
+                    col_defs: vec![col("bar", None, vec![]), col("qux", None, vec![])],
+                    constraints: vec![],
+                    without_rowid: false,
                 }
             )
         }
@@ -249,7 +1260,9 @@ mod test {
                 sql_stmt("create table \"my tbl!\" (\"my col!\")").unwrap(),
                 SqlStmt::CreateTbl {
                     name: "my tbl!",
-                    col_defs: vec![ColDef::Col("my col!")],
+                    col_defs: vec![col("my col!", None, vec![])],
+                    constraints: vec![],
+                    without_rowid: false,
                 }
             )
         }
@@ -264,8 +1277,45 @@ mod test {
                 sql_stmt("create index foo on bar (qux)").unwrap(),
                 SqlStmt::CreateIdx {
                     name: "foo",
+                    unique: false,
+                    target_tbl: "bar",
+                    target_cols: vec![IndexedCol::asc("qux")],
+                    partial: false,
+                }
+            )
+        }
+
+        #[test]
+        fn multiple_cols() {
+            assert_eq!(
+                sql_stmt("create index foo on bar (qux desc, baz)").unwrap(),
+                SqlStmt::CreateIdx {
+                    name: "foo",
+                    unique: false,
+                    target_tbl: "bar",
+                    target_cols: vec![
+                        IndexedCol {
+                            name: "qux",
+                            order: SortOrder::Desc,
+                            collation: None,
+                        },
+                        IndexedCol::asc("baz")
+                    ],
+                    partial: false,
+                }
+            )
+        }
+
+        #[test]
+        fn partial() {
+            assert_eq!(
+                sql_stmt("create index foo on bar (qux) where qux is not null").unwrap(),
+                SqlStmt::CreateIdx {
+                    name: "foo",
+                    unique: false,
                     target_tbl: "bar",
-                    target_col: "qux",
+                    target_cols: vec![IndexedCol::asc("qux")],
+                    partial: true,
                 }
             )
         }
@@ -276,8 +1326,10 @@ mod test {
                 sql_stmt("create index if not exists foo on bar (qux)").unwrap(),
                 SqlStmt::CreateIdx {
                     name: "foo",
+                    unique: false,
                     target_tbl: "bar",
-                    target_col: "qux",
+                    target_cols: vec![IndexedCol::asc("qux")],
+                    partial: false,
                 }
             )
         }
@@ -288,8 +1340,56 @@ mod test {
                 sql_stmt("create index \"my idx!\" on \"my tbl!\" (\"my col!\")").unwrap(),
                 SqlStmt::CreateIdx {
                     name: "my idx!",
+                    unique: false,
                     target_tbl: "my tbl!",
-                    target_col: "my col!",
+                    target_cols: vec![IndexedCol::asc("my col!")],
+                    partial: false,
+                }
+            )
+        }
+    }
+
+    mod pragma {
+        use super::super::*;
+
+        #[test]
+        fn without_arg() {
+            assert_eq!(
+                sql_stmt("pragma page_size").unwrap(),
+                SqlStmt::Pragma {
+                    schema: None,
+                    name: "page_size",
+                    arg: None,
+                }
+            )
+        }
+
+        #[test]
+        fn with_schema_and_arg() {
+            for sql in [
+                "PRAGMA main.table_info(foo)",
+                "pragma main . table_info ( 'foo' )",
+                "pragma main.table_info = \"foo\"",
+            ] {
+                assert_eq!(
+                    sql_stmt(sql).unwrap(),
+                    SqlStmt::Pragma {
+                        schema: Some("main"),
+                        name: "table_info",
+                        arg: Some("foo"),
+                    }
+                )
+            }
+        }
+
+        #[test]
+        fn with_signed_number() {
+            assert_eq!(
+                sql_stmt("pragma user_version = -3").unwrap(),
+                SqlStmt::Pragma {
+                    schema: None,
+                    name: "user_version",
+                    arg: Some("-3"),
                 }
             )
         }
@@ -297,16 +1397,18 @@ mod test {
 
     mod select {
         use super::super::*;
+        use super::{simple, tbl};
 
         #[test]
         fn single_col() {
             assert_eq!(
                 sql_stmt("select foo from bar").unwrap(),
-                SqlStmt::Select(Select {
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
                     cols: vec![Expr::ColName("foo")],
-                    tbl: "bar",
+                    from: Some(tbl("bar")),
                     filter: None
-                })
+                }))
             )
         }
 
@@ -314,27 +1416,123 @@ mod test {
         fn count() {
             assert_eq!(
                 sql_stmt("select count(*) from bar").unwrap(),
-                SqlStmt::Select(Select {
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
                     cols: vec![Expr::Count],
-                    tbl: "bar",
+                    from: Some(tbl("bar")),
                     filter: None
-                })
+                }))
             )
         }
 
+        #[test]
+        fn distinct() {
+            let select = |distinct, cols| {
+                SqlStmt::Select(simple(Select {
+                    distinct,
+                    cols,
+                    from: Some(tbl("bar")),
+                    filter: None,
+                }))
+            };
+            assert_eq!(
+                sql_stmt("select DISTINCT foo, count(*) from bar").unwrap(),
+                select(true, vec![Expr::ColName("foo"), Expr::Count])
+            );
+            assert_eq!(
+                sql_stmt("select all foo from bar").unwrap(),
+                select(false, vec![Expr::ColName("foo")])
+            );
+            assert_eq!(
+                sql_stmt("select Count ( distinct foo ) from bar").unwrap(),
+                select(
+                    false,
+                    vec![Expr::CountDistinct(Box::new(Expr::ColName("foo")))]
+                )
+            );
+            assert_eq!(
+                sql_stmt("select distinctfoo from bar").unwrap(),
+                select(false, vec![Expr::ColName("distinctfoo")])
+            );
+            assert!(sql_stmt("select count(distinct) from bar").is_err());
+        }
+
+        #[test]
+        fn without_from() {
+            let int = |n| Expr::Literal(Literal::Int(n));
+            assert_eq!(
+                sql_stmt("select 1, 'from' where 2 = 2").unwrap(),
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
+                    cols: vec![int(1), Expr::Literal(Literal::String("from".into()))],
+                    from: None,
+                    filter: Some(BoolExpr::Equals {
+                        l: int(2),
+                        r: int(2)
+                    })
+                }))
+            );
+            assert!(sql_stmt("select 1 from").is_err());
+        }
+
+        #[test]
+        fn arithmetic() {
+            let int = |n| Box::new(Expr::Literal(Literal::Int(n)));
+            let sql = "select 1 - 2 * (3 + x) % 4, 5/6 from t";
+            let product = Expr::Arith {
+                op: ArithOp::Mul,
+                l: int(2),
+                r: Box::new(Expr::Arith {
+                    op: ArithOp::Add,
+                    l: int(3),
+                    r: Box::new(Expr::ColName("x")),
+                    text: "(3 + x)",
+                }),
+                text: "2 * (3 + x)",
+            };
+            assert_eq!(
+                sql_stmt(sql).unwrap(),
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
+                    cols: vec![
+                        Expr::Arith {
+                            op: ArithOp::Sub,
+                            l: int(1),
+                            r: Box::new(Expr::Arith {
+                                op: ArithOp::Rem,
+                                l: Box::new(product),
+                                r: int(4),
+                                text: "2 * (3 + x) % 4",
+                            }),
+                            text: "1 - 2 * (3 + x) % 4",
+                        },
+                        Expr::Arith {
+                            op: ArithOp::Div,
+                            l: int(5),
+                            r: int(6),
+                            text: "5/6",
+                        },
+                    ],
+                    from: Some(tbl("t")),
+                    filter: None
+                }))
+            );
+        }
+
         #[test]
         fn multiple_cols() {
             assert_eq!(
                 sql_stmt("select foo, bar, qux from my_tbl").unwrap(),
-                SqlStmt::Select(Select {
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
                     cols: vec![
                         Expr::ColName("foo"),
                         Expr::ColName("bar"),
                         Expr::ColName("qux")
                     ],
-                    tbl: "my_tbl",
+                    from: Some(tbl("my_tbl")),
                     filter: None
-                })
+                }))
             )
         }
 
@@ -342,11 +1540,12 @@ mod test {
         fn delimited_table_name() {
             assert_eq!(
                 sql_stmt("select foo from \"my tbl!\"").unwrap(),
-                SqlStmt::Select(Select {
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
                     cols: vec![Expr::ColName("foo")],
-                    tbl: "my tbl!",
+                    from: Some(tbl("my tbl!")),
                     filter: None
-                })
+                }))
             )
         }
 
@@ -354,15 +1553,432 @@ mod test {
         fn with_filter() {
             assert_eq!(
                 sql_stmt("select foo from bar where qux = 'my filter'").unwrap(),
-                SqlStmt::Select(Select {
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
                     cols: vec![Expr::ColName("foo")],
-                    tbl: "bar",
+                    from: Some(tbl("bar")),
                     filter: Some(BoolExpr::Equals {
                         l: Expr::ColName("qux"),
-                        r: Expr::Literal(Literal::String("my filter"))
+                        r: Expr::Literal(Literal::String("my filter".into()))
                     })
+                }))
+            )
+        }
+
+        #[test]
+        fn with_or_and_in() {
+            let col = Expr::ColName;
+            let int = |n| Expr::Literal(Literal::Int(n));
+            let eq = |l, r| BoolExpr::Equals { l, r };
+            let select = |filter| {
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
+                    cols: vec![col("foo")],
+                    from: Some(tbl("bar")),
+                    filter: Some(filter),
+                }))
+            };
+            assert_eq!(
+                sql_stmt("select foo from bar where a = 1 OR (b=2 or a in(3, 4))").unwrap(),
+                select(BoolExpr::Or(
+                    Box::new(eq(col("a"), int(1))),
+                    Box::new(BoolExpr::Or(
+                        Box::new(eq(col("b"), int(2))),
+                        Box::new(BoolExpr::In {
+                            expr: col("a"),
+                            list: vec![int(3), int(4)]
+                        })
+                    ))
+                ))
+            );
+            assert_eq!(
+                sql_stmt("select foo from bar where x=1 or origin=2").unwrap(),
+                select(BoolExpr::Or(
+                    Box::new(eq(col("x"), int(1))),
+                    Box::new(eq(col("origin"), int(2)))
+                ))
+            );
+            assert!(sql_stmt("select foo from bar where a in ()").is_err());
+        }
+
+        #[test]
+        fn with_and_before_or() {
+            let col = Expr::ColName;
+            let int = |n| Expr::Literal(Literal::Int(n));
+            let eq = |l, r| Box::new(BoolExpr::Equals { l, r });
+            assert_eq!(
+                sql_stmt("select foo from bar where a = 1 and b=2 OR a = 3 AND android = 4")
+                    .unwrap(),
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
+                    cols: vec![col("foo")],
+                    from: Some(tbl("bar")),
+                    filter: Some(BoolExpr::Or(
+                        Box::new(BoolExpr::And(eq(col("a"), int(1)), eq(col("b"), int(2)))),
+                        Box::new(BoolExpr::And(
+                            eq(col("a"), int(3)),
+                            eq(col("android"), int(4))
+                        ))
+                    ))
+                }))
+            );
+        }
+
+        #[test]
+        fn with_subqueries() {
+            let col = Expr::ColName;
+            let select = |cols, from, filter| {
+                simple(Select {
+                    distinct: false,
+                    cols,
+                    from: Some(from),
+                    filter,
                 })
+            };
+            let inner = || Box::new(select(vec![col("a")], tbl("baz"), None));
+            assert_eq!(
+                sql_stmt(
+                    "select (SELECT a FROM baz), foo from (select a from baz ) AS qux \
+                     where foo in ( select a from baz) or exists(select a from baz)"
+                )
+                .unwrap(),
+                SqlStmt::Select(select(
+                    vec![
+                        Expr::Subquery {
+                            select: inner(),
+                            text: "(SELECT a FROM baz)"
+                        },
+                        col("foo")
+                    ],
+                    Source::Subquery {
+                        select: inner(),
+                        alias: Some("qux")
+                    },
+                    Some(BoolExpr::Or(
+                        Box::new(BoolExpr::InSubquery {
+                            expr: col("foo"),
+                            select: inner()
+                        }),
+                        Box::new(BoolExpr::Exists(inner()))
+                    ))
+                ))
+            );
+            assert_eq!(
+                sql_stmt("select foo from (select a from baz) where a = 1").unwrap(),
+                SqlStmt::Select(select(
+                    vec![col("foo")],
+                    Source::Subquery {
+                        select: inner(),
+                        alias: None
+                    },
+                    Some(BoolExpr::Equals {
+                        l: col("a"),
+                        r: Expr::Literal(Literal::Int(1))
+                    })
+                ))
+            );
+        }
+
+        #[test]
+        fn with_joins_and_aliases() {
+            let col = |tbl, col| Expr::QualifiedColName { tbl, col };
+            let join = |l, r, on| Source::Join {
+                l: Box::new(l),
+                r: Box::new(r),
+                on,
+            };
+            let eq = |l, r| BoolExpr::Equals { l, r };
+            assert_eq!(
+                sql_stmt(
+                    "select a.x, y from foo a, bar AS b inner join (select y from baz) \
+                     on a.x = b.\"y\" join qux where y = 1"
+                )
+                .unwrap(),
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
+                    cols: vec![col("a", "x"), Expr::ColName("y")],
+                    from: Some(join(
+                        join(
+                            join(
+                                Source::Tbl {
+                                    name: "foo",
+                                    alias: Some("a")
+                                },
+                                Source::Tbl {
+                                    name: "bar",
+                                    alias: Some("b")
+                                },
+                                None
+                            ),
+                            Source::Subquery {
+                                select: Box::new(simple(Select {
+                                    distinct: false,
+                                    cols: vec![Expr::ColName("y")],
+                                    from: Some(tbl("baz")),
+                                    filter: None
+                                })),
+                                alias: None
+                            },
+                            Some(Box::new(eq(col("a", "x"), col("b", "y"))))
+                        ),
+                        tbl("qux"),
+                        None
+                    )),
+                    filter: Some(eq(Expr::ColName("y"), Expr::Literal(Literal::Int(1))))
+                }))
+            );
+            assert!(sql_stmt("select x from foo, bar on x = 1").is_err());
+        }
+
+        #[test]
+        fn with_ctes() {
+            let select = |col, from| Select {
+                distinct: false,
+                cols: vec![Expr::ColName(col)],
+                from: Some(tbl(from)),
+                filter: None,
+            };
+            let stmt = sql_stmt(
+                "WITH RECURSIVE c(n) AS (select a from foo union all select n from c), \
+                 d as (select b from bar) select n from c",
             )
+            .unwrap();
+            assert_eq!(
+                stmt,
+                SqlStmt::Select(Query {
+                    with: vec![
+                        Cte {
+                            name: "c",
+                            cols: vec!["n"],
+                            query: Query {
+                                compound: vec![(CompoundOp::UnionAll, select("n", "c"))],
+                                ..simple(select("a", "foo"))
+                            }
+                        },
+                        Cte {
+                            name: "d",
+                            cols: vec![],
+                            query: simple(select("b", "bar"))
+                        }
+                    ],
+                    ..simple(select("n", "c"))
+                })
+            );
+            assert!(sql_stmt("with c as (select a from foo) union select b from bar").is_err());
+        }
+
+        #[test]
+        fn compound_with_order_by_and_limit() {
+            let select = |col, from| Select {
+                distinct: false,
+                cols: vec![Expr::ColName(col)],
+                from: Some(tbl(from)),
+                filter: None,
+            };
+            let int = |n| Expr::Literal(Literal::Int(n));
+            assert_eq!(
+                sql_stmt(
+                    "select a from foo union select b from bar UNION  ALL select c from baz \
+                     intersect select d from qux except select e from quux \
+                     order by 1 desc, a limit 10 offset ?"
+                )
+                .unwrap(),
+                SqlStmt::Select(Query {
+                    compound: vec![
+                        (CompoundOp::Union, select("b", "bar")),
+                        (CompoundOp::UnionAll, select("c", "baz")),
+                        (CompoundOp::Intersect, select("d", "qux")),
+                        (CompoundOp::Except, select("e", "quux"))
+                    ],
+                    order_by: vec![
+                        (int(1), SortOrder::Desc),
+                        (Expr::ColName("a"), SortOrder::Asc)
+                    ],
+                    limit: Some(Box::new(Limit {
+                        limit: int(10),
+                        offset: Some(Expr::Param {
+                            name: "?",
+                            value: Literal::Null
+                        })
+                    })),
+                    ..simple(select("a", "foo"))
+                })
+            );
+            assert_eq!(
+                sql_stmt("select a from foo limit 2, 3").unwrap(),
+                SqlStmt::Select(Query {
+                    limit: Some(Box::new(Limit {
+                        limit: int(3),
+                        offset: Some(int(2))
+                    })),
+                    ..simple(select("a", "foo"))
+                })
+            );
+            assert!(sql_stmt("select a from foo order by a union select b from bar").is_err());
+            assert!(sql_stmt("select a from foo union").is_err());
+        }
+
+        #[test]
+        fn with_params() {
+            let param = |name| Expr::Param {
+                name,
+                value: Literal::Null,
+            };
+            assert_eq!(
+                sql_stmt("select ?, ?12, @b from bar where :a_1 != $c").unwrap(),
+                SqlStmt::Select(simple(Select {
+                    distinct: false,
+                    cols: vec![param("?"), param("?12"), param("@b")],
+                    from: Some(tbl("bar")),
+                    filter: Some(BoolExpr::NotEquals {
+                        l: param(":a_1"),
+                        r: param("$c")
+                    })
+                }))
+            )
+        }
+    }
+
+    mod explain {
+        use super::super::*;
+        use super::{simple, tbl};
+
+        #[test]
+        fn query_plan() {
+            assert_eq!(
+                sql_stmt("EXPLAIN query  plan select foo from bar").unwrap(),
+                SqlStmt::ExplainQueryPlan(simple(Select {
+                    distinct: false,
+                    cols: vec![Expr::ColName("foo")],
+                    from: Some(tbl("bar")),
+                    filter: None
+                }))
+            );
+            assert!(sql_stmt("explain select foo from bar").is_err());
+        }
+    }
+
+    mod analyze {
+        use super::super::*;
+
+        #[test]
+        fn with_and_without_name() {
+            assert_eq!(sql_stmt("ANALYZE").unwrap(), SqlStmt::Analyze(None));
+            assert_eq!(
+                sql_stmt(" analyze \"my tbl\" ").unwrap(),
+                SqlStmt::Analyze(Some("my tbl"))
+            );
+            assert!(sql_stmt("analyzed").is_err());
+        }
+    }
+
+    mod dot_cmd {
+        use super::super::*;
+
+        fn dot_cmd(cmd: &str) -> DotCmd {
+            match sqlite(cmd).unwrap() {
+                Sqlite::DotCmd(cmd) => cmd,
+                stmt => panic!("Expected dot-command but got {:?}", stmt),
+            }
+        }
+
+        #[test]
+        fn mode_with_tbl() {
+            assert_eq!(
+                dot_cmd(".mode insert 'my tbl'"),
+                DotCmd::Mode {
+                    mode: OutputMode::Insert,
+                    tbl: Some("my tbl".to_owned())
+                }
+            );
+            assert!(sqlite(".mode tablex").is_err());
+        }
+
+        #[test]
+        fn resolves_escapes_in_args() {
+            assert_eq!(
+                dot_cmd(r#".separator \t "a\" b""#),
+                DotCmd::Separator {
+                    col: "\t".to_owned(),
+                    row: Some("a\" b".to_owned())
+                }
+            );
+            assert_eq!(
+                dot_cmd(r#".nullvalue '\n'"#),
+                DotCmd::NullValue(r"\n".to_owned())
+            );
+            assert_eq!(
+                dot_cmd(r#".nullvalue """#),
+                DotCmd::NullValue(String::new())
+            );
+        }
+
+        #[test]
+        fn schema_with_pattern_and_indent() {
+            assert_eq!(
+                dot_cmd(".schema --indent t%"),
+                DotCmd::Schema {
+                    pattern: Some("t%".to_owned()),
+                    indent: true
+                }
+            );
+            assert_eq!(
+                dot_cmd(".schema"),
+                DotCmd::Schema {
+                    pattern: None,
+                    indent: false
+                }
+            );
+            assert_eq!(
+                dot_cmd(".fullschema --indent"),
+                DotCmd::FullSchema { indent: true }
+            );
+            assert!(sqlite(".schema --pretty").is_err());
+            assert!(sqlite(".schema a b").is_err());
+            assert!(sqlite(".fullschema t").is_err());
+        }
+
+        #[test]
+        fn tables_and_indexes_with_pattern() {
+            assert_eq!(dot_cmd(".tables"), DotCmd::Tables(None));
+            assert_eq!(
+                dot_cmd(".tables 'a b%'"),
+                DotCmd::Tables(Some("a b%".to_owned()))
+            );
+            assert_eq!(dot_cmd(".indexes t"), DotCmd::Indexes(Some("t".to_owned())));
+        }
+
+        #[test]
+        fn dump_with_patterns() {
+            assert_eq!(dot_cmd(".dump"), DotCmd::Dump(vec![]));
+            assert_eq!(
+                dot_cmd(".dump t% 'my tbl'"),
+                DotCmd::Dump(vec!["t%".to_owned(), "my tbl".to_owned()])
+            );
+            assert!(sqlite(".dump --data-only").is_err());
+        }
+
+        #[test]
+        fn import_with_options() {
+            assert_eq!(
+                dot_cmd(".import --skip 1 'my file.csv' t --csv"),
+                DotCmd::Import {
+                    file: "my file.csv".to_owned(),
+                    tbl: "t".to_owned(),
+                    csv: true,
+                    skip: 1
+                }
+            );
+            assert!(sqlite(".import a.csv").is_err());
+            assert!(sqlite(".import a.csv t --skip x").is_err());
+            assert!(sqlite(".import a.csv t --ascii").is_err());
+        }
+
+        #[test]
+        fn headers() {
+            assert_eq!(dot_cmd(".headers on"), DotCmd::Headers(true));
+            assert_eq!(dot_cmd(".headers 0"), DotCmd::Headers(false));
+            assert_eq!(dot_cmd(".scanstats on"), DotCmd::ScanStats(true));
         }
     }
 }
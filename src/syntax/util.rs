@@ -1,4 +1,7 @@
-use nom::{character::complete::*, combinator::*, error::*, multi::*, sequence::*, Parser};
+use nom::{
+    bytes::complete::*, character::complete::*, combinator::*, error::*, multi::*, sequence::*,
+    Parser,
+};
 
 pub fn skip<'a, O, E: ParseError<&'a str>, P: Parser<&'a str, O, E>>(
     p: P,
@@ -41,3 +44,216 @@ pub fn comma_separated_list1<'a, O, E: ParseError<&'a str>, F: Parser<&'a str, O
 ) -> impl Parser<&'a str, Vec<O>, E> {
     separated_list1(delimited_ws0(char(',')), f)
 }
+
+/// Matches the keyword `kw` case-insensitively, but only if it isn't just the prefix of a longer
+/// identifier.
+pub fn keyword<'a, E: ParseError<&'a str>>(kw: &'static str) -> impl Parser<&'a str, &'a str, E> {
+    terminated(
+        tag_no_case(kw),
+        not(peek(satisfy(|c| c.is_alphanumeric() || c == '_'))),
+    )
+}
+
+/// Recognizes a string quoted with `q` where the quote character can be escaped by doubling it.
+pub fn quoted<'a, E: ParseError<&'a str>>(q: char) -> impl Parser<&'a str, &'a str, E> {
+    recognize(many1_count(delimited(
+        char(q),
+        take_while(move |c| c != q),
+        char(q),
+    )))
+}
+
+pub fn is_tbl_constraint_keyword(word: &str) -> bool {
+    ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+        .iter()
+        .any(|kw| word.eq_ignore_ascii_case(kw))
+}
+
+/// Whether `word` starts a clause that may follow the result columns of a SELECT, which a column
+/// can't be named without quotes, as the columns may now be all there is.
+pub fn is_select_clause_keyword(word: &str) -> bool {
+    [
+        "FROM",
+        "WHERE",
+        "ORDER",
+        "LIMIT",
+        "UNION",
+        "INTERSECT",
+        "EXCEPT",
+    ]
+    .iter()
+    .any(|kw| word.eq_ignore_ascii_case(kw))
+}
+
+pub fn is_col_constraint_keyword(word: &str) -> bool {
+    [
+        "CONSTRAINT",
+        "PRIMARY",
+        "NOT",
+        "NULL",
+        "UNIQUE",
+        "CHECK",
+        "DEFAULT",
+        "COLLATE",
+        "REFERENCES",
+        "GENERATED",
+        "AS",
+    ]
+    .iter()
+    .any(|kw| word.eq_ignore_ascii_case(kw))
+}
+
+/// Whether `word` is one of SQLite's keywords, which have to be quoted to be used as identifiers.
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|kw| word.eq_ignore_ascii_case(kw))
+}
+
+const KEYWORDS: &[&str] = &[
+    "ABORT",
+    "ACTION",
+    "ADD",
+    "AFTER",
+    "ALL",
+    "ALTER",
+    "ALWAYS",
+    "ANALYZE",
+    "AND",
+    "AS",
+    "ASC",
+    "ATTACH",
+    "AUTOINCREMENT",
+    "BEFORE",
+    "BEGIN",
+    "BETWEEN",
+    "BY",
+    "CASCADE",
+    "CASE",
+    "CAST",
+    "CHECK",
+    "COLLATE",
+    "COLUMN",
+    "COMMIT",
+    "CONFLICT",
+    "CONSTRAINT",
+    "CREATE",
+    "CROSS",
+    "CURRENT",
+    "CURRENT_DATE",
+    "CURRENT_TIME",
+    "CURRENT_TIMESTAMP",
+    "DATABASE",
+    "DEFAULT",
+    "DEFERRABLE",
+    "DEFERRED",
+    "DELETE",
+    "DESC",
+    "DETACH",
+    "DISTINCT",
+    "DO",
+    "DROP",
+    "EACH",
+    "ELSE",
+    "END",
+    "ESCAPE",
+    "EXCEPT",
+    "EXCLUDE",
+    "EXCLUSIVE",
+    "EXISTS",
+    "EXPLAIN",
+    "FAIL",
+    "FILTER",
+    "FIRST",
+    "FOLLOWING",
+    "FOR",
+    "FOREIGN",
+    "FROM",
+    "FULL",
+    "GENERATED",
+    "GLOB",
+    "GROUP",
+    "GROUPS",
+    "HAVING",
+    "IF",
+    "IGNORE",
+    "IMMEDIATE",
+    "IN",
+    "INDEX",
+    "INDEXED",
+    "INITIALLY",
+    "INNER",
+    "INSERT",
+    "INSTEAD",
+    "INTERSECT",
+    "INTO",
+    "IS",
+    "ISNULL",
+    "JOIN",
+    "KEY",
+    "LAST",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "MATCH",
+    "MATERIALIZED",
+    "NATURAL",
+    "NO",
+    "NOT",
+    "NOTHING",
+    "NOTNULL",
+    "NULL",
+    "NULLS",
+    "OF",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OTHERS",
+    "OUTER",
+    "OVER",
+    "PARTITION",
+    "PLAN",
+    "PRAGMA",
+    "PRECEDING",
+    "PRIMARY",
+    "QUERY",
+    "RAISE",
+    "RANGE",
+    "RECURSIVE",
+    "REFERENCES",
+    "REGEXP",
+    "REINDEX",
+    "RELEASE",
+    "RENAME",
+    "REPLACE",
+    "RESTRICT",
+    "RETURNING",
+    "RIGHT",
+    "ROLLBACK",
+    "ROW",
+    "ROWS",
+    "SAVEPOINT",
+    "SELECT",
+    "SET",
+    "TABLE",
+    "TEMP",
+    "TEMPORARY",
+    "THEN",
+    "TIES",
+    "TO",
+    "TRANSACTION",
+    "TRIGGER",
+    "UNBOUNDED",
+    "UNION",
+    "UNIQUE",
+    "UPDATE",
+    "USING",
+    "VACUUM",
+    "VALUES",
+    "VIEW",
+    "VIRTUAL",
+    "WHEN",
+    "WHERE",
+    "WINDOW",
+    "WITH",
+    "WITHOUT",
+];
@@ -0,0 +1,214 @@
+//! Formatting of floating point numbers that reproduces SQLite's `printf()` digit for digit,
+//! which differs from Rust's shortest round-trip formatting, e.g. `0.1` is `0.1000000000000000055`
+//! with 20 significant digits.
+
+/// Formats `r` like SQLite's `%!.{precision}g`, which is what sqlite3 prints for REAL values
+/// with a precision of 15. Unlike C's `%g`, the `!` flag keeps a `.0` on integral values.
+pub fn generic(r: f64, precision: usize) -> String {
+    let precision = precision.max(1);
+    let (neg, digits, dp) = match decode(r, precision, 26) {
+        Decoded::Finite { neg, digits, dp } => (neg, digits, dp),
+        Decoded::Inf { neg } => return if neg { "-Inf" } else { "Inf" }.to_owned(),
+        Decoded::NaN => return "NaN".to_owned(),
+    };
+
+    let exp = dp - 1;
+    let mut precision = precision as i32 - 1;
+    let is_exp = exp < -4 || exp > precision;
+    if !is_exp {
+        precision -= exp;
+    }
+
+    let mut digits = digits.iter().copied();
+    let mut next_digit = || digits.next().unwrap_or(b'0') as char;
+    let mut s = String::new();
+    if neg {
+        s.push('-');
+    }
+
+    let mut e2 = if is_exp { 0 } else { exp };
+    if e2 < 0 {
+        s.push('0');
+    } else {
+        while e2 >= 0 {
+            s.push(next_digit());
+            e2 -= 1;
+        }
+    }
+    s.push('.');
+    e2 += 1;
+    while e2 < 0 && precision > 0 {
+        s.push('0');
+        precision -= 1;
+        e2 += 1;
+    }
+    while precision > 0 {
+        s.push(next_digit());
+        precision -= 1;
+    }
+
+    // Trailing zeros are removed, but one is kept after the decimal point.
+    s.truncate(s.trim_end_matches('0').len());
+    if s.ends_with('.') {
+        s.push('0');
+    }
+
+    if is_exp {
+        let sign = if exp < 0 { '-' } else { '+' };
+        s.push_str(&format!("e{}{:02}", sign, exp.abs()));
+    }
+    s
+}
+
+enum Decoded {
+    /// The significant `digits` without trailing zeros and the position of the decimal point
+    /// relative to their start.
+    Finite {
+        neg: bool,
+        digits: Vec<u8>,
+        dp: i32,
+    },
+    Inf {
+        neg: bool,
+    },
+    NaN,
+}
+
+/// Converts `r` into decimal digits rounded to `round` significant digits, but no more than
+/// `max_round`, the way SQLite's `sqlite3FpDecode()` does with double-double arithmetic.
+// The constants are spelled exactly like in SQLite's source.
+#[allow(clippy::excessive_precision)]
+fn decode(r: f64, round: usize, max_round: usize) -> Decoded {
+    let neg = r < 0.0;
+    let r = r.abs();
+    if r.is_nan() {
+        return Decoded::NaN;
+    } else if r.is_infinite() {
+        return Decoded::Inf { neg };
+    } else if r == 0.0 {
+        return Decoded::Finite {
+            neg: false,
+            digits: vec![b'0'],
+            dp: 1,
+        };
+    }
+
+    // Scales the number into the range of a u64 while keeping track of the lost precision.
+    let mut rr = [r, 0.0];
+    let mut exp = 0;
+    if rr[0] > 9.223372036854774784e18 {
+        while rr[0] > 9.223372036854774784e118 {
+            exp += 100;
+            dekker_mul2(&mut rr, 1.0e-100, -1.99918998026028836196e-117);
+        }
+        while rr[0] > 9.223372036854774784e28 {
+            exp += 10;
+            dekker_mul2(&mut rr, 1.0e-10, -3.6432197315497741579e-27);
+        }
+        while rr[0] > 9.223372036854774784e18 {
+            exp += 1;
+            dekker_mul2(&mut rr, 1.0e-01, -5.5511151231257827021e-18);
+        }
+    } else {
+        while rr[0] < 9.223372036854774784e-83 {
+            exp -= 100;
+            dekker_mul2(&mut rr, 1.0e100, -1.5902891109759918046e83);
+        }
+        while rr[0] < 9.223372036854774784e07 {
+            exp -= 10;
+            dekker_mul2(&mut rr, 1.0e10, 0.0);
+        }
+        while rr[0] < 9.22337203685477478e17 {
+            exp -= 1;
+            dekker_mul2(&mut rr, 1.0e01, 0.0);
+        }
+    }
+    let v = if rr[1] < 0.0 {
+        (rr[0] as u64).wrapping_sub((-rr[1]) as u64)
+    } else {
+        (rr[0] as u64).wrapping_add(rr[1] as u64)
+    };
+
+    let mut digits = v.to_string().into_bytes();
+    let mut dp = digits.len() as i32 + exp;
+
+    if round < digits.len() || digits.len() > max_round {
+        let round = round.min(max_round);
+        let round_up = digits[round] >= b'5';
+        digits.truncate(round);
+        if round_up {
+            match digits.iter().rposition(|d| *d != b'9') {
+                Some(i) => {
+                    digits[i] += 1;
+                    digits[i + 1..].iter_mut().for_each(|d| *d = b'0');
+                }
+                None => {
+                    digits.iter_mut().for_each(|d| *d = b'0');
+                    digits.insert(0, b'1');
+                    dp += 1;
+                }
+            }
+        }
+    }
+    while digits.len() > 1 && digits.last() == Some(&b'0') {
+        digits.pop();
+    }
+
+    Decoded::Finite { neg, digits, dp }
+}
+
+/// Multiplies the double-double `x` by the double-double `y + yy`.
+fn dekker_mul2(x: &mut [f64; 2], y: f64, yy: f64) {
+    let split = |v: f64| {
+        let hi = f64::from_bits(v.to_bits() & 0xffff_ffff_fc00_0000);
+        (hi, v - hi)
+    };
+    let (hx, tx) = split(x[0]);
+    let (hy, ty) = split(y);
+    let p = hx * hy;
+    let q = hx * ty + tx * hy;
+    let c = p + q;
+    let mut cc = p - c + q + tx * ty;
+    cc += x[0] * yy + x[1] * y;
+    x[0] = c + cc;
+    x[1] = c - x[0];
+    x[1] += cc;
+}
+
+#[cfg(test)]
+mod test {
+    use super::generic;
+
+    #[test]
+    fn formats_like_sqlite_with_15_digits() {
+        let cases = [
+            (2.0, "2.0"),
+            (-3.5, "-3.5"),
+            (0.0, "0.0"),
+            (-0.0, "0.0"),
+            (1e14, "100000000000000.0"),
+            (1e15, "1.0e+15"),
+            (1e20, "1.0e+20"),
+            (1e-5, "1.0e-05"),
+            (0.0001, "0.0001"),
+            (1.0 / 3.0, "0.333333333333333"),
+            (123_456_789_012_345_680.0, "1.23456789012346e+17"),
+            (f64::MAX, "1.79769313486232e+308"),
+            (5e-324, "4.94065645841247e-324"),
+            (f64::INFINITY, "Inf"),
+            (f64::NEG_INFINITY, "-Inf"),
+        ];
+        for (r, expected) in cases.iter() {
+            assert_eq!(generic(*r, 15), *expected, "{:e}", r);
+        }
+    }
+
+    #[test]
+    fn formats_like_sqlite_with_20_digits() {
+        assert_eq!(generic(-0.1, 20), "-0.1000000000000000055");
+        assert_eq!(generic(1e20, 20), "1.0e+20");
+        assert_eq!(generic(1e300, 20), "9.99999999999999956e+299");
+        assert_eq!(generic(1e100, 20), "1.000000000000000015e+100");
+        assert_eq!(generic(0.5, 20), "0.5");
+    }
+}
@@ -45,7 +45,7 @@ where
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if matches!(self.inner, None) {
+        if self.inner.is_none() {
             self.inner = Some(
                 self.left
                     .take()
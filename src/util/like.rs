@@ -0,0 +1,42 @@
+/// Matches `s` against an SQL LIKE `pattern`, where `%` matches any sequence of characters and
+/// `_` any single character. Like in SQLite, ASCII letters match regardless of case. A `\`
+/// escapes the character following it, as the patterns of sqlite3's dot-commands do.
+pub fn like(pattern: &str, s: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+    matches(&pattern, &s)
+}
+
+fn matches(pattern: &[char], s: &[char]) -> bool {
+    match pattern {
+        [] => s.is_empty(),
+        ['%', rest @ ..] => (0..=s.len()).any(|i| matches(rest, &s[i..])),
+        ['_', rest @ ..] => !s.is_empty() && matches(rest, &s[1..]),
+        ['\\', c, rest @ ..] | [c, rest @ ..] => match s {
+            [first, s @ ..] => first.eq_ignore_ascii_case(c) && matches(rest, s),
+            [] => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::like;
+
+    #[test]
+    fn matches_wildcards_case_insensitively() {
+        assert!(like("t%", "Tbl"));
+        assert!(like("%", ""));
+        assert!(like("_b_", "abc"));
+        assert!(!like("_b", "abc"));
+        assert!(like("%c%", "abc"));
+        assert!(!like("abc", "abcd"));
+    }
+
+    #[test]
+    fn escapes_wildcards() {
+        assert!(like(r"a\_c", "a_c"));
+        assert!(!like(r"a\_c", "abc"));
+        assert!(like(r"100\%", "100%"));
+    }
+}
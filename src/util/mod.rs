@@ -2,8 +2,10 @@ mod contains_;
 mod flat_map_ok_and_then;
 mod flatten_;
 mod flip;
+pub mod float_fmt;
 mod iter_either;
 mod join_ok;
+mod like;
 mod map_ok_and_then;
 pub mod str_sim;
 mod take_while_incl;
@@ -14,5 +16,6 @@ pub use flatten_::FlattenExt;
 pub use flip::flip;
 pub use iter_either::IterEither;
 pub use join_ok::JoinOkExt;
+pub use like::like;
 pub use map_ok_and_then::MapOkAndThenExt;
 pub use take_while_incl::TakeWhileInclExt;
@@ -88,7 +88,7 @@ mod tests {
 
     #[test]
     fn test() {
-        let xs = vec![-2, -1, 0, 1, 2];
+        let xs = [-2, -1, 0, 1, 2];
         let mut it = xs.iter().take_while_incl(|&&x| x < 0);
 
         assert_eq!((0, Some(5)), it.size_hint());